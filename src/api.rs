@@ -1,17 +1,22 @@
+use crate::cdf::CdfContext;
+use crate::cpu::get_cpu_flags;
+use crate::error::DecodeError;
 use crate::frame::Frame;
 use crate::headers::*;
 use crate::obu::*;
 use crate::util::Pixel;
 use crate::internal::*;
+use crate::ref_mvs::RefMvs;
 
+use std::collections::VecDeque;
 use std::rc::Rc;
-use std::{cmp, fmt, io};
+use std::{cmp, fmt};
 use std::vec::Vec;
 
 use arg_enum_proc_macro::ArgEnum;
 use num_derive::*;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(C)]
 pub enum CodecStatus {
     /// The codec needs more data to produce an output Packet/Frame
@@ -22,8 +27,8 @@ pub enum CodecStatus {
     LimitReached,
     /// A Frame had been decoded but not emitted yet
     //Decoded,
-    /// Generic fatal error
-    Failure,
+    /// The packet failed to decode; further packets can still be sent
+    Failure(DecodeError),
 }
 
 impl Default for CodecStatus {
@@ -115,6 +120,10 @@ pub struct Config {
     pub operating_point: isize, // select an operating point for scalable AV1 bitstreams (0 - 31)
     pub all_layers: isize,      // output all spatial layers of a scalable AV1 biststream
     pub frame_size_limit: usize, // maximum frame size, in pixels (0 = unlimited)
+    pub cpu_flags_mask: u32, // CPU features the DSP functions may use (see cpu::CPU_FLAG_*), 0 forces the scalar code
+    pub large_scale_tile: isize, // decode the tile lists of large scale tile streams
+    pub output_all_tiles: isize, // output tile list tiles one by one, not composed
+    pub conceal_errors: isize, // conceal tiles that fail to decode instead of dropping the frame
 }
 
 impl Default for Config {
@@ -126,35 +135,94 @@ impl Default for Config {
             operating_point: 0,
             all_layers: 1, // just until the tests are adjusted
             frame_size_limit: 0,
+            cpu_flags_mask: !0,
+            large_scale_tile: 0,
+            output_all_tiles: 0,
+            conceal_errors: 0,
         }
     }
 }
 
-// reference/entropy state
-pub(crate) struct RefState{
-    //Dav1dThreadPicture p;
-    //Dav1dRef *segmap;
-    //Dav1dRef *refmvs;
-    refpoc:[u32; 7],
+// reference/entropy state of one of the 8 reference slots. Everything is
+// reference counted, a frame refreshing several slots shares it between
+// them. The frame header holds the dimensions, order hint, global motion,
+// segmentation data and loop filter deltas later frames inherit from the
+// reference.
+#[derive(Clone)]
+pub(crate) struct RefState<T: Pixel> {
+    // the picture, after super-resolution upscaling
+    pub(crate) p: Option<Rc<Frame<T>>>,
+    pub(crate) frame_hdr: Option<Rc<FrameHeader>>,
+    // the CDFs later frames start from, see 7.20
+    pub(crate) cdf: Option<Rc<CdfContext>>,
+    pub(crate) segmap: Option<Rc<Vec<u8>>>,
+    pub(crate) refmvs: Option<Rc<Vec<RefMvs>>>,
+    // order hints of the references the frame itself used
+    pub(crate) refpoc: [u32; 7],
+}
+
+impl<T: Pixel> Default for RefState<T> {
+    fn default() -> Self {
+        RefState {
+            p: None,
+            frame_hdr: None,
+            cdf: None,
+            segmap: None,
+            refmvs: None,
+            refpoc: [0; 7],
+        }
+    }
 }
 
 pub struct Context<T: Pixel> {
     pub(crate) n_fc: usize,
-    pub(crate) fc: Vec<FrameContext>,
+    pub(crate) fc: Vec<FrameContext<T>>,
 
     pub(crate) seq_hdr: Option<Rc<SequenceHeader>>,
     pub(crate) frame_hdr: Option<Rc<FrameHeader>>,
     pub(crate) tile: Vec<TileGroup>,
     pub(crate) n_tiles: i32,
-    //pub(crate) refs: [RefState; 8],
+    pub(crate) refs: [RefState<T>; 8],
+    // HDR static metadata of the current sequence
+    pub(crate) content_light: Option<ContentLightLevel>,
+    pub(crate) mastering_display: Option<MasteringDisplay>,
+    // T.35 metadata received since the last output picture
+    pub(crate) itut_t35: Vec<ITUTT35>,
+    // scalability structure of the current sequence
+    pub(crate) scalability: Option<Scalability>,
+    // timecode of the next output picture
+    pub(crate) timecode: Option<Timecode>,
 
     pub(crate) apply_grain: bool,
     pub(crate) operating_point: usize,
     pub(crate) operating_point_idc: u32,
-    pub(crate) all_layers: i32,
+    // highest spatial layer of the operating point
+    pub(crate) max_spatial_id: u32,
+    pub(crate) all_layers: bool,
+    // a temporal delimiter was seen since the last output picture
+    pub(crate) new_temporal_unit: bool,
+    // the output picture is the first of its temporal unit
+    pub(crate) frame_new_temporal_unit: bool,
     pub(crate) frame_size_limit: usize,
+    pub(crate) large_scale_tile: bool,
+    pub(crate) output_all_tiles: bool,
+    // the pictures the tiles of tile lists are predicted from
+    pub(crate) anchors: Vec<Rc<Frame<T>>>,
+    // decoded tiles waiting for output, with output_all_tiles
+    pub(crate) tile_out: VecDeque<Frame<T>>,
     pub(crate) drain: bool,
+    // after an error, no frame is decoded before a random access point, a
+    // key frame or a switch frame
+    pub(crate) waiting_for_rap: bool,
+    // frames dropped while waiting_for_rap or because of missing references
+    pub(crate) skipped_frames: u64,
+    // an error to report once the picture decoded before it was returned
+    pub(crate) cached_error: Option<DecodeError>,
+    pub(crate) conceal_errors: bool,
     pub(crate) frame: Option<Frame<T>>,
+    // without all_layers, the highest spatial layer of the temporal unit
+    // received so far
+    pub(crate) cache: Option<Frame<T>>,
     pub(crate) packet: Option<Packet>,
     //pub(crate) pool: rayon::ThreadPool,
 }
@@ -162,27 +230,75 @@ pub struct Context<T: Pixel> {
 impl<T: Pixel> Context<T> {
     pub fn new(cfg: &Config) -> Self {
         debug_assert!(cfg.n_frame_threads > 0);
+        debug_assert!(
+            cfg.operating_point >= 0 && cfg.operating_point < MAX_OPERATING_POINTS as isize
+        );
+        debug_assert!(cfg.all_layers == 0 || cfg.all_layers == 1);
+
+        let dsp = DSPContext::new(get_cpu_flags(cfg.cpu_flags_mask));
+        let mut fc = vec![FrameContext::default(); cfg.n_frame_threads];
+        for f in fc.iter_mut() {
+            f.dsp = dsp;
+            f.n_tc = cfg.n_tile_threads as i32;
+            f.tc = vec![TileContext::default(); cfg.n_tile_threads];
+        }
 
         Context {
             n_fc: cfg.n_frame_threads,
-            fc: vec![FrameContext::default(); cfg.n_frame_threads],
+            fc,
 
             seq_hdr: None,
             frame_hdr: None,
             tile: vec![],
             n_tiles: 0,
-
-            apply_grain: false,
-            operating_point: 0,
+            refs: Default::default(),
+            content_light: None,
+            mastering_display: None,
+            itut_t35: vec![],
+            scalability: None,
+            timecode: None,
+
+            apply_grain: cfg.apply_grain != 0,
+            operating_point: cfg.operating_point as usize,
             operating_point_idc: 0,
-            all_layers: 0,
-            frame_size_limit: 0,
+            max_spatial_id: 0,
+            all_layers: cfg.all_layers != 0,
+            new_temporal_unit: false,
+            frame_new_temporal_unit: false,
+            frame_size_limit: cfg.frame_size_limit,
+            large_scale_tile: cfg.large_scale_tile != 0,
+            output_all_tiles: cfg.output_all_tiles != 0,
+            conceal_errors: cfg.conceal_errors != 0,
+            anchors: vec![],
+            tile_out: VecDeque::new(),
             drain: false,
+            waiting_for_rap: true,
+            skipped_frames: 0,
+            cached_error: None,
             frame: None,
+            cache: None,
             packet: None,
         }
     }
 
+    // Select the operating point of scalable streams, it takes effect from
+    // the next sequence header on.
+    pub fn set_operating_point(&mut self, operating_point: usize) {
+        debug_assert!(operating_point < MAX_OPERATING_POINTS);
+        self.operating_point = operating_point;
+    }
+
+    // Load the anchor frames the tile lists of a large scale tile stream are
+    // decoded against, indexed by the anchor_frame_idx of the tile list
+    // entries. They replace the previously loaded ones.
+    pub fn set_anchor_frames(&mut self, anchors: Vec<Frame<T>>) -> Result<(), DecodeError> {
+        if anchors.len() > MAX_ANCHOR_FRAMES {
+            return Err(DecodeError::ResourceLimit("anchor frames"));
+        }
+        self.anchors = anchors.into_iter().map(Rc::new).collect();
+        Ok(())
+    }
+
     pub fn send_packet(&mut self, pkt: &mut Option<Packet>) -> Result<(), CodecStatus> {
         if pkt.is_none() {
             return Err(CodecStatus::NeedMoreData);
@@ -200,51 +316,520 @@ impl<T: Pixel> Context<T> {
     }
 
     pub fn receive_frame(&mut self) -> Result<Frame<T>, CodecStatus> {
+        if let Some(e) = self.cached_error.take() {
+            return Err(CodecStatus::Failure(e));
+        }
+
         if self.drain {
             return self.drain_frame();
         }
 
-        if self.packet.is_none() {
-            return Err(CodecStatus::NeedMoreData);
+        if !self.output_picture_ready(false) && self.packet.is_some() {
+            let pkt = self.packet.as_ref().unwrap();
+            let (mut offset, size) = (pkt.offset, pkt.data.len());
+
+            while offset < size {
+                match self.parse_obus(offset, false) {
+                    Ok(len) => {
+                        offset += len;
+                        if offset >= size {
+                            self.packet.take();
+                        }
+                    }
+                    Err(e) => {
+                        // the rest of the packet is dropped, decoding
+                        // resumes with the next random access point
+                        self.packet.take();
+                        if !self.output_picture_ready(false) {
+                            return Err(CodecStatus::Failure(e));
+                        }
+                        self.cached_error = Some(e);
+                        break;
+                    }
+                }
+                if self.output_picture_ready(false) {
+                    break;
+                }
+            }
+
+            if self.packet.is_some() {
+                self.packet.as_mut().unwrap().offset = offset;
+            }
+        }
+
+        // with a single frame context nothing else of the temporal unit is
+        // pending once the packet is consumed
+        if self.output_picture_ready(self.n_fc == 1) {
+            Ok(self.output_image())
+        } else {
+            Err(CodecStatus::NeedMoreData)
         }
+    }
 
-        let pkt = self.packet.as_ref().unwrap();
-        let (mut offset, size) = (pkt.offset, pkt.data.len());
+    // Whether a picture can be returned. Unless all layers are output, a
+    // picture of a lower spatial layer is held back until the next layer of
+    // the same temporal unit replaces it, or the next temporal unit starts.
+    fn output_picture_ready(&mut self, drain: bool) -> bool {
+        if !self.tile_out.is_empty() {
+            return true;
+        }
 
-        while offset < size {
-            let res = self.parse_obus(offset, false);
-            let err = res.is_err();
-            if err {
-                self.packet.take(); // all packet data are consumed, then release it
-            } else {
-                offset += res.unwrap();
-                if offset >= size {
-                    self.packet.take();
+        if !self.all_layers && self.max_spatial_id != 0 {
+            if self.frame.is_some() && self.cache.is_some() {
+                let cache = self.cache.as_ref().unwrap();
+                if cache.spatial_id as u32 == self.max_spatial_id || self.frame_new_temporal_unit
+                {
+                    return true;
                 }
-            }
-            if self.frame.is_some() {
-                break;
-            } else if err {
-                return Err(CodecStatus::Failure);
+                self.cache = self.frame.take();
+                return false;
+            } else if self.cache.is_some() && drain {
+                return true;
+            } else if self.frame.is_some() {
+                self.cache = self.frame.take();
+                return false;
             }
         }
 
-        if self.packet.is_some() {
-            self.packet.as_mut().unwrap().offset = offset;
+        self.frame.is_some()
+    }
+
+    fn output_image(&mut self) -> Frame<T> {
+        if let Some(tile) = self.tile_out.pop_front() {
+            return tile;
         }
 
-        let frame = self.frame.take();
-        match frame {
-            Some(f) => Ok(f),
-            None => Err(CodecStatus::NeedMoreData),
+        if self.all_layers || self.max_spatial_id == 0 {
+            self.frame.take().unwrap()
+        } else {
+            // the picture of the next temporal unit is held back in turn
+            let out = self.cache.take().unwrap();
+            self.cache = self.frame.take();
+            out
         }
     }
 
+    // The number of frames dropped so far because they followed an error
+    // before the next random access point, or were predicted from frames
+    // that were lost or dropped themselves. The frames failing to decode
+    // are reported by receive_frame instead.
+    pub fn skipped_frames(&self) -> u64 {
+        self.skipped_frames
+    }
+
     pub fn flush(&mut self) {
         self.drain = true;
     }
 
     fn drain_frame(&mut self) -> Result<Frame<T>, CodecStatus> {
+        if self.output_picture_ready(true) {
+            return Ok(self.output_image());
+        }
         Err(CodecStatus::LimitReached)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::getbits::test::PutBits;
+    use crate::levels::ObuType;
+    use crate::obu::test::{layer_obu, obu};
+
+    // a 16x16 key frame, with its sequence header, and the two inter frames
+    // following it, one temporal unit each
+    const KEY_FRAME: [u8; 42] = [
+        0x12, 0x00, 0x0a, 0x0a, 0x00, 0x00, 0x00, 0xf8, 0xcf, 0xfc, 0x42, 0x14, 0x01, 0x40, 0x32,
+        0x1a, 0x10, 0x02, 0x9f, 0x23, 0x89, 0xfa, 0xed, 0xe3, 0x60, 0x00, 0x10, 0xa8, 0x0f, 0xac,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x41, 0x04, 0x10, 0x10, 0x75, 0xc0,
+    ];
+    const INTER_FRAMES: [[u8; 25]; 2] = [
+        [
+            0x12, 0x00, 0x32, 0x15, 0x30, 0x0a, 0x02, 0x00, 0x00, 0x00, 0x05, 0x9e, 0x37, 0x05,
+            0xed, 0xcf, 0xb4, 0xc0, 0xa2, 0x8a, 0x28, 0x50, 0x00, 0x74, 0xe0,
+        ],
+        [
+            0x12, 0x00, 0x32, 0x15, 0x30, 0x12, 0x04, 0x12, 0x49, 0x24, 0x05, 0x9e, 0x37, 0x05,
+            0xed, 0xcf, 0xb4, 0xc0, 0xa2, 0x8a, 0x28, 0x50, 0x00, 0x73, 0x80,
+        ],
+    ];
+    // a 128x64 key frame of two 64x64 tiles, whose frame OBU is made of a
+    // 26 byte frame header, then the tile group: a byte with the
+    // tile_start_and_end_present_flag, the size of the first tile and the
+    // 5 bytes of each tile
+    const TWO_TILE_KEY_FRAME: [u8; 54] = [
+        0x12, 0x00, 0x0a, 0x0a, 0x00, 0x00, 0x00, 0xf9, 0x97, 0xff, 0xe2, 0x10, 0xa8, 0x0a, 0x32,
+        0x26, 0x10, 0x03, 0x89, 0xf2, 0x38, 0x9f, 0xae, 0xde, 0x36, 0x00, 0x01, 0x0a, 0x80, 0xfa,
+        0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x10, 0x41, 0x01, 0x04, 0x11, 0x00, 0x04, 0xb4,
+        0xf8, 0xd4, 0xf1, 0xa8, 0xb4, 0xf8, 0xd4, 0xf1, 0xa8,
+    ];
+
+    fn decode(ctx: &mut Context<u8>, data: &[u8]) -> Result<Frame<u8>, CodecStatus> {
+        let pkt = Packet {
+            data: data.to_vec(),
+            offset: 0,
+            pts: 0,
+        };
+        ctx.send_packet(&mut Some(pkt)).unwrap();
+        ctx.receive_frame()
+    }
+
+    fn size(frame: Frame<u8>) -> (usize, usize) {
+        (frame.width(), frame.height())
+    }
+
+    fn invalid_obu_type(res: Result<Frame<u8>, CodecStatus>) -> u32 {
+        match res {
+            Err(CodecStatus::Failure(DecodeError::InvalidBitstream { obu_type, .. })) => obu_type,
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("unexpected frame"),
+        }
+    }
+
+    #[test]
+    fn resync_after_error() {
+        let mut ctx = Context::<u8>::new(&Config::default());
+        assert_eq!(decode(&mut ctx, &KEY_FRAME).map(size), Ok((16, 16)));
+        assert_eq!(decode(&mut ctx, &INTER_FRAMES[0]).map(size), Ok((16, 16)));
+        assert_eq!(decode(&mut ctx, &INTER_FRAMES[1]).map(size), Ok((16, 16)));
+        assert_eq!(ctx.skipped_frames(), 0);
+
+        // the first inter frame is cut short, which fails it; the second
+        // one is then skipped up to the next key frame
+        let mut ctx = Context::<u8>::new(&Config::default());
+        assert_eq!(decode(&mut ctx, &KEY_FRAME).map(size), Ok((16, 16)));
+        let res = decode(&mut ctx, &INTER_FRAMES[0][..20]);
+        assert_eq!(invalid_obu_type(res), ObuType::OBU_FRAME as u32);
+        let res = decode(&mut ctx, &INTER_FRAMES[1]).map(size);
+        assert_eq!(res, Err(CodecStatus::NeedMoreData));
+        assert_eq!(decode(&mut ctx, &KEY_FRAME).map(size), Ok((16, 16)));
+        assert_eq!(ctx.skipped_frames(), 1);
+    }
+
+    // A 16x16 inter or switch frame of the sequence of KEY_FRAME, with a
+    // single tile of arbitrary data. Switch frames also signal the order
+    // hints of the slots they don't refresh, 0 here.
+    fn inter_frame(frame_type: FrameType, order_hint: u32, refresh: u32, refidx: u32) -> Vec<u8> {
+        let none = PRIMARY_REF_NONE as u32;
+        inter_frame_with_cdfs(frame_type, order_hint, refresh, refidx, none, None)
+    }
+
+    // inter_frame() loading the CDFs of its primary_ref_frame, with CDF
+    // updates enabled if refresh_context, whether the frame saves the CDFs
+    // it ends with, is given.
+    fn inter_frame_with_cdfs(
+        frame_type: FrameType,
+        order_hint: u32,
+        refresh: u32,
+        refidx: u32,
+        primary_ref_frame: u32,
+        refresh_context: Option<bool>,
+    ) -> Vec<u8> {
+        let switch = frame_type == FrameType::FRAME_TYPE_SWITCH;
+        let mut pb = PutBits::default();
+        // show_existing_frame, frame_type, show_frame, and for inter frames
+        // error_resilient_mode; disable_cdf_update
+        pb.put_bits(0, 1);
+        pb.put_bits(frame_type as u32, 2).put_bits(1, 1);
+        if !switch {
+            pb.put_bits(0, 1);
+        }
+        pb.put_bits(refresh_context.is_none() as u32, 1);
+        // frame_size_override_flag, order_hint, primary_ref_frame
+        if !switch {
+            pb.put_bits(0, 1);
+        }
+        pb.put_bits(order_hint, 6);
+        if !switch {
+            pb.put_bits(primary_ref_frame, 3);
+        }
+        pb.put_bits(refresh, 8);
+        if switch && refresh != 0xff {
+            for _ in 0..8 {
+                pb.put_bits(0, 6);
+            }
+        }
+        // frame_refs_short_signaling, ref_frame_idx, the frame size of
+        // switch frames and render_and_frame_size_different
+        pb.put_bits(0, 1);
+        for _ in 0..7 {
+            pb.put_bits(refidx, 3);
+        }
+        if switch {
+            pb.put_bits(15, 4).put_bits(15, 4);
+        }
+        pb.put_bits(0, 1);
+        // allow_high_precision_mv, is_filter_switchable,
+        // is_motion_mode_switchable, disable_frame_end_update_cdf,
+        // uniform_tile_spacing_flag
+        pb.put_bits(0, 1).put_bits(1, 1).put_bits(0, 1);
+        if let Some(refresh_context) = refresh_context {
+            pb.put_bits(!refresh_context as u32, 1);
+        }
+        pb.put_bits(1, 1);
+        // base_q_idx, no quantizer deltas and matrices, segmentation or
+        // delta_q
+        pb.put_bits(100, 8).put_bits(0, 4).put_bits(0, 3);
+        // no loop filter, tx_mode_select, reference_select,
+        // reduced_tx_set, and identity global motion for all references
+        pb.put_bits(0, 12).put_bits(0, 3).put_bits(0, 1);
+        pb.put_bits(0, 3).put_bits(0, 7);
+        let data = [&pb.data()[..], &[0x12, 0x34]].concat();
+        [&KEY_FRAME[..2], &obu(ObuType::OBU_FRAME, &data)].concat()
+    }
+
+    #[test]
+    fn resync_at_switch_frame() {
+        let inter = FrameType::FRAME_TYPE_INTER;
+        let switch = FrameType::FRAME_TYPE_SWITCH;
+        let mut ctx = Context::<u8>::new(&Config::default());
+        assert_eq!(decode(&mut ctx, &KEY_FRAME).map(size), Ok((16, 16)));
+        let data = inter_frame(inter, 1, 0x02, 0);
+        assert_eq!(decode(&mut ctx, &data).map(size), Ok((16, 16)));
+
+        // after an error, the frame refreshing slot 2 is skipped, and so is
+        // a switch frame predicting from it; the frames following it wait
+        // for the next random access point, even if their references are
+        // still there
+        let res = decode(&mut ctx, &INTER_FRAMES[0][..20]);
+        assert_eq!(invalid_obu_type(res), ObuType::OBU_FRAME as u32);
+        let data = inter_frame(inter, 2, 0x04, 1);
+        let res = decode(&mut ctx, &data).map(size);
+        assert_eq!(res, Err(CodecStatus::NeedMoreData));
+        let data = inter_frame(switch, 3, 0x7f, 2);
+        let res = decode(&mut ctx, &data).map(size);
+        assert_eq!(res, Err(CodecStatus::NeedMoreData));
+        let data = inter_frame(inter, 4, 0x01, 7);
+        let res = decode(&mut ctx, &data).map(size);
+        assert_eq!(res, Err(CodecStatus::NeedMoreData));
+        assert_eq!(ctx.skipped_frames(), 3);
+
+        // a switch frame with all of its references resumes decoding
+        let data = inter_frame(switch, 5, 0xff, 7);
+        assert_eq!(decode(&mut ctx, &data).map(size), Ok((16, 16)));
+        let data = inter_frame(inter, 6, 0x01, 0);
+        assert_eq!(decode(&mut ctx, &data).map(size), Ok((16, 16)));
+        assert_eq!(ctx.skipped_frames(), 3);
+    }
+
+    #[test]
+    fn cdfs_refresh_and_inheritance() {
+        let inter = FrameType::FRAME_TYPE_INTER;
+        let mut ctx = Context::<u8>::new(&Config::default());
+        assert_eq!(decode(&mut ctx, &KEY_FRAME).map(size), Ok((16, 16)));
+        let default_cdf = CdfContext::default();
+        let cdf = |r: &RefState<u8>| r.cdf.as_deref() == Some(&default_cdf);
+        assert!(ctx.refs.iter().all(cdf));
+
+        // the CDFs of slot 3, as adapted by the frame stored there
+        let mut adapted = CdfContext::default();
+        adapted.m.skip_mode[1] = [1234, 7];
+        ctx.refs[3].cdf = Some(Rc::new(adapted.clone()));
+
+        // a frame with slot 3 as primary reference starts its tile from
+        // these CDFs, and saves the ones it ends with to slot 5, with the
+        // adaptation counters reset
+        let data = inter_frame_with_cdfs(inter, 1, 1 << 5, 3, 0, Some(true));
+        assert_eq!(decode(&mut ctx, &data).map(size), Ok((16, 16)));
+        assert_eq!(ctx.fc[0].ts[0].cdf, adapted);
+        let mut saved = adapted;
+        saved.m.skip_mode[1][1] = 0;
+        assert_eq!(ctx.refs[5].cdf.as_deref(), Some(&saved));
+
+        // the next frame inherits them from slot 5; without refresh_context
+        // it saves the CDFs it started from
+        let data = inter_frame_with_cdfs(inter, 2, 1 << 6, 5, 0, Some(false));
+        assert_eq!(decode(&mut ctx, &data).map(size), Ok((16, 16)));
+        assert_eq!(ctx.fc[0].ts[0].cdf, saved);
+        let cdf = |i: usize| ctx.refs[i].cdf.clone().unwrap();
+        assert!(Rc::ptr_eq(&cdf(6), &cdf(5)));
+
+        // and a frame without primary reference starts from the defaults
+        let data = inter_frame_with_cdfs(inter, 3, 1 << 7, 5, 7, Some(true));
+        assert_eq!(decode(&mut ctx, &data).map(size), Ok((16, 16)));
+        assert_eq!(ctx.fc[0].ts[0].cdf, default_cdf);
+        assert_eq!(ctx.refs[7].cdf.as_deref(), Some(&default_cdf));
+    }
+
+    // a temporal unit showing the frame of the given slot again
+    fn show_existing(idx: u32) -> Vec<u8> {
+        let mut pb = PutBits::default();
+        // show_existing_frame, frame_to_show_map_idx and the trailing bits
+        pb.put_bits(1, 1).put_bits(idx, 3).trailing_bits();
+        [&KEY_FRAME[..2], &obu(ObuType::OBU_FRAME_HDR, &pb.data())].concat()
+    }
+
+    #[test]
+    fn show_existing_key_frame() {
+        let inter = FrameType::FRAME_TYPE_INTER;
+        let mut ctx = Context::<u8>::new(&Config::default());
+        let key = decode(&mut ctx, &KEY_FRAME).unwrap();
+        let data = inter_frame(inter, 1, 1 << 1, 0);
+        assert_eq!(decode(&mut ctx, &data).map(size), Ok((16, 16)));
+        assert!(ctx.refs[1].refmvs.is_some());
+        // stands for a motion field stored along with the key frame
+        ctx.refs[0].refmvs = Some(Rc::new(vec![RefMvs::default(); 4]));
+
+        // the key frame is output again and refreshes all of the slots
+        let frame = decode(&mut ctx, &show_existing(0)).unwrap();
+        assert_eq!(frame.frame_type, crate::frame::FrameType::KEY);
+        assert!(frame.iter().eq(key.iter()));
+        for r in &ctx.refs[1..] {
+            let shown = &ctx.refs[0];
+            assert!(Rc::ptr_eq(r.p.as_ref().unwrap(), shown.p.as_ref().unwrap()));
+            let cdf = r.cdf.as_ref().unwrap();
+            assert!(Rc::ptr_eq(cdf, shown.cdf.as_ref().unwrap()));
+            let refmvs = r.refmvs.as_ref().unwrap();
+            assert!(Rc::ptr_eq(refmvs, shown.refmvs.as_ref().unwrap()));
+            assert_eq!(r.refpoc, shown.refpoc);
+        }
+
+        // so an inter frame predicting from slot 1 now refers to the key
+        // frame, of order hint 0
+        let data = inter_frame(inter, 2, 1 << 2, 1);
+        assert_eq!(decode(&mut ctx, &data).map(size), Ok((16, 16)));
+        assert_eq!(ctx.refs[2].refpoc, [0; 7]);
+        assert_eq!(ctx.skipped_frames(), 0);
+    }
+
+    // The sequence header of KEY_FRAME with two operating points, of the
+    // temporal layers 0 and 1: one with the spatial layers 0 and 1, one
+    // with the spatial layer 0 alone.
+    fn two_layer_seq_hdr() -> Vec<u8> {
+        let payload = &KEY_FRAME[4..14];
+        let mut pb = PutBits::default();
+        // seq_profile, still_picture, reduced_still_picture_header,
+        // timing_info_present_flag, initial_display_delay_present_flag,
+        // operating_points_cnt_minus_1
+        pb.put_bits(0, 7).put_bits(1, 5);
+        // operating_point_idc, seq_level_idx and seq_tier of each
+        pb.put_bits(0x303, 12).put_bits(31, 5).put_bits(0, 1);
+        pb.put_bits(0x103, 12).put_bits(31, 5).put_bits(0, 1);
+        // the rest of the header, which follows the single operating point
+        // of KEY_FRAME
+        for i in 30..payload.len() * 8 {
+            pb.put_bits((payload[i >> 3] >> (7 - (i & 7)) & 1) as u32, 1);
+        }
+        obu(ObuType::OBU_SEQ_HDR, &pb.data())
+    }
+
+    // a temporal unit with the key frame of KEY_FRAME in each of the given
+    // spatial layers, after the sequence header if seq_hdr
+    fn layers(temporal_id: u32, spatial_ids: &[u32], seq_hdr: bool) -> Vec<u8> {
+        let mut data = KEY_FRAME[..2].to_vec();
+        if seq_hdr {
+            data.extend(two_layer_seq_hdr());
+        }
+        for &spatial_id in spatial_ids.iter() {
+            let frame = &KEY_FRAME[16..];
+            let obu = layer_obu(ObuType::OBU_FRAME, temporal_id, spatial_id, frame);
+            data.extend(obu);
+        }
+        data
+    }
+
+    fn layer(frame: Frame<u8>) -> (u8, u8) {
+        (frame.temporal_id(), frame.spatial_id())
+    }
+
+    #[test]
+    fn spatial_layer_output() {
+        let data = layers(0, &[0, 1], true);
+        // all layers are output, in decoding order
+        let mut ctx = Context::<u8>::new(&Config::default());
+        assert_eq!(decode(&mut ctx, &data).map(layer), Ok((0, 0)));
+        assert_eq!(ctx.receive_frame().map(layer), Ok((0, 1)));
+        let res = ctx.receive_frame().map(layer);
+        assert_eq!(res, Err(CodecStatus::NeedMoreData));
+
+        // or only the highest one of the operating point
+        let cfg = Config {
+            all_layers: 0,
+            ..Default::default()
+        };
+        let mut ctx = Context::<u8>::new(&cfg);
+        assert_eq!(decode(&mut ctx, &data).map(layer), Ok((0, 1)));
+        let res = ctx.receive_frame().map(layer);
+        assert_eq!(res, Err(CodecStatus::NeedMoreData));
+
+        // a temporal unit without the highest layer outputs the lower one
+        // once the next temporal unit starts, the lower layer of which is
+        // held back until flushed
+        let data = [layers(0, &[0], false), layers(1, &[0], false)].concat();
+        assert_eq!(decode(&mut ctx, &data).map(layer), Ok((0, 0)));
+        ctx.flush();
+        assert_eq!(ctx.receive_frame().map(layer), Ok((1, 0)));
+        let res = ctx.receive_frame().map(layer);
+        assert_eq!(res, Err(CodecStatus::LimitReached));
+
+        // the second operating point drops the upper layer
+        let cfg = Config {
+            operating_point: 1,
+            all_layers: 0,
+            ..Default::default()
+        };
+        let mut ctx = Context::<u8>::new(&cfg);
+        let data = layers(0, &[0, 1], true);
+        assert_eq!(decode(&mut ctx, &data).map(layer), Ok((0, 0)));
+        let res = ctx.receive_frame().map(layer);
+        assert_eq!(res, Err(CodecStatus::NeedMoreData));
+        assert_eq!(ctx.max_spatial_id, 0);
+    }
+
+    fn corrupted_tiles(frame: Frame<u8>) -> Vec<(isize, isize, usize, usize)> {
+        assert_eq!(frame.corrupted(), !frame.corrupted_tiles().is_empty());
+        let tiles = frame.corrupted_tiles().iter();
+        tiles.map(|r| (r.x, r.y, r.width, r.height)).collect()
+    }
+
+    fn contexts() -> (Context<u8>, Context<u8>) {
+        let cfg = Config {
+            conceal_errors: 1,
+            ..Default::default()
+        };
+        (Context::new(&Config::default()), Context::new(&cfg))
+    }
+
+    #[test]
+    fn conceal_tile_size() {
+        let (mut ctx, mut conceal_ctx) = contexts();
+        let res = decode(&mut conceal_ctx, &TWO_TILE_KEY_FRAME);
+        assert_eq!(res.map(corrupted_tiles), Ok(vec![]));
+
+        // the first tile overruns the tile group, neither tile can be
+        // located
+        let mut data = TWO_TILE_KEY_FRAME;
+        data[43] = 0xff;
+        let res = decode(&mut ctx, &data);
+        assert_eq!(invalid_obu_type(res), ObuType::OBU_FRAME as u32);
+        let res = decode(&mut conceal_ctx, &data);
+        let tiles = vec![(0, 0, 64, 64), (64, 0, 64, 64)];
+        assert_eq!(res.map(corrupted_tiles), Ok(tiles));
+    }
+
+    #[test]
+    fn conceal_lost_tile_group() {
+        // the frame of TWO_TILE_KEY_FRAME coded as a frame header and a tile
+        // group per tile
+        let frame_hdr = [&TWO_TILE_KEY_FRAME[16..42], &[0x80]].concat();
+        let tiles = [&TWO_TILE_KEY_FRAME[44..49], &TWO_TILE_KEY_FRAME[49..54]];
+        // with the tile_start_and_end_present_flag, tg_start and tg_end
+        let tile_groups = [
+            obu(ObuType::OBU_TILE_GRP, &[&[0x80], tiles[0]].concat()),
+            obu(ObuType::OBU_TILE_GRP, &[&[0xe0], tiles[1]].concat()),
+        ];
+        let mut data = TWO_TILE_KEY_FRAME[..14].to_vec();
+        data.extend(obu(ObuType::OBU_FRAME_HDR, &frame_hdr));
+
+        let (mut ctx, mut conceal_ctx) = contexts();
+        let all_tiles = [&data[..], &tile_groups.concat()].concat();
+        let res = decode(&mut ctx, &all_tiles);
+        assert_eq!(res.map(corrupted_tiles), Ok(vec![]));
+
+        let lost_tile = [&data[..], &tile_groups[1]].concat();
+        let res = decode(&mut ctx, &lost_tile);
+        assert_eq!(invalid_obu_type(res), ObuType::OBU_TILE_GRP as u32);
+        let res = decode(&mut conceal_ctx, &lost_tile);
+        assert_eq!(res.map(corrupted_tiles), Ok(vec![(0, 0, 64, 64)]));
+    }
+}
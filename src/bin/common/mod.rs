@@ -38,8 +38,8 @@ pub struct FrameSummary {
 impl<T: Pixel> From<Frame<T>> for FrameSummary {
     fn from(frame: Frame<T>) -> Self {
         Self {
-            width: frame.planes[0].cfg.width,
-            height: frame.planes[0].cfg.height,
+            width: frame.width(),
+            height: frame.height(),
             pts: frame.pts,
             frame_type: frame.frame_type,
         }
@@ -4,6 +4,8 @@ mod muxer;
 
 use clap::{App, AppSettings, Arg};
 use rav1d::api::*;
+use rav1d::obu::parse_sequence_header;
+use rav1d::util::Pixel;
 
 use std::io;
 
@@ -106,9 +108,10 @@ pub fn parse_cli() -> CLISettings {
 }
 
 // Decode and write a frame, returns frame information.
-fn process_frame(
+fn process_frame<T: Pixel>(
     cli: &mut CLISettings,
-    ctx: &mut Context<u8>,
+    ctx: &mut Context<T>,
+    pending: &mut Option<Packet>,
     count: &mut usize,
 ) -> Option<Vec<common::FrameSummary>> {
     let mut frame_summaries = Vec::new();
@@ -116,7 +119,11 @@ fn process_frame(
     if cli.limit != 0 && *count == cli.limit {
         ctx.flush();
     } else {
-        match cli.demuxer.read() {
+        let pkt = match pending.take() {
+            Some(pkt) => Ok(pkt),
+            None => cli.demuxer.read(),
+        };
+        match pkt {
             Ok(pkt) => {
                 if cli.verbose {
                     eprintln!("{}", pkt);
@@ -144,14 +151,46 @@ fn process_frame(
             Err(CodecStatus::LimitReached) => {
                 return None;
             }
-            Err(CodecStatus::Failure) => {
-                panic!("Failed to decode video");
+            Err(CodecStatus::Failure(e)) => {
+                // the decoder picks up again with the next packets
+                eprintln!("Failed to decode packet: {}", e);
+                break;
             } //Err(CodecStatus::Decoded) => {}
         }
     }
     Some(frame_summaries)
 }
 
+// Decode the stream starting with the packet first into pixels of type T.
+fn decode<T: Pixel>(
+    cli: &mut CLISettings,
+    cfg: &Config,
+    first: Packet,
+    frame_rate: Rational,
+    total: Option<usize>,
+) {
+    let mut ctx: Context<T> = Context::new(cfg);
+
+    let mut progress = common::ProgressInfo::new(frame_rate, total);
+
+    let mut pending = Some(first);
+    let mut count = 0;
+    while let Some(frame_info) = process_frame(cli, &mut ctx, &mut pending, &mut count) {
+        for frame in frame_info {
+            progress.add_frame(frame);
+            if cli.verbose {
+                eprintln!("{} - {}", frame, progress);
+            } else {
+                eprint!("\r{}     ", progress);
+            };
+        }
+    }
+    eprint!("\n{}\n", progress.print_summary());
+    if ctx.skipped_frames() > 0 {
+        eprintln!("{} frames skipped after errors", ctx.skipped_frames());
+    }
+}
+
 fn main() -> io::Result<()> {
     let mut cli = parse_cli();
     let cfg = Config {
@@ -174,33 +213,28 @@ fn main() -> io::Result<()> {
         }
     }
 
-    // TODO: use seq header probe to find out pixel type
-    let mut ctx: Context<u8> = Context::new(&cfg);
-
-    let mut progress = common::ProgressInfo::new(
-        Rational {
-            num: video_info.time_base.den,
-            den: video_info.time_base.num,
-        },
-        if cli.limit == 0 {
-            None
-        } else {
-            Some(cli.limit)
-        },
-    );
+    // the first packet carries the sequence header, which decides whether
+    // the frames are decoded into 8-bit or 16-bit pixels
+    let first = cli.demuxer.read()?;
+    let seq_hdr = parse_sequence_header(&first.data[first.offset..])?;
+    if cli.verbose {
+        eprintln!("{}-bit stream", seq_hdr.bit_depth());
+    }
 
-    let mut count = 0;
-    while let Some(frame_info) = process_frame(&mut cli, &mut ctx, &mut count) {
-        for frame in frame_info {
-            progress.add_frame(frame);
-            if cli.verbose {
-                eprintln!("{} - {}", frame, progress);
-            } else {
-                eprint!("\r{}     ", progress);
-            };
-        }
+    let frame_rate = Rational {
+        num: video_info.time_base.den,
+        den: video_info.time_base.num,
+    };
+    let total = if cli.limit == 0 {
+        None
+    } else {
+        Some(cli.limit)
+    };
+    if seq_hdr.bit_depth() == 8 {
+        decode::<u8>(&mut cli, &cfg, first, frame_rate, total);
+    } else {
+        decode::<u16>(&mut cli, &cfg, first, frame_rate, total);
     }
-    eprint!("\n{}\n", progress.print_summary());
 
     cli.muxer.close();
     cli.demuxer.close();
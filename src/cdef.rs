@@ -0,0 +1,413 @@
+use crate::tables::*;
+use crate::util::*;
+
+use std::cmp;
+
+pub(crate) const CDEF_HAVE_LEFT: u32 = 1 << 0;
+pub(crate) const CDEF_HAVE_RIGHT: u32 = 1 << 1;
+pub(crate) const CDEF_HAVE_TOP: u32 = 1 << 2;
+pub(crate) const CDEF_HAVE_BOTTOM: u32 = 1 << 3;
+
+// marks unavailable pixels in the padded filter input
+pub(crate) const CDEF_VERY_LARGE: i32 = 30000;
+
+#[inline(always)]
+pub(crate) fn ulog2(v: i32) -> i32 {
+    31 - (v as u32).leading_zeros() as i32
+}
+
+#[inline(always)]
+fn constrain(diff: i32, threshold: i32, shift: i32) -> i32 {
+    if threshold == 0 {
+        return 0;
+    }
+    let adiff = diff.abs();
+    let v = cmp::min(adiff, cmp::max(0, threshold - (adiff >> shift)));
+    if diff < 0 {
+        -v
+    } else {
+        v
+    }
+}
+
+// Copy the w x h block at off of dst and the 2 px border around it into
+// tmp (stride w + 4). Pixels above come from the pre-filter line buffers
+// top[0..2] (indexed from top_off), pixels to the left from the pre-filter
+// column backup; unavailable ones are marked as CDEF_VERY_LARGE.
+pub(crate) fn padding<T: Pixel>(
+    tmp: &mut [i32],
+    dst: &[T],
+    off: usize,
+    stride: usize,
+    left: &[[T; 2]; 8],
+    top: [&[T]; 2],
+    top_off: usize,
+    w: usize,
+    h: usize,
+    edges: u32,
+) {
+    let tmp_stride = w + 4;
+    let (w, h) = (w as isize, h as isize);
+    let x_start = if edges & CDEF_HAVE_LEFT != 0 { -2 } else { 0 };
+    let x_end = if edges & CDEF_HAVE_RIGHT != 0 {
+        w + 2
+    } else {
+        w
+    };
+    let y_start = if edges & CDEF_HAVE_TOP != 0 { -2 } else { 0 };
+    let y_end = if edges & CDEF_HAVE_BOTTOM != 0 {
+        h + 2
+    } else {
+        h
+    };
+
+    for y in -2..h + 2 {
+        for x in -2..w + 2 {
+            let v = if y < y_start || y >= y_end || x < x_start || x >= x_end {
+                CDEF_VERY_LARGE
+            } else if y < 0 {
+                i32::cast_from(top[(y & 1) as usize][(top_off as isize + x) as usize])
+            } else if x < 0 && y < h {
+                i32::cast_from(left[y as usize][(2 + x) as usize])
+            } else {
+                i32::cast_from(dst[(off as isize + y * stride as isize + x) as usize])
+            };
+            tmp[((y + 2) * tmp_stride as isize + x + 2) as usize] = v;
+        }
+    }
+}
+
+// Filter a w x h block (8x8 luma, or 8x8/4x8/4x4 chroma) in place, see
+// 7.15.2 "CDEF filter process"
+pub(crate) fn cdef_filter_block<T: Pixel>(
+    dst: &mut [T],
+    off: usize,
+    stride: usize,
+    left: &[[T; 2]; 8],
+    top: [&[T]; 2],
+    top_off: usize,
+    w: usize,
+    h: usize,
+    pri_strength: i32,
+    sec_strength: i32,
+    dir: usize,
+    damping: i32,
+    edges: u32,
+    bitdepth_max: i32,
+) {
+    let tmp_stride = w + 4;
+    let mut tmp_buf = [0i32; 12 * 12];
+    padding(
+        &mut tmp_buf,
+        dst,
+        off,
+        stride,
+        left,
+        top,
+        top_off,
+        w,
+        h,
+        edges,
+    );
+
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+    let pri_tap = 4 - ((pri_strength >> bitdepth_min_8) & 1);
+    let pri_shift = if pri_strength != 0 {
+        cmp::max(0, damping - ulog2(pri_strength))
+    } else {
+        0
+    };
+    let sec_shift = if sec_strength != 0 {
+        cmp::max(0, damping - ulog2(sec_strength))
+    } else {
+        0
+    };
+    let tap_off = |d: usize, k: usize| {
+        let [y, x] = cdef_directions[d][k];
+        y as isize * tmp_stride as isize + x as isize
+    };
+
+    for y in 0..h {
+        for x in 0..w {
+            let pos = ((y + 2) * tmp_stride + x + 2) as isize;
+            let at = |o: isize| tmp_buf[(pos + o) as usize];
+            let px = i32::cast_from(dst[off + y * stride + x]);
+            let (mut sum, mut max, mut min) = (0, px, px);
+            let mut pri_tap_k = pri_tap;
+            for k in 0..2 {
+                let off1 = tap_off(dir, k);
+                let (p0, p1) = (at(off1), at(-off1));
+                sum += pri_tap_k * constrain(p0 - px, pri_strength, pri_shift);
+                sum += pri_tap_k * constrain(p1 - px, pri_strength, pri_shift);
+                // if pri_tap_k == 4 then it becomes 2 else it remains 3
+                pri_tap_k -= (pri_tap_k << 1) - 6;
+
+                let off2 = tap_off((dir + 2) & 7, k);
+                let off3 = tap_off((dir + 6) & 7, k);
+                let s = [at(off2), at(-off2), at(off3), at(-off3)];
+                // sec_tap starts at 2 and becomes 1
+                let sec_tap = 2 - k as i32;
+                for &v in [p0, p1].iter().chain(s.iter()) {
+                    if v != CDEF_VERY_LARGE {
+                        max = cmp::max(v, max);
+                    }
+                    min = cmp::min(v, min);
+                }
+                for &v in s.iter() {
+                    sum += sec_tap * constrain(v - px, sec_strength, sec_shift);
+                }
+            }
+            let v = px + ((8 + sum - (sum < 0) as i32) >> 4);
+            dst[off + y * stride + x] = T::cast_from(clip(v, min, max));
+        }
+    }
+}
+
+// Find the dominant direction of the 8x8 block at off, along with the
+// variance used to adjust the luma primary strength, see 7.15.1 "CDEF
+// direction process"
+pub(crate) fn cdef_find_dir<T: Pixel>(
+    img: &[T],
+    off: usize,
+    stride: usize,
+    bitdepth_max: i32,
+) -> (usize, u32) {
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+    let mut partial_sum_hv = [[0i32; 8]; 2];
+    let mut partial_sum_diag = [[0i32; 15]; 2];
+    let mut partial_sum_alt = [[0i32; 11]; 4];
+
+    for y in 0..8 {
+        for x in 0..8 {
+            let px = (i32::cast_from(img[off + y * stride + x]) >> bitdepth_min_8) - 128;
+
+            partial_sum_diag[0][y + x] += px;
+            partial_sum_alt[0][y + (x >> 1)] += px;
+            partial_sum_hv[0][y] += px;
+            partial_sum_alt[1][3 + y - (x >> 1)] += px;
+            partial_sum_diag[1][7 + y - x] += px;
+            partial_sum_alt[2][3 - (y >> 1) + x] += px;
+            partial_sum_hv[1][x] += px;
+            partial_sum_alt[3][(y >> 1) + x] += px;
+        }
+    }
+
+    best_dir(&partial_sum_hv, &partial_sum_diag, &partial_sum_alt)
+}
+
+// The direction with the highest cost, from the partial sums of the pixels
+// along the lines of each direction, and the variance; shared with the SIMD
+// versions of cdef_find_dir.
+pub(crate) fn best_dir(
+    partial_sum_hv: &[[i32; 8]; 2],
+    partial_sum_diag: &[[i32; 15]; 2],
+    partial_sum_alt: &[[i32; 11]; 4],
+) -> (usize, u32) {
+    let sq = |v: i32| (v * v) as u32;
+    let mut cost = [0u32; 8];
+    for n in 0..8 {
+        cost[2] += sq(partial_sum_hv[0][n]);
+        cost[6] += sq(partial_sum_hv[1][n]);
+    }
+    cost[2] *= 105;
+    cost[6] *= 105;
+
+    const DIV_TABLE: [u32; 7] = [840, 420, 280, 210, 168, 140, 120];
+    for n in 0..7 {
+        let d = DIV_TABLE[n];
+        cost[0] += (sq(partial_sum_diag[0][n]) + sq(partial_sum_diag[0][14 - n])) * d;
+        cost[4] += (sq(partial_sum_diag[1][n]) + sq(partial_sum_diag[1][14 - n])) * d;
+    }
+    cost[0] += sq(partial_sum_diag[0][7]) * 105;
+    cost[4] += sq(partial_sum_diag[1][7]) * 105;
+
+    for n in 0..4 {
+        let cost = &mut cost[n * 2 + 1];
+        for m in 0..5 {
+            *cost += sq(partial_sum_alt[n][3 + m]);
+        }
+        *cost *= 105;
+        for m in 0..3 {
+            let d = DIV_TABLE[2 * m + 1];
+            *cost += (sq(partial_sum_alt[n][m]) + sq(partial_sum_alt[n][10 - m])) * d;
+        }
+    }
+
+    let mut best_dir = 0;
+    let mut best_cost = cost[0];
+    for n in 1..8 {
+        if cost[n] > best_cost {
+            best_cost = cost[n];
+            best_dir = n;
+        }
+    }
+
+    (best_dir, (best_cost - cost[best_dir ^ 4]) >> 10)
+}
+
+pub(crate) type CdefFn<T> = fn(
+    &mut [T],
+    usize,
+    usize,
+    &[[T; 2]; 8],
+    [&[T]; 2],
+    usize,
+    usize,
+    usize,
+    i32,
+    i32,
+    usize,
+    i32,
+    u32,
+    i32,
+);
+pub(crate) type CdefDirFn<T> = fn(&[T], usize, usize, i32) -> (usize, u32);
+
+#[derive(Clone, Copy)]
+pub(crate) struct CdefDSPContext<T: Pixel> {
+    pub(crate) dir: CdefDirFn<T>,
+    pub(crate) fb: CdefFn<T>,
+}
+
+impl<T: Pixel> CdefDSPContext<T> {
+    pub(crate) fn new(flags: u32) -> Self {
+        let mut c = CdefDSPContext {
+            dir: cdef_find_dir,
+            fb: cdef_filter_block,
+        };
+
+        #[cfg(target_arch = "x86_64")]
+        crate::x86::cdef::cdef_dsp_init_x86(&mut c, flags);
+
+        c
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn find_dir(f: impl Fn(usize, usize) -> u16, bitdepth_max: i32) -> (usize, u32) {
+        let img: Vec<u16> = (0..64).map(|i| f(i / 8, i % 8)).collect();
+        cdef_find_dir(&img, 0, 8, bitdepth_max)
+    }
+
+    #[test]
+    fn direction_search() {
+        // lines of constant pixels along each direction, in 8 and 10 bits
+        let patterns: [(fn(usize, usize) -> u16, (usize, u32)); 6] = [
+            (|y, x| (x + y) as u16 * 8, (0, 35280)),
+            (|y, x| (x + 2 * y) as u16 * 6, (1, 45714)),
+            (|y, _| y as u16 * 16, (2, 70560)),
+            (|y, x| (x + 8 - y) as u16 * 8, (4, 35280)),
+            (|_, x| x as u16 * 16, (6, 70560)),
+            (|_, _| 77, (0, 0)),
+        ];
+        for &(f, expected) in &patterns {
+            assert_eq!(find_dir(f, 255), expected);
+            assert_eq!(find_dir(|y, x| f(y, x) << 2, 1023), expected);
+        }
+    }
+
+    // a pixel of the block: row, column and value
+    type Px = (usize, usize, u8);
+
+    const ALL: u32 = CDEF_HAVE_LEFT | CDEF_HAVE_RIGHT | CDEF_HAVE_TOP | CDEF_HAVE_BOTTOM;
+
+    // Filter the 8x8 block at (2, 2) of a 12x12 image of 100 px, with the
+    // given pixels set and its left column backup and the two lines above
+    // at border. Returns the pixels that changed.
+    fn filter(
+        set: &[Px],
+        border: u8,
+        pri_strength: i32,
+        sec_strength: i32,
+        dir: usize,
+        edges: u32,
+    ) -> Vec<Px> {
+        let mut dst = [100u8; 12 * 12];
+        for &(y, x, v) in set {
+            dst[(y + 2) * 12 + x + 2] = v;
+        }
+        let src = dst;
+        let (left, top) = ([[border; 2]; 8], [border; 12]);
+        cdef_filter_block(
+            &mut dst,
+            2 * 12 + 2,
+            12,
+            &left,
+            [&top, &top],
+            2,
+            8,
+            8,
+            pri_strength,
+            sec_strength,
+            dir,
+            6,
+            edges,
+            255,
+        );
+        let px = |i: usize| (i / 8 + 2) * 12 + i % 8 + 2;
+        let changed = (0..64).filter(|&i| dst[px(i)] != src[px(i)]);
+        changed.map(|i| (i / 8, i % 8, dst[px(i)])).collect()
+    }
+
+    #[test]
+    fn primary_taps() {
+        // a 120 px in a vertical direction: with a strength of 4 and a
+        // damping of 6 each difference of 20 is constrained to 3, and the
+        // taps are 4 and 2, so -(2 * 4 + 2 * 2) * 3 = -36 is added to it in
+        // 1/16, and 4 * 3 = 12 to its nearest neighbours on the line
+        let impulse = [(3, 4, 120)];
+        let changed = vec![(2, 4, 101), (3, 4, 118), (4, 4, 101)];
+        assert_eq!(filter(&impulse, 100, 4, 0, 6, ALL), changed);
+        // with an odd strength the taps are 3 and 3, and differences of 20
+        // constrained to 4: -48 and 12
+        let changed = vec![
+            (1, 4, 101),
+            (2, 4, 101),
+            (3, 4, 117),
+            (4, 4, 101),
+            (5, 4, 101),
+        ];
+        assert_eq!(filter(&impulse, 100, 5, 0, 6, ALL), changed);
+        // the same in the horizontal direction
+        let changed = vec![(3, 3, 101), (3, 4, 118), (3, 5, 101)];
+        assert_eq!(filter(&impulse, 100, 4, 0, 2, ALL), changed);
+    }
+
+    #[test]
+    fn secondary_taps() {
+        // the secondary taps of the vertical direction are on the two
+        // diagonals, 2 and 1 times the differences constrained to 3:
+        // -(4 * 2 + 4 * 1) * 3 = -36 on the 120 px, and 2 * 3 = 6 on its
+        // neighbours, rounded away
+        let impulse = [(3, 4, 120)];
+        assert_eq!(filter(&impulse, 100, 0, 4, 6, ALL), vec![(3, 4, 118)]);
+        // a diagonal line of 120 px with a gap, which only the secondary
+        // taps see across: the gap gets 2 * (2 * 3) + 2 * (1 * 3) = 18
+        let line = [(1, 6, 120), (2, 5, 120), (4, 3, 120), (5, 2, 120)];
+        assert!(filter(&line, 100, 0, 4, 6, ALL).contains(&(3, 4, 101)));
+        let gap = |&(y, x, _): &Px| (y, x) == (3, 4);
+        assert!(!filter(&line, 100, 4, 0, 6, ALL).iter().any(gap));
+    }
+
+    #[test]
+    fn edges() {
+        // the left column backup is used if the block has a left
+        // neighbour: the 110 px left of the block add 4 * 4 + 2 * 4 = 24
+        // to the first column and 2 * 4 = 8 to the second one
+        let changed = filter(&[], 110, 4, 0, 2, ALL);
+        assert_eq!(changed.len(), 16);
+        let first_cols = |&(y, x, v): &Px| [(y, 0, 102), (y, 1, 101)].contains(&(y, x, v));
+        assert!(changed.iter().all(first_cols));
+        // at the left edge of the frame, the pixels past it are ignored
+        assert_eq!(filter(&[], 110, 4, 0, 2, ALL & !CDEF_HAVE_LEFT), vec![]);
+        // likewise for the two lines above
+        let changed = filter(&[], 110, 4, 0, 6, ALL);
+        assert_eq!(changed.len(), 16);
+        let first_rows = |&(y, x, v): &Px| [(0, x, 102), (1, x, 101)].contains(&(y, x, v));
+        assert!(changed.iter().all(first_rows));
+        assert_eq!(filter(&[], 110, 4, 0, 6, ALL & !CDEF_HAVE_TOP), vec![]);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::cdef::*;
+use crate::headers::*;
+use crate::internal::*;
+use crate::plane::Plane;
+use crate::util::*;
+
+use std::cmp;
+
+// chroma direction per luma direction, for 4:2:0/4:4:4 and 4:2:2
+static uv_dirs: [[usize; 8]; 2] = [[0, 1, 2, 3, 4, 5, 6, 7], [7, 0, 2, 4, 5, 6, 6, 6]];
+
+#[inline(always)]
+fn pixel_offset<T: Pixel>(p: &Plane<T>, x: usize, y: usize) -> usize {
+    (p.cfg.yorigin + y) * p.cfg.stride + p.cfg.xorigin + x
+}
+
+// Save the last 2 px rows of the 8 px block row at y (in luma px) of each
+// plane, i.e. the pre-filter pixels above the next block row.
+fn backup2lines<T: Pixel>(
+    dst: &mut [[Vec<T>; 2]; 3],
+    planes: &[Plane<T>; 3],
+    y: usize,
+    w: usize,
+    layout: PixelLayout,
+) {
+    let n_planes = if layout == PixelLayout::PIXEL_LAYOUT_I400 {
+        1
+    } else {
+        3
+    };
+    let ss_ver = (layout == PixelLayout::PIXEL_LAYOUT_I420) as usize;
+    let ss_hor = (layout != PixelLayout::PIXEL_LAYOUT_I444) as usize;
+
+    for pl in 0..n_planes {
+        let (w, y) = if pl == 0 {
+            (w, y + 8)
+        } else {
+            (w >> ss_hor, (y + 8) >> ss_ver)
+        };
+        for i in 0..2 {
+            let off = pixel_offset(&planes[pl], 0, y - 2 + i);
+            dst[pl][i][..w].copy_from_slice(&planes[pl].data[off..off + w]);
+        }
+    }
+}
+
+// Save the 2 px columns left of x of the 8 px block row at y (in luma px)
+fn backup2x8<T: Pixel>(
+    dst: &mut [[[T; 2]; 8]; 3],
+    planes: &[Plane<T>; 3],
+    x: usize,
+    y: usize,
+    layout: PixelLayout,
+) {
+    let n_planes = if layout == PixelLayout::PIXEL_LAYOUT_I400 {
+        1
+    } else {
+        3
+    };
+    let ss_ver = (layout == PixelLayout::PIXEL_LAYOUT_I420) as usize;
+    let ss_hor = (layout != PixelLayout::PIXEL_LAYOUT_I444) as usize;
+
+    for pl in 0..n_planes {
+        let (x, y, h) = if pl == 0 {
+            (x, y, 8)
+        } else {
+            (x >> ss_hor, y >> ss_ver, 8 >> ss_ver)
+        };
+        for i in 0..h {
+            let off = pixel_offset(&planes[pl], x - 2, y + i);
+            dst[pl][i].copy_from_slice(&planes[pl].data[off..off + 2]);
+        }
+    }
+}
+
+// luma primary strength scaled by the block's directional variance
+#[inline(always)]
+fn adjust_strength(strength: i32, var: u32) -> i32 {
+    if var == 0 {
+        return 0;
+    }
+    let i = if var >> 6 != 0 {
+        cmp::min(31 - (var >> 6).leading_zeros() as i32, 12)
+    } else {
+        0
+    };
+    (strength * (4 + i) + 8) >> 4
+}
+
+// Apply CDEF to the 8x8 block rows by_start..by_end (in 4 px units) of the
+// current picture, see 7.15 "CDEF process". lflvl_off is the index of the
+// first 128x128 mask area covering these rows. Rows above by_start must
+// have been saved in lf.cdef_line by the previous call, and the 2 px rows
+// below by_end must not be filtered yet.
+pub(crate) fn cdef_brow<T: Pixel>(
+    f: &mut FrameContext<T>,
+    lflvl_off: usize,
+    by_start: usize,
+    by_end: usize,
+) {
+    let FrameContext {
+        seq_hdr,
+        frame_hdr,
+        cur,
+        lf,
+        dsp,
+        ..
+    } = f;
+    let seq_hdr = seq_hdr.as_ref().unwrap();
+    let frame_hdr = frame_hdr.as_ref().unwrap();
+    let planes = &mut cur.as_mut().unwrap().planes;
+    let lflvl = &lf.mask[lflvl_off..];
+    let dsp = &dsp.cdef;
+    let (bw, bh) = (f.bw as usize, f.bh as usize);
+    let bitdepth_max = seq_hdr.bitdepth_max();
+
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+    let mut edges = CDEF_HAVE_BOTTOM | if by_start > 0 { CDEF_HAVE_TOP } else { 0 };
+    let sbsz = 16;
+    let sb64w = f.sb128w as usize * 2;
+    let damping = frame_hdr.cdef.damping + bitdepth_min_8;
+    let layout = seq_hdr.layout;
+    let has_chroma = layout != PixelLayout::PIXEL_LAYOUT_I400;
+    let ss_ver = (layout == PixelLayout::PIXEL_LAYOUT_I420) as usize;
+    let ss_hor = (layout != PixelLayout::PIXEL_LAYOUT_I444) as usize;
+    let uv_dir = &uv_dirs[(layout == PixelLayout::PIXEL_LAYOUT_I422) as usize];
+    let zero = T::cast_from(0);
+    let mut lr_bak = [[[[zero; 2]; 8]; 3]; 2];
+    let mut bit = 0;
+
+    for by in (by_start..by_end).step_by(2) {
+        let tf = lf.top_pre_cdef_toggle as usize;
+        let by_idx = by & 30;
+        if by + 2 >= bh {
+            edges &= !CDEF_HAVE_BOTTOM;
+        }
+
+        if edges & CDEF_HAVE_BOTTOM != 0 {
+            // backup pre-filter data for next iteration
+            backup2lines(&mut lf.cdef_line[tf ^ 1], planes, by * 4, bw * 4, layout);
+        }
+
+        edges &= !CDEF_HAVE_LEFT;
+        edges |= CDEF_HAVE_RIGHT;
+        let mut last_skip = true;
+        for sbx in 0..sb64w {
+            let sb128x = sbx >> 1;
+            let sb64_idx = ((by & sbsz) >> 3) + (sbx & 1);
+            let cdef_idx = lflvl[sb128x].cdef_idx[sb64_idx];
+            if cdef_idx == -1
+                || (frame_hdr.cdef.y_strength[cdef_idx as usize] == 0
+                    && frame_hdr.cdef.uv_strength[cdef_idx as usize] == 0)
+            {
+                last_skip = true;
+                edges |= CDEF_HAVE_LEFT;
+                continue;
+            }
+
+            let y_lvl = frame_hdr.cdef.y_strength[cdef_idx as usize];
+            let uv_lvl = frame_hdr.cdef.uv_strength[cdef_idx as usize];
+            for bx in (sbx * sbsz..cmp::min((sbx + 1) * sbsz, bw)).step_by(2) {
+                if bx + 2 >= bw {
+                    edges &= !CDEF_HAVE_RIGHT;
+                }
+
+                // check if this 8x8 block had any coded coefficients; if not,
+                // go to the next block
+                let bx_mask = 3u16 << (bx & 14);
+                let bx_idx = (bx & 16) >> 4;
+                let noskip_mask = &lflvl[sb128x].noskip_mask;
+                if (noskip_mask[by_idx][bx_idx] | noskip_mask[by_idx + 1][bx_idx]) & bx_mask == 0 {
+                    last_skip = true;
+                    edges |= CDEF_HAVE_LEFT;
+                    continue;
+                }
+
+                if last_skip && edges & CDEF_HAVE_LEFT != 0 {
+                    // we didn't backup the prefilter data because it wasn't
+                    // there, so do it here instead
+                    backup2x8(&mut lr_bak[bit], planes, bx * 4, by * 4, layout);
+                }
+                if edges & CDEF_HAVE_RIGHT != 0 {
+                    // backup pre-filter data for next iteration
+                    backup2x8(&mut lr_bak[bit ^ 1], planes, bx * 4 + 8, by * 4, layout);
+                }
+
+                // the actual filter
+                let y_pri_lvl = (y_lvl >> 2) << bitdepth_min_8;
+                let mut y_sec_lvl = y_lvl & 3;
+                y_sec_lvl += (y_sec_lvl == 3) as i32;
+                y_sec_lvl <<= bitdepth_min_8;
+                let uv_pri_lvl = (uv_lvl >> 2) << bitdepth_min_8;
+                let mut uv_sec_lvl = uv_lvl & 3;
+                uv_sec_lvl += (uv_sec_lvl == 3) as i32;
+                uv_sec_lvl <<= bitdepth_min_8;
+
+                let stride = planes[0].cfg.stride;
+                let off = pixel_offset(&planes[0], bx * 4, by * 4);
+                let (dir, variance) = (dsp.dir)(&planes[0].data, off, stride, bitdepth_max);
+                if y_lvl != 0 {
+                    let [top0, top1] = &lf.cdef_line[tf][0];
+                    (dsp.fb)(
+                        &mut planes[0].data,
+                        off,
+                        stride,
+                        &lr_bak[bit][0],
+                        [top0, top1],
+                        bx * 4,
+                        8,
+                        8,
+                        adjust_strength(y_pri_lvl, variance),
+                        y_sec_lvl,
+                        if y_pri_lvl != 0 { dir } else { 0 },
+                        damping,
+                        edges,
+                        bitdepth_max,
+                    );
+                }
+                if uv_lvl != 0 && has_chroma {
+                    let uvdir = if uv_pri_lvl != 0 { uv_dir[dir] } else { 0 };
+                    for pl in 1..3 {
+                        let stride = planes[pl].cfg.stride;
+                        let off = pixel_offset(&planes[pl], (bx * 4) >> ss_hor, (by * 4) >> ss_ver);
+                        let [top0, top1] = &lf.cdef_line[tf][pl];
+                        (dsp.fb)(
+                            &mut planes[pl].data,
+                            off,
+                            stride,
+                            &lr_bak[bit][pl],
+                            [top0, top1],
+                            (bx * 4) >> ss_hor,
+                            8 >> ss_hor,
+                            8 >> ss_ver,
+                            uv_pri_lvl,
+                            uv_sec_lvl,
+                            uvdir,
+                            damping - 1,
+                            edges,
+                            bitdepth_max,
+                        );
+                    }
+                }
+
+                bit ^= 1;
+                last_skip = false;
+                edges |= CDEF_HAVE_LEFT;
+            }
+            edges |= CDEF_HAVE_LEFT;
+        }
+
+        lf.top_pre_cdef_toggle ^= 1;
+        edges |= CDEF_HAVE_TOP;
+    }
+}
@@ -0,0 +1,41 @@
+// The adaptive CDFs of the symbol decoder, see msac.rs for their layout.
+// Only the CDFs of the symbols decoded so far are there.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CdfModeContext {
+    pub(crate) skip_mode: [[u16; 2]; 3],
+    pub(crate) restore_wiener: [u16; 2],
+    pub(crate) restore_sgrproj: [u16; 2],
+    pub(crate) restore_switchable: [u16; 3],
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CdfContext {
+    pub(crate) m: CdfModeContext,
+}
+
+// the initial CDFs, see the Default_*_Cdf tables of the spec
+impl Default for CdfContext {
+    fn default() -> Self {
+        CdfContext {
+            m: CdfModeContext {
+                skip_mode: [[32768 - 32621, 0], [32768 - 20708, 0], [32768 - 8127, 0]],
+                restore_wiener: [32768 - 11570, 0],
+                restore_sgrproj: [32768 - 16855, 0],
+                restore_switchable: [32768 - 9413, 32768 - 22581, 0],
+            },
+        }
+    }
+}
+
+// Save the CDFs a tile ended with for later frames, with the adaptation
+// counters reset, see 7.20 "Reference frame update process".
+pub(crate) fn cdf_thread_update(dst: &mut CdfContext, src: &CdfContext) {
+    dst.clone_from(src);
+    for cdf in dst.m.skip_mode.iter_mut() {
+        cdf[1] = 0;
+    }
+    dst.m.restore_wiener[1] = 0;
+    dst.m.restore_sgrproj[1] = 0;
+    dst.m.restore_switchable[2] = 0;
+}
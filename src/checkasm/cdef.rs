@@ -0,0 +1,157 @@
+use super::*;
+use crate::cdef::*;
+
+fn check_dir<T: Pixel>(
+    rng: &mut Rng,
+    c_ref: CdefDirFn<T>,
+    c: CdefDirFn<T>,
+    version: &str,
+    bd: i32,
+) {
+    const RUNS: usize = 64;
+    for _ in 0..RUNS {
+        let stride = 8 + rng.range(16);
+        let mut img = vec![T::cast_from(0); 8 * stride];
+        // random blocks have no dominant direction, so mix in smooth ones
+        if rng.range(2) == 0 {
+            rng.smooth_pixels(&mut img, bd);
+        } else {
+            for px in img.iter_mut() {
+                *px = rng.pixel(bd);
+            }
+        }
+
+        let expected = c_ref(&img, 0, stride, bd);
+        let got = c(&img, 0, stride, bd);
+        if expected != got {
+            panic!(
+                "checkasm: cdef_dir FAILED (bd={}): expected (dir, var) {:?}, got {:?}",
+                bd, expected, got
+            );
+        }
+    }
+    report("cdef_dir", version, RUNS);
+
+    let img: Vec<T> = (0..8 * 8).map(|_| rng.pixel(bd)).collect();
+    bench("cdef_dir", "c", || {
+        c_ref(&img, 0, 8, bd);
+    });
+    bench("cdef_dir", version, || {
+        c(&img, 0, 8, bd);
+    });
+}
+
+// Test block of w x h px at (2, 0) of dst, with the 2 px border the filter
+// reads to its left, right and bottom; the 2 rows above come from top.
+struct CdefSetup<T: Pixel> {
+    dst: Vec<T>,
+    stride: usize,
+    left: [[T; 2]; 8],
+    top: [Vec<T>; 2],
+    top_off: usize,
+}
+
+impl<T: Pixel> CdefSetup<T> {
+    fn new(rng: &mut Rng, w: usize, h: usize, bd: i32) -> Self {
+        let stride = w + 4 + rng.range(16);
+        let mut dst = vec![T::cast_from(0); (h + 2) * stride];
+        rng.smooth_pixels(&mut dst, bd);
+        let mut left = [[T::cast_from(0); 2]; 8];
+        for px in left.iter_mut().flat_map(|l| l.iter_mut()) {
+            *px = rng.pixel(bd);
+        }
+        let top_off = 2 + rng.range(8);
+        let mut top = [
+            vec![T::cast_from(0); top_off + w + 2],
+            vec![T::cast_from(0); top_off + w + 2],
+        ];
+        rng.smooth_pixels(&mut top[0], bd);
+        rng.smooth_pixels(&mut top[1], bd);
+
+        CdefSetup {
+            dst,
+            stride,
+            left,
+            top,
+            top_off,
+        }
+    }
+}
+
+fn check_fb<T: Pixel>(rng: &mut Rng, c_ref: CdefFn<T>, c: CdefFn<T>, version: &str, bd: i32) {
+    let bitdepth_min_8 = (32 - bd.leading_zeros()) as i32 - 8;
+    let sizes = [(8, 8), (4, 8), (4, 4)];
+    let mut n = 0;
+    for &(w, h) in &sizes {
+        for edges in 0..16 {
+            let s = CdefSetup::<T>::new(rng, w, h, bd);
+            let pri = rng.between(0, 15) << bitdepth_min_8;
+            let sec = *rng.pick(&[0, 1, 2, 4]) << bitdepth_min_8;
+            let dir = rng.range(8);
+            let damping = rng.between(3, 6) + bitdepth_min_8;
+            let top = [&s.top[0][..], &s.top[1][..]];
+            let mut dst_ref = s.dst.clone();
+            let mut dst = s.dst.clone();
+
+            #[rustfmt::skip]
+            c_ref(&mut dst_ref, 2, s.stride, &s.left, top, s.top_off, w, h, pri, sec, dir, damping, edges, bd);
+            #[rustfmt::skip]
+            c(&mut dst, 2, s.stride, &s.left, top, s.top_off, w, h, pri, sec, dir, damping, edges, bd);
+            let params = format!(
+                "{}x{} pri={} sec={} dir={} damping={} edges={:#x} bd={}",
+                w, h, pri, sec, dir, damping, edges, bd
+            );
+            check_block(
+                "cdef_filter",
+                &params,
+                &dst_ref,
+                &dst,
+                s.stride,
+                h + 2,
+                s.stride,
+            );
+            n += 1;
+        }
+    }
+    report("cdef_filter", version, n);
+
+    let s = CdefSetup::<T>::new(rng, 8, 8, bd);
+    let top = [&s.top[0][..], &s.top[1][..]];
+    let (pri, sec, damping) = (8 << bitdepth_min_8, 2 << bitdepth_min_8, 5 + bitdepth_min_8);
+    let mut dst = s.dst.clone();
+    bench("cdef_filter_8x8", "c", || {
+        c_ref(
+            &mut dst, 2, s.stride, &s.left, top, s.top_off, 8, 8, pri, sec, 3, damping, 15, bd,
+        )
+    });
+    bench("cdef_filter_8x8", version, || {
+        c(
+            &mut dst, 2, s.stride, &s.left, top, s.top_off, 8, 8, pri, sec, 3, damping, 15, bd,
+        )
+    });
+}
+
+fn check_cdef_dsp<T: Pixel>(rng: &mut Rng, bitdepths: &[i32]) {
+    let c_ref = CdefDSPContext::<T>::new(0);
+    // each version is only tested if it differs from the previous one
+    let mut prev = c_ref;
+    for (version, flags) in cpu_flag_sets() {
+        let c = CdefDSPContext::<T>::new(flags);
+        for &bd in bitdepths {
+            if c.dir as usize != prev.dir as usize {
+                check_dir(rng, c_ref.dir, c.dir, version, bd);
+            }
+            if c.fb as usize != prev.fb as usize {
+                check_fb(rng, c_ref.fb, c.fb, version, bd);
+            }
+        }
+        prev = c;
+    }
+}
+
+#[test]
+fn checkasm_cdef() {
+    let mut rng = Rng::new();
+    check_cdef_dsp::<u8>(&mut rng, &BITDEPTHS_8);
+    check_cdef_dsp::<u16>(&mut rng, &BITDEPTHS_HBD);
+}
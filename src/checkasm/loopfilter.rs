@@ -0,0 +1,262 @@
+use super::*;
+use crate::lf_mask::*;
+use crate::loopfilter::*;
+
+// Random edge masks for 32 4px units, with each edge assigned to at most
+// one filter size; sizes is the number of filter sizes (3 for luma, 2 for
+// chroma).
+fn edge_masks(rng: &mut Rng, sizes: usize) -> [u32; 3] {
+    let mut vmask = [0u32; 3];
+    for i in 0..32 {
+        let idx = rng.range(sizes + 1);
+        if idx < sizes {
+            vmask[idx] |= 1 << i;
+        }
+    }
+    vmask
+}
+
+// Random filter levels for a 34x34 4px unit area, with some zero levels so
+// that the fallback to the neighbouring level is also exercised.
+fn filter_levels(rng: &mut Rng, b4_stride: usize) -> Vec<[u8; 4]> {
+    (0..b4_stride * 34)
+        .map(|_| {
+            let mut l = [0u8; 4];
+            for v in l.iter_mut() {
+                *v = if rng.range(4) == 0 {
+                    0
+                } else {
+                    rng.between(1, 63) as u8
+                };
+            }
+            l
+        })
+        .collect()
+}
+
+// Test picture: the filtered edges are at x = 16 (h) or y = 16 (v), which
+// leaves room for the 7 px read on either side of them.
+struct LfSetup<T: Pixel> {
+    buf: Vec<T>,
+    stride: usize,
+    lvl: Vec<[u8; 4]>,
+    b4_stride: usize,
+    lut: Av1FilterLUT,
+}
+
+impl<T: Pixel> LfSetup<T> {
+    fn new(rng: &mut Rng, bd: i32) -> Self {
+        let stride = 16 + 128 + 16 + rng.range(16);
+        let mut buf = vec![T::cast_from(0); (16 + 128 + 16) * stride];
+        if rng.range(2) == 0 {
+            rng.smooth_pixels(&mut buf, bd);
+        } else {
+            rng.flat_pixels(&mut buf, bd);
+        }
+        let b4_stride = 34 + rng.range(8);
+        let lvl = filter_levels(rng, b4_stride);
+        let mut lut = Av1FilterLUT::default();
+        calc_eih(&mut lut, rng.between(0, 7));
+
+        LfSetup {
+            buf,
+            stride,
+            lvl,
+            b4_stride,
+            lut,
+        }
+    }
+}
+
+fn check_lf_y<T: Pixel>(
+    rng: &mut Rng,
+    dir: usize,
+    c_ref: LoopFilterSbYFn<T>,
+    c: LoopFilterSbYFn<T>,
+    version: &str,
+    bd: i32,
+) {
+    let func = ["lf_h_sb_y", "lf_v_sb_y"][dir];
+    const RUNS: usize = 16;
+    for _ in 0..RUNS {
+        let s = LfSetup::<T>::new(rng, bd);
+        let vmask = edge_masks(rng, 3);
+        let off = 16 * s.stride + 16;
+        let lvl_off = s.b4_stride + 1;
+        let mut dst_ref = s.buf.clone();
+        let mut dst = s.buf.clone();
+
+        c_ref(
+            &mut dst_ref,
+            off,
+            s.stride,
+            &vmask,
+            &s.lvl,
+            lvl_off,
+            s.b4_stride,
+            &s.lut,
+            bd,
+        );
+        c(
+            &mut dst,
+            off,
+            s.stride,
+            &vmask,
+            &s.lvl,
+            lvl_off,
+            s.b4_stride,
+            &s.lut,
+            bd,
+        );
+        let params = format!("vmask={:08x?} bd={}", vmask, bd);
+        let h = dst.len() / s.stride;
+        check_block(func, &params, &dst_ref, &dst, s.stride, h, s.stride);
+    }
+    report(func, version, RUNS);
+
+    let s = LfSetup::<T>::new(rng, bd);
+    let vmask = edge_masks(rng, 3);
+    let (off, lvl_off) = (16 * s.stride + 16, s.b4_stride + 1);
+    let mut dst = s.buf.clone();
+    bench(func, "c", || {
+        c_ref(
+            &mut dst,
+            off,
+            s.stride,
+            &vmask,
+            &s.lvl,
+            lvl_off,
+            s.b4_stride,
+            &s.lut,
+            bd,
+        )
+    });
+    bench(func, version, || {
+        c(
+            &mut dst,
+            off,
+            s.stride,
+            &vmask,
+            &s.lvl,
+            lvl_off,
+            s.b4_stride,
+            &s.lut,
+            bd,
+        )
+    });
+}
+
+fn check_lf_uv<T: Pixel>(
+    rng: &mut Rng,
+    dir: usize,
+    c_ref: LoopFilterSbUVFn<T>,
+    c: LoopFilterSbUVFn<T>,
+    version: &str,
+    bd: i32,
+) {
+    let func = ["lf_h_sb_uv", "lf_v_sb_uv"][dir];
+    const RUNS: usize = 16;
+    for _ in 0..RUNS {
+        let s = LfSetup::<T>::new(rng, bd);
+        let m = edge_masks(rng, 2);
+        let vmask = [m[0], m[1]];
+        // the u (2) or v (3) filter level
+        let pl = 2 + rng.range(2);
+        let off = 16 * s.stride + 16;
+        let lvl_off = s.b4_stride + 1;
+        let mut dst_ref = s.buf.clone();
+        let mut dst = s.buf.clone();
+
+        c_ref(
+            &mut dst_ref,
+            off,
+            s.stride,
+            &vmask,
+            &s.lvl,
+            lvl_off,
+            pl,
+            s.b4_stride,
+            &s.lut,
+            bd,
+        );
+        c(
+            &mut dst,
+            off,
+            s.stride,
+            &vmask,
+            &s.lvl,
+            lvl_off,
+            pl,
+            s.b4_stride,
+            &s.lut,
+            bd,
+        );
+        let params = format!("vmask={:08x?} pl={} bd={}", vmask, pl, bd);
+        let h = dst.len() / s.stride;
+        check_block(func, &params, &dst_ref, &dst, s.stride, h, s.stride);
+    }
+    report(func, version, RUNS);
+
+    let s = LfSetup::<T>::new(rng, bd);
+    let m = edge_masks(rng, 2);
+    let vmask = [m[0], m[1]];
+    let (off, lvl_off) = (16 * s.stride + 16, s.b4_stride + 1);
+    let mut dst = s.buf.clone();
+    bench(func, "c", || {
+        c_ref(
+            &mut dst,
+            off,
+            s.stride,
+            &vmask,
+            &s.lvl,
+            lvl_off,
+            2,
+            s.b4_stride,
+            &s.lut,
+            bd,
+        )
+    });
+    bench(func, version, || {
+        c(
+            &mut dst,
+            off,
+            s.stride,
+            &vmask,
+            &s.lvl,
+            lvl_off,
+            2,
+            s.b4_stride,
+            &s.lut,
+            bd,
+        )
+    });
+}
+
+fn check_lf_dsp<T: Pixel>(rng: &mut Rng, bitdepths: &[i32]) {
+    let c_ref = LoopFilterDSPContext::<T>::new(0);
+    // each version is only tested if it differs from the previous one
+    let mut prev = c_ref;
+    for (version, flags) in cpu_flag_sets() {
+        let c = LoopFilterDSPContext::<T>::new(flags);
+        for &bd in bitdepths {
+            for dir in 0..2 {
+                let (y_ref, y) = (c_ref.loop_filter_sby[dir], c.loop_filter_sby[dir]);
+                if y as usize != prev.loop_filter_sby[dir] as usize {
+                    check_lf_y(rng, dir, y_ref, y, version, bd);
+                }
+                let (uv_ref, uv) = (c_ref.loop_filter_sbuv[dir], c.loop_filter_sbuv[dir]);
+                if uv as usize != prev.loop_filter_sbuv[dir] as usize {
+                    check_lf_uv(rng, dir, uv_ref, uv, version, bd);
+                }
+            }
+        }
+        prev = c;
+    }
+}
+
+#[test]
+fn checkasm_loopfilter() {
+    let mut rng = Rng::new();
+    check_lf_dsp::<u8>(&mut rng, &BITDEPTHS_8);
+    check_lf_dsp::<u16>(&mut rng, &BITDEPTHS_HBD);
+}
@@ -0,0 +1,317 @@
+use super::*;
+use crate::levels::Filter2d;
+use crate::levels::Filter2d::*;
+use crate::mc::*;
+
+const FILTERS: [Filter2d; 10] = [
+    FILTER_2D_8TAP_REGULAR,
+    FILTER_2D_8TAP_REGULAR_SMOOTH,
+    FILTER_2D_8TAP_REGULAR_SHARP,
+    FILTER_2D_8TAP_SHARP_REGULAR,
+    FILTER_2D_8TAP_SHARP_SMOOTH,
+    FILTER_2D_8TAP_SHARP,
+    FILTER_2D_8TAP_SMOOTH_REGULAR,
+    FILTER_2D_8TAP_SMOOTH,
+    FILTER_2D_8TAP_SMOOTH_SHARP,
+    FILTER_2D_BILINEAR,
+];
+
+const SIZES: [usize; 7] = [2, 4, 8, 16, 32, 64, 128];
+
+fn check_mc<T: Pixel>(rng: &mut Rng, c_ref: Put8tapFn<T>, c: Put8tapFn<T>, version: &str, bd: i32) {
+    let mut n = 0;
+    for &w in &SIZES {
+        for &h in &SIZES {
+            let filter = *rng.pick(&FILTERS);
+            let (mx, my) = (rng.range(16), rng.range(16));
+            let src_stride = w + 7 + rng.range(16);
+            let dst_stride = w + rng.range(16);
+            let src: Vec<T> = (0..(h + 7) * src_stride).map(|_| rng.pixel(bd)).collect();
+            let mut dst_ref: Vec<T> = (0..h * dst_stride).map(|_| rng.pixel(bd)).collect();
+            let mut dst = dst_ref.clone();
+
+            c_ref(
+                &mut dst_ref,
+                dst_stride,
+                &src,
+                src_stride,
+                w,
+                h,
+                mx,
+                my,
+                filter,
+                bd,
+            );
+            c(
+                &mut dst, dst_stride, &src, src_stride, w, h, mx, my, filter, bd,
+            );
+            let params = format!("{}x{} {:?} mx={} my={} bd={}", w, h, filter, mx, my, bd);
+            check_block("mc", &params, &dst_ref, &dst, dst_stride, h, dst_stride);
+            n += 1;
+        }
+    }
+    report("mc", version, n);
+
+    let src: Vec<T> = (0..71 * 71).map(|_| rng.pixel(bd)).collect();
+    let mut dst = vec![T::cast_from(0); 64 * 64];
+    let filter = FILTER_2D_8TAP_REGULAR;
+    bench("mc_64x64", "c", || {
+        c_ref(&mut dst, 64, &src, 71, 64, 64, 8, 8, filter, bd)
+    });
+    bench("mc_64x64", version, || {
+        c(&mut dst, 64, &src, 71, 64, 64, 8, 8, filter, bd)
+    });
+}
+
+fn check_mct<T: Pixel>(
+    rng: &mut Rng,
+    c_ref: Prep8tapFn<T>,
+    c: Prep8tapFn<T>,
+    version: &str,
+    bd: i32,
+) {
+    let mut n = 0;
+    for &w in &SIZES {
+        for &h in &SIZES {
+            let filter = *rng.pick(&FILTERS);
+            let (mx, my) = (rng.range(16), rng.range(16));
+            let src_stride = w + 7 + rng.range(16);
+            let src: Vec<T> = (0..(h + 7) * src_stride).map(|_| rng.pixel(bd)).collect();
+            let mut tmp_ref = vec![0i32; w * h];
+            let mut tmp = vec![0i32; w * h];
+
+            c_ref(&mut tmp_ref, &src, src_stride, w, h, mx, my, filter, bd);
+            c(&mut tmp, &src, src_stride, w, h, mx, my, filter, bd);
+            let params = format!("{}x{} {:?} mx={} my={} bd={}", w, h, filter, mx, my, bd);
+            check_block("mct", &params, &tmp_ref, &tmp, w, h, w);
+            n += 1;
+        }
+    }
+    report("mct", version, n);
+
+    let src: Vec<T> = (0..71 * 71).map(|_| rng.pixel(bd)).collect();
+    let mut tmp = vec![0i32; 64 * 64];
+    let filter = FILTER_2D_8TAP_REGULAR;
+    bench("mct_64x64", "c", || {
+        c_ref(&mut tmp, &src, 71, 64, 64, 8, 8, filter, bd)
+    });
+    bench("mct_64x64", version, || {
+        c(&mut tmp, &src, 71, 64, 64, 8, 8, filter, bd)
+    });
+}
+
+fn check_avg<T: Pixel>(rng: &mut Rng, c_ref: AvgFn<T>, c: AvgFn<T>, version: &str, bd: i32) {
+    // real compound intermediates, so that their range is the one avg sees
+    let prep = |rng: &mut Rng, w: usize, h: usize| -> Vec<i32> {
+        let filter = *rng.pick(&FILTERS);
+        let (mx, my) = (rng.range(16), rng.range(16));
+        let src: Vec<T> = (0..(h + 7) * (w + 7)).map(|_| rng.pixel(bd)).collect();
+        let mut tmp = vec![0i32; w * h];
+        prep_8tap(&mut tmp, &src, w + 7, w, h, mx, my, filter, bd);
+        tmp
+    };
+
+    let mut n = 0;
+    for &w in &SIZES {
+        for &h in &SIZES {
+            let dst_stride = w + rng.range(16);
+            let tmp1 = prep(rng, w, h);
+            let tmp2 = prep(rng, w, h);
+            let mut dst_ref: Vec<T> = (0..h * dst_stride).map(|_| rng.pixel(bd)).collect();
+            let mut dst = dst_ref.clone();
+
+            c_ref(&mut dst_ref, dst_stride, &tmp1, &tmp2, w, h, bd);
+            c(&mut dst, dst_stride, &tmp1, &tmp2, w, h, bd);
+            let params = format!("{}x{} bd={}", w, h, bd);
+            check_block("avg", &params, &dst_ref, &dst, dst_stride, h, dst_stride);
+            n += 1;
+        }
+    }
+    report("avg", version, n);
+
+    let (tmp1, tmp2) = (prep(rng, 64, 64), prep(rng, 64, 64));
+    let mut dst = vec![T::cast_from(0); 64 * 64];
+    bench("avg_64x64", "c", || {
+        c_ref(&mut dst, 64, &tmp1, &tmp2, 64, 64, bd)
+    });
+    bench("avg_64x64", version, || {
+        c(&mut dst, 64, &tmp1, &tmp2, 64, 64, bd)
+    });
+}
+
+// Random shear parameters and subpel positions that keep all filter
+// positions of an 8x8 block within the warp filter table.
+fn warp_params(rng: &mut Rng) -> ([i16; 4], i32, i32) {
+    let mut abcd = [0i16; 4];
+    for v in abcd.iter_mut() {
+        *v = rng.between(-2048, 2047) as i16;
+    }
+    let mx = rng.between(-1 << 14, (1 << 14) - 1) & !0x3f;
+    let my = rng.between(-1 << 14, (1 << 14) - 1) & !0x3f;
+
+    (abcd, mx, my)
+}
+
+fn check_warp8x8<T: Pixel>(
+    rng: &mut Rng,
+    c_ref: WarpAffine8x8Fn<T>,
+    c: WarpAffine8x8Fn<T>,
+    version: &str,
+    bd: i32,
+) {
+    const RUNS: usize = 32;
+    for _ in 0..RUNS {
+        let (abcd, mx, my) = warp_params(rng);
+        let src_stride = 15 + rng.range(16);
+        let dst_stride = 8 + rng.range(16);
+        let src: Vec<T> = (0..15 * src_stride).map(|_| rng.pixel(bd)).collect();
+        let mut dst_ref: Vec<T> = (0..8 * dst_stride).map(|_| rng.pixel(bd)).collect();
+        let mut dst = dst_ref.clone();
+
+        c_ref(
+            &mut dst_ref,
+            dst_stride,
+            &src,
+            src_stride,
+            &abcd,
+            mx,
+            my,
+            bd,
+        );
+        c(&mut dst, dst_stride, &src, src_stride, &abcd, mx, my, bd);
+        let params = format!("abcd={:?} mx={} my={} bd={}", abcd, mx, my, bd);
+        check_block(
+            "warp8x8", &params, &dst_ref, &dst, dst_stride, 8, dst_stride,
+        );
+    }
+    report("warp8x8", version, RUNS);
+
+    let (abcd, mx, my) = warp_params(rng);
+    let src: Vec<T> = (0..15 * 15).map(|_| rng.pixel(bd)).collect();
+    let mut dst = vec![T::cast_from(0); 8 * 8];
+    bench("warp8x8", "c", || {
+        c_ref(&mut dst, 8, &src, 15, &abcd, mx, my, bd)
+    });
+    bench("warp8x8", version, || {
+        c(&mut dst, 8, &src, 15, &abcd, mx, my, bd)
+    });
+}
+
+fn check_warp8x8t<T: Pixel>(
+    rng: &mut Rng,
+    c_ref: WarpAffine8x8tFn<T>,
+    c: WarpAffine8x8tFn<T>,
+    version: &str,
+    bd: i32,
+) {
+    const RUNS: usize = 32;
+    for _ in 0..RUNS {
+        let (abcd, mx, my) = warp_params(rng);
+        let src_stride = 15 + rng.range(16);
+        let tmp_stride = 8 + rng.range(16);
+        let src: Vec<T> = (0..15 * src_stride).map(|_| rng.pixel(bd)).collect();
+        let mut tmp_ref: Vec<i32> = (0..8 * tmp_stride).map(|_| rng.next() as i32).collect();
+        let mut tmp = tmp_ref.clone();
+
+        c_ref(
+            &mut tmp_ref,
+            tmp_stride,
+            &src,
+            src_stride,
+            &abcd,
+            mx,
+            my,
+            bd,
+        );
+        c(&mut tmp, tmp_stride, &src, src_stride, &abcd, mx, my, bd);
+        let params = format!("abcd={:?} mx={} my={} bd={}", abcd, mx, my, bd);
+        check_block(
+            "warp8x8t", &params, &tmp_ref, &tmp, tmp_stride, 8, tmp_stride,
+        );
+    }
+    report("warp8x8t", version, RUNS);
+
+    let (abcd, mx, my) = warp_params(rng);
+    let src: Vec<T> = (0..15 * 15).map(|_| rng.pixel(bd)).collect();
+    let mut tmp = vec![0i32; 8 * 8];
+    bench("warp8x8t", "c", || {
+        c_ref(&mut tmp, 8, &src, 15, &abcd, mx, my, bd)
+    });
+    bench("warp8x8t", version, || {
+        c(&mut tmp, 8, &src, 15, &abcd, mx, my, bd)
+    });
+}
+
+// blend_v masks along x, so w is limited to 32; blend_h masks along y
+fn check_blend<T: Pixel>(
+    rng: &mut Rng,
+    func: &str,
+    c_ref: BlendDirFn<T>,
+    c: BlendDirFn<T>,
+    version: &str,
+    bd: i32,
+) {
+    let masked_sizes = [2, 4, 8, 16, 32];
+    let mut n = 0;
+    for &m in &masked_sizes {
+        for &o in &SIZES {
+            let (w, h) = if func == "blend_v" { (m, o) } else { (o, m) };
+            let dst_stride = w + rng.range(16);
+            let tmp: Vec<T> = (0..w * h).map(|_| rng.pixel(bd)).collect();
+            let mut dst_ref: Vec<T> = (0..h * dst_stride).map(|_| rng.pixel(bd)).collect();
+            let mut dst = dst_ref.clone();
+
+            c_ref(&mut dst_ref, dst_stride, &tmp, w, h);
+            c(&mut dst, dst_stride, &tmp, w, h);
+            let params = format!("{}x{} bd={}", w, h, bd);
+            check_block(func, &params, &dst_ref, &dst, dst_stride, h, dst_stride);
+            n += 1;
+        }
+    }
+    report(func, version, n);
+
+    let tmp: Vec<T> = (0..32 * 32).map(|_| rng.pixel(bd)).collect();
+    let mut dst = vec![T::cast_from(0); 32 * 32];
+    bench(func, "c", || c_ref(&mut dst, 32, &tmp, 32, 32));
+    bench(func, version, || c(&mut dst, 32, &tmp, 32, 32));
+}
+
+fn check_mc_dsp<T: Pixel>(rng: &mut Rng, bitdepths: &[i32]) {
+    let c_ref = MCDSPContext::<T>::new(0);
+    // each version is only tested if it differs from the previous one
+    let mut prev = c_ref;
+    for (version, flags) in cpu_flag_sets() {
+        let c = MCDSPContext::<T>::new(flags);
+        for &bd in bitdepths {
+            if c.mc as usize != prev.mc as usize {
+                check_mc(rng, c_ref.mc, c.mc, version, bd);
+            }
+            if c.mct as usize != prev.mct as usize {
+                check_mct(rng, c_ref.mct, c.mct, version, bd);
+            }
+            if c.avg as usize != prev.avg as usize {
+                check_avg(rng, c_ref.avg, c.avg, version, bd);
+            }
+            if c.warp8x8 as usize != prev.warp8x8 as usize {
+                check_warp8x8(rng, c_ref.warp8x8, c.warp8x8, version, bd);
+            }
+            if c.warp8x8t as usize != prev.warp8x8t as usize {
+                check_warp8x8t(rng, c_ref.warp8x8t, c.warp8x8t, version, bd);
+            }
+            if c.blend_v as usize != prev.blend_v as usize {
+                check_blend(rng, "blend_v", c_ref.blend_v, c.blend_v, version, bd);
+            }
+            if c.blend_h as usize != prev.blend_h as usize {
+                check_blend(rng, "blend_h", c_ref.blend_h, c.blend_h, version, bd);
+            }
+        }
+        prev = c;
+    }
+}
+
+#[test]
+fn checkasm_mc() {
+    let mut rng = Rng::new();
+    check_mc_dsp::<u8>(&mut rng, &BITDEPTHS_8);
+    check_mc_dsp::<u16>(&mut rng, &BITDEPTHS_HBD);
+}
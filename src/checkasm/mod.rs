@@ -0,0 +1,164 @@
+// checkasm: verify that the specialized (SIMD) entries of the DSP tables
+// are bit-exact with the scalar functions, by driving both with the same
+// randomised inputs. Run with `cargo test checkasm`; CHECKASM_SEED=<n>
+// reproduces a run and CHECKASM_BENCH=1 also times every version.
+
+mod cdef;
+mod loopfilter;
+mod mc;
+
+use crate::cpu::*;
+use crate::util::*;
+
+use std::env;
+use std::fmt::Debug;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+pub(crate) struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    pub(crate) fn new() -> Self {
+        let seed = env::var("CHECKASM_SEED")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(|| {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+                now.subsec_nanos()
+            });
+        eprintln!("checkasm: seed {}", seed);
+
+        Rng { state: seed | 1 }
+    }
+
+    // xorshift32
+    pub(crate) fn next(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    // uniform in 0..n
+    pub(crate) fn range(&mut self, n: usize) -> usize {
+        (self.next() as u64 * n as u64 >> 32) as usize
+    }
+
+    // uniform in lo..=hi
+    pub(crate) fn between(&mut self, lo: i32, hi: i32) -> i32 {
+        lo + self.range((hi - lo + 1) as usize) as i32
+    }
+
+    pub(crate) fn pick<'a, V>(&mut self, values: &'a [V]) -> &'a V {
+        &values[self.range(values.len())]
+    }
+
+    pub(crate) fn pixel<T: Pixel>(&mut self, bitdepth_max: i32) -> T {
+        T::cast_from(self.next() as i32 & bitdepth_max)
+    }
+
+    // Fill buf with a mostly smooth signal with occasional steps, so that
+    // edge-adaptive filters are exercised in both their filtering and
+    // their skipping branches.
+    pub(crate) fn smooth_pixels<T: Pixel>(&mut self, buf: &mut [T], bitdepth_max: i32) {
+        let mut v = self.between(0, bitdepth_max);
+        for px in buf.iter_mut() {
+            if self.range(16) == 0 {
+                v = self.between(0, bitdepth_max);
+            }
+            let range = (bitdepth_max + 1) >> 5;
+            v = clip(v + self.between(-range, range), 0, bitdepth_max);
+            *px = T::cast_from(v);
+        }
+    }
+
+    // Like smooth_pixels, but with steps small enough for the flat
+    // filters of the loop filter to be taken, which need all 8 or 14 pixels
+    // around an edge within 1 << (bitdepth - 8) of each other.
+    pub(crate) fn flat_pixels<T: Pixel>(&mut self, buf: &mut [T], bitdepth_max: i32) {
+        let step = (bitdepth_max + 1) >> 8;
+        let mut v = self.between(0, bitdepth_max);
+        for px in buf.iter_mut() {
+            if self.range(64) == 0 {
+                v = self.between(0, bitdepth_max);
+            }
+            *px = T::cast_from(clip(v + self.between(0, step), 0, bitdepth_max));
+        }
+    }
+}
+
+// the tested bit depths, as bitdepth_max
+pub(crate) const BITDEPTHS_8: [i32; 1] = [255];
+pub(crate) const BITDEPTHS_HBD: [i32; 2] = [1023, 4095];
+
+// The CPU flag sets to test against the scalar functions (flags 0); only
+// those supported by this machine are returned.
+pub(crate) fn cpu_flag_sets() -> Vec<(&'static str, u32)> {
+    let ssse3 = CPU_FLAG_SSE2 | CPU_FLAG_SSSE3;
+    let sse41 = ssse3 | CPU_FLAG_SSE41;
+    let avx2 = sse41 | CPU_FLAG_AVX2;
+    let detected = get_cpu_flags(!0);
+
+    [("ssse3", ssse3), ("sse4.1", sse41), ("avx2", avx2)]
+        .iter()
+        .cloned()
+        .filter(|&(_, flags)| flags & detected == flags)
+        .collect()
+}
+
+// Panic with the position of the first of the w x h values with a stride of
+// stride that differ between the reference and the tested function.
+pub(crate) fn check_block<V: PartialEq + Debug>(
+    func: &str,
+    params: &str,
+    expected: &[V],
+    got: &[V],
+    w: usize,
+    h: usize,
+    stride: usize,
+) {
+    for y in 0..h {
+        for x in 0..w {
+            let (e, g) = (&expected[y * stride + x], &got[y * stride + x]);
+            if e != g {
+                panic!(
+                    "checkasm: {} FAILED ({}): first mismatch at ({}, {}), expected {:?}, got {:?}",
+                    func, params, x, y, e, g
+                );
+            }
+        }
+    }
+}
+
+pub(crate) fn bench_enabled() -> bool {
+    env::var("CHECKASM_BENCH").map_or(false, |v| v != "0")
+}
+
+// Time run and print the average duration of a call, in bench mode only.
+pub(crate) fn bench<F: FnMut()>(func: &str, version: &str, mut run: F) {
+    if !bench_enabled() {
+        return;
+    }
+
+    const ITERATIONS: u32 = 256;
+    run();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        run();
+    }
+    let elapsed = start.elapsed() / ITERATIONS;
+    eprintln!(
+        "checkasm: {:>24}_{:<8} {:>10} ns",
+        func,
+        version,
+        elapsed.as_nanos()
+    );
+}
+
+// Report a function version that matched the scalar one in all n runs
+pub(crate) fn report(func: &str, version: &str, n: usize) {
+    eprintln!("checkasm: {:>24}_{:<8} OK ({} runs)", func, version, n);
+}
@@ -0,0 +1,42 @@
+// CPU features the DSP functions are specialized for, see
+// Config::cpu_flags_mask
+pub const CPU_FLAG_SSE2: u32 = 1 << 0;
+pub const CPU_FLAG_SSSE3: u32 = 1 << 1;
+pub const CPU_FLAG_SSE41: u32 = 1 << 2;
+pub const CPU_FLAG_AVX2: u32 = 1 << 3;
+
+// Detect the CPU features at runtime and restrict them to mask. A feature
+// is only reported along with all the features it implies, so that each
+// DSP init function only has to check for the highest one it uses.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub(crate) fn get_cpu_flags(mask: u32) -> u32 {
+    let mut flags = 0;
+    if is_x86_feature_detected!("sse2") {
+        flags |= CPU_FLAG_SSE2;
+        if is_x86_feature_detected!("ssse3") {
+            flags |= CPU_FLAG_SSSE3;
+            if is_x86_feature_detected!("sse4.1") {
+                flags |= CPU_FLAG_SSE41;
+                if is_x86_feature_detected!("avx2") {
+                    flags |= CPU_FLAG_AVX2;
+                }
+            }
+        }
+    }
+
+    // masking a feature also masks every feature implying it
+    let mut allowed = 0;
+    for &flag in &[CPU_FLAG_SSE2, CPU_FLAG_SSSE3, CPU_FLAG_SSE41, CPU_FLAG_AVX2] {
+        if mask & flag == 0 {
+            break;
+        }
+        allowed |= flag;
+    }
+
+    flags & allowed
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub(crate) fn get_cpu_flags(_mask: u32) -> u32 {
+    0
+}
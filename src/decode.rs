@@ -1,17 +1,27 @@
 use crate::api::*;
+use crate::cdf::{cdf_thread_update, CdfContext};
 use crate::dequant_tables::*;
+use crate::error::DecodeError;
+use crate::fg_apply::apply_grain;
 use crate::frame::Frame;
 use crate::getbits::*;
 use crate::headers::*;
 use crate::internal::*;
 use crate::levels::*;
+use crate::lf_mask::*;
+use crate::msac::MsacContext;
 use crate::plane::PlaneType;
+use crate::plane_region::Rect;
+use crate::recon::filter_sbrow;
+use crate::ref_mvs::RefMvs;
+use crate::tables::*;
 use crate::util::*;
+use crate::warpmv::*;
 
 use std::rc::Rc;
 use std::slice;
 use std::vec::Vec;
-use std::{cmp, io};
+use std::{cmp, mem};
 
 fn init_quant_tables(
     seq_hdr: &SequenceHeader,
@@ -43,52 +53,83 @@ fn init_quant_tables(
 fn reset_context(ctx: &mut BlockContext, keyframe: bool, pass: i32) {
     (&mut ctx.intra.array)
         .iter_mut()
-        .map(|x| *x = keyframe as u8);
+        .for_each(|x| *x = keyframe as u8);
     (&mut ctx.uvmode.array)
         .iter_mut()
-        .map(|x| *x = IntraPredMode::DC_PRED as u8);
+        .for_each(|x| *x = IntraPredMode::DC_PRED as u8);
     if keyframe {
         (&mut ctx.mode.array)
             .iter_mut()
-            .map(|x| *x = IntraPredMode::DC_PRED as u8);
+            .for_each(|x| *x = IntraPredMode::DC_PRED as u8);
     }
     if pass == 2 {
         return;
     }
 
-    (&mut ctx.partition.array).iter_mut().map(|x| *x = 0);
-    (&mut ctx.skip.array).iter_mut().map(|x| *x = 0);
-    (&mut ctx.skip_mode.array).iter_mut().map(|x| *x = 0);
-    (&mut ctx.tx_lpf_y.array).iter_mut().map(|x| *x = 2);
-    (&mut ctx.tx_lpf_uv.array).iter_mut().map(|x| *x = 1);
-    (&mut ctx.tx_intra.array).iter_mut().map(|x| *x = -1);
+    (&mut ctx.partition.array).iter_mut().for_each(|x| *x = 0);
+    (&mut ctx.skip.array).iter_mut().for_each(|x| *x = 0);
+    (&mut ctx.skip_mode.array).iter_mut().for_each(|x| *x = 0);
+    (&mut ctx.tx_lpf_y.array).iter_mut().for_each(|x| *x = 2);
+    (&mut ctx.tx_lpf_uv.array).iter_mut().for_each(|x| *x = 1);
+    (&mut ctx.tx_intra.array).iter_mut().for_each(|x| *x = -1);
     (&mut ctx.tx.array)
         .iter_mut()
-        .map(|x| *x = TxfmSize::TX_64X64 as i8);
+        .for_each(|x| *x = TxfmSize::TX_64X64 as i8);
     if !keyframe {
-        (&mut ctx.ref_frame.array[0]).iter_mut().map(|x| *x = -1);
-        (&mut ctx.ref_frame.array[1]).iter_mut().map(|x| *x = -1);
-        (&mut ctx.comp_type.array).iter_mut().map(|x| *x = 0);
+        (&mut ctx.ref_frame.array[0])
+            .iter_mut()
+            .for_each(|x| *x = -1);
+        (&mut ctx.ref_frame.array[1])
+            .iter_mut()
+            .for_each(|x| *x = -1);
+        (&mut ctx.comp_type.array).iter_mut().for_each(|x| *x = 0);
         (&mut ctx.mode.array)
             .iter_mut()
-            .map(|x| *x = InterPredMode::NEARESTMV as u8);
+            .for_each(|x| *x = InterPredMode::NEARESTMV as u8);
     }
-    (&mut ctx.lcoef.array).iter_mut().map(|x| *x = 0x40);
-    (&mut ctx.ccoef.array[0]).iter_mut().map(|x| *x = 0x40);
-    (&mut ctx.ccoef.array[1]).iter_mut().map(|x| *x = 0x40);
+    (&mut ctx.lcoef.array).iter_mut().for_each(|x| *x = 0x40);
+    (&mut ctx.ccoef.array[0]).iter_mut().for_each(|x| *x = 0x40);
+    (&mut ctx.ccoef.array[1]).iter_mut().for_each(|x| *x = 0x40);
     (&mut ctx.filter.array[0])
         .iter_mut()
-        .map(|x| *x = FilterMode::N_SWITCHABLE_FILTERS_OR_FILTER_BILINEAR as u8);
+        .for_each(|x| *x = FilterMode::N_SWITCHABLE_FILTERS_OR_FILTER_BILINEAR as u8);
     (&mut ctx.filter.array[1])
         .iter_mut()
-        .map(|x| *x = FilterMode::N_SWITCHABLE_FILTERS_OR_FILTER_BILINEAR as u8);
-    (&mut ctx.seg_pred.array).iter_mut().map(|x| *x = 0);
-    (&mut ctx.pal_sz.array).iter_mut().map(|x| *x = 0);
+        .for_each(|x| *x = FilterMode::N_SWITCHABLE_FILTERS_OR_FILTER_BILINEAR as u8);
+    (&mut ctx.seg_pred.array).iter_mut().for_each(|x| *x = 0);
+    (&mut ctx.pal_sz.array).iter_mut().for_each(|x| *x = 0);
+}
+
+// The size of a tile of a tile group, read from the size prefixing all but
+// the last tile, see 5.11.1. data_offset and size locate the rest of the
+// tile group in the packet, and are moved past the size.
+fn tile_size(
+    data: &[u8],
+    data_offset: &mut usize,
+    size: &mut usize,
+    last: bool,
+    n_bytes: usize,
+) -> Result<usize, DecodeError> {
+    let mut tile_sz = *size;
+    if !last {
+        check_error(n_bytes > *size, "frame_hdr.tiling.n_bytes > size")?;
+        tile_sz = 0;
+        for k in 0..n_bytes {
+            tile_sz |= (data[*data_offset] as usize) << (k * 8);
+            *data_offset += 1;
+        }
+        tile_sz += 1;
+        *size -= n_bytes;
+        check_error(tile_sz > *size, "tile_sz > size")?;
+    }
+    check_error(tile_sz == 0, "empty tile")?;
+    Ok(tile_sz)
 }
 
 fn setup_tile(
     seq_hdr: &SequenceHeader,
     frame_hdr: &FrameHeader,
+    in_cdf: &CdfContext,
     data: &[u8],
     tile_row: i32,
     tile_col: i32,
@@ -96,26 +137,28 @@ fn setup_tile(
     sb_shift: i32,
     bw: i32,
     bh: i32,
-    sr_sb128w: i32,
-    sb128w: i32,
     n_tc: i32,
     ts: &mut TileState,
 ) {
     let col_sb_start = frame_hdr.tiling.col_start_sb[tile_col as usize] as i32;
-    let col_sb128_start = col_sb_start >> (!seq_hdr.sb128) as i32;
     let col_sb_end = frame_hdr.tiling.col_start_sb[tile_col as usize + 1] as i32;
     let row_sb_start = frame_hdr.tiling.row_start_sb[tile_row as usize] as i32;
     let row_sb_end = frame_hdr.tiling.row_start_sb[tile_row as usize + 1] as i32;
 
     //ts.frame_thread.pal_idx = &f->frame_thread.pal_idx[tile_start_off * 2];
     //ts.frame_thread.cf = &((int32_t *) f->frame_thread.cf)[tile_start_off * 3];
-    //dav1d_cdf_thread_copy(&ts.cdf, &f->in_cdf);
+    ts.cdf.clone_from(in_cdf);
     ts.last_qidx = frame_hdr.quant.yac;
-    ts.last_delta_lf.iter_mut().map(|x| *x = 0);
+    ts.last_delta_lf = [0; 4];
+    // delta_lf is coded per superblock, starting from the frame's levels at
+    // the beginning of each tile
+    if frame_hdr.delta.lf.present {
+        calc_lf_values(&mut ts.lflvlmem, frame_hdr, &ts.last_delta_lf);
+    }
 
-    //TODO
-    //dav1d_msac_init(&ts.msac, data, sz, frame_hdr.disable_cdf_update);
+    ts.msac = MsacContext::new(data, frame_hdr.disable_cdf_update);
 
+    ts.error = false;
     ts.tiling.row = tile_row;
     ts.tiling.col = tile_col;
     ts.tiling.col_start = col_sb_start << sb_shift;
@@ -124,62 +167,386 @@ fn setup_tile(
     ts.tiling.row_end = cmp::min(row_sb_end << sb_shift, bh);
 
     // Reference Restoration Unit (used for exp coding)
-    let mut sb_idx;
-    let mut unit_idx;
-    if (frame_hdr.super_res.enabled) {
-        // vertical components only
-        sb_idx = (ts.tiling.row_start >> 5) * sr_sb128w;
-        unit_idx = (ts.tiling.row_start & 16) >> 3;
-    } else {
-        sb_idx = (ts.tiling.row_start >> 5) * sb128w + col_sb128_start;
-        unit_idx = ((ts.tiling.row_start & 16) >> 3) + ((ts.tiling.col_start & 16) >> 4);
-    }
-    for p in 0..3 {
-        if frame_hdr.restoration.t[p] == RestorationType::RESTORATION_NONE {
-            continue;
-        }
-
-        if frame_hdr.super_res.enabled {
-            unimplemented!();
-        /*const int ss_hor = p && f->cur.p.layout != DAV1D_PIXEL_LAYOUT_I444;
-        const int d = frame_hdr.super_res.width_scale_denominator;
-        const int unit_size_log2 = frame_hdr.restoration.unit_size[!!p];
-        const int rnd = (8 << unit_size_log2) - 1, shift = unit_size_log2 + 3;
-        const int x = ((4 * ts.tiling.col_start * d >> ss_hor) + rnd) >> shift;
-        const int px_x = x << (unit_size_log2 + ss_hor);
-        const int u_idx = unit_idx + ((px_x & 64) >> 6);
-        const int sb128x = px_x >> 7;
-        if (sb128x >= f->sr_sb128w) continue;
-        ts.lr_ref[p] = &f->lf.lr_mask[sb_idx + sb128x].lr[p][u_idx];*/
+    let lr_ref = Av1RestorationUnit {
+        filter_h: [3, -7, 15],
+        filter_v: [3, -7, 15],
+        sgr_weights: [-32, 31],
+        ..Default::default()
+    };
+    ts.lr_ref = [lr_ref; 3];
+
+    if n_tc > 1 {
+        //TODO
+        //atomic_init(&ts.progress, row_sb_start);
+    }
+}
+
+// Find the above and left neighbours that use the same single reference as
+// the current block. The bit at each neighbour's offset (in 4px units) is
+// set in masks[0] (above) and masks[1] (left); bit 32 of masks[0] and
+// masks[1] flags the top-right and top-left neighbour respectively.
+fn find_matching_ref<T: Pixel>(
+    f: &FrameContext<T>,
+    t: &TileContext,
+    ts: &TileState,
+    top_has_right: bool,
+    bw4: i32,
+    bh4: i32,
+    w4: i32,
+    h4: i32,
+    have_left: bool,
+    have_top: bool,
+    r#ref: i32,
+    masks: &mut [u64; 2],
+) {
+    let b4_stride = f.b4_stride as isize;
+    let r = t.by as isize * b4_stride + t.bx as isize;
+    let rp = |off: isize| &f.mvs[(r + off) as usize];
+    let bs = |rp: &RefMvs| &block_dimensions[rp.sb_type as usize];
+    let matches = |rp: &RefMvs| rp.ref_frame[0] as i32 == r#ref + 1 && rp.ref_frame[1] == -1;
+    let mut count = 0;
+    let mut have_topleft = have_top && have_left;
+    let mut have_topright = cmp::max(bw4, bh4) < 32
+        && have_top
+        && t.bx + bw4 < ts.tiling.col_end
+        && top_has_right;
+
+    if have_top {
+        let mut r2 = -b4_stride;
+        if matches(rp(r2)) {
+            masks[0] |= 1;
+            count = 1;
+        }
+        let mut aw4 = bs(rp(r2))[0] as i32;
+        if aw4 >= bw4 {
+            let off = t.bx & (aw4 - 1);
+            if off != 0 {
+                have_topleft = false;
+            }
+            if aw4 - off > bw4 {
+                have_topright = false;
+            }
+        } else {
+            let mut mask = 1u64 << aw4;
+            let mut x = aw4;
+            while x < w4 {
+                r2 += aw4 as isize;
+                if matches(rp(r2)) {
+                    masks[0] |= mask;
+                    count += 1;
+                    if count >= 8 {
+                        return;
+                    }
+                }
+                aw4 = bs(rp(r2))[0] as i32;
+                mask <<= aw4;
+                x += aw4;
+            }
+        }
+    }
+    if have_left {
+        let mut r2 = -1;
+        if matches(rp(r2)) {
+            masks[1] |= 1;
+            count += 1;
+            if count >= 8 {
+                return;
+            }
+        }
+        let mut lh4 = bs(rp(r2))[1] as i32;
+        if lh4 >= bh4 {
+            if (t.by & (lh4 - 1)) != 0 {
+                have_topleft = false;
+            }
         } else {
-            //ts.lr_ref[p] = &f->lf.lr_mask[sb_idx].lr[p][unit_idx];
+            let mut mask = 1u64 << lh4;
+            let mut y = lh4;
+            while y < h4 {
+                r2 += lh4 as isize * b4_stride;
+                if matches(rp(r2)) {
+                    masks[1] |= mask;
+                    count += 1;
+                    if count >= 8 {
+                        return;
+                    }
+                }
+                lh4 = bs(rp(r2))[1] as i32;
+                mask <<= lh4;
+                y += lh4;
+            }
         }
+    }
+    if have_topleft && matches(rp(-b4_stride - 1)) {
+        masks[1] |= 1 << 32;
+        count += 1;
+        if count >= 8 {
+            return;
+        }
+    }
+    if have_topright && matches(rp(-b4_stride + bw4 as isize)) {
+        masks[0] |= 1 << 32;
+    }
+}
 
-        /*ts.lr_ref[p]->filter_v[0] = 3;
-        ts.lr_ref[p]->filter_v[1] = -7;
-        ts.lr_ref[p]->filter_v[2] = 15;
-        ts.lr_ref[p]->filter_h[0] = 3;
-        ts.lr_ref[p]->filter_h[1] = -7;
-        ts.lr_ref[p]->filter_h[2] = 15;
-        ts.lr_ref[p]->sgr_weights[0] = -32;
-        ts.lr_ref[p]->sgr_weights[1] = 31;*/
+// Estimate the local warp model of a block from the neighbours found by
+// find_matching_ref, see 7.10.4 "Has overlappable candidates process" and
+// 7.10.4.2 "Add sample process".
+fn derive_warpmv<T: Pixel>(
+    f: &FrameContext<T>,
+    t: &TileContext,
+    bw4: i32,
+    bh4: i32,
+    masks: &[u64; 2],
+    mv: MV,
+    wmp: &mut WarpedMotionParams,
+) {
+    let mut pts: Vec<[[i32; 2]; 2]> = Vec::with_capacity(8);
+    let b4_stride = f.b4_stride as isize;
+    let r = t.by as isize * b4_stride + t.bx as isize;
+    let rp = |off: isize| &f.mvs[(r + off) as usize];
+    let bs = |rp: &RefMvs| &block_dimensions[rp.sb_type as usize];
+
+    // centre of the neighbouring block and its projection by the
+    // neighbour's motion vector, in 1/8 px relative to the block position
+    fn add_sample(pts: &mut Vec<[[i32; 2]; 2]>, dx: i32, dy: i32, sx: i32, sy: i32, rp: &RefMvs) {
+        let bs = &block_dimensions[rp.sb_type as usize];
+        let x = 16 * (2 * dx + sx * bs[0] as i32) - 8;
+        let y = 16 * (2 * dy + sy * bs[1] as i32) - 8;
+        pts.push([[x, y], [x + rp.mv[0].x as i32, y + rp.mv[0].y as i32]]);
     }
 
-    if n_tc > 1 {
-        //TODO
-        //atomic_init(&ts.progress, row_sb_start);
+    // use masks[] to find the projectable motion vectors in the edges
+    if masks[0] as u32 == 1 && (masks[1] >> 32) == 0 {
+        let off = t.bx & (bs(rp(-b4_stride))[0] as i32 - 1);
+        add_sample(&mut pts, -off, 0, 1, -1, rp(-b4_stride));
+    } else {
+        // top
+        let (mut off, mut xmask) = (0, masks[0] as u32);
+        while pts.len() < 8 && xmask != 0 {
+            let tz = xmask.trailing_zeros();
+            off += tz;
+            xmask >>= tz;
+            add_sample(&mut pts, off as i32, 0, 1, -1, rp(-b4_stride + off as isize));
+            xmask &= !1;
+        }
+    }
+    if pts.len() < 8 && masks[1] == 1 {
+        let off = t.by & (bs(rp(-1))[1] as i32 - 1);
+        add_sample(&mut pts, 0, -off, -1, 1, rp(-off as isize * b4_stride - 1));
+    } else {
+        // left
+        let (mut off, mut ymask) = (0, masks[1] as u32);
+        while pts.len() < 8 && ymask != 0 {
+            let tz = ymask.trailing_zeros();
+            off += tz;
+            ymask >>= tz;
+            add_sample(&mut pts, 0, off as i32, -1, 1, rp(off as isize * b4_stride - 1));
+            ymask &= !1;
+        }
+    }
+    if pts.len() < 8 && (masks[1] >> 32) != 0 {
+        // top/left
+        add_sample(&mut pts, 0, 0, -1, -1, rp(-b4_stride - 1));
     }
+    if pts.len() < 8 && (masks[0] >> 32) != 0 {
+        // top/right
+        add_sample(&mut pts, bw4, 0, 1, -1, rp(-b4_stride + bw4 as isize));
+    }
+    debug_assert!(!pts.is_empty() && pts.len() <= 8);
+
+    // select according to motion vector difference against a threshold
+    let np = pts.len();
+    let thresh = 4 * clip(cmp::max(bw4, bh4), 4, 28);
+    let mut mvd: Vec<i32> = pts
+        .iter()
+        .map(|pt| {
+            let d = (pt[1][0] - pt[0][0] - mv.x as i32).abs()
+                + (pt[1][1] - pt[0][1] - mv.y as i32).abs();
+            if d > thresh {
+                -1
+            } else {
+                d
+            }
+        })
+        .collect();
+    let mut ret = mvd.iter().filter(|&&d| d != -1).count();
+    if ret == 0 {
+        ret = 1;
+    } else {
+        let (mut i, mut j) = (0, np - 1);
+        for _ in 0..np - ret {
+            while mvd[i] != -1 {
+                i += 1;
+            }
+            while mvd[j] == -1 {
+                j -= 1;
+            }
+            debug_assert!(i != j);
+            if i > j {
+                break;
+            }
+            // replace the discarded samples
+            mvd[i] = mvd[j];
+            pts[i] = pts[j];
+            i += 1;
+            j -= 1;
+        }
+    }
+
+    wmp.t = if !find_affine_int(&pts[..ret], bw4, bh4, mv, wmp, t.bx, t.by)
+        && !get_shear_params(wmp)
+    {
+        WarpedMotionType::WM_TYPE_AFFINE
+    } else {
+        WarpedMotionType::WM_TYPE_IDENTITY
+    };
+}
+
+// Read the skip_mode flag of a bw4 x bh4 block at (bx4, by4) 4px units
+// into the superblock, see 5.11.10 "Skip mode syntax", and store it in the
+// above and left contexts.
+fn read_skip_mode(
+    frame_hdr: &FrameHeader,
+    ts: &mut TileState,
+    a: &mut BlockContext,
+    l: &mut BlockContext,
+    seg_id: usize,
+    bx4: usize,
+    by4: usize,
+    bw4: usize,
+    bh4: usize,
+) -> bool {
+    let seg = &frame_hdr.segmentation;
+    let seg_data = &seg.seg_data.d[seg_id];
+    let seg_forbids =
+        seg.enabled && (seg_data.skip || seg_data.globalmv || seg_data.ref_frame != -1);
+    let skip_mode = if frame_hdr.skip_mode_enabled && cmp::min(bw4, bh4) > 1 && !seg_forbids {
+        let ctx = (a.skip_mode.array[bx4] + l.skip_mode.array[by4]) as usize;
+        ts.msac.decode_bool_adapt(&mut ts.cdf.m.skip_mode[ctx])
+    } else {
+        false
+    };
+    a.skip_mode.array[bx4..][..bw4]
+        .iter_mut()
+        .for_each(|x| *x = skip_mode as u8);
+    l.skip_mode.array[by4..][..bh4]
+        .iter_mut()
+        .for_each(|x| *x = skip_mode as u8);
+    skip_mode
+}
+
+// Read the restoration unit lr of plane p, coded ahead of the superblock it
+// starts in, see 5.11.58 "Read loop restoration unit syntax". The filter
+// coefficients and weights are coded as deltas to the last unit of the
+// plane read in the tile.
+fn read_restoration_info(
+    ts: &mut TileState,
+    lr: &mut Av1RestorationUnit,
+    p: usize,
+    frame_type: RestorationType,
+) {
+    let msac = &mut ts.msac;
+    if frame_type == RestorationType::RESTORATION_SWITCHABLE {
+        let filter = msac.decode_symbol_adapt(&mut ts.cdf.m.restore_switchable, 2);
+        lr.t = match filter {
+            0 => RestorationType::RESTORATION_NONE,
+            1 => RestorationType::RESTORATION_WIENER,
+            _ => RestorationType::RESTORATION_SGRPROJ,
+        };
+    } else {
+        let cdf = if frame_type == RestorationType::RESTORATION_WIENER {
+            &mut ts.cdf.m.restore_wiener
+        } else {
+            &mut ts.cdf.m.restore_sgrproj
+        };
+        lr.t = if msac.decode_bool_adapt(cdf) {
+            frame_type
+        } else {
+            RestorationType::RESTORATION_NONE
+        };
+    }
+
+    let lr_ref = &mut ts.lr_ref[p];
+    if lr.t == RestorationType::RESTORATION_WIENER {
+        let mut read_filter = |r: &[i16; 3]| {
+            [
+                if p != 0 {
+                    0
+                } else {
+                    msac.decode_subexp(r[0] as i32 + 5, 16, 1) - 5
+                },
+                msac.decode_subexp(r[1] as i32 + 23, 32, 2) - 23,
+                msac.decode_subexp(r[2] as i32 + 17, 64, 3) - 17,
+            ]
+        };
+        let filter_v = read_filter(&lr_ref.filter_v);
+        let filter_h = read_filter(&lr_ref.filter_h);
+        for k in 0..3 {
+            lr.filter_v[k] = filter_v[k] as i16;
+            lr.filter_h[k] = filter_h[k] as i16;
+        }
+        lr.sgr_weights = lr_ref.sgr_weights;
+        *lr_ref = *lr;
+    } else if lr.t == RestorationType::RESTORATION_SGRPROJ {
+        let idx = msac.decode_bools(4);
+        let params = &sgr_params[idx as usize];
+        lr.sgr_idx = idx as u8;
+        lr.sgr_weights[0] = if params[0] != 0 {
+            (msac.decode_subexp(lr_ref.sgr_weights[0] as i32 + 96, 128, 4) - 96) as i16
+        } else {
+            0
+        };
+        lr.sgr_weights[1] = if params[1] != 0 {
+            (msac.decode_subexp(lr_ref.sgr_weights[1] as i32 + 32, 128, 4) - 32) as i16
+        } else {
+            95
+        };
+        lr.filter_v = lr_ref.filter_v;
+        lr.filter_h = lr_ref.filter_h;
+        *lr_ref = *lr;
+    }
+}
+
+// The CDFs a frame starts from: the default ones, or those saved with its
+// primary reference frame, see load_cdfs() and setup_past_independence().
+fn primary_ref_cdf<T: Pixel>(
+    refs: &[RefState<T>; 8],
+    frame_hdr: &FrameHeader,
+) -> Result<Rc<CdfContext>, DecodeError> {
+    if frame_hdr.primary_ref_frame == PRIMARY_REF_NONE as u32 {
+        return Ok(Rc::new(CdfContext::default()));
+    }
+    let pri_ref = frame_hdr.refidx[frame_hdr.primary_ref_frame as usize] as usize;
+    let cdf = refs[pri_ref].cdf.as_ref();
+    check_error(cdf.is_none(), "primary reference frame has no CDFs")?;
+    Ok(Rc::clone(cdf.unwrap()))
+}
+
+// step between source positions in 1/16384 px when scaling ref_sz px to
+// this_sz px
+#[inline(always)]
+pub(crate) fn scale_fac(ref_sz: i32, this_sz: i32) -> i32 {
+    ((ref_sz << 14) + (this_sz >> 1)) / this_sz
+}
+
+// initial subpel position of the upscaling filter, see 7.16 "Upscaling
+// process"
+fn get_upscale_x0(in_w: i32, out_w: i32, step: i32) -> i32 {
+    let err = out_w * step - (in_w << 14);
+    let x0 = (-((out_w - in_w) << 13) + (out_w >> 1)) / out_w + 128 - (err / 2);
+    x0 & 0x3fff
 }
 
 fn decode_tile_sbrow(
     seq_hdr: &SequenceHeader,
     frame_hdr: &FrameHeader,
     t: &mut TileContext,
-    ts: &TileState,
+    ts: &mut TileState,
+    lr_mask: &mut [Av1Restoration],
     sb_step: i32,
     pass: i32,
     n_fc: usize,
-) -> io::Result<()> {
+) -> Result<(), DecodeError> {
     let root_bl = if seq_hdr.sb128 {
         BlockLevel::BL_128X128
     } else {
@@ -192,14 +559,14 @@ fn decode_tile_sbrow(
 
     reset_context(&mut t.l, frame_hdr.frame_is_intra(), pass);
     if pass == 2 {
-        unimplemented!();
+        return Err(DecodeError::Unsupported("2-pass decoding"));
     }
 
     // error out on symbol decoder overread
-    // TODO: if (ts->msac.cnt < -15) return 1;
+    check_error(ts.msac.cnt < -15, "symbol decoder overread")?;
 
     if n_fc > 1 && frame_hdr.use_ref_frame_mvs {
-        unimplemented!()
+        return Err(DecodeError::Unsupported("frame threading"));
     }
     (&mut t.pal_sz_uv[1]).iter_mut().map(|x| *x = 0);
     let sb128y = t.by >> 5;
@@ -226,10 +593,64 @@ fn decode_tile_sbrow(
 
         // Restoration filter
         for p in 0..3 {
-            if frame_hdr.restoration.t[p] == RestorationType::RESTORATION_NONE {
+            let frame_type = frame_hdr.restoration.t[p];
+            if frame_type == RestorationType::RESTORATION_NONE {
                 continue;
             }
-            unimplemented!();
+
+            let ss_ver = p != 0 && seq_hdr.layout == PixelLayout::PIXEL_LAYOUT_I420;
+            let ss_hor = p != 0 && seq_hdr.layout != PixelLayout::PIXEL_LAYOUT_I444;
+            let unit_size_log2 = frame_hdr.restoration.unit_size[(p != 0) as usize];
+            let y = t.by * 4 >> ss_ver as i32;
+            let h = (frame_hdr.height as i32 + ss_ver as i32) >> ss_ver as i32;
+
+            let unit_size = 1 << unit_size_log2;
+            let mask = unit_size - 1;
+            if y & mask != 0 {
+                continue;
+            }
+            let half_unit = unit_size >> 1;
+            // round half up at frame boundaries, if there's more than one
+            // restoration unit
+            if y != 0 && y + half_unit > h {
+                continue;
+            }
+
+            let sr_sb128w = (frame_hdr.width[1] as i32 + 127) >> 7;
+            if frame_hdr.super_res.enabled {
+                // units are in upscaled coordinates, so an sb can cover
+                // any number of them
+                let w = (frame_hdr.width[1] as i32 + ss_hor as i32) >> ss_hor as i32;
+                let n_units = cmp::max(1, (w + half_unit) >> unit_size_log2);
+
+                let d = frame_hdr.super_res.width_scale_denominator as i32;
+                let (rnd, shift) = (unit_size * 8 - 1, unit_size_log2 + 3);
+                let x0 = ((4 * t.bx * d >> ss_hor as i32) + rnd) >> shift;
+                let x1 = ((4 * (t.bx + sb_step) * d >> ss_hor as i32) + rnd) >> shift;
+
+                for x in x0..cmp::min(x1, n_units) {
+                    let px_x = x << (unit_size_log2 + ss_hor as i32);
+                    let sb_idx = (t.by >> 5) * sr_sb128w + (px_x >> 7);
+                    let unit_idx = ((t.by & 16) >> 3) + ((px_x & 64) >> 6);
+                    let lr = &mut lr_mask[sb_idx as usize].lr[p][unit_idx as usize];
+                    read_restoration_info(ts, lr, p, frame_type);
+                }
+            } else {
+                let x = 4 * t.bx >> ss_hor as i32;
+                if x & mask != 0 {
+                    continue;
+                }
+                let w = (frame_hdr.width[0] as i32 + ss_hor as i32) >> ss_hor as i32;
+                // round half up at frame boundaries, if there's more than one
+                // restoration unit
+                if x != 0 && x + half_unit > w {
+                    continue;
+                }
+                let sb_idx = (t.by >> 5) * sr_sb128w + (t.bx >> 5);
+                let unit_idx = ((t.by & 16) >> 3) + ((t.bx & 16) >> 4);
+                let lr = &mut lr_mask[sb_idx as usize].lr[p][unit_idx as usize];
+                read_restoration_info(ts, lr, p, frame_type);
+            }
         }
 
         //decode_sb(t, root_bl, c->intra_edge.root[root_bl])?;
@@ -244,58 +665,548 @@ fn decode_tile_sbrow(
     Ok(())
 }
 
+fn chroma_sampling(layout: PixelLayout) -> ChromaSampling {
+    match layout {
+        PixelLayout::PIXEL_LAYOUT_I400 => ChromaSampling::Cs400,
+        PixelLayout::PIXEL_LAYOUT_I420 => ChromaSampling::Cs420,
+        PixelLayout::PIXEL_LAYOUT_I422 => ChromaSampling::Cs422,
+        PixelLayout::PIXEL_LAYOUT_I444 => ChromaSampling::Cs444,
+    }
+}
+
+// Allocate the current picture, in pre-superres dimensions, and derive the
+// frame dimensions in block units and the super-resolution steps.
+fn alloc_picture<T: Pixel>(
+    f: &mut FrameContext<T>,
+    seq_hdr: &SequenceHeader,
+    frame_hdr: &FrameHeader,
+) {
+    let chroma_sampling = chroma_sampling(seq_hdr.layout);
+    f.cur = Some(Frame::new(
+        frame_hdr.width[0] as usize,
+        frame_hdr.height as usize,
+        chroma_sampling,
+    ));
+    // the upscaled picture, if super-resolution is used
+    f.sr_cur = if frame_hdr.super_res.enabled {
+        Some(Frame::new(
+            frame_hdr.width[1] as usize,
+            frame_hdr.height as usize,
+            chroma_sampling,
+        ))
+    } else {
+        None
+    };
+
+    f.bw = (((frame_hdr.width[0] + 7) >> 3) << 1) as i32;
+    f.bh = (((frame_hdr.height + 7) >> 3) << 1) as i32;
+    f.w4 = ((frame_hdr.width[0] + 3) >> 2) as i32;
+    f.h4 = ((frame_hdr.height + 3) >> 2) as i32;
+    f.sb128w = (f.bw + 31) >> 5;
+    f.sb128h = (f.bh + 31) >> 5;
+    f.sr_sb128w = ((frame_hdr.width[1] + 127) >> 7) as i32;
+    f.sb_shift = 4 + seq_hdr.sb128 as i32;
+    f.sb_step = 16 << seq_hdr.sb128 as i32;
+    f.sbh = (f.bh + f.sb_step - 1) >> f.sb_shift;
+    f.b4_stride = ((f.bw + 31) & !31) as usize;
+
+    // super-resolution step and initial subpel position per plane type
+    if frame_hdr.super_res.enabled {
+        let ss_hor = (seq_hdr.layout != PixelLayout::PIXEL_LAYOUT_I444) as i32;
+        let (in_w, out_w) = (frame_hdr.width[0] as i32, frame_hdr.width[1] as i32);
+        let (in_cw, out_cw) = ((in_w + ss_hor) >> ss_hor, (out_w + ss_hor) >> ss_hor);
+        f.resize_step = [scale_fac(in_w, out_w), scale_fac(in_cw, out_cw)];
+        f.resize_start = [
+            get_upscale_x0(in_w, out_w, f.resize_step[0]),
+            get_upscale_x0(in_cw, out_cw, f.resize_step[1]),
+        ];
+    }
+}
+
+// Copy the w x h luma px area at (sx, sy) of src, and the corresponding
+// chroma areas, to (dx, dy) in dst.
+fn copy_area<T: Pixel>(
+    dst: &mut Frame<T>,
+    dx: usize,
+    dy: usize,
+    src: &Frame<T>,
+    sx: usize,
+    sy: usize,
+    w: usize,
+    h: usize,
+) {
+    for (dp, sp) in dst.planes.iter_mut().zip(src.planes.iter()) {
+        let (xdec, ydec) = (sp.cfg.xdec, sp.cfg.ydec);
+        let (pw, ph) = ((w + xdec) >> xdec, (h + ydec) >> ydec);
+        let s = (sp.cfg.yorigin + (sy >> ydec)) * sp.cfg.stride + sp.cfg.xorigin + (sx >> xdec);
+        let d = (dp.cfg.yorigin + (dy >> ydec)) * dp.cfg.stride + dp.cfg.xorigin + (dx >> xdec);
+        for y in 0..ph {
+            let (s, d) = (s + y * sp.cfg.stride, d + y * dp.cfg.stride);
+            dp.data[d..d + pw].copy_from_slice(&sp.data[s..s + pw]);
+        }
+    }
+}
+
+// Conceal the w x h luma px area at (x, y) of a tile that failed to decode,
+// and the corresponding chroma areas, with the co-located area of the
+// reference closest in display order, or with mid-grey in intra frames.
+fn conceal_area<T: Pixel>(
+    dst: &mut Frame<T>,
+    refp: &[Option<Rc<Frame<T>>>; 7],
+    refpoc: &[u32; 7],
+    seq_hdr: &SequenceHeader,
+    frame_hdr: &FrameHeader,
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+) {
+    let nearest = refp
+        .iter()
+        .zip(refpoc.iter())
+        .filter_map(|(p, &poc)| p.as_ref().map(|p| (p, poc)))
+        // references are upscaled with super-resolution, only use those
+        // of the same size
+        .filter(|(p, _)| p.width == dst.width && p.height == dst.height)
+        .min_by_key(|&(_, poc)| {
+            get_poc_diff(seq_hdr.order_hint_n_bits, poc as i32, frame_hdr.frame_offset as i32)
+                .abs()
+        });
+    if let Some((p, _)) = nearest {
+        copy_area(dst, x, y, p, x, y, w, h);
+        return;
+    }
+
+    let grey = T::cast_from((seq_hdr.bitdepth_max() + 1) >> 1);
+    for p in dst.planes.iter_mut() {
+        let (xdec, ydec) = (p.cfg.xdec, p.cfg.ydec);
+        let (pw, ph) = ((w + xdec) >> xdec, (h + ydec) >> ydec);
+        let d = (p.cfg.yorigin + (y >> ydec)) * p.cfg.stride + p.cfg.xorigin + (x >> xdec);
+        for y in 0..ph {
+            let d = d + y * p.cfg.stride;
+            p.data[d..d + pw].iter_mut().for_each(|px| *px = grey);
+        }
+    }
+}
+
 impl<T: Pixel> Context<T> {
-    pub fn submit_frame(&mut self) -> io::Result<()> {
+    pub fn submit_frame(&mut self) -> Result<(), DecodeError> {
         // TODO:
         // initialize context
         let f_idx = if self.n_fc > 1 {
             // multi-threading
-            unimplemented!()
+            return Err(DecodeError::Unsupported("frame threading"));
         } else {
             0
         };
 
+        let seq_hdr = Rc::clone(self.seq_hdr.as_ref().unwrap());
+        let frame_hdr = Rc::clone(self.frame_hdr.as_ref().unwrap());
+
+        // after an error, frames are skipped up to the next random access
+        // point, and so are frames predicting from a missing or stale
+        // reference
+        let is_rap = frame_hdr.frame_type == FrameType::FRAME_TYPE_KEY
+            || frame_hdr.frame_type == FrameType::FRAME_TYPE_SWITCH;
+        if self.waiting_for_rap && !is_rap {
+            self.skip_frame(&frame_hdr);
+            return Ok(());
+        }
+        if !frame_hdr.frame_is_intra() {
+            let missing_ref = frame_hdr.refidx.iter().any(|&idx| {
+                self.refs[idx as usize].p.is_none() || (frame_hdr.invalid_refs & (1 << idx)) != 0
+            });
+            if missing_ref {
+                self.skip_frame(&frame_hdr);
+                return Ok(());
+            }
+        }
+        // a switch frame is only a random access point if all of its
+        // references are there
+        self.waiting_for_rap = false;
+
+        let f = &mut self.fc[f_idx];
+        f.seq_hdr = Some(Rc::clone(&seq_hdr));
+        f.frame_hdr = Some(Rc::clone(&frame_hdr));
+        f.tile = mem::take(&mut self.tile);
+
+        // a shown key frame refreshes all slots, so release the references
+        // of the previous frames before allocating the new picture
+        if frame_hdr.frame_type == FrameType::FRAME_TYPE_KEY && frame_hdr.show_frame {
+            self.refs = Default::default();
+        }
+
+        // the pictures this frame predicts from
+        f.refp = Default::default();
+        f.refpoc = [0; 7];
+        f.svc = Default::default();
+        if !frame_hdr.frame_is_intra() {
+            let (w, h) = (frame_hdr.width[0] as usize, frame_hdr.height as usize);
+            for i in 0..7 {
+                let r = &self.refs[frame_hdr.refidx[i] as usize];
+                // a reference may be at most twice as large and 16 times
+                // smaller than the frame, see 7.9.1
+                let p = r.p.as_ref().unwrap();
+                check_error(
+                    2 * w < p.width || 2 * h < p.height || w > 16 * p.width || h > 16 * p.height,
+                    "invalid reference frame size",
+                )?;
+                f.refp[i] = r.p.clone();
+                f.refpoc[i] = r.frame_hdr.as_ref().unwrap().frame_offset;
+                f.svc[i] = if p.width != w || p.height != h {
+                    [ScalableMotionParams::new(p.width, w), ScalableMotionParams::new(p.height, h)]
+                } else {
+                    Default::default()
+                };
+            }
+        }
+
+        alloc_picture(f, &seq_hdr, &frame_hdr);
+        f.in_cdf = primary_ref_cdf(&self.refs, &frame_hdr)?;
+        f.out_cdf = None;
+
+        // segmentation map, see 7.20 and load_previous_segment_ids()
+        f.prev_segmap = None;
+        f.cur_segmap = None;
+        if frame_hdr.segmentation.enabled {
+            // the previous map is used for temporal prediction of the new
+            // one, or as is if the map isn't updated; it only exists if
+            // the reference has the same dimensions
+            if frame_hdr.segmentation.temporal || !frame_hdr.segmentation.update_map {
+                let pri_ref = frame_hdr.primary_ref_frame as usize;
+                check_error(pri_ref == PRIMARY_REF_NONE, "no primary reference frame")?;
+                let r = &self.refs[frame_hdr.refidx[pri_ref] as usize];
+                if let Some(ref_hdr) = r.frame_hdr.as_ref() {
+                    let ref_w = (((ref_hdr.width[0] + 7) >> 3) << 1) as i32;
+                    let ref_h = (((ref_hdr.height + 7) >> 3) << 1) as i32;
+                    if ref_w == f.bw && ref_h == f.bh {
+                        f.prev_segmap = r.segmap.clone();
+                    }
+                }
+            }
+            f.cur_segmap = match f.prev_segmap.as_ref() {
+                Some(prev) if !frame_hdr.segmentation.update_map => Some(Rc::clone(prev)),
+                // the new map is filled in during block decoding
+                _ => Some(Rc::new(vec![0; f.b4_stride * 32 * f.sb128h as usize])),
+            };
+        }
+
+        let refresh_frame_flags = frame_hdr.refresh_frame_flags;
+        let show_frame = frame_hdr.show_frame;
+
         if self.n_fc == 1 {
             // single threading
-            self.decode_frame(f_idx)?;
-        //if ((res = dav1d_decode_frame(f)) < 0) {
-        /*let refresh_frame_flags = frame_hdr.refresh_frame_flags;
-        dav1d_picture_unref_internal(&c->out);
-        for (int i = 0; i < 8; i++) {
-            if (refresh_frame_flags & (1 << i)) {
-                if (c->refs[i].p.p.data[0])
-                dav1d_thread_picture_unref(&c->refs[i].p);
-                dav1d_cdf_thread_unref(&c->cdf[i]);
-                dav1d_ref_dec(&c->refs[i].segmap);
-                dav1d_ref_dec(&c->refs[i].refmvs);
-            }
-        }
-        return res;*/
-        //}
+            let res = self.decode_frame(f_idx);
+
+            // release this frame's references, they're kept by the slots
+            let f = &mut self.fc[f_idx];
+            f.refp = Default::default();
+            f.prev_segmap = None;
+            let cur_segmap = f.cur_segmap.take();
+
+            if let Err(e) = res {
+                //dav1d_picture_unref_internal(&c->out);
+                for i in 0..8 {
+                    if (refresh_frame_flags & (1 << i)) != 0 {
+                        self.refs[i] = RefState::default();
+                    }
+                }
+                return Err(e);
+            }
+
+            // references and output use the picture after super-resolution
+            // upscaling
+            let out = f.sr_cur.take().or_else(|| f.cur.take()).map(Rc::new);
+
+            // update references, see 7.20
+            if refresh_frame_flags != 0 {
+                let r = RefState {
+                    p: out.clone(),
+                    frame_hdr: Some(Rc::clone(&frame_hdr)),
+                    // the CDFs saved at the end of the frame, if any
+                    cdf: Some(f.out_cdf.take().unwrap_or_else(|| Rc::clone(&f.in_cdf))),
+                    segmap: cur_segmap,
+                    // only inter frames have a motion field to project
+                    refmvs: if !frame_hdr.frame_is_intra() {
+                        Some(Rc::new(mem::take(&mut f.mvs)))
+                    } else {
+                        None
+                    },
+                    refpoc: f.refpoc,
+                };
+                for i in 0..8 {
+                    if (refresh_frame_flags & (1 << i)) != 0 {
+                        self.refs[i] = r.clone();
+                    }
+                }
+            }
+
+            if show_frame {
+                if let Some(p) = out {
+                    self.output_picture(p, &frame_hdr, &seq_hdr)?;
+                }
+            }
         } else {
             // multi-threading
-            unimplemented!();
+            return Err(DecodeError::Unsupported("frame threading"));
         }
 
         Ok(())
     }
 
-    fn decode_frame(&mut self, f_idx: usize) -> io::Result<()> {
+    // Decode the tiles of a tile list and output them, see 7.3. The tiles are
+    // coded with the last frame header; all the references of a tile are its
+    // anchor frame, with the order hints of the reference slots. The in-loop
+    // filters are off in large scale tile streams, so a tile is complete once
+    // its blocks are reconstructed.
+    pub(crate) fn decode_tile_list(&mut self, tile_list: &TileList) -> Result<(), DecodeError> {
+        let f_idx = if self.n_fc > 1 {
+            // multi-threading
+            return Err(DecodeError::Unsupported("frame threading"));
+        } else {
+            0
+        };
+
+        let seq_hdr = Rc::clone(self.seq_hdr.as_ref().unwrap());
+        let frame_hdr = Rc::clone(self.frame_hdr.as_ref().unwrap());
+        check_error(
+            frame_hdr.super_res.enabled,
+            "super-resolution with large scale tiles",
+        )?;
+        let is_intra = frame_hdr.frame_is_intra();
+
+        let f = &mut self.fc[f_idx];
+        f.seq_hdr = Some(Rc::clone(&seq_hdr));
+        f.frame_hdr = Some(Rc::clone(&frame_hdr));
+        f.tile = mem::take(&mut self.tile);
+        f.refpoc = [0; 7];
+        f.svc = Default::default();
+        if !is_intra {
+            for i in 0..7 {
+                if let Some(ref_hdr) = &self.refs[frame_hdr.refidx[i] as usize].frame_hdr {
+                    f.refpoc[i] = ref_hdr.frame_offset;
+                }
+            }
+        }
+        alloc_picture(f, &seq_hdr, &frame_hdr);
+        f.in_cdf = primary_ref_cdf(&self.refs, &frame_hdr)?;
+        f.prev_segmap = None;
+        f.cur_segmap = if frame_hdr.segmentation.enabled {
+            Some(Rc::new(vec![0; f.b4_stride * 32 * f.sb128h as usize]))
+        } else {
+            None
+        };
+        self.decode_frame_init(f_idx)?;
+
+        // the tiles are placed on a grid of the size of the first tile of
+        // the frame, those of the last column and row may be smaller
+        let (w, h) = (frame_hdr.width[0] as usize, frame_hdr.height as usize);
+        let sb_px = 64 << seq_hdr.sb128 as usize;
+        let tiling = &frame_hdr.tiling;
+        let tile_w = (tiling.col_start_sb[1] - tiling.col_start_sb[0]) as usize * sb_px;
+        let tile_h = (tiling.row_start_sb[1] - tiling.row_start_sb[0]) as usize * sb_px;
+        let (tile_w, tile_h) = (cmp::min(tile_w, w), cmp::min(tile_h, h));
+        let mut composed = if self.output_all_tiles {
+            None
+        } else {
+            Some(Frame::new(
+                tile_list.output_width * tile_w,
+                tile_list.output_height * tile_h,
+                chroma_sampling(seq_hdr.layout),
+            ))
+        };
+
         let f = &mut self.fc[f_idx];
-        let seq_hdr = f.seq_hdr.as_ref().unwrap();
-        let frame_hdr = f.frame_hdr.as_ref().unwrap();
+        for (n, entry) in tile_list.entries.iter().enumerate() {
+            check_error(
+                entry.anchor_frame_idx >= self.anchors.len(),
+                "missing anchor frame",
+            )?;
+            if !is_intra {
+                // same limits as for the size of references, see 7.9.1
+                let p = &self.anchors[entry.anchor_frame_idx];
+                check_error(
+                    2 * w < p.width || 2 * h < p.height || w > 16 * p.width || h > 16 * p.height,
+                    "invalid anchor frame size",
+                )?;
+                for refp in f.refp.iter_mut() {
+                    *refp = Some(Rc::clone(p));
+                }
+            }
+
+            // every tile is decoded on its own
+            f.frame_thread.pass = 0;
+            for i in 0..f.sb128w * tiling.rows {
+                reset_context(&mut f.a[i as usize], is_intra, 0);
+            }
+
+            let (tile_row, tile_col) = (entry.tile_row, entry.tile_col);
+            let tile_idx = (tile_row * tiling.cols + tile_col) as usize;
+            let data = &self.packet.as_ref().unwrap().data[entry.data_offset..][..entry.data_sz];
+            setup_tile(
+                &seq_hdr,
+                &frame_hdr,
+                &f.in_cdf,
+                data,
+                tile_row,
+                tile_col,
+                0,
+                f.sb_shift,
+                f.bw,
+                f.bh,
+                f.n_tc,
+                &mut f.ts[tile_idx],
+            );
+
+            let ts = &mut f.ts[tile_idx];
+            let t = &mut f.tc[0];
+            let sbh_end = cmp::min(tiling.row_start_sb[tile_row as usize + 1] as i32, f.sbh);
+            for sby in tiling.row_start_sb[tile_row as usize] as i32..sbh_end {
+                t.by = sby << (4 + seq_hdr.sb128 as i32);
+                let lr_mask = &mut f.lf.lr_mask;
+                decode_tile_sbrow(
+                    &seq_hdr, &frame_hdr, t, ts, lr_mask, f.sb_step, 0, self.n_fc,
+                )?;
+            }
+
+            // the area of the tile, in luma px
+            let x = ts.tiling.col_start as usize * 4;
+            let y = ts.tiling.row_start as usize * 4;
+            let tw = cmp::min(ts.tiling.col_end as usize * 4, w) - x;
+            let th = cmp::min(ts.tiling.row_end as usize * 4, h) - y;
+            let cur = f.cur.as_ref().unwrap();
+            match composed.as_mut() {
+                Some(out) => {
+                    // the tiles are composed in raster order
+                    let (col, row) = (n % tile_list.output_width, n / tile_list.output_width);
+                    check_error(
+                        row >= tile_list.output_height,
+                        "tile outside the output frame",
+                    )?;
+                    copy_area(out, col * tile_w, row * tile_h, cur, x, y, tw, th);
+                }
+                None => {
+                    let mut out = Frame::new(tw, th, chroma_sampling(seq_hdr.layout));
+                    copy_area(&mut out, 0, 0, cur, x, y, tw, th);
+                    self.tile_out.push_back(out);
+                }
+            }
+        }
+
+        // release the anchor frames and the picture
+        f.refp = Default::default();
+        f.cur = None;
+        f.cur_segmap = None;
+
+        if let Some(out) = composed {
+            self.output_picture(Rc::new(out), &frame_hdr, &seq_hdr)?;
+        }
+
+        Ok(())
+    }
+
+    // Output the picture of a reference slot again, see 7.21. Showing a key
+    // frame this way also makes it the only reference, as if it had just
+    // been decoded.
+    pub(crate) fn show_existing_frame(&mut self) -> Result<(), DecodeError> {
+        let seq_hdr = Rc::clone(self.seq_hdr.as_ref().unwrap());
+        let idx = self.frame_hdr.as_ref().unwrap().existing_frame_idx as usize;
+        let r = &self.refs[idx];
+        // the slot is empty if the frame was lost or skipped; showing a key
+        // frame is a random access point
+        let is_key = r
+            .frame_hdr
+            .as_ref()
+            .map_or(false, |h| h.frame_type == FrameType::FRAME_TYPE_KEY);
+        if r.p.is_none() || (self.waiting_for_rap && !is_key) {
+            self.skipped_frames += 1;
+            return Ok(());
+        }
+        self.waiting_for_rap = false;
+        let p = Rc::clone(r.p.as_ref().unwrap());
+        let ref_hdr = Rc::clone(r.frame_hdr.as_ref().unwrap());
+
+        if is_key {
+            // every slot loads the state of the shown key frame, see 7.21
+            let r = r.clone();
+            for i in 0..8 {
+                if i != idx {
+                    self.refs[i] = r.clone();
+                }
+            }
+        }
+
+        // the film grain is applied with the stored parameters
+        self.output_picture(p, &ref_hdr, &seq_hdr)
+    }
+
+    // Drop a frame that can't be decoded. The slots it would refresh don't
+    // hold a valid picture anymore, so the frames predicting from them are
+    // dropped in turn.
+    fn skip_frame(&mut self, frame_hdr: &FrameHeader) {
+        for i in 0..8 {
+            if (frame_hdr.refresh_frame_flags & (1 << i)) != 0 {
+                self.refs[i] = RefState::default();
+            }
+        }
+        self.skipped_frames += 1;
+    }
+
+    // Queue a picture for output, along with the current HDR metadata. Film
+    // grain is only applied to the output picture, if disabled the
+    // parameters are exported along with it.
+    fn output_picture(
+        &mut self,
+        p: Rc<Frame<T>>,
+        frame_hdr: &FrameHeader,
+        seq_hdr: &SequenceHeader,
+    ) -> Result<(), DecodeError> {
+        let film_grain = &frame_hdr.film_grain;
+        let mut out = if film_grain.present && self.apply_grain {
+            apply_grain(&p, &film_grain.data, seq_hdr)
+        } else {
+            // the picture is only copied if it is still referenced
+            let mut p = Rc::try_unwrap(p).unwrap_or_else(|p| (*p).clone());
+            if film_grain.present {
+                p.film_grain = Some(film_grain.data);
+            }
+            p
+        };
+        out.spatial_id = frame_hdr.spatial_id as u8;
+        out.temporal_id = frame_hdr.temporal_id as u8;
+        self.frame_new_temporal_unit = mem::replace(&mut self.new_temporal_unit, false);
+        out.content_light = self.content_light;
+        out.mastering_display = self.mastering_display;
+        out.scalability = self.scalability.clone();
+        // T.35 metadata and timecodes are only attached to the first frame
+        // following them
+        out.itut_t35 = mem::take(&mut self.itut_t35);
+        out.timecode = self.timecode.take();
+        self.frame = Some(out);
+
+        Ok(())
+    }
+
+    // Set up the frame context for decoding the tiles of the current frame
+    fn decode_frame_init(&mut self, f_idx: usize) -> Result<(), DecodeError> {
+        let f = &mut self.fc[f_idx];
+        let seq_hdr = Rc::clone(f.seq_hdr.as_ref().unwrap());
+        let seq_hdr = &*seq_hdr;
+        let frame_hdr = Rc::clone(f.frame_hdr.as_ref().unwrap());
+        let frame_hdr = &*frame_hdr;
 
         if f.n_tc > 1 {
-            unimplemented!();
+            return Err(DecodeError::Unsupported("tile threading"));
         }
 
         if frame_hdr.tiling.cols * frame_hdr.tiling.rows > f.n_ts {
             //TODO: add threading-related code
             f.n_ts = frame_hdr.tiling.cols * frame_hdr.tiling.rows;
+            f.ts.resize(f.n_ts as usize, TileState::default());
         }
 
         if self.n_fc > 1 {
-            unimplemented!();
+            return Err(DecodeError::Unsupported("frame threading"));
         }
 
         if f.sb128w * frame_hdr.tiling.rows > f.a.len() as i32 {
@@ -304,27 +1215,80 @@ impl<T: Pixel> Context<T> {
 
         // update allocation of block contexts for above
         if f.sb128w > f.lf.line_sz {
-            //TODO: add cdef_line related code
+            let w = f.sb128w as usize * 128;
+            for line in f.lf.cdef_line.iter_mut().flatten().flatten() {
+                *line = vec![T::cast_from(0); w];
+            }
             f.lf.line_sz = f.sb128w;
         }
 
-        //TODO: add lr-related code
+        // update allocation for loop restoration line buffers
+        let lr_line_sz = ((frame_hdr.width[1] + 31) & !31) as i32;
+        if lr_line_sz != f.lf.lr_line_sz {
+            for line in f.lf.lr_lpf_line.iter_mut() {
+                *line = vec![T::cast_from(0); lr_line_sz as usize * 12];
+            }
+            f.lf.lr_line_sz = lr_line_sz;
+        }
 
         // update allocation for loopfilter masks
         if f.sb128w * f.sb128h > f.lf.mask_sz {
-            //TODO: add masks related code
             f.lf.mask_sz = f.sb128w * f.sb128h;
+            f.lf.level = vec![[0; 4]; f.lf.mask_sz as usize * 32 * 32];
+        }
+        f.lf.mask = vec![Av1Filter::default(); f.lf.mask_sz as usize];
+        let lr_mask_sz = f.sr_sb128w * f.sb128h;
+        if lr_mask_sz != f.lf.lr_mask_sz {
+            f.lf.lr_mask = vec![Av1Restoration::default(); lr_mask_sz as usize];
+            f.lf.lr_mask_sz = lr_mask_sz;
+        }
+        f.lf.restore_planes = frame_hdr
+            .restoration
+            .t
+            .iter()
+            .enumerate()
+            .map(|(p, &t)| ((t != RestorationType::RESTORATION_NONE) as i32) << p)
+            .sum();
+        if frame_hdr.loopfilter.sharpness != f.lf.last_sharpness {
+            calc_eih(&mut f.lf.lim_lut, frame_hdr.loopfilter.sharpness);
+            f.lf.last_sharpness = frame_hdr.loopfilter.sharpness;
+        }
+        // the levels of blocks without a delta_lf, tiles keep their own
+        // levels if it is coded, see setup_tile()
+        calc_lf_values(&mut f.lf.lvl, frame_hdr, &[0; 4]);
+
+        let re_sz = f.sb128h * frame_hdr.tiling.cols;
+        if re_sz != f.lf.re_sz {
+            let ss_ver = (seq_hdr.layout == PixelLayout::PIXEL_LAYOUT_I420) as usize;
+            f.lf.tx_lpf_right_edge = [
+                vec![0; re_sz as usize * 32],
+                vec![0; (re_sz as usize * 32) >> ss_ver],
+            ];
+            f.lf.re_sz = re_sz;
         }
 
         // init ref mvs
         if !frame_hdr.frame_is_intra() || frame_hdr.allow_intrabc {
+            // the previous frame's motion field was handed over to the
+            // reference slots, so start a new one
+            f.mvs = vec![RefMvs::default(); f.sb128h as usize * 32 * f.b4_stride];
             //TODO: add ref mv related code
         }
 
+        // global motion models that can be used for warped prediction, which
+        // isn't available from scaled references
+        for i in 0..7 {
+            let mut gmv = frame_hdr.gmv[i];
+            f.gmv_warp_allowed[i] = gmv.t as u32 > WarpedMotionType::WM_TYPE_TRANSLATION as u32
+                && frame_hdr.force_integer_mv == AdaptiveBoolean::OFF
+                && !get_shear_params(&mut gmv)
+                && f.svc[i][0].scale == 0;
+        }
+
         // setup dequant tables
         init_quant_tables(seq_hdr, frame_hdr, frame_hdr.quant.yac, &mut f.dq);
         if frame_hdr.quant.qm {
-            unimplemented!();
+            return Err(DecodeError::Unsupported("quantizer matrices"));
             /*
             for j in 0..RectTxfmSize::N_RECT_TX_SIZES{
                 f.qm[0][j][0] = qm_tbl[frame_hdr.quant.qm_y][0][j];
@@ -339,59 +1303,67 @@ impl<T: Pixel> Context<T> {
 
         // setup jnt_comp weights
         if frame_hdr.switchable_comp_refs {
-            unimplemented!();
+            return Err(DecodeError::Unsupported("distance weighted compound"));
         }
 
+        Ok(())
+    }
+
+    fn decode_frame(&mut self, f_idx: usize) -> Result<(), DecodeError> {
+        self.decode_frame_init(f_idx)?;
+
+        let f = &mut self.fc[f_idx];
+        let seq_hdr = Rc::clone(f.seq_hdr.as_ref().unwrap());
+        let seq_hdr = &*seq_hdr;
+        let frame_hdr = Rc::clone(f.frame_hdr.as_ref().unwrap());
+        let frame_hdr = &*frame_hdr;
+
         // init loopfilter pointers
-        //TODO
+        f.lf.top_pre_cdef_toggle = 0;
+        f.lf.tile_row = 1;
 
         // parse individual tiles per tile group
         let (mut update_set, mut tile_row, mut tile_col) = (0, 0, 0);
         let data = &self.packet.as_ref().unwrap().data;
+        let conceal_errors = self.conceal_errors;
         for i in 0..f.tile.len() {
             let mut data_offset = f.tile[i].data_offset;
             let mut size = f.tile[i].data_sz;
+            let mut missing = false;
 
             for j in f.tile[i].start..=f.tile[i].end {
-                let mut tile_sz;
-                if j == f.tile[i].end {
-                    tile_sz = size;
-                } else {
-                    check_error(
-                        frame_hdr.tiling.n_bytes > size as u32,
-                        "frame_hdr.tiling.n_bytes > size",
-                    )?;
-                    tile_sz = 0;
-                    for k in 0..frame_hdr.tiling.n_bytes as usize {
-                        tile_sz |= (data[data_offset] as usize) << (k * 8);
-                        data_offset += 1;
+                let mut tile_sz = 0;
+                if !missing {
+                    let last = j == f.tile[i].end;
+                    let n_bytes = frame_hdr.tiling.n_bytes as usize;
+                    match tile_size(data, &mut data_offset, &mut size, last, n_bytes) {
+                        Ok(sz) => tile_sz = sz,
+                        // the rest of the tile group can't be located, and
+                        // is concealed
+                        Err(DecodeError::InvalidBitstream { .. }) if conceal_errors => {
+                            missing = true
+                        }
+                        Err(e) => return Err(e),
                     }
-                    tile_sz += 1;
-                    size -= frame_hdr.tiling.n_bytes as usize;
-                    check_error(tile_sz > size, "tile_sz > size")?;
                 }
 
                 setup_tile(
                     seq_hdr,
                     frame_hdr,
-                    data,
-                    //tile_sz,
+                    &f.in_cdf,
+                    &data[data_offset..][..tile_sz],
                     tile_row,
                     tile_col,
-                    if self.n_fc > 1 {
-                        /*frame_thread.tile_start_off[j]*/
-                        unimplemented!()
-                    } else {
-                        0
-                    },
+                    // frame_thread.tile_start_off[j], decode_frame_init() fails
+                    // with frame threading
+                    0,
                     f.sb_shift,
                     f.bw,
                     f.bh,
-                    f.sr_sb128w,
-                    f.sb128w,
                     f.n_tc,
                     &mut f.ts[j as usize],
                 );
+                f.ts[j as usize].error = missing;
                 tile_col += 1;
 
                 if tile_col == frame_hdr.tiling.cols {
@@ -436,45 +1408,454 @@ impl<T: Pixel> Context<T> {
         }
 
         if f.n_tc == 1 {
-            let t = f.tc.first_mut().unwrap();
-
             // no tile threading - we explicitly interleave tile/sbrow decoding
             // and post-filtering, so that the full process runs in-line, so
             // that frame threading is still possible
+            let ss_ver = (seq_hdr.layout == PixelLayout::PIXEL_LAYOUT_I420) as i32;
+            let align_h = (f.bh + 31) & !31;
             for tile_row in 0..frame_hdr.tiling.rows {
                 let sbh_end = cmp::min(
                     frame_hdr.tiling.row_start_sb[tile_row as usize + 1] as i32,
                     f.sbh,
                 );
-                for sby in frame_hdr.tiling.row_start_sb[tile_row as usize] as i32..sbh_end {
+                let sby_start = frame_hdr.tiling.row_start_sb[tile_row as usize] as i32;
+                for sby in sby_start..sbh_end {
+                    let t = &mut f.tc[0];
                     t.by = sby << (4 + seq_hdr.sb128 as i32);
                     for tile_col in 0..frame_hdr.tiling.cols {
-                        let ts = &f.ts[(tile_row * frame_hdr.tiling.cols + tile_col) as usize];
-
-                        decode_tile_sbrow(
-                            seq_hdr,
-                            frame_hdr,
-                            t,
-                            ts,
-                            f.sb_step,
-                            f.frame_thread.pass,
-                            self.n_fc,
-                        )?;
+                        let ts = &mut f.ts[(tile_row * frame_hdr.tiling.cols + tile_col) as usize];
+
+                        // the tiles that couldn't be located are concealed
+                        // from the start
+                        let mut failed = ts.error && sby == sby_start;
+                        if !ts.error {
+                            let res = decode_tile_sbrow(
+                                seq_hdr,
+                                frame_hdr,
+                                t,
+                                ts,
+                                &mut f.lf.lr_mask,
+                                f.sb_step,
+                                f.frame_thread.pass,
+                                self.n_fc,
+                            );
+                            match res {
+                                Err(DecodeError::InvalidBitstream { .. }) if conceal_errors => {
+                                    ts.error = true;
+                                    failed = true;
+                                }
+                                res => res?,
+                            }
+                        }
+                        if ts.error {
+                            // conceal the rest of the tile, row by row so that
+                            // the post-filters see the concealed pixels
+                            let cur = f.cur.as_mut().unwrap();
+                            let x = (ts.tiling.col_start * 4) as usize;
+                            let w = cmp::min((ts.tiling.col_end * 4) as usize, cur.width) - x;
+                            let y = (t.by * 4) as usize;
+                            let h = cmp::min(((t.by + f.sb_step) * 4) as usize, cur.height) - y;
+                            if failed {
+                                let bottom = cmp::min((ts.tiling.row_end * 4) as usize, cur.height);
+                                cur.corrupted = true;
+                                cur.corrupted_tiles.push(Rect {
+                                    x: x as isize,
+                                    y: y as isize,
+                                    width: w,
+                                    height: bottom - y,
+                                });
+                            }
+                            conceal_area(cur, &f.refp, &f.refpoc, seq_hdr, frame_hdr, x, y, w, h);
+                        }
+
+                        // backup t.l.tx_lpf_y/uv at tile boundaries to use them to "fix"
+                        // up the initial value in neighbour tiles when running the loopfilter
+                        let sb_step = f.sb_step as usize;
+                        let off = (align_h * tile_col + t.by) as usize;
+                        f.lf.tx_lpf_right_edge[0][off..off + sb_step]
+                            .copy_from_slice(&t.l.tx_lpf_y.array[(t.by & 16) as usize..][..sb_step]);
+                        let off = ((align_h >> ss_ver) * tile_col + (t.by >> ss_ver)) as usize;
+                        f.lf.tx_lpf_right_edge[1][off..off + (sb_step >> ss_ver)].copy_from_slice(
+                            &t.l.tx_lpf_uv.array[((t.by & 16) >> ss_ver) as usize..][..sb_step >> ss_ver],
+                        );
                     }
 
                     // loopfilter + cdef + restoration
                     if f.frame_thread.pass != 1 {
-                        unimplemented!();
-                        //f -> bd_fn.filter_sbrow(f, sby);
+                        filter_sbrow(f, sby);
                     }
                     //dav1d_thread_picture_signal(&f->sr_cur, (sby + 1) * f->sb_step * 4,
                     //                            progress_plane_type);
                 }
             }
         } else {
-            unimplemented!();
+            return Err(DecodeError::Unsupported("tile threading"));
+        }
+
+        // the CDFs the tile given by context_update_tile_id ended with are
+        // saved for later frames
+        if frame_hdr.refresh_context && update_set != 0 {
+            let mut out_cdf = CdfContext::default();
+            cdf_thread_update(&mut out_cdf, &f.ts[frame_hdr.tiling.update as usize].cdf);
+            f.out_cdf = Some(Rc::new(out_cdf));
+        }
+
+        // the output picture is the upscaled one with super-resolution
+        if let (Some(cur), Some(sr_cur)) = (f.cur.as_mut(), f.sr_cur.as_mut()) {
+            let (w0, w1) = (frame_hdr.width[0] as usize, frame_hdr.width[1] as usize);
+            sr_cur.corrupted = cur.corrupted;
+            sr_cur.corrupted_tiles = cur
+                .corrupted_tiles
+                .drain(..)
+                .map(|r| Rect {
+                    x: r.x * w1 as isize / w0 as isize,
+                    width: (r.width * w1 + w0 - 1) / w0,
+                    ..r
+                })
+                .collect();
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn warpmv_from_uniform_neighbour_motion() {
+        // a 16x16 block at (16, 16), whose above and left neighbours are
+        // 8x8 blocks all moving by (2, 1) px from LAST
+        let mut f = FrameContext::<u8>::default();
+        f.b4_stride = 16;
+        f.mvs = vec![RefMvs::default(); 16 * 16];
+        let mv = MV { y: 8, x: 16 };
+        let nb = RefMvs {
+            mv: [mv, MV::default()],
+            ref_frame: [1, -1],
+            sb_type: BlockSize::BS_8x8 as u8,
+            ..Default::default()
+        };
+        for i in 0..16 {
+            f.mvs[3 * 16 + i] = nb;
+            f.mvs[i * 16 + 3] = nb;
+        }
+        let t = TileContext {
+            bx: 4,
+            by: 4,
+            ..Default::default()
+        };
+        let mut ts = TileState::default();
+        ts.tiling.col_end = 16;
+
+        let mut masks = [0u64; 2];
+        find_matching_ref(&f, &t, &ts, true, 4, 4, 4, 4, true, true, 0, &mut masks);
+        // both 8x8 blocks above and to the left, and the top-left and
+        // top-right neighbours
+        assert_eq!(masks, [0b101 | 1 << 32, 0b101 | 1 << 32]);
+
+        // none of the neighbours uses ALTREF
+        let mut no_masks = [0u64; 2];
+        find_matching_ref(&f, &t, &ts, true, 4, 4, 4, 4, true, true, 6, &mut no_masks);
+        assert_eq!(no_masks, [0, 0]);
+
+        // all samples move by the block's own motion, so the fitted model
+        // is a translation, up to the precision of the divisor lookup
+        let mut wmp = WarpedMotionParams::default();
+        derive_warpmv(&f, &t, 4, 4, &masks, mv, &mut wmp);
+        assert_eq!(wmp.t, WarpedMotionType::WM_TYPE_AFFINE);
+        let mat = wmp.matrix;
+        assert_eq!((mat[3], mat[4]), (0, 0));
+        assert!((mat[2] - 0x10000).abs() < 0x100 && mat[5] == mat[2]);
+        // the centre of the block is moved by exactly mv
+        let (isux, isuy) = (4 * 4 + 7, 4 * 4 + 7);
+        assert_eq!(mat[0] + (mat[2] - 0x10000) * isux + mat[3] * isuy, 16 * 0x2000);
+        assert_eq!(mat[1] + mat[4] * isux + (mat[5] - 0x10000) * isuy, 8 * 0x2000);
+    }
+
+    #[test]
+    fn skip_mode_per_block() {
+        use crate::msac::test::MsacWriter;
+
+        let mut frame_hdr = FrameHeader::default();
+        frame_hdr.skip_mode_enabled = true;
+        // (bx4, by4, bw4, bh4, coded flag); the 4x8 block codes nothing
+        let blocks = [
+            (0, 0, 4, 4, Some(true)),
+            (4, 0, 4, 4, Some(false)),
+            (0, 4, 2, 2, Some(true)),
+            (2, 4, 1, 2, None),
+            (4, 4, 4, 4, Some(true)),
+        ];
+        let mut cdf = CdfContext::default();
+        let mut w = MsacWriter::new(false);
+        let (mut a, mut l) = (BlockContext::default(), BlockContext::default());
+        for &(bx4, by4, bw4, bh4, flag) in &blocks {
+            if let Some(flag) = flag {
+                let ctx = (a.skip_mode.array[bx4] + l.skip_mode.array[by4]) as usize;
+                w.encode_bool_adapt(flag, &mut cdf.m.skip_mode[ctx]);
+            }
+            let v = flag.unwrap_or(false) as u8;
+            a.skip_mode.array[bx4..][..bw4]
+                .iter_mut()
+                .for_each(|x| *x = v);
+            l.skip_mode.array[by4..][..bh4]
+                .iter_mut()
+                .for_each(|x| *x = v);
+        }
+        let data = w.data();
+
+        let mut ts = TileState::default();
+        ts.msac = MsacContext::new(&data, false);
+        let (mut a, mut l) = (BlockContext::default(), BlockContext::default());
+        for &(bx4, by4, bw4, bh4, flag) in &blocks {
+            let skip_mode =
+                read_skip_mode(&frame_hdr, &mut ts, &mut a, &mut l, 0, bx4, by4, bw4, bh4);
+            assert_eq!(skip_mode, flag.unwrap_or(false));
+            let set = |c: &[u8]| c.iter().all(|&x| x == skip_mode as u8);
+            assert!(set(&a.skip_mode.array[bx4..][..bw4]));
+            assert!(set(&l.skip_mode.array[by4..][..bh4]));
+        }
+        assert_eq!(ts.cdf, cdf);
+        assert!(ts.msac.cnt >= -15);
+
+        // a segment with a reference frame feature never codes skip_mode
+        frame_hdr.segmentation.enabled = true;
+        frame_hdr.segmentation.seg_data.d[1].ref_frame = 1;
+        let mut ts = TileState::default();
+        ts.msac = MsacContext::new(&[0xff; 8], false);
+        let (mut a, mut l) = (BlockContext::default(), BlockContext::default());
+        let skip_mode = read_skip_mode(&frame_hdr, &mut ts, &mut a, &mut l, 1, 0, 0, 4, 4);
+        assert!(!skip_mode);
+        assert_eq!(ts.msac, MsacContext::new(&[0xff; 8], false));
+    }
+
+    // the inverse of read_restoration_info(), returning the unit with the
+    // fields it doesn't code taken from the reference unit
+    fn write_restoration_info(
+        w: &mut crate::msac::test::MsacWriter,
+        cdf: &mut CdfContext,
+        lr_ref: &mut [Av1RestorationUnit; 3],
+        lr: &Av1RestorationUnit,
+        p: usize,
+        frame_type: RestorationType,
+    ) -> Av1RestorationUnit {
+        use RestorationType::*;
+        if frame_type == RESTORATION_SWITCHABLE {
+            let filter = match lr.t {
+                RESTORATION_NONE => 0,
+                RESTORATION_WIENER => 1,
+                _ => 2,
+            };
+            w.encode_symbol_adapt(filter, &mut cdf.m.restore_switchable, 2);
+        } else if frame_type == RESTORATION_WIENER {
+            w.encode_bool_adapt(lr.t != RESTORATION_NONE, &mut cdf.m.restore_wiener);
+        } else {
+            w.encode_bool_adapt(lr.t != RESTORATION_NONE, &mut cdf.m.restore_sgrproj);
+        }
+
+        let r = &mut lr_ref[p];
+        if lr.t == RESTORATION_WIENER {
+            for (f, r) in [(lr.filter_v, r.filter_v), (lr.filter_h, r.filter_h)].iter() {
+                if p == 0 {
+                    w.encode_subexp(f[0] as i32 + 5, r[0] as i32 + 5, 16, 1);
+                }
+                w.encode_subexp(f[1] as i32 + 23, r[1] as i32 + 23, 32, 2);
+                w.encode_subexp(f[2] as i32 + 17, r[2] as i32 + 17, 64, 3);
+            }
+            *r = Av1RestorationUnit {
+                sgr_weights: r.sgr_weights,
+                ..*lr
+            };
+            *r
+        } else if lr.t == RESTORATION_SGRPROJ {
+            w.encode_bools(lr.sgr_idx as u32, 4);
+            let params = &sgr_params[lr.sgr_idx as usize];
+            if params[0] != 0 {
+                let (x, r) = (lr.sgr_weights[0] as i32 + 96, r.sgr_weights[0] as i32 + 96);
+                w.encode_subexp(x, r, 128, 4);
+            }
+            if params[1] != 0 {
+                let (x, r) = (lr.sgr_weights[1] as i32 + 32, r.sgr_weights[1] as i32 + 32);
+                w.encode_subexp(x, r, 128, 4);
+            }
+            *r = Av1RestorationUnit {
+                filter_v: r.filter_v,
+                filter_h: r.filter_h,
+                ..*lr
+            };
+            *r
+        } else {
+            *lr
+        }
+    }
+
+    #[test]
+    fn restoration_units_per_64x64_unit() {
+        use crate::msac::test::MsacWriter;
+        use RestorationType::*;
+
+        // a 256x128 4:2:0 frame of 64x64 superblocks and restoration units,
+        // whose 2x1 128x128 areas hold 4 luma units each and 1 unit per
+        // chroma plane
+        let mut seq_hdr = SequenceHeader::default();
+        seq_hdr.layout = PixelLayout::PIXEL_LAYOUT_I420;
+        let mut frame_hdr = FrameHeader::default();
+        frame_hdr.width = [256, 256];
+        frame_hdr.height = 128;
+        frame_hdr.tiling.cols = 1;
+        frame_hdr.tiling.rows = 1;
+        frame_hdr.tiling.col_start_sb[1] = 4;
+        frame_hdr.tiling.row_start_sb[1] = 2;
+        frame_hdr.restoration.t = [
+            RESTORATION_SWITCHABLE,
+            RESTORATION_WIENER,
+            RESTORATION_SGRPROJ,
+        ];
+        frame_hdr.restoration.unit_size = [6, 6];
+
+        let wiener = |v: [i16; 3], h: [i16; 3]| Av1RestorationUnit {
+            t: RESTORATION_WIENER,
+            filter_v: v,
+            filter_h: h,
+            ..Default::default()
+        };
+        let sgr = |idx: u8, w: [i16; 2]| Av1RestorationUnit {
+            t: RESTORATION_SGRPROJ,
+            sgr_idx: idx,
+            sgr_weights: w,
+            ..Default::default()
+        };
+        let none = Av1RestorationUnit::default();
+        // luma units in raster order, then the chroma ones left to right
+        let y_units = [
+            wiener([-5, 8, 46], [10, -23, -17]),
+            none,
+            sgr(3, [31, -32]),
+            sgr(11, [0, 95]),
+            none,
+            wiener([0, 0, 0], [3, -7, 15]),
+            sgr(15, [-96, 95]),
+            wiener([1, 2, 3], [-1, -2, -3]),
+        ];
+        let u_units = [none, wiener([0, -20, 40], [0, 5, -10])];
+        let v_units = [sgr(0, [-50, 60]), none];
+
+        // the units are coded ahead of the superblock they start in, the
+        // chroma ones only in the superblocks at multiples of 128 luma px
+        let lr_ref = Av1RestorationUnit {
+            filter_v: [3, -7, 15],
+            filter_h: [3, -7, 15],
+            sgr_weights: [-32, 31],
+            ..Default::default()
+        };
+        let mut lr_ref = [lr_ref; 3];
+        let mut cdf = CdfContext::default();
+        let mut w = MsacWriter::new(false);
+        let mut expected = vec![Av1Restoration::default(); 2];
+        for sby in 0..2 {
+            for sbx in 0..4 {
+                let lr = &y_units[sby * 4 + sbx];
+                let t = frame_hdr.restoration.t[0];
+                expected[sbx >> 1].lr[0][sby * 2 + (sbx & 1)] =
+                    write_restoration_info(&mut w, &mut cdf, &mut lr_ref, lr, 0, t);
+                if sby == 0 && sbx & 1 == 0 {
+                    for (p, lr) in [(1, &u_units[sbx >> 1]), (2, &v_units[sbx >> 1])].iter() {
+                        let t = frame_hdr.restoration.t[*p];
+                        expected[sbx >> 1].lr[*p][0] =
+                            write_restoration_info(&mut w, &mut cdf, &mut lr_ref, lr, *p, t);
+                    }
+                }
+            }
+        }
+        let data = w.data();
+
+        let mut ts = TileState::default();
+        let in_cdf = CdfContext::default();
+        setup_tile(
+            &seq_hdr, &frame_hdr, &in_cdf, &data, 0, 0, 0, 4, 64, 32, 1, &mut ts,
+        );
+        let mut t = TileContext::default();
+        let mut lr_mask = vec![Av1Restoration::default(); 2];
+        for sby in 0..2 {
+            t.by = sby << 4;
+            let (seq_hdr, frame_hdr) = (&seq_hdr, &frame_hdr);
+            decode_tile_sbrow(seq_hdr, frame_hdr, &mut t, &mut ts, &mut lr_mask, 16, 0, 1).unwrap();
+        }
+        assert_eq!(lr_mask, expected);
+        assert_eq!(ts.lr_ref, lr_ref);
+        assert_eq!(ts.cdf, cdf);
+        assert!(ts.msac.cnt >= -15);
+    }
+
+    #[test]
+    fn superres_upscaling() {
+        // a 208x100 frame coded at 8 / 13 of its width, 128 px, so that
+        // the filter reaches past both edges of the coded picture
+        let mut seq_hdr = SequenceHeader::default();
+        seq_hdr.layout = PixelLayout::PIXEL_LAYOUT_I420;
+        let mut frame_hdr = FrameHeader::default();
+        frame_hdr.super_res.enabled = true;
+        frame_hdr.super_res.width_scale_denominator = 13;
+        frame_hdr.width = [128, 208];
+        frame_hdr.height = 100;
+        let mut f = FrameContext::<u8>::default();
+        alloc_picture(&mut f, &seq_hdr, &frame_hdr);
+        // the steps are (128 << 14) / 208 and (64 << 14) / 104 rounded,
+        // with the errors of -96 and -48 over the whole row taken half
+        // off the initial positions
+        assert_eq!(f.resize_step, [10082, 10082]);
+        assert_eq!(f.resize_start, [13410, 13386]);
+
+        // noise in the picture, zeros in the padding around it, which the
+        // filter must not see
+        let pixel = |pl: usize, x: usize, y: usize| {
+            let i = ((pl * 128 + y) * 128 + x) as u32;
+            (i.wrapping_mul(2654435761) >> 24) as u8 | 16
+        };
+        for (pl, p) in f.cur.as_mut().unwrap().planes.iter_mut().enumerate() {
+            let ss = (pl != 0) as usize;
+            let (stride, origin) = (p.cfg.stride, p.cfg.yorigin * p.cfg.stride + p.cfg.xorigin);
+            p.data.iter_mut().for_each(|v| *v = 0);
+            for y in 0..(100 + ss) >> ss {
+                for x in 0..128 >> ss {
+                    p.data[origin + y * stride + x] = pixel(pl, x, y);
+                }
+            }
+        }
+        f.seq_hdr = Some(Rc::new(seq_hdr));
+        f.frame_hdr = Some(Rc::new(frame_hdr));
+        for sby in 0..f.sbh {
+            filter_sbrow(&mut f, sby);
+        }
+
+        // 7.16 "Upscaling process"
+        let upscale = |pl: usize, x: i32, y: usize| {
+            let ss = (pl != 0) as i32;
+            let (down_w, up_w) = ((128 + ss) >> ss, (208 + ss) >> ss);
+            let step = ((down_w << 14) + up_w / 2) / up_w;
+            let err = up_w * step - (down_w << 14);
+            let x0 = (-((up_w - down_w) << 13) + up_w / 2) / up_w + (1 << 7) - err / 2;
+            let src_x = -(1 << 14) + (x0 & 0x3fff) + x * step;
+            let (src_px, subpel) = (src_x >> 14, (src_x & 0x3fff) >> 8);
+            let max_x = (32 >> ss) * 4 - 1;
+            let sum: i32 = (0..8)
+                .map(|k| {
+                    let sample_x = clip(src_px + k as i32 - 3, 0, max_x) as usize;
+                    resize_filter[subpel as usize][k] as i32 * pixel(pl, sample_x, y) as i32
+                })
+                .sum();
+            clip((sum + 64) >> 7, 0, 255) as u8
+        };
+        for (pl, p) in f.sr_cur.as_ref().unwrap().planes.iter().enumerate() {
+            let ss = (pl != 0) as usize;
+            for y in 0..(100 + ss) >> ss {
+                for x in 0..(208 + ss) >> ss {
+                    let msg = format!("plane {} ({}, {})", pl, x, y);
+                    assert_eq!(p.p(x, y), upscale(pl, x as i32, y), "{}", msg);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+// Errors reported by the decoder. An error fails the frame being decoded
+// only, the Context stays usable and decoding can resume with a later key
+// frame.
+
+use std::{error, fmt, io};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    // a coding tool or decoder mode that isn't implemented
+    Unsupported(&'static str),
+    // the bitstream doesn't conform to the specification; bit_pos is the
+    // position in the packet at which the error was detected, or the start
+    // of the OBU if it isn't known more precisely
+    InvalidBitstream {
+        obu_type: u32,
+        bit_pos: usize,
+        reason: &'static str,
+    },
+    // a limit of the decoder configuration was exceeded
+    ResourceLimit(&'static str),
+}
+
+impl DecodeError {
+    pub(crate) fn invalid(reason: &'static str) -> Self {
+        DecodeError::InvalidBitstream {
+            obu_type: 0,
+            bit_pos: 0,
+            reason,
+        }
+    }
+
+    // Locate an error raised while parsing the OBU of type obu_type, which
+    // starts at byte offset of the packet. Positions are relative to the
+    // start of the OBU until then.
+    pub(crate) fn in_obu(self, obu_type: u32, offset: usize) -> Self {
+        match self {
+            DecodeError::InvalidBitstream {
+                bit_pos, reason, ..
+            } => DecodeError::InvalidBitstream {
+                obu_type,
+                bit_pos: offset * 8 + bit_pos,
+                reason,
+            },
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Unsupported(feature) => write!(f, "unsupported: {}", feature),
+            DecodeError::InvalidBitstream {
+                obu_type,
+                bit_pos,
+                reason,
+            } => write!(
+                f,
+                "invalid bitstream: {} (OBU type {}, bit {})",
+                reason, obu_type, bit_pos
+            ),
+            DecodeError::ResourceLimit(limit) => write!(f, "resource limit: {}", limit),
+        }
+    }
+}
+
+impl error::Error for DecodeError {}
+
+impl From<DecodeError> for io::Error {
+    fn from(e: DecodeError) -> Self {
+        let kind = match e {
+            DecodeError::Unsupported(_) => io::ErrorKind::Other,
+            DecodeError::InvalidBitstream { .. } => io::ErrorKind::InvalidData,
+            DecodeError::ResourceLimit(_) => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn io_error() {
+        let e = DecodeError::invalid("reason").in_obu(6, 10);
+        assert_eq!(
+            e,
+            DecodeError::InvalidBitstream {
+                obu_type: 6,
+                bit_pos: 80,
+                reason: "reason",
+            }
+        );
+        let io_e = io::Error::from(e);
+        assert_eq!(io_e.kind(), io::ErrorKind::InvalidData);
+        let inner = io_e.get_ref().unwrap().downcast_ref::<DecodeError>();
+        assert_eq!(inner, Some(&e));
+        assert_eq!(
+            io_e.to_string(),
+            "invalid bitstream: reason (OBU type 6, bit 80)"
+        );
+
+        for &e in [
+            DecodeError::Unsupported("feature"),
+            DecodeError::ResourceLimit("limit"),
+        ]
+        .iter()
+        {
+            // only bitstream errors are located
+            assert_eq!(e.in_obu(6, 10), e);
+            assert_eq!(io::Error::from(e).kind(), io::ErrorKind::Other);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use crate::filmgrain::*;
+use crate::frame::Frame;
+use crate::headers::*;
+use crate::plane::Plane;
+use crate::util::*;
+
+use std::cmp;
+
+#[inline(always)]
+fn pixel_offset<T: Pixel>(p: &Plane<T>, x: usize, y: usize) -> usize {
+    (p.cfg.yorigin + y) * p.cfg.stride + p.cfg.xorigin + x
+}
+
+// Generate the grain templates and scaling functions of all planes from
+// the film grain parameters, see 7.18.3.3 and 7.18.3.4.
+fn prep_grain(
+    data: &FilmGrainData,
+    ss_x: usize,
+    ss_y: usize,
+    bitdepth_max: i32,
+) -> (Vec<GrainLut>, [Vec<u8>; 3]) {
+    let bitdepth = (32 - bitdepth_max.leading_zeros()) as i32;
+    let mut grain_lut = vec![[[0; GRAIN_WIDTH]; GRAIN_HEIGHT]; 3];
+    let mut scaling = [
+        vec![0; 1 << bitdepth],
+        vec![0; 1 << bitdepth],
+        vec![0; 1 << bitdepth],
+    ];
+
+    // the luma grain is also an input to the chroma auto-regression
+    let (lut_y, lut_uv) = grain_lut.split_at_mut(1);
+    generate_grain_y(&mut lut_y[0], data, bitdepth_max);
+    for pl in 0..2 {
+        if data.num_uv_points[pl] != 0 || data.chroma_scaling_from_luma != 0 {
+            generate_grain_uv(
+                &mut lut_uv[pl],
+                &lut_y[0],
+                data,
+                pl,
+                ss_x,
+                ss_y,
+                bitdepth_max,
+            );
+        }
+    }
+
+    if data.num_y_points != 0 || data.chroma_scaling_from_luma != 0 {
+        generate_scaling(
+            bitdepth,
+            &data.y_points,
+            data.num_y_points as usize,
+            &mut scaling[0],
+        );
+    }
+    for pl in 0..2 {
+        if data.num_uv_points[pl] != 0 {
+            generate_scaling(
+                bitdepth,
+                &data.uv_points[pl],
+                data.num_uv_points[pl] as usize,
+                &mut scaling[1 + pl],
+            );
+        }
+    }
+
+    (grain_lut, scaling)
+}
+
+// Return a copy of the output picture input with film grain applied, see
+// 7.18.3. The grain is never applied to input itself, since the picture
+// may still be used as a reference.
+pub(crate) fn apply_grain<T: Pixel>(
+    input: &Frame<T>,
+    data: &FilmGrainData,
+    seq_hdr: &SequenceHeader,
+) -> Frame<T> {
+    let bitdepth_max = seq_hdr.bitdepth_max();
+    let ss_x = seq_hdr.ss_hor as usize;
+    let ss_y = seq_hdr.ss_ver as usize;
+    let is_id = seq_hdr.mtrx == MatrixCoefficients::MC_IDENTITY;
+    let has_chroma = seq_hdr.layout != PixelLayout::PIXEL_LAYOUT_I400;
+
+    let (grain_lut, scaling) = prep_grain(data, ss_x, ss_y, bitdepth_max);
+
+    let mut out = input.clone();
+    let w = input.width;
+    let h = input.height;
+    let cpw = (w + ss_x) >> ss_x;
+
+    // apply the grain in rows of 32 luma px high blocks
+    for row in 0..(h + BLOCK_SIZE - 1) / BLOCK_SIZE {
+        let y = row * BLOCK_SIZE;
+        let bh = cmp::min(BLOCK_SIZE, h - y);
+
+        let luma = &input.planes[0];
+        let luma_off = pixel_offset(luma, 0, y);
+        if data.num_y_points != 0 {
+            fgy_32x32xn(
+                &mut out.planes[0].data,
+                luma_off,
+                &luma.data,
+                luma_off,
+                luma.cfg.stride,
+                data,
+                w,
+                &scaling[0],
+                &grain_lut[0],
+                bh,
+                row,
+                bitdepth_max,
+            );
+        }
+
+        if !has_chroma {
+            continue;
+        }
+
+        for pl in 0..2 {
+            if data.num_uv_points[pl] == 0 && data.chroma_scaling_from_luma == 0 {
+                continue;
+            }
+
+            let src = &input.planes[1 + pl];
+            let off = pixel_offset(src, 0, y >> ss_y);
+            let scaling = if data.chroma_scaling_from_luma != 0 {
+                &scaling[0]
+            } else {
+                &scaling[1 + pl]
+            };
+            fguv_32x32xn(
+                &mut out.planes[1 + pl].data,
+                off,
+                &src.data,
+                off,
+                src.cfg.stride,
+                data,
+                cpw,
+                scaling,
+                &grain_lut[1 + pl],
+                (bh + ss_y) >> ss_y,
+                row,
+                &luma.data,
+                luma_off,
+                luma.cfg.stride,
+                w,
+                pl,
+                is_id,
+                ss_x,
+                ss_y,
+                bitdepth_max,
+            );
+        }
+    }
+
+    out
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::ChromaSampling;
+    use crate::filmgrain::test::grain_params;
+
+    const W: usize = 72;
+    const H: usize = 48;
+
+    // apply the grain to a 4:2:0 frame with two rows of blocks, the last
+    // of which is only partially covered, and return the sum and absolute
+    // sum of the noise added to each plane
+    fn grain_sums<T: Pixel>(hbd: u32) -> [[i32; 2]; 3] {
+        let seq_hdr = SequenceHeader {
+            hbd,
+            ss_hor: 1,
+            ss_ver: 1,
+            layout: PixelLayout::PIXEL_LAYOUT_I420,
+            mtrx: MatrixCoefficients::MC_BT709,
+            ..Default::default()
+        };
+        let bitdepth_max = seq_hdr.bitdepth_max() as usize;
+
+        let mut input = Frame::<T>::new(W, H, ChromaSampling::Cs420);
+        for (pl, p) in input.planes.iter_mut().enumerate() {
+            let (w, h) = (W >> (pl != 0) as usize, H >> (pl != 0) as usize);
+            for y in 0..h {
+                for x in 0..w {
+                    let off = pixel_offset(p, x, y);
+                    p.data[off] = T::cast_from(((x * 29 + y * 53 + pl * 71) * 7) & bitdepth_max);
+                }
+            }
+        }
+
+        let out = apply_grain(&input, &grain_params(), &seq_hdr);
+        let mut sums = [[0; 2]; 3];
+        for (pl, (p, src)) in out.planes.iter().zip(input.planes.iter()).enumerate() {
+            let (w, h) = (W >> (pl != 0) as usize, H >> (pl != 0) as usize);
+            for y in 0..h {
+                for x in 0..w {
+                    let px = i32::cast_from(p.data[pixel_offset(p, x, y)]);
+                    let src_px = i32::cast_from(src.data[pixel_offset(src, x, y)]);
+                    assert!((0..=bitdepth_max as i32).contains(&px));
+                    sums[pl][0] += px - src_px;
+                    sums[pl][1] += (px - src_px).abs();
+                }
+            }
+        }
+
+        sums
+    }
+
+    #[test]
+    fn apply_grain_8bpc() {
+        assert_eq!(grain_sums::<u8>(0), [[70, 9614], [-45, 1459], [38, 2418]]);
+    }
+
+    #[test]
+    fn apply_grain_10bpc() {
+        assert_eq!(
+            grain_sums::<u16>(1),
+            [[602, 38686], [-170, 5816], [129, 9693]]
+        );
+    }
+}
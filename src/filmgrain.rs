@@ -0,0 +1,583 @@
+use crate::headers::*;
+use crate::tables::*;
+use crate::util::*;
+
+use std::cmp;
+
+pub(crate) const GRAIN_WIDTH: usize = 82;
+pub(crate) const GRAIN_HEIGHT: usize = 73;
+pub(crate) const SUB_GRAIN_WIDTH: usize = 44;
+pub(crate) const SUB_GRAIN_HEIGHT: usize = 38;
+pub(crate) const BLOCK_SIZE: usize = 32;
+
+pub(crate) type GrainLut = [[i16; GRAIN_WIDTH]; GRAIN_HEIGHT];
+
+#[inline(always)]
+fn round2(x: i32, shift: i32) -> i32 {
+    if shift == 0 {
+        x
+    } else {
+        (x + (1 << (shift - 1))) >> shift
+    }
+}
+
+// see 7.18.3.2 random number process
+#[inline(always)]
+pub(crate) fn get_random_number(bits: i32, state: &mut u32) -> i32 {
+    let r = *state;
+    let bit = ((r >> 0) ^ (r >> 1) ^ (r >> 3) ^ (r >> 12)) & 1;
+    *state = (r >> 1) | (bit << 15);
+
+    ((*state >> (16 - bits)) & ((1 << bits) - 1)) as i32
+}
+
+fn grain_min_max(bitdepth_max: i32) -> (i32, i32) {
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+    let grain_ctr = 128 << bitdepth_min_8;
+
+    (-grain_ctr, grain_ctr - 1)
+}
+
+// see 7.18.3.3 generate grain process, luma part
+pub(crate) fn generate_grain_y(buf: &mut GrainLut, data: &FilmGrainData, bitdepth_max: i32) {
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+    let mut seed = data.seed as u32;
+    let shift = 4 - bitdepth_min_8 + data.grain_scale_shift as i32;
+    let (grain_min, grain_max) = grain_min_max(bitdepth_max);
+
+    for row in buf.iter_mut() {
+        for px in row.iter_mut() {
+            let value = get_random_number(11, &mut seed) as usize;
+            *px = round2(gaussian_sequence[value] as i32, shift) as i16;
+        }
+    }
+
+    let ar_pad = 3;
+    let ar_lag = data.ar_coeff_lag as isize;
+
+    for y in ar_pad..GRAIN_HEIGHT {
+        for x in ar_pad..GRAIN_WIDTH - ar_pad {
+            let mut coeff = data.ar_coeffs_y.iter();
+            let mut sum = 0;
+            'ar: for dy in -ar_lag..=0 {
+                for dx in -ar_lag..=ar_lag {
+                    if dx == 0 && dy == 0 {
+                        break 'ar;
+                    }
+                    let yy = (y as isize + dy) as usize;
+                    let xx = (x as isize + dx) as usize;
+                    sum += *coeff.next().unwrap() as i32 * buf[yy][xx] as i32;
+                }
+            }
+
+            let grain = buf[y][x] as i32 + round2(sum, data.ar_coeff_shift as i32);
+            buf[y][x] = clip(grain, grain_min, grain_max) as i16;
+        }
+    }
+}
+
+// see 7.18.3.3 generate grain process, chroma part; the luma grain
+// contributes to the auto-regression when luma scaling points are coded
+pub(crate) fn generate_grain_uv(
+    buf: &mut GrainLut,
+    buf_y: &GrainLut,
+    data: &FilmGrainData,
+    uv: usize,
+    ss_x: usize,
+    ss_y: usize,
+    bitdepth_max: i32,
+) {
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+    let mut seed = data.seed as u32 ^ if uv != 0 { 0x49d8 } else { 0xb524 };
+    let shift = 4 - bitdepth_min_8 + data.grain_scale_shift as i32;
+    let (grain_min, grain_max) = grain_min_max(bitdepth_max);
+
+    let chroma_w = if ss_x != 0 {
+        SUB_GRAIN_WIDTH
+    } else {
+        GRAIN_WIDTH
+    };
+    let chroma_h = if ss_y != 0 {
+        SUB_GRAIN_HEIGHT
+    } else {
+        GRAIN_HEIGHT
+    };
+
+    for row in buf[..chroma_h].iter_mut() {
+        for px in row[..chroma_w].iter_mut() {
+            let value = get_random_number(11, &mut seed) as usize;
+            *px = round2(gaussian_sequence[value] as i32, shift) as i16;
+        }
+    }
+
+    let ar_pad = 3;
+    let ar_lag = data.ar_coeff_lag as isize;
+
+    for y in ar_pad..chroma_h {
+        for x in ar_pad..chroma_w - ar_pad {
+            let mut coeff = data.ar_coeffs_uv[uv].iter();
+            let mut sum = 0;
+            'ar: for dy in -ar_lag..=0 {
+                for dx in -ar_lag..=ar_lag {
+                    if dx == 0 && dy == 0 {
+                        if data.num_y_points == 0 {
+                            break 'ar;
+                        }
+                        let luma_x = ((x - ar_pad) << ss_x) + ar_pad;
+                        let luma_y = ((y - ar_pad) << ss_y) + ar_pad;
+                        let mut luma = 0;
+                        for i in 0..=ss_y {
+                            for j in 0..=ss_x {
+                                luma += buf_y[luma_y + i][luma_x + j] as i32;
+                            }
+                        }
+                        luma = round2(luma, (ss_x + ss_y) as i32);
+
+                        sum += luma * *coeff.next().unwrap() as i32;
+                        break 'ar;
+                    }
+                    let yy = (y as isize + dy) as usize;
+                    let xx = (x as isize + dx) as usize;
+                    sum += *coeff.next().unwrap() as i32 * buf[yy][xx] as i32;
+                }
+            }
+
+            let grain = buf[y][x] as i32 + round2(sum, data.ar_coeff_shift as i32);
+            buf[y][x] = clip(grain, grain_min, grain_max) as i16;
+        }
+    }
+}
+
+// see 7.18.3.4 scaling lookup initialization process; for high bit depths
+// the 8-bit piecewise-linear function is interpolated to every input value
+pub(crate) fn generate_scaling(bitdepth: i32, points: &[[u8; 2]], num: usize, scaling: &mut [u8]) {
+    let shift_x = bitdepth as usize - 8;
+    let scaling_size = 1 << bitdepth;
+
+    if num == 0 {
+        for s in scaling[..scaling_size].iter_mut() {
+            *s = 0;
+        }
+        return;
+    }
+
+    // fill up the preceding entries with the initial value
+    let n = (points[0][0] as usize) << shift_x;
+    for s in scaling[..n].iter_mut() {
+        *s = points[0][1];
+    }
+
+    // linearly interpolate the values in the middle
+    for i in 0..num - 1 {
+        let bx = points[i][0] as i32;
+        let by = points[i][1] as i32;
+        let ex = points[i + 1][0] as i32;
+        let ey = points[i + 1][1] as i32;
+        let dx = ex - bx;
+        let dy = ey - by;
+        debug_assert!(dx > 0);
+        let delta = dy * ((0x10000 + (dx >> 1)) / dx);
+        let mut d = 0x8000;
+        for x in 0..dx {
+            scaling[((bx + x) as usize) << shift_x] = (by + (d >> 16)) as u8;
+            d += delta;
+        }
+    }
+
+    // fill up the remaining entries with the final value
+    let n = (points[num - 1][0] as usize) << shift_x;
+    for s in scaling[n..scaling_size].iter_mut() {
+        *s = points[num - 1][1];
+    }
+
+    if bitdepth != 8 {
+        let pad = 1 << shift_x;
+        let rnd = pad >> 1;
+        for i in 0..num - 1 {
+            let bx = (points[i][0] as usize) << shift_x;
+            let ex = (points[i + 1][0] as usize) << shift_x;
+            for x in (0..ex - bx).step_by(pad) {
+                let range = scaling[bx + x + pad] as i32 - scaling[bx + x] as i32;
+                let mut r = rnd as i32;
+                for n in 1..pad {
+                    r += range;
+                    scaling[bx + x + n] = (scaling[bx + x] as i32 + (r >> shift_x)) as u8;
+                }
+            }
+        }
+    }
+}
+
+#[inline(always)]
+fn sample_lut(
+    grain_lut: &GrainLut,
+    offsets: &[[i32; 2]; 2],
+    ss_x: usize,
+    ss_y: usize,
+    bx: usize,
+    by: usize,
+    x: usize,
+    y: usize,
+) -> i32 {
+    let randval = offsets[bx][by];
+    let offx = 3 + (2 >> ss_x) * (3 + (randval >> 4)) as usize;
+    let offy = 3 + (2 >> ss_y) * (3 + (randval & 0xF)) as usize;
+
+    grain_lut[offy + y + (BLOCK_SIZE >> ss_y) * by][offx + x + (BLOCK_SIZE >> ss_x) * bx] as i32
+}
+
+// per 32 luma px row seeds of the current and, for vertical overlap, the
+// previous block row
+fn row_seeds(data: &FilmGrainData, row_num: usize, rows: usize) -> [u32; 2] {
+    let mut seed = [0; 2];
+    for i in 0..rows {
+        let row = (row_num - i) as u32;
+        seed[i] = data.seed as u32;
+        seed[i] ^= ((row * 37 + 178) & 0xFF) << 8;
+        seed[i] ^= (row * 173 + 105) & 0xFF;
+    }
+
+    seed
+}
+
+// blend weights across block boundaries, indexed by subsampling and the
+// distance to the boundary
+static OVERLAP_WEIGHTS: [[[i32; 2]; 2]; 2] = [[[27, 17], [17, 27]], [[23, 22], [0, 0]]];
+
+// Blend the grain of the neighbouring blocks into the first rows and
+// columns of a block when overlap is enabled and call add_noise on every
+// pixel of the bw x bh block.
+fn blend_block<F: FnMut(usize, usize, i32)>(
+    grain_lut: &GrainLut,
+    offsets: &[[i32; 2]; 2],
+    ss_x: usize,
+    ss_y: usize,
+    xstart: usize,
+    ystart: usize,
+    bw: usize,
+    bh: usize,
+    bitdepth_max: i32,
+    mut add_noise: F,
+) {
+    let (grain_min, grain_max) = grain_min_max(bitdepth_max);
+    let w = &OVERLAP_WEIGHTS;
+    let blend = |old: i32, grain: i32, w: &[i32; 2]| {
+        clip(round2(old * w[0] + grain * w[1], 5), grain_min, grain_max)
+    };
+
+    // main body of the block
+    for y in ystart..bh {
+        for x in xstart..bw {
+            let grain = sample_lut(grain_lut, offsets, ss_x, ss_y, 0, 0, x, y);
+            add_noise(x, y, grain);
+        }
+    }
+
+    // special case for the overlapped column
+    for y in ystart..bh {
+        for x in 0..xstart {
+            let grain = sample_lut(grain_lut, offsets, ss_x, ss_y, 0, 0, x, y);
+            let old = sample_lut(grain_lut, offsets, ss_x, ss_y, 1, 0, x, y);
+            add_noise(x, y, blend(old, grain, &w[ss_x][x]));
+        }
+    }
+
+    for y in 0..ystart {
+        // special case for the overlapped row (sans corner)
+        for x in xstart..bw {
+            let grain = sample_lut(grain_lut, offsets, ss_x, ss_y, 0, 0, x, y);
+            let old = sample_lut(grain_lut, offsets, ss_x, ss_y, 0, 1, x, y);
+            add_noise(x, y, blend(old, grain, &w[ss_y][y]));
+        }
+
+        // special case for the doubly-overlapped corner
+        for x in 0..xstart {
+            // blend the top pixel with the top left block
+            let top = sample_lut(grain_lut, offsets, ss_x, ss_y, 0, 1, x, y);
+            let old = sample_lut(grain_lut, offsets, ss_x, ss_y, 1, 1, x, y);
+            let top = blend(old, top, &w[ss_x][x]);
+
+            // blend the current pixel with the left block
+            let grain = sample_lut(grain_lut, offsets, ss_x, ss_y, 0, 0, x, y);
+            let old = sample_lut(grain_lut, offsets, ss_x, ss_y, 1, 0, x, y);
+            let grain = blend(old, grain, &w[ss_x][x]);
+
+            // mix the two rows together and apply grain
+            add_noise(x, y, blend(top, grain, &w[ss_y][y]));
+        }
+    }
+}
+
+// see 7.18.3.5 add noise synthesis process, luma part, for a row of 32 px
+// high blocks starting at src_off/dst_off
+pub(crate) fn fgy_32x32xn<T: Pixel>(
+    dst: &mut [T],
+    dst_off: usize,
+    src: &[T],
+    src_off: usize,
+    stride: usize,
+    data: &FilmGrainData,
+    pw: usize,
+    scaling: &[u8],
+    grain_lut: &GrainLut,
+    bh: usize,
+    row_num: usize,
+    bitdepth_max: i32,
+) {
+    let overlap = data.overlap_flag != 0;
+    let rows = 1 + (overlap && row_num > 0) as usize;
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+
+    let (min_value, max_value) = if data.clip_to_restricted_range != 0 {
+        (16 << bitdepth_min_8, 235 << bitdepth_min_8)
+    } else {
+        (0, bitdepth_max)
+    };
+
+    let mut seed = row_seeds(data, row_num, rows);
+    let mut offsets = [[0; 2]; 2];
+
+    for bx in (0..pw).step_by(BLOCK_SIZE) {
+        let bw = cmp::min(BLOCK_SIZE, pw - bx);
+
+        if overlap && bx != 0 {
+            // shift previous offsets left
+            for i in 0..rows {
+                offsets[1][i] = offsets[0][i];
+            }
+        }
+
+        // update current offsets
+        for i in 0..rows {
+            offsets[0][i] = get_random_number(8, &mut seed[i]);
+        }
+
+        let ystart = if overlap && row_num != 0 {
+            cmp::min(2, bh)
+        } else {
+            0
+        };
+        let xstart = if overlap && bx != 0 {
+            cmp::min(2, bw)
+        } else {
+            0
+        };
+
+        blend_block(
+            grain_lut,
+            &offsets,
+            0,
+            0,
+            xstart,
+            ystart,
+            bw,
+            bh,
+            bitdepth_max,
+            |x, y, grain| {
+                let src = i32::cast_from(src[src_off + y * stride + bx + x]);
+                let noise = round2(
+                    scaling[src as usize] as i32 * grain,
+                    data.scaling_shift as i32,
+                );
+                dst[dst_off + y * stride + bx + x] =
+                    T::cast_from(clip(src + noise, min_value, max_value));
+            },
+        );
+    }
+}
+
+// see 7.18.3.5 add noise synthesis process, chroma part; luma holds the
+// un-grained luma rows co-located with the chroma block row, luma_w is the
+// visible luma width
+pub(crate) fn fguv_32x32xn<T: Pixel>(
+    dst: &mut [T],
+    dst_off: usize,
+    src: &[T],
+    src_off: usize,
+    stride: usize,
+    data: &FilmGrainData,
+    pw: usize,
+    scaling: &[u8],
+    grain_lut: &GrainLut,
+    bh: usize,
+    row_num: usize,
+    luma: &[T],
+    luma_off: usize,
+    luma_stride: usize,
+    luma_w: usize,
+    uv: usize,
+    is_id: bool,
+    ss_x: usize,
+    ss_y: usize,
+    bitdepth_max: i32,
+) {
+    let overlap = data.overlap_flag != 0;
+    let rows = 1 + (overlap && row_num > 0) as usize;
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+
+    let (min_value, max_value) = if data.clip_to_restricted_range != 0 {
+        (
+            16 << bitdepth_min_8,
+            (if is_id { 235 } else { 240 }) << bitdepth_min_8,
+        )
+    } else {
+        (0, bitdepth_max)
+    };
+
+    let mut seed = row_seeds(data, row_num, rows);
+    let mut offsets = [[0; 2]; 2];
+
+    for bx in (0..pw).step_by(BLOCK_SIZE >> ss_x) {
+        let bw = cmp::min(BLOCK_SIZE >> ss_x, pw - bx);
+
+        if overlap && bx != 0 {
+            // shift previous offsets left
+            for i in 0..rows {
+                offsets[1][i] = offsets[0][i];
+            }
+        }
+
+        // update current offsets
+        for i in 0..rows {
+            offsets[0][i] = get_random_number(8, &mut seed[i]);
+        }
+
+        let ystart = if overlap && row_num != 0 {
+            cmp::min(2 >> ss_y, bh)
+        } else {
+            0
+        };
+        let xstart = if overlap && bx != 0 {
+            cmp::min(2 >> ss_x, bw)
+        } else {
+            0
+        };
+
+        blend_block(
+            grain_lut,
+            &offsets,
+            ss_x,
+            ss_y,
+            xstart,
+            ystart,
+            bw,
+            bh,
+            bitdepth_max,
+            |x, y, grain| {
+                let lx = (bx + x) << ss_x;
+                let ly = y << ss_y;
+                let l = luma_off + ly * luma_stride + lx;
+                let mut avg = i32::cast_from(luma[l]);
+                if ss_x != 0 {
+                    // replicate the last column for odd luma widths
+                    let r = luma_off + ly * luma_stride + cmp::min(lx + 1, luma_w - 1);
+                    avg = (avg + i32::cast_from(luma[r]) + 1) >> 1;
+                }
+
+                let src = i32::cast_from(src[src_off + y * stride + bx + x]);
+                let val = if data.chroma_scaling_from_luma != 0 {
+                    avg
+                } else {
+                    let combined =
+                        avg * data.uv_luma_mult[uv] as i32 + src * data.uv_mult[uv] as i32;
+                    clip(
+                        (combined >> 6) + data.uv_offset[uv] as i32 * (1 << bitdepth_min_8),
+                        0,
+                        bitdepth_max,
+                    )
+                };
+
+                let noise = round2(
+                    scaling[val as usize] as i32 * grain,
+                    data.scaling_shift as i32,
+                );
+                dst[dst_off + y * stride + bx + x] =
+                    T::cast_from(clip(src + noise, min_value, max_value));
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    // grain with auto-regression over a lag of 2 in all planes, scaled
+    // by the luma as well as the chroma values
+    pub(crate) fn grain_params() -> FilmGrainData {
+        let mut data = FilmGrainData {
+            seed: 0x1234,
+            num_y_points: 3,
+            num_uv_points: [2, 2],
+            scaling_shift: 9,
+            ar_coeff_lag: 2,
+            ar_coeff_shift: 7,
+            uv_mult: [64, -32],
+            uv_luma_mult: [-64, 32],
+            uv_offset: [10, -20],
+            overlap_flag: 1,
+            ..Default::default()
+        };
+        data.y_points[..3].copy_from_slice(&[[0, 40], [128, 80], [255, 20]]);
+        data.uv_points[0][..2].copy_from_slice(&[[0, 30], [255, 50]]);
+        data.uv_points[1][..2].copy_from_slice(&[[16, 60], [200, 20]]);
+        for i in 0..12 {
+            data.ar_coeffs_y[i] = (i * 7 % 23) as i8 - 11;
+        }
+        for i in 0..13 {
+            data.ar_coeffs_uv[0][i] = (i * 5 % 19) as i8 - 9;
+            data.ar_coeffs_uv[1][i] = (i * 3 % 17) as i8 - 8;
+        }
+        data
+    }
+
+    #[test]
+    fn grain_templates() {
+        let data = grain_params();
+        // rows 0 and 3 of luma are the raw Gaussian noise and the first
+        // auto-regressed row, chroma is subsampled in both directions
+        let expected: [(i32, [[i16; 8]; 6]); 2] = [
+            (
+                255,
+                [
+                    [-29, -1, -31, -20, 49, 8, -56, 54],
+                    [-22, 73, 27, -42, -83, 3, 7, 1],
+                    [60, -23, 32, 17, 17, -38, 11, 3],
+                    [10, -23, -18, -4, 52, -42, 20, 19],
+                    [54, -14, 11, 25, 17, 60, 0, -49],
+                    [13, -39, 26, -35, 26, 6, -13, 58],
+                ],
+            ),
+            (
+                1023,
+                [
+                    [-117, -5, -125, -80, 197, 31, -225, 215],
+                    [-91, 291, 105, -170, -331, 12, 29, 2],
+                    [241, -95, 125, 65, 69, -154, 46, 12],
+                    [39, -93, -76, -18, 205, -167, 82, 76],
+                    [215, -56, 44, 97, 69, 243, -3, -197],
+                    [53, -157, 104, -140, 103, 25, -52, 233],
+                ],
+            ),
+        ];
+
+        for (bitdepth_max, rows) in expected.iter() {
+            let mut y = [[0; GRAIN_WIDTH]; GRAIN_HEIGHT];
+            let mut u = [[0; GRAIN_WIDTH]; GRAIN_HEIGHT];
+            let mut v = [[0; GRAIN_WIDTH]; GRAIN_HEIGHT];
+            generate_grain_y(&mut y, &data, *bitdepth_max);
+            generate_grain_uv(&mut u, &y, &data, 0, 1, 1, *bitdepth_max);
+            generate_grain_uv(&mut v, &y, &data, 1, 1, 1, *bitdepth_max);
+
+            assert_eq!(y[0][..8], rows[0]);
+            assert_eq!(y[3][3..11], rows[1]);
+            assert_eq!(y[GRAIN_HEIGHT - 1][71..79], rows[2]);
+            assert_eq!(u[3][3..11], rows[3]);
+            assert_eq!(u[SUB_GRAIN_HEIGHT - 1][33..41], rows[4]);
+            assert_eq!(v[3][3..11], rows[5]);
+            // nothing is generated outside of the subsampled template
+            assert!(u[SUB_GRAIN_HEIGHT..].iter().flatten().all(|&g| g == 0));
+            assert!(u.iter().all(|r| r[SUB_GRAIN_WIDTH..] == [0; GRAIN_WIDTH - SUB_GRAIN_WIDTH]));
+        }
+    }
+}
@@ -4,6 +4,9 @@ use crate::util::*;
 
 use crate::api::ChromaSampling;
 use crate::context::{MAX_SB_SIZE, SUBPEL_FILTER_SIZE};
+use crate::headers::{
+  ContentLightLevel, FilmGrainData, MasteringDisplay, Scalability, Timecode, ITUTT35,
+};
 
 use std::fmt;
 
@@ -34,8 +37,30 @@ impl fmt::Display for FrameType {
 #[derive(Debug, Clone)]
 pub struct Frame<T: Pixel> {
     pub planes: [Plane<T>; 3],
+    // visible luma dimensions, the planes are allocated 8px aligned
+    pub(crate) width: usize,
+    pub(crate) height: usize,
     pub pts: u64,
     pub frame_type: FrameType,
+    // layer of the frame in scalable streams
+    pub(crate) spatial_id: u8,
+    pub(crate) temporal_id: u8,
+    // film grain parameters of the frame, if grain is present but was not
+    // applied by the decoder, so that it can be rendered by the caller
+    pub(crate) film_grain: Option<FilmGrainData>,
+    // HDR static metadata of the sequence, as last signalled before the
+    // frame was output
+    pub(crate) content_light: Option<ContentLightLevel>,
+    pub(crate) mastering_display: Option<MasteringDisplay>,
+    // T.35 metadata received since the previous output frame
+    pub(crate) itut_t35: Vec<ITUTT35>,
+    pub(crate) timecode: Option<Timecode>,
+    // scalability structure of the sequence, if signalled
+    pub(crate) scalability: Option<Scalability>,
+    // some tiles failed to decode and were concealed, see
+    // Config::conceal_errors; the concealed areas are in luma px
+    pub(crate) corrupted: bool,
+    pub(crate) corrupted_tiles: Vec<Rect>,
 }
 
 impl<T: Pixel> Frame<T> {
@@ -71,12 +96,85 @@ impl<T: Pixel> Frame<T> {
           chroma_padding_x, chroma_padding_y
         )
       ],
+      width,
+      height,
       pts: 0,
-      frame_type: FrameType::KEY
+      frame_type: FrameType::KEY,
+      spatial_id: 0,
+      temporal_id: 0,
+      film_grain: None,
+      content_light: None,
+      mastering_display: None,
+      itut_t35: Vec::new(),
+      timecode: None,
+      scalability: None,
+      corrupted: false,
+      corrupted_tiles: Vec::new()
     }
   }
 
 
+  /// The visible width of the frame in luma pixels
+  pub fn width(&self) -> usize {
+    self.width
+  }
+
+  /// The visible height of the frame in luma pixels
+  pub fn height(&self) -> usize {
+    self.height
+  }
+
+  /// The spatial layer of the frame in scalable streams
+  pub fn spatial_id(&self) -> u8 {
+    self.spatial_id
+  }
+
+  /// The temporal layer of the frame in scalable streams
+  pub fn temporal_id(&self) -> u8 {
+    self.temporal_id
+  }
+
+  /// The film grain parameters of the frame, if grain is present but was
+  /// not applied by the decoder
+  pub fn film_grain(&self) -> Option<&FilmGrainData> {
+    self.film_grain.as_ref()
+  }
+
+  /// The content light level of the sequence, if signalled
+  pub fn content_light(&self) -> Option<&ContentLightLevel> {
+    self.content_light.as_ref()
+  }
+
+  /// The mastering display color volume of the sequence, if signalled
+  pub fn mastering_display(&self) -> Option<&MasteringDisplay> {
+    self.mastering_display.as_ref()
+  }
+
+  /// The ITU-T T.35 metadata received since the previous output frame
+  pub fn itut_t35(&self) -> &[ITUTT35] {
+    &self.itut_t35
+  }
+
+  /// The timecode of the frame, if signalled
+  pub fn timecode(&self) -> Option<&Timecode> {
+    self.timecode.as_ref()
+  }
+
+  /// The scalability structure of the sequence, if signalled
+  pub fn scalability(&self) -> Option<&Scalability> {
+    self.scalability.as_ref()
+  }
+
+  /// Whether some tiles failed to decode and were concealed
+  pub fn corrupted(&self) -> bool {
+    self.corrupted
+  }
+
+  /// The concealed areas of the frame, in luma pixels
+  pub fn corrupted_tiles(&self) -> &[Rect] {
+    &self.corrupted_tiles
+  }
+
   pub fn pad(&mut self, w: usize, h: usize) {
     for p in self.planes.iter_mut() {
       p.pad(w, h);
@@ -1,4 +1,5 @@
-use std::io;
+use crate::error::DecodeError;
+
 use std::mem;
 
 #[inline(always)]
@@ -7,7 +8,7 @@ const fn num_bits<T>() -> usize {
 }
 
 #[inline(always)]
-fn ulog2(v: u32) -> u32 {
+pub(crate) fn ulog2(v: u32) -> u32 {
     num_bits::<u32>() as u32 - 1 - v.leading_zeros()
 }
 
@@ -65,12 +66,9 @@ impl<'a> GetBits<'a> {
         self.state |= state << (64 - self.bits_left as u64);
     }
 
-    pub fn check_error(&self) -> io::Result<()> {
+    pub fn check_error(&self) -> Result<(), DecodeError> {
         if self.error {
-            Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Error parsing frame header",
-            ))
+            Err(self.invalid("Error parsing frame header"))
         } else {
             Ok(())
         }
@@ -79,13 +77,10 @@ impl<'a> GetBits<'a> {
 
     // Check that we haven't read more than obu_len bytes from the buffer
     // since init_bit_pos.
-    pub fn check_for_overrun(&self, init_bit_pos: u32, obu_len: u32) -> io::Result<()> {
+    pub fn check_for_overrun(&self, init_bit_pos: u32, obu_len: u32) -> Result<(), DecodeError> {
         // Make sure we haven't actually read past the end of the gb buffer
         if self.error {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Overrun in OBU bit buffer",
-            ));
+            return Err(self.invalid("Overrun in OBU bit buffer"));
         }
 
         let pos = self.get_bits_pos();
@@ -95,15 +90,21 @@ impl<'a> GetBits<'a> {
         debug_assert! (init_bit_pos <= pos);
 
         if pos - init_bit_pos > 8 * obu_len {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Overrun in OBU bit buffer into next OBU",
-            ));
+            return Err(self.invalid("Overrun in OBU bit buffer into next OBU"));
         }
 
         Ok(())
     }
 
+    // an error detected at the current position
+    fn invalid(&self, reason: &'static str) -> DecodeError {
+        DecodeError::InvalidBitstream {
+            obu_type: 0,
+            bit_pos: self.get_bits_pos() as usize,
+            reason,
+        }
+    }
+
     pub fn get_bits(&mut self, n: u32) -> u32 {
         debug_assert!(n <= 32 /* can go up to 57 if we change return type */);
         debug_assert!(n != 0 /* can't shift state by 64 */);
@@ -228,3 +229,50 @@ impl<'a> GetBits<'a> {
         self.ptr as u32 * 8 - self.bits_left
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    // Writes hand-built bitstreams for the parser tests, the bits of each
+    // syntax element MSB first.
+    #[derive(Default)]
+    pub(crate) struct PutBits {
+        data: Vec<u8>,
+        bits: usize,
+    }
+
+    impl PutBits {
+        pub(crate) fn put_bits(&mut self, v: u32, n: u32) -> &mut Self {
+            for i in (0..n).rev() {
+                if self.bits & 7 == 0 {
+                    self.data.push(0);
+                }
+                *self.data.last_mut().unwrap() |= ((v >> i & 1) as u8) << (7 - (self.bits & 7));
+                self.bits += 1;
+            }
+            self
+        }
+
+        pub(crate) fn put_uleb128(&mut self, mut v: u32) -> &mut Self {
+            loop {
+                let byte = v & 0x7f;
+                v >>= 7;
+                if v == 0 {
+                    return self.put_bits(byte, 8);
+                }
+                self.put_bits(byte | 0x80, 8);
+            }
+        }
+
+        // a one bit followed by zero bits up to the next byte boundary,
+        // see 5.3.4
+        pub(crate) fn trailing_bits(&mut self) -> &mut Self {
+            self.put_bits(1, 1);
+            let n = (8 - (self.bits & 7) as u32) & 7;
+            self.put_bits(0, n)
+        }
+
+        pub(crate) fn data(&self) -> Vec<u8> {
+            self.data.clone()
+        }
+    }
+}
@@ -0,0 +1,271 @@
+// HDR10+ dynamic metadata, the SMPTE ST 2094-40 application carried in
+// ITU-T T.35 metadata OBUs, see ANSI/CTA-861-G annex S.
+
+use crate::getbits::GetBits;
+use crate::headers::ITUTT35;
+
+const COUNTRY_CODE_US: u8 = 0xb5;
+const TERMINAL_PROVIDER_CODE: u32 = 0x003c;
+const TERMINAL_PROVIDER_ORIENTED_CODE: u32 = 0x0001;
+const APPLICATION_IDENTIFIER: u32 = 4;
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Hdr10PlusWindowGeometry {
+    pub upper_left_corner: [u16; 2],
+    pub lower_right_corner: [u16; 2],
+    pub center_of_ellipse: [u16; 2],
+    pub rotation_angle: u8,
+    pub semimajor_axis_internal_ellipse: u16,
+    pub semimajor_axis_external_ellipse: u16,
+    pub semiminor_axis_external_ellipse: u16,
+    pub overlap_process_option: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Hdr10PlusToneMapping {
+    // 0.12 fixed point
+    pub knee_point: [u16; 2],
+    // 0.10 fixed point
+    pub bezier_curve_anchors: Vec<u16>,
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Hdr10PlusWindow {
+    // None for the first window, which is the whole picture
+    pub geometry: Option<Hdr10PlusWindowGeometry>,
+    // maximum of each of the r, g and b components, in 0.00001 cd/m2
+    pub maxscl: [u32; 3],
+    pub average_maxrgb: u32,
+    // (percentage, percentile) pairs of the maxrgb distribution
+    pub distribution_maxrgb: Vec<(u8, u32)>,
+    pub fraction_bright_pixels: u16,
+    pub tone_mapping: Option<Hdr10PlusToneMapping>,
+    // 0.3 fixed point
+    pub color_saturation_weight: Option<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Hdr10Plus {
+    pub application_version: u8,
+    // in cd/m2
+    pub targeted_system_display_maximum_luminance: u32,
+    // rows of normalised luminance values, 0.4 fixed point
+    pub targeted_system_display_actual_peak_luminance: Option<Vec<Vec<u8>>>,
+    pub windows: Vec<Hdr10PlusWindow>,
+    pub mastering_display_actual_peak_luminance: Option<Vec<Vec<u8>>>,
+}
+
+fn parse_peak_luminance(gb: &mut GetBits) -> Vec<Vec<u8>> {
+    let rows = gb.get_bits(5) as usize;
+    let cols = gb.get_bits(5) as usize;
+    (0..rows)
+        .map(|_| (0..cols).map(|_| gb.get_bits(4) as u8).collect())
+        .collect()
+}
+
+impl Hdr10Plus {
+    // Parse the payload of a T.35 metadata OBU, None if it isn't HDR10+
+    // metadata or is malformed.
+    pub fn parse(t35: &ITUTT35) -> Option<Self> {
+        if t35.country_code != COUNTRY_CODE_US {
+            return None;
+        }
+        let mut gb = GetBits::new(&t35.payload);
+        if gb.get_bits(16) != TERMINAL_PROVIDER_CODE
+            || gb.get_bits(16) != TERMINAL_PROVIDER_ORIENTED_CODE
+            || gb.get_bits(8) != APPLICATION_IDENTIFIER
+        {
+            return None;
+        }
+
+        let mut hdr10plus = Hdr10Plus::default();
+        hdr10plus.application_version = gb.get_bits(8) as u8;
+        if hdr10plus.application_version > 1 {
+            return None;
+        }
+        let num_windows = gb.get_bits(2) as usize;
+        if num_windows == 0 {
+            return None;
+        }
+        hdr10plus.windows = vec![Hdr10PlusWindow::default(); num_windows];
+
+        for window in hdr10plus.windows.iter_mut().skip(1) {
+            let mut geometry = Hdr10PlusWindowGeometry::default();
+            geometry.upper_left_corner = [gb.get_bits(16) as u16, gb.get_bits(16) as u16];
+            geometry.lower_right_corner = [gb.get_bits(16) as u16, gb.get_bits(16) as u16];
+            geometry.center_of_ellipse = [gb.get_bits(16) as u16, gb.get_bits(16) as u16];
+            geometry.rotation_angle = gb.get_bits(8) as u8;
+            geometry.semimajor_axis_internal_ellipse = gb.get_bits(16) as u16;
+            geometry.semimajor_axis_external_ellipse = gb.get_bits(16) as u16;
+            geometry.semiminor_axis_external_ellipse = gb.get_bits(16) as u16;
+            geometry.overlap_process_option = gb.get_bits(1) != 0;
+            window.geometry = Some(geometry);
+        }
+
+        hdr10plus.targeted_system_display_maximum_luminance = gb.get_bits(27);
+        if gb.get_bits(1) != 0 {
+            hdr10plus.targeted_system_display_actual_peak_luminance =
+                Some(parse_peak_luminance(&mut gb));
+        }
+
+        for window in hdr10plus.windows.iter_mut() {
+            for maxscl in window.maxscl.iter_mut() {
+                *maxscl = gb.get_bits(17);
+            }
+            window.average_maxrgb = gb.get_bits(17);
+            let num_percentiles = gb.get_bits(4);
+            window.distribution_maxrgb = (0..num_percentiles)
+                .map(|_| (gb.get_bits(7) as u8, gb.get_bits(17)))
+                .collect();
+            window.fraction_bright_pixels = gb.get_bits(10) as u16;
+        }
+
+        if gb.get_bits(1) != 0 {
+            hdr10plus.mastering_display_actual_peak_luminance = Some(parse_peak_luminance(&mut gb));
+        }
+
+        for window in hdr10plus.windows.iter_mut() {
+            if gb.get_bits(1) != 0 {
+                let knee_point = [gb.get_bits(12) as u16, gb.get_bits(12) as u16];
+                let num_anchors = gb.get_bits(4);
+                let bezier_curve_anchors =
+                    (0..num_anchors).map(|_| gb.get_bits(10) as u16).collect();
+                window.tone_mapping = Some(Hdr10PlusToneMapping {
+                    knee_point,
+                    bezier_curve_anchors,
+                });
+            }
+            if gb.get_bits(1) != 0 {
+                window.color_saturation_weight = Some(gb.get_bits(6) as u8);
+            }
+        }
+
+        // the payload was too short
+        if gb.check_error().is_err() {
+            return None;
+        }
+
+        Some(hdr10plus)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::getbits::test::PutBits;
+
+    // two windows, with the targeted display peak luminance, a tone
+    // mapping and a color saturation weight for the first one
+    fn payload() -> Vec<u8> {
+        let mut pb = PutBits::default();
+        pb.put_bits(TERMINAL_PROVIDER_CODE, 16);
+        pb.put_bits(TERMINAL_PROVIDER_ORIENTED_CODE, 16);
+        pb.put_bits(APPLICATION_IDENTIFIER, 8);
+        pb.put_bits(1, 8).put_bits(2, 2);
+        for v in 1..7 {
+            pb.put_bits(v * 100, 16);
+        }
+        pb.put_bits(45, 8);
+        pb.put_bits(700, 16).put_bits(800, 16).put_bits(900, 16);
+        pb.put_bits(1, 1);
+
+        pb.put_bits(400, 27).put_bits(1, 1);
+        pb.put_bits(2, 5).put_bits(3, 5);
+        for v in 0..6 {
+            pb.put_bits(v * 3, 4);
+        }
+        for w in 0..2 {
+            for c in 0..3 {
+                pb.put_bits(90000 + w * 10 + c, 17);
+            }
+            pb.put_bits(5000 + w, 17);
+            pb.put_bits(2, 4);
+            pb.put_bits(1, 7).put_bits(100 + w, 17);
+            pb.put_bits(99, 7).put_bits(80000 + w, 17);
+            pb.put_bits(512 + w, 10);
+        }
+        pb.put_bits(0, 1);
+
+        pb.put_bits(1, 1).put_bits(2048, 12).put_bits(1024, 12);
+        pb.put_bits(3, 4)
+            .put_bits(256, 10)
+            .put_bits(512, 10)
+            .put_bits(768, 10);
+        pb.put_bits(1, 1).put_bits(8, 6);
+        pb.put_bits(0, 1).put_bits(0, 1);
+        pb.trailing_bits().data()
+    }
+
+    fn t35(country_code: u8, payload: Vec<u8>) -> ITUTT35 {
+        ITUTT35 {
+            country_code,
+            country_code_extension_byte: 0,
+            payload,
+        }
+    }
+
+    #[test]
+    fn parse_hdr10plus() {
+        let window = |w: u32| Hdr10PlusWindow {
+            maxscl: [90000 + w * 10, 90001 + w * 10, 90002 + w * 10],
+            average_maxrgb: 5000 + w,
+            distribution_maxrgb: vec![(1, 100 + w), (99, 80000 + w)],
+            fraction_bright_pixels: 512 + w as u16,
+            ..Default::default()
+        };
+        let expected = Hdr10Plus {
+            application_version: 1,
+            targeted_system_display_maximum_luminance: 400,
+            targeted_system_display_actual_peak_luminance: Some(vec![
+                vec![0, 3, 6],
+                vec![9, 12, 15],
+            ]),
+            windows: vec![
+                Hdr10PlusWindow {
+                    tone_mapping: Some(Hdr10PlusToneMapping {
+                        knee_point: [2048, 1024],
+                        bezier_curve_anchors: vec![256, 512, 768],
+                    }),
+                    color_saturation_weight: Some(8),
+                    ..window(0)
+                },
+                Hdr10PlusWindow {
+                    geometry: Some(Hdr10PlusWindowGeometry {
+                        upper_left_corner: [100, 200],
+                        lower_right_corner: [300, 400],
+                        center_of_ellipse: [500, 600],
+                        rotation_angle: 45,
+                        semimajor_axis_internal_ellipse: 700,
+                        semimajor_axis_external_ellipse: 800,
+                        semiminor_axis_external_ellipse: 900,
+                        overlap_process_option: true,
+                    }),
+                    ..window(1)
+                },
+            ],
+            mastering_display_actual_peak_luminance: None,
+        };
+        assert_eq!(
+            Hdr10Plus::parse(&t35(COUNTRY_CODE_US, payload())),
+            Some(expected)
+        );
+    }
+
+    #[test]
+    fn parse_hdr10plus_invalid() {
+        // not from the US
+        assert_eq!(Hdr10Plus::parse(&t35(0xff, payload())), None);
+        // another application
+        let mut data = payload();
+        data[4] = 5;
+        assert_eq!(Hdr10Plus::parse(&t35(COUNTRY_CODE_US, data)), None);
+        // no windows
+        let mut data = payload();
+        data[6] &= 0x3f;
+        assert_eq!(Hdr10Plus::parse(&t35(COUNTRY_CODE_US, data)), None);
+        // cut short
+        let mut data = payload();
+        data.truncate(data.len() - 4);
+        assert_eq!(Hdr10Plus::parse(&t35(COUNTRY_CODE_US, data)), None);
+    }
+}
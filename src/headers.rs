@@ -11,6 +11,8 @@ pub const MAX_SEGMENTS: usize = 8;
 pub const NUM_REF_FRAMES: usize = 8;
 pub const PRIMARY_REF_NONE: usize = 7;
 pub const REFS_PER_FRAME: usize = 7;
+// scalability_mode_idc of a scalability structure coded in the metadata
+pub const SCALABILITY_SS: u8 = 14;
 pub const TOTAL_REFS_PER_FRAME: usize = (REFS_PER_FRAME + 1);
 
 #[derive(Copy, Clone, Debug, PartialEq, FromPrimitive)]
@@ -110,6 +112,16 @@ impl Default for WarpedMotionParamsUnion {
     }
 }
 
+impl WarpedMotionParamsUnion {
+    // alpha, beta, gamma, delta
+    pub(crate) fn abcd(&self) -> [i16; 4] {
+        match *self {
+            WarpedMotionParamsUnion::Abgd(p) => [p.alpha, p.beta, p.gamma, p.delta],
+            WarpedMotionParamsUnion::Abcd(abcd) => abcd,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 #[repr(C)]
 pub struct WarpedMotionParams {
@@ -252,21 +264,68 @@ impl Default for ChromaSamplePosition {
 #[derive(Copy, Clone, Debug, PartialEq, Default)]
 #[repr(C)]
 pub struct ContentLightLevel {
-    pub(crate) max_content_light_level: isize,
-    pub(crate) max_frame_average_light_level: isize,
+    // in cd/m2
+    pub max_content_light_level: isize,
+    pub max_frame_average_light_level: isize,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Default)]
 #[repr(C)]
 pub struct MasteringDisplay {
+    // x, y of the red, green and blue primaries, 0.16 fixed point
+    pub primaries: [[u16; 2]; 3],
     // 0.16 fixed point
-    pub(crate) primaries: [[u16; 3]; 2], //TODO: confirm [3][2]?
-    // 0.16 fixed point
-    pub(crate) white_point: [u16; 2],
+    pub white_point: [u16; 2],
     // 24.8 fixed point
-    pub(crate) max_luminance: u32,
+    pub max_luminance: u32,
     // 18.14 fixed point
-    pub(crate) min_luminance: u32,
+    pub min_luminance: u32,
+}
+
+// payload of an ITU-T T.35 metadata OBU, see hdr10plus::Hdr10Plus for the
+// HDR10+ application
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct ITUTT35 {
+    pub country_code: u8,
+    // only present if country_code is 0xff
+    pub country_code_extension_byte: u8,
+    pub payload: Vec<u8>,
+}
+
+// SMPTE timecode of the next frame, see 5.8.7. Without a full timestamp
+// the seconds, minutes and hours may be omitted, from the largest unit.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+#[repr(C)]
+pub struct Timecode {
+    pub counting_type: u8,
+    pub full_timestamp: bool,
+    pub discontinuity: bool,
+    pub cnt_dropped: bool,
+    pub n_frames: u16,
+    pub seconds: Option<u8>,
+    pub minutes: Option<u8>,
+    pub hours: Option<u8>,
+    pub time_offset_length: u8,
+    pub time_offset_value: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct TemporalGroupEntry {
+    pub temporal_id: u8,
+    pub temporal_switching_up_point: bool,
+    pub spatial_switching_up_point: bool,
+    pub ref_pic_diff: Vec<u8>,
+}
+
+// see 5.8.6, the layer structure is only coded with SCALABILITY_SS; the
+// per layer vectors are empty if not present
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Scalability {
+    pub mode_idc: u8,
+    pub spatial_layers_cnt: u8,
+    pub spatial_layer_max_dimensions: Vec<[u16; 2]>,
+    pub spatial_layer_ref_id: Vec<u8>,
+    pub temporal_group: Vec<TemporalGroupEntry>,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Default)]
@@ -378,6 +437,17 @@ pub struct SequenceHeader {
         [SequenceHeaderOperatingParameterInfo; MAX_OPERATING_POINTS],
 }
 
+impl SequenceHeader {
+    // 8, 10 or 12; streams of more than 8 bits need a Context<u16>
+    pub fn bit_depth(&self) -> usize {
+        8 + 2 * self.hbd as usize
+    }
+
+    pub(crate) fn bitdepth_max(&self) -> i32 {
+        (1 << (8 + 2 * self.hbd)) - 1
+    }
+}
+
 impl PartialEq for SequenceHeader {
     fn eq(&self, other: &Self) -> bool {
         self.profile == other.profile
@@ -482,23 +552,23 @@ impl Default for LoopfilterModeRefDeltas{
 #[derive(Copy, Clone, Debug, PartialEq, Default)]
 #[repr(C)]
 pub struct FilmGrainData {
-    pub(crate) seed: u16,
-    pub(crate) num_y_points: isize,
-    pub(crate) y_points: [[u8; 14]; 2], //TODO: [14][2]
-    pub(crate) chroma_scaling_from_luma: isize,
-    pub(crate) num_uv_points: [isize; 2],
-    pub(crate) uv_points: [[[u8; 2]; 10]; 2], //TODO: [2][10][2]
-    pub(crate) scaling_shift: isize,
-    pub(crate) ar_coeff_lag: isize,
-    pub(crate) ar_coeffs_y: [i8; 24],
-    pub(crate) ar_coeffs_uv: [[i8; 2]; 25], //TODO: [2][25]
-    pub(crate) ar_coeff_shift: isize,
-    pub(crate) grain_scale_shift: isize,
-    pub(crate) uv_mult: [isize; 2],
-    pub(crate) uv_luma_mult: [isize; 2],
-    pub(crate) uv_offset: [isize; 2],
-    pub(crate) overlap_flag: isize,
-    pub(crate) clip_to_restricted_range: isize,
+    pub seed: u16,
+    pub num_y_points: isize,
+    pub y_points: [[u8; 2]; 14],
+    pub chroma_scaling_from_luma: isize,
+    pub num_uv_points: [isize; 2],
+    pub uv_points: [[[u8; 2]; 10]; 2],
+    pub scaling_shift: isize,
+    pub ar_coeff_lag: isize,
+    pub ar_coeffs_y: [i8; 24],
+    pub ar_coeffs_uv: [[i8; 25]; 2],
+    pub ar_coeff_shift: isize,
+    pub grain_scale_shift: isize,
+    pub uv_mult: [isize; 2],
+    pub uv_luma_mult: [isize; 2],
+    pub uv_offset: [isize; 2],
+    pub overlap_flag: isize,
+    pub clip_to_restricted_range: isize,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Default)]
@@ -723,6 +793,9 @@ pub struct FrameHeader {
     pub(crate) buffer_removal_time_present: bool,
     pub(crate) operating_points: [FrameHeaderOperatingPoint; MAX_OPERATING_POINTS],
     pub(crate) refresh_frame_flags: u32,
+    // slots not holding the frame signalled for them in error resilient
+    // mode, because a frame refreshing them was lost
+    pub(crate) invalid_refs: u32,
     pub(crate) render_width: u32,
     pub(crate) render_height: u32,
     pub(crate) super_res: SuperResolution,
@@ -1,8 +1,16 @@
 use std::rc::Rc;
 use std::fmt;
 
+use crate::cdef::CdefDSPContext;
+use crate::cdf::CdfContext;
+use crate::frame::Frame;
 use crate::headers::*;
 use crate::levels::*;
+use crate::lf_mask::*;
+use crate::loopfilter::LoopFilterDSPContext;
+use crate::mc::MCDSPContext;
+use crate::msac::MsacContext;
+use crate::ref_mvs::RefMvs;
 use crate::util::*;
 
 #[derive(Copy, Clone, Debug, PartialEq, Default)]
@@ -14,6 +22,31 @@ pub struct TileGroup {
     pub(crate) end: i32,
 }
 
+// limits of large scale tile decoding, see 6.11.1 and 6.11.2
+pub const MAX_ANCHOR_FRAMES: usize = 128;
+pub const MAX_TILE_LIST_ENTRIES: usize = 512;
+
+// a tile of a tile list, decoded against one of the anchor frames; the data
+// offset is relative to the start of the packet
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+#[repr(C)]
+pub struct TileListEntry {
+    pub(crate) anchor_frame_idx: usize,
+    pub(crate) tile_row: i32,
+    pub(crate) tile_col: i32,
+    pub(crate) data_offset: usize,
+    pub(crate) data_sz: usize,
+}
+
+// the tiles of a tile list OBU, composed in raster order into an output
+// frame of output_width x output_height tiles
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct TileList {
+    pub(crate) output_width: usize,
+    pub(crate) output_height: usize,
+    pub(crate) entries: Vec<TileListEntry>,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Default)]
 #[repr(C)]
 pub struct FrameThread {
@@ -36,69 +69,134 @@ pub struct FrameThread {
     int *tile_start_off;*/
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Default)]
+#[derive(Clone)]
 #[repr(C)]
-pub struct LoopFilter {
-    /*uint8_t (*level)[4];
-    Av1Filter *mask;
-    Av1Restoration *lr_mask;
-    int top_pre_cdef_toggle;*/
+pub struct LoopFilter<T: Pixel> {
+    pub(crate) level: Vec<[u8; 4]>,
+    pub(crate) mask: Vec<Av1Filter>,
+    pub(crate) lr_mask: Vec<Av1Restoration>,
+    pub(crate) top_pre_cdef_toggle: i32,
     pub(crate) mask_sz: i32, /* w*h */
     pub(crate) lr_mask_sz: i32,
     pub(crate) line_sz: i32, /* w */
     pub(crate) lr_line_sz: i32,
     pub(crate) re_sz: i32, /* h */
-    //Av1FilterLUT lim_lut;
+    pub(crate) lim_lut: Av1FilterLUT,
     pub(crate) last_sharpness: i32,
-    /*uint8_t lvl[8 /* seg_id */][4 /* dir */][8 /* ref */][2 /* is_gmv */];
-    uint8_t *tx_lpf_right_edge[2];
-    pixel *cdef_line;
-    pixel *cdef_line_ptr[2 /* pre, post */][3 /* plane */][2 /* y */];
-    pixel *lr_lpf_line;
-    pixel *lr_lpf_line_ptr[3 /* plane */];
+    pub(crate) lvl: FilterLevels,
+    pub(crate) tx_lpf_right_edge: [Vec<u8>; 2],
+    pub(crate) cdef_line: [[[Vec<T>; 2 /* y */]; 3 /* plane */]; 2 /* pre, post */],
+    // deblocked (pre-CDEF) rows around the restoration stripe boundaries,
+    // 12 rows of lr_line_sz px per plane
+    pub(crate) lr_lpf_line: [Vec<T>; 3 /* plane */],
 
     // in-loop filter per-frame state keeping
-    int tile_row; // for carry-over at tile row edges
-    pixel *p[3], *sr_p[3];
+    pub(crate) tile_row: i32, // for carry-over at tile row edges
+    pub(crate) restore_planes: i32, // enum LrRestorePlanes
+    /*pixel *p[3], *sr_p[3];
     Av1Filter *mask_ptr, *prev_mask_ptr;*/
 }
 
+impl<T: Pixel> Default for LoopFilter<T> {
+    fn default() -> Self {
+        LoopFilter {
+            level: vec![],
+            mask: vec![],
+            lr_mask: vec![],
+            top_pre_cdef_toggle: 0,
+            mask_sz: 0,
+            lr_mask_sz: 0,
+            line_sz: 0,
+            lr_line_sz: 0,
+            re_sz: 0,
+            lim_lut: Av1FilterLUT::default(),
+            last_sharpness: 0,
+            lvl: Default::default(),
+            tx_lpf_right_edge: Default::default(),
+            cdef_line: Default::default(),
+            lr_lpf_line: Default::default(),
+            tile_row: 0,
+            restore_planes: 0,
+        }
+    }
+}
+
+// Scale factor and per-pixel step of a reference of a different size than
+// the frame, in 1/16384 and 1/1024 px, see 7.11.3.3
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub(crate) struct ScalableMotionParams {
+    pub(crate) scale: i32, // if no scaling, this is 0
+    pub(crate) step: i32,
+}
+
+impl ScalableMotionParams {
+    pub(crate) fn new(ref_sz: usize, this_sz: usize) -> Self {
+        let scale = crate::decode::scale_fac(ref_sz as i32, this_sz as i32);
+        ScalableMotionParams {
+            scale,
+            step: (scale + 8) >> 4,
+        }
+    }
+}
+
+// the DSP functions, chosen once per Context based on the CPU features
+#[derive(Clone, Copy)]
+pub(crate) struct DSPContext<T: Pixel> {
+    pub(crate) mc: MCDSPContext<T>,
+    pub(crate) lf: LoopFilterDSPContext<T>,
+    pub(crate) cdef: CdefDSPContext<T>,
+}
+
+impl<T: Pixel> DSPContext<T> {
+    pub(crate) fn new(flags: u32) -> Self {
+        DSPContext {
+            mc: MCDSPContext::new(flags),
+            lf: LoopFilterDSPContext::new(flags),
+            cdef: CdefDSPContext::new(flags),
+        }
+    }
+}
+
 #[derive(Clone)]
 #[repr(C)]
-pub struct FrameContext {
+pub struct FrameContext<T: Pixel> {
     pub(crate) seq_hdr: Option<Rc<SequenceHeader>>,
     pub(crate) frame_hdr: Option<Rc<FrameHeader>>,
-    /*
-    Dav1dThreadPicture refp[7];
-    Dav1dPicture cur; // during block coding / reconstruction
-    Dav1dThreadPicture sr_cur; // after super-resolution upscaling
-    Dav1dRef *mvs_ref;
-    refmvs *mvs, *ref_mvs[7];
-    Dav1dRef *ref_mvs_ref[7];
-    Dav1dRef *cur_segmap_ref, *prev_segmap_ref;
-    uint8_t *cur_segmap;
-    const uint8_t *prev_segmap;
-    unsigned refpoc[7], refrefpoc[7][7];
-    uint8_t gmv_warp_allowed[7];
-    CdfThreadContext in_cdf, out_cdf;*/
+    pub(crate) refp: [Option<Rc<Frame<T>>>; 7],
+    pub(crate) cur: Option<Frame<T>>, // during block coding / reconstruction
+    // after super-resolution upscaling, None if the frame isn't upscaled
+    pub(crate) sr_cur: Option<Frame<T>>,
+    //Dav1dRef *mvs_ref;
+    pub(crate) mvs: Vec<RefMvs>,
+    /*refmvs *ref_mvs[7];
+    Dav1dRef *ref_mvs_ref[7];*/
+    // segmentation map of this frame and the one it is predicted from,
+    // shared with the reference slots
+    pub(crate) cur_segmap: Option<Rc<Vec<u8>>>,
+    pub(crate) prev_segmap: Option<Rc<Vec<u8>>>,
+    pub(crate) refpoc: [u32; 7],
+    //unsigned refrefpoc[7][7];
+    pub(crate) gmv_warp_allowed: [bool; 7],
+    // the CDFs the tiles start from, and those the frame saves for later
+    // frames if it refreshes them
+    pub(crate) in_cdf: Rc<CdfContext>,
+    pub(crate) out_cdf: Option<Rc<CdfContext>>,
     pub(crate) tile: Vec<TileGroup>,
     /*int n_tile_data_alloc;
-    int n_tile_data;
+    int n_tile_data;*/
 
     // for scalable references
-    struct ScalableMotionParams {
-        int scale; // if no scaling, this is 0
-        int step;
-    } svc[7][2 /* x, y */];
-    int resize_step[2 /* y, uv */], resize_start[2 /* y, uv */];
+    pub(crate) svc: [[ScalableMotionParams; 2 /* x, y */]; 7],
+    pub(crate) resize_step: [i32; 2 /* y, uv */],
+    pub(crate) resize_start: [i32; 2 /* y, uv */],
 
-    const Dav1dContext *c;*/
+    //const Dav1dContext *c;
     pub(crate) tc: Vec<TileContext>,
     pub(crate) n_tc: i32,
     pub(crate) ts: Vec<TileState>,
     pub(crate) n_ts: i32,
+    pub(crate) dsp: DSPContext<T>,
     /*
-    const Dav1dDSPContext *dsp;
     struct {
         recon_b_intra_fn recon_b_intra;
         recon_b_inter_fn recon_b_inter;
@@ -109,8 +207,8 @@ pub struct FrameContext {
 
     int ipred_edge_sz;
     pixel *ipred_edge[3];
-    ptrdiff_t b4_stride;
     */
+    pub(crate) b4_stride: usize,
     pub(crate) w4: i32,
     pub(crate) h4: i32,
     pub(crate) bw: i32,
@@ -126,7 +224,7 @@ pub struct FrameContext {
     pub(crate) a: Vec<BlockContext>,
 
     pub(crate) frame_thread: FrameThread,
-    pub(crate) lf: LoopFilter,
+    pub(crate) lf: LoopFilter<T>,
     /*
     // threading (refer to tc[] for per-thread things)
     struct FrameTileThreadData {
@@ -140,18 +238,33 @@ pub struct FrameContext {
     } tile_thread;*/
 }
 
-impl Default for FrameContext {
+impl<T: Pixel> Default for FrameContext<T> {
     fn default() -> Self {
         FrameContext {
             seq_hdr: None,
             frame_hdr: None,
+            refp: Default::default(),
+            cur: None,
+            sr_cur: None,
+            mvs: vec![],
+            cur_segmap: None,
+            prev_segmap: None,
+            refpoc: [0; 7],
+            gmv_warp_allowed: [false; 7],
+            in_cdf: Default::default(),
+            out_cdf: None,
 
             tile: vec![],
+            svc: Default::default(),
+            resize_step: [0; 2],
+            resize_start: [0; 2],
 
             tc: vec![],
             n_tc: 0,
             ts: vec![],
             n_ts: 0,
+            dsp: DSPContext::new(0),
+            b4_stride: 0,
             w4: 0,
             h4: 0,
             bw: 0,
@@ -189,10 +302,13 @@ pub struct tiling {
 #[repr(C)]
 pub struct TileState {
     pub(crate) tiling: tiling,
-    /*CdfContext cdf;
-    MsacContext msac;
+    // the tile failed to decode, its remaining superblock rows are
+    // concealed (TILE_ERROR)
+    pub(crate) error: bool,
+    pub(crate) cdf: CdfContext,
+    pub(crate) msac: MsacContext,
 
-    atomic_int progress; // in sby units, TILE_ERROR after a decoding error
+    /*atomic_int progress; // in sby units, TILE_ERROR after a decoding error
     struct {
         pthread_mutex_t lock;
         pthread_cond_t cond;
@@ -207,10 +323,39 @@ pub struct TileState {
     pub(crate) last_qidx: i32,
 
     pub(crate) last_delta_lf: [i8; 4],
-    /*uint8_t lflvlmem[8 /* seg_id */][4 /* dir */][8 /* ref */][2 /* is_gmv */];
-    const uint8_t (*lflvl)[4][8][2];
+    pub(crate) lflvlmem: FilterLevels,
+    // the last restoration unit read per plane, the reference of the
+    // delta coding of the next one
+    pub(crate) lr_ref: [Av1RestorationUnit; 3],
+}
 
-    Av1RestorationUnit *lr_ref[3];*/
+impl TileState {
+    // The filter levels of the tile's blocks: with delta_lf coded, the ones
+    // derived from the tile's last_delta_lf, the frame's otherwise
+    pub(crate) fn lflvl<'a>(
+        &'a self,
+        frame_hdr: &FrameHeader,
+        frame_lvl: &'a FilterLevels,
+    ) -> &'a FilterLevels {
+        if frame_hdr.delta.lf.present {
+            &self.lflvlmem
+        } else {
+            frame_lvl
+        }
+    }
+
+    // Apply the delta_lf values coded in a superblock (5.11.4), which the
+    // following blocks of the tile use
+    pub(crate) fn update_delta_lf(&mut self, frame_hdr: &FrameHeader, delta_lf: &[i32; 4]) {
+        let prev_delta_lf = self.last_delta_lf;
+        for (last, &delta) in self.last_delta_lf.iter_mut().zip(delta_lf.iter()) {
+            let delta = delta << frame_hdr.delta.lf.res_log2;
+            *last = clip(*last as i32 + delta, -63, 63) as i8;
+        }
+        if self.last_delta_lf != prev_delta_lf {
+            calc_lf_values(&mut self.lflvlmem, frame_hdr, &self.last_delta_lf);
+        }
+    }
 }
 
 #[derive(Clone, Default)]
@@ -229,9 +374,9 @@ pub struct TileContext {
     uint16_t al_pal[2 /* a/l */][32 /* bx/y4 */][3 /* plane */][8 /* palette_idx */];
     ALIGN(uint16_t pal[3 /* plane */][8 /* palette_idx */], 16);*/
     pub(crate)  pal_sz_uv: [[u32;32 /* bx4/by4 */]; 2 /* a/l */],
+    pub(crate) warpmv: WarpedMotionParams,
     /*
     uint8_t txtp_map[32 * 32]; // inter-only
-    Dav1dWarpedMotionParams warpmv;
     union {
     void *mem;
     uint8_t *pal_idx;
@@ -13,6 +13,7 @@ pub enum ObuType {
     OBU_METADATA = 5,
     OBU_FRAME = 6,
     OBU_REDUNDANT_FRAME_HDR = 7,
+    OBU_TILE_LIST = 8,
     OBU_PADDING = 15,
 }
 
@@ -266,11 +267,11 @@ pub enum InterIntraType {
     INTER_INTRA_WEDGE,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 #[repr(C)]
 pub struct MV {
-    y: i16,
-    x: i16,
+    pub(crate) y: i16,
+    pub(crate) x: i16,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, FromPrimitive)]
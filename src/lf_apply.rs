@@ -0,0 +1,403 @@
+use crate::headers::*;
+use crate::internal::*;
+use crate::lf_mask::*;
+use crate::loopfilter::*;
+use crate::plane::Plane;
+use crate::util::*;
+
+use std::cmp;
+
+// pixel offset of the top-left corner of the sbrow starting at 4px row y4
+#[inline(always)]
+fn sbrow_offset<T: Pixel>(p: &Plane<T>, y4: usize) -> usize {
+    (p.cfg.yorigin + y4 * 4) * p.cfg.stride + p.cfg.xorigin
+}
+
+fn filter_plane_cols_y<T: Pixel>(
+    dsp: &LoopFilterDSPContext<T>,
+    lim_lut: &Av1FilterLUT,
+    have_left: bool,
+    lvl: &[[u8; 4]],
+    lvl_off: usize,
+    b4_stride: usize,
+    mask: &[[[u16; 2]; 3]; 32],
+    p: &mut Plane<T>,
+    off: usize,
+    w: usize,
+    starty4: usize,
+    endy4: usize,
+    bitdepth_max: i32,
+) {
+    let stride = p.cfg.stride;
+
+    // filter edges between columns (e.g. block1 | block2)
+    for x in 0..w {
+        if !have_left && x == 0 {
+            continue;
+        }
+        let mut hmask = [0u32; 3];
+        for i in 0..3 {
+            hmask[i] = if starty4 == 0 {
+                let mut m = mask[x][i][0] as u32;
+                if endy4 > 16 {
+                    m |= (mask[x][i][1] as u32) << 16;
+                }
+                m
+            } else {
+                mask[x][i][1] as u32
+            };
+        }
+        (dsp.loop_filter_sby[0])(
+            &mut p.data,
+            off + x * 4,
+            stride,
+            &hmask,
+            lvl,
+            lvl_off + x,
+            b4_stride,
+            lim_lut,
+            bitdepth_max,
+        );
+    }
+}
+
+fn filter_plane_rows_y<T: Pixel>(
+    dsp: &LoopFilterDSPContext<T>,
+    lim_lut: &Av1FilterLUT,
+    have_top: bool,
+    lvl: &[[u8; 4]],
+    mut lvl_off: usize,
+    b4_stride: usize,
+    mask: &[[[u16; 2]; 3]; 32],
+    p: &mut Plane<T>,
+    mut off: usize,
+    starty4: usize,
+    endy4: usize,
+    bitdepth_max: i32,
+) {
+    let stride = p.cfg.stride;
+
+    //                                 block1
+    // filter edges between rows (e.g. ------)
+    //                                 block2
+    for y in starty4..endy4 {
+        if have_top || y != 0 {
+            let mut vmask = [0u32; 3];
+            for i in 0..3 {
+                vmask[i] = mask[y][i][0] as u32 | (mask[y][i][1] as u32) << 16;
+            }
+            (dsp.loop_filter_sby[1])(
+                &mut p.data,
+                off,
+                stride,
+                &vmask,
+                lvl,
+                lvl_off,
+                b4_stride,
+                lim_lut,
+                bitdepth_max,
+            );
+        }
+        off += 4 * stride;
+        lvl_off += b4_stride;
+    }
+}
+
+fn filter_plane_cols_uv<T: Pixel>(
+    dsp: &LoopFilterDSPContext<T>,
+    lim_lut: &Av1FilterLUT,
+    have_left: bool,
+    lvl: &[[u8; 4]],
+    lvl_off: usize,
+    b4_stride: usize,
+    mask: &[[[u16; 2]; 2]; 32],
+    u: &mut Plane<T>,
+    v: &mut Plane<T>,
+    off: usize,
+    w: usize,
+    starty4: usize,
+    endy4: usize,
+    ss_ver: usize,
+    bitdepth_max: i32,
+) {
+    let stride = u.cfg.stride;
+
+    // filter edges between columns (e.g. block1 | block2)
+    for x in 0..w {
+        if !have_left && x == 0 {
+            continue;
+        }
+        let mut hmask = [0u32; 2];
+        for i in 0..2 {
+            hmask[i] = if starty4 == 0 {
+                let mut m = mask[x][i][0] as u32;
+                if endy4 > (16 >> ss_ver) {
+                    m |= (mask[x][i][1] as u32) << (16 >> ss_ver);
+                }
+                m
+            } else {
+                mask[x][i][1] as u32
+            };
+        }
+        for (pl, dir) in [(&mut *u, 2), (&mut *v, 3)].iter_mut() {
+            (dsp.loop_filter_sbuv[0])(
+                &mut pl.data,
+                off + x * 4,
+                stride,
+                &hmask,
+                lvl,
+                lvl_off + x,
+                *dir,
+                b4_stride,
+                lim_lut,
+                bitdepth_max,
+            );
+        }
+    }
+}
+
+fn filter_plane_rows_uv<T: Pixel>(
+    dsp: &LoopFilterDSPContext<T>,
+    lim_lut: &Av1FilterLUT,
+    have_top: bool,
+    lvl: &[[u8; 4]],
+    mut lvl_off: usize,
+    b4_stride: usize,
+    mask: &[[[u16; 2]; 2]; 32],
+    u: &mut Plane<T>,
+    v: &mut Plane<T>,
+    mut off: usize,
+    starty4: usize,
+    endy4: usize,
+    ss_hor: usize,
+    bitdepth_max: i32,
+) {
+    let stride = u.cfg.stride;
+
+    //                                 block1
+    // filter edges between rows (e.g. ------)
+    //                                 block2
+    for y in starty4..endy4 {
+        if have_top || y != 0 {
+            let mut vmask = [0u32; 2];
+            for i in 0..2 {
+                vmask[i] = mask[y][i][0] as u32 | (mask[y][i][1] as u32) << (16 >> ss_hor);
+            }
+            for (pl, dir) in [(&mut *u, 2), (&mut *v, 3)].iter_mut() {
+                (dsp.loop_filter_sbuv[1])(
+                    &mut pl.data,
+                    off,
+                    stride,
+                    &vmask,
+                    lvl,
+                    lvl_off,
+                    *dir,
+                    b4_stride,
+                    lim_lut,
+                    bitdepth_max,
+                );
+            }
+        }
+        off += 4 * stride;
+        lvl_off += b4_stride;
+    }
+}
+
+// Deblock one superblock row of the current picture using the edge masks
+// and filter levels collected during block decoding. lflvl are the masks of
+// the 128x128 area(s) covering this sbrow; start_of_tile_row is the tile
+// row starting at this sbrow, or 0.
+pub(crate) fn loopfilter_sbrow<T: Pixel>(
+    f: &mut FrameContext<T>,
+    lflvl_off: usize,
+    sby: usize,
+    start_of_tile_row: usize,
+) {
+    let FrameContext {
+        seq_hdr,
+        frame_hdr,
+        cur,
+        lf,
+        a,
+        dsp,
+        ..
+    } = f;
+    let seq_hdr = seq_hdr.as_ref().unwrap();
+    let frame_hdr = frame_hdr.as_ref().unwrap();
+    let planes = &mut cur.as_mut().unwrap().planes;
+    let lflvl = &mut lf.mask[lflvl_off..];
+    let (bw, w4, h4) = (f.bw as usize, f.w4 as usize, f.h4 as usize);
+    let (b4_stride, sb128w) = (f.b4_stride, f.sb128w as usize);
+    let bitdepth_max = seq_hdr.bitdepth_max();
+
+    // Don't filter outside the frame
+    let is_sb64 = !seq_hdr.sb128 as usize;
+    let starty4 = (sby & is_sb64) << 4;
+    let sbsz = 32 >> is_sb64;
+    let sbl2 = 5 - is_sb64;
+    let halign = (f.bh as usize + 31) & !31;
+    let is_mono = seq_hdr.layout == PixelLayout::PIXEL_LAYOUT_I400;
+    let ss_ver = (seq_hdr.layout == PixelLayout::PIXEL_LAYOUT_I420) as usize;
+    let ss_hor = (seq_hdr.layout != PixelLayout::PIXEL_LAYOUT_I444) as usize;
+    let vbits = 4 - ss_ver;
+    let hbits = 4 - ss_hor;
+    let endy4 = starty4 + cmp::min(h4 - sby * sbsz, sbsz);
+    let uv_endy4 = (endy4 + ss_ver) >> ss_ver;
+
+    // fix lpf strength at tile col boundaries
+    let mut lpf_y = sby << sbl2;
+    let mut lpf_uv = sby << (sbl2 - ss_ver);
+    for tile_col in 1.. {
+        let x = frame_hdr.tiling.col_start_sb[tile_col] as usize;
+        if (x << sbl2) >= bw {
+            break;
+        }
+        let bx4 = if x & is_sb64 != 0 { 16 } else { 0 };
+        let cbx4 = bx4 >> ss_hor;
+        let x = x >> is_sb64;
+
+        let y_hmask = &mut lflvl[x].filter_y[0][bx4];
+        for y in starty4..endy4 {
+            let (sidx, smask) = (y >> 4, 1u16 << (y & 15));
+            let idx = 2 * (y_hmask[2][sidx] & smask != 0) as usize
+                + (y_hmask[1][sidx] & smask != 0) as usize;
+            for m in y_hmask.iter_mut() {
+                m[sidx] &= !smask;
+            }
+            let lpf = lf.tx_lpf_right_edge[0][lpf_y + y - starty4] as usize;
+            y_hmask[cmp::min(idx, lpf)][sidx] |= smask;
+        }
+
+        if !is_mono {
+            let uv_hmask = &mut lflvl[x].filter_uv[0][cbx4];
+            for y in starty4 >> ss_ver..uv_endy4 {
+                let (sidx, smask) = (y >> vbits, 1u16 << (y & ((1 << vbits) - 1)));
+                let idx = (uv_hmask[1][sidx] & smask != 0) as usize;
+                for m in uv_hmask.iter_mut() {
+                    m[sidx] &= !smask;
+                }
+                let lpf = lf.tx_lpf_right_edge[1][lpf_uv + y - (starty4 >> ss_ver)] as usize;
+                uv_hmask[cmp::min(idx, lpf)][sidx] |= smask;
+            }
+        }
+        lpf_y += halign;
+        lpf_uv += halign >> ss_ver;
+    }
+
+    // fix lpf strength at tile row boundaries
+    if start_of_tile_row != 0 {
+        let a = &a[sb128w * (start_of_tile_row - 1)..];
+        for x in 0..sb128w {
+            let y_vmask = &mut lflvl[x].filter_y[1][starty4];
+            let w = cmp::min(32, w4 - (x << 5));
+            for i in 0..w {
+                let (sidx, smask) = (i >> 4, 1u16 << (i & 15));
+                let idx = 2 * (y_vmask[2][sidx] & smask != 0) as usize
+                    + (y_vmask[1][sidx] & smask != 0) as usize;
+                for m in y_vmask.iter_mut() {
+                    m[sidx] &= !smask;
+                }
+                y_vmask[cmp::min(idx, a[x].tx_lpf_y.array[i] as usize)][sidx] |= smask;
+            }
+
+            if !is_mono {
+                let cw = (w + ss_hor) >> ss_hor;
+                let uv_vmask = &mut lflvl[x].filter_uv[1][starty4 >> ss_ver];
+                for i in 0..cw {
+                    let (sidx, smask) = (i >> hbits, 1u16 << (i & ((1 << hbits) - 1)));
+                    let idx = (uv_vmask[1][sidx] & smask != 0) as usize;
+                    for m in uv_vmask.iter_mut() {
+                        m[sidx] &= !smask;
+                    }
+                    uv_vmask[cmp::min(idx, a[x].tx_lpf_uv.array[i] as usize)][sidx] |= smask;
+                }
+            }
+        }
+    }
+
+    let have_top = sby > 0;
+    let dsp = &dsp.lf;
+    let lim_lut = &lf.lim_lut;
+    let level = &lf.level;
+    let [py, pu, pv] = planes;
+
+    let y_off = sbrow_offset(py, sby * sbsz);
+    let level_off = b4_stride * sby * sbsz;
+    for x in 0..sb128w {
+        filter_plane_cols_y(
+            dsp,
+            lim_lut,
+            x > 0,
+            level,
+            level_off + x * 32,
+            b4_stride,
+            &lflvl[x].filter_y[0],
+            py,
+            y_off + x * 128,
+            cmp::min(32, w4 - x * 32),
+            starty4,
+            endy4,
+            bitdepth_max,
+        );
+    }
+    for x in 0..sb128w {
+        filter_plane_rows_y(
+            dsp,
+            lim_lut,
+            have_top,
+            level,
+            level_off + x * 32,
+            b4_stride,
+            &lflvl[x].filter_y[1],
+            py,
+            y_off + x * 128,
+            starty4,
+            endy4,
+            bitdepth_max,
+        );
+    }
+
+    if is_mono || (frame_hdr.loopfilter.level_u == 0 && frame_hdr.loopfilter.level_v == 0) {
+        return;
+    }
+
+    let uv_off = sbrow_offset(pu, (sby * sbsz) >> ss_ver);
+    let level_off = b4_stride * ((sby * sbsz) >> ss_ver);
+    for x in 0..sb128w {
+        filter_plane_cols_uv(
+            dsp,
+            lim_lut,
+            x > 0,
+            level,
+            level_off + x * (32 >> ss_hor),
+            b4_stride,
+            &lflvl[x].filter_uv[0],
+            pu,
+            pv,
+            uv_off + x * (128 >> ss_hor),
+            (cmp::min(32, w4 - x * 32) + ss_hor) >> ss_hor,
+            starty4 >> ss_ver,
+            uv_endy4,
+            ss_ver,
+            bitdepth_max,
+        );
+    }
+    for x in 0..sb128w {
+        filter_plane_rows_uv(
+            dsp,
+            lim_lut,
+            have_top,
+            level,
+            level_off + x * (32 >> ss_hor),
+            b4_stride,
+            &lflvl[x].filter_uv[1],
+            pu,
+            pv,
+            uv_off + x * (128 >> ss_hor),
+            starty4 >> ss_ver,
+            uv_endy4,
+            ss_hor,
+            bitdepth_max,
+        );
+    }
+}
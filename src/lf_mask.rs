@@ -1,29 +1,764 @@
 use crate::headers::*;
+use crate::levels::*;
+use crate::tables::*;
+use crate::util::*;
 
-struct Av1FilterLUT {
-    e: [u8; 64],
-    i: [u8; 64],
-    sharp: [u64; 2],
+use std::cmp;
+
+#[derive(Copy, Clone)]
+pub(crate) struct Av1FilterLUT {
+    pub(crate) e: [u8; 64],
+    pub(crate) i: [u8; 64],
+    pub(crate) sharp: [u64; 2],
+}
+
+impl Default for Av1FilterLUT {
+    fn default() -> Self {
+        Av1FilterLUT {
+            e: [0; 64],
+            i: [0; 64],
+            sharp: [0; 2],
+        }
+    }
 }
 
-struct Av1RestorationUnit {
-    t: RestorationType,
-    filter_h: [i16; 3],
-    filter_v: [i16; 3],
-    sgr_idx: u8,
-    sgr_weights: [i16; 2],
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub(crate) struct Av1RestorationUnit {
+    pub(crate) t: RestorationType,
+    pub(crate) filter_h: [i16; 3],
+    pub(crate) filter_v: [i16; 3],
+    pub(crate) sgr_idx: u8,
+    pub(crate) sgr_weights: [i16; 2],
 }
 
 // each struct describes one 128x128 area (1 or 4 SBs), pre-superres-scaling
-struct Av1Filter {
+#[derive(Copy, Clone, Default)]
+pub(crate) struct Av1Filter {
     // each bit is 1 col
-    filter_y: [[[[u16; 2]; 3]; 32]; 2],
-    filter_uv: [[[[u16; 2]; 2]; 32]; 2],
-    cdef_idx: [i8; 4], // -1 means "unset"
-    noskip_mask: [[u16; 2]; 32],
+    pub(crate) filter_y: [[[[u16; 2]; 3]; 32]; 2],
+    pub(crate) filter_uv: [[[[u16; 2]; 2]; 32]; 2],
+    pub(crate) cdef_idx: [i8; 4], // -1 means "unset"
+    pub(crate) noskip_mask: [[u16; 2]; 32],
 }
 
 // each struct describes one 128x128 area (1 or 4 SBs), post-superres-scaling
-struct Av1Restoration {
-    lr: [[Av1RestorationUnit; 4]; 3],
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub(crate) struct Av1Restoration {
+    pub(crate) lr: [[Av1RestorationUnit; 4]; 3],
+}
+
+// filter level per [seg_id][dir][ref][!is_gmv]; dir 0/1 are luma
+// vertical/horizontal edges, 2/3 the u/v planes
+pub(crate) type FilterLevels = [[[[u8; 2]; 8]; 4]; 8];
+
+// The level per direction of the edges of a block of segment seg_id,
+// predicted from ref_frame (0 for intra blocks), as create_lf_mask_intra()
+// and create_lf_mask_inter() take it
+pub(crate) fn filter_level(
+    lflvl: &FilterLevels,
+    seg_id: usize,
+    ref_frame: usize,
+    is_gmv: bool,
+) -> [u8; 4] {
+    let lvl = &lflvl[seg_id];
+    let m = !is_gmv as usize;
+    [
+        lvl[0][ref_frame][m],
+        lvl[1][ref_frame][m],
+        lvl[2][ref_frame][m],
+        lvl[3][ref_frame][m],
+    ]
+}
+
+// transform size (as log2 of the 4px width/height, capped at 16px for the
+// filter selection) and step per 4x4 block: txa[edge][txsz/step][y][x]
+type TxArea = [[[[u8; 32]; 32]; 2]; 2];
+
+fn decomp_tx(
+    txa: &mut TxArea,
+    y0: usize,
+    x0: usize,
+    from: usize,
+    depth: usize,
+    y_off: usize,
+    x_off: usize,
+    tx_masks: &[u16; 2],
+) {
+    let t_dim = &txfm_dimensions[from];
+    let is_split = if from == TxfmSize::TX_4X4 as usize || depth > 1 {
+        false
+    } else {
+        (tx_masks[depth] >> (y_off * 4 + x_off)) & 1 != 0
+    };
+
+    if is_split {
+        let sub = t_dim.sub as usize;
+        let htw4 = (t_dim.w >> 1) as usize;
+        let hth4 = (t_dim.h >> 1) as usize;
+
+        decomp_tx(txa, y0, x0, sub, depth + 1, y_off * 2, x_off * 2, tx_masks);
+        if t_dim.w >= t_dim.h {
+            decomp_tx(
+                txa,
+                y0,
+                x0 + htw4,
+                sub,
+                depth + 1,
+                y_off * 2,
+                x_off * 2 + 1,
+                tx_masks,
+            );
+        }
+        if t_dim.h >= t_dim.w {
+            decomp_tx(
+                txa,
+                y0 + hth4,
+                x0,
+                sub,
+                depth + 1,
+                y_off * 2 + 1,
+                x_off * 2,
+                tx_masks,
+            );
+            if t_dim.w >= t_dim.h {
+                decomp_tx(
+                    txa,
+                    y0 + hth4,
+                    x0 + htw4,
+                    sub,
+                    depth + 1,
+                    y_off * 2 + 1,
+                    x_off * 2 + 1,
+                    tx_masks,
+                );
+            }
+        }
+    } else {
+        let lw = cmp::min(2, t_dim.lw);
+        let lh = cmp::min(2, t_dim.lh);
+        let (w, h) = (t_dim.w as usize, t_dim.h as usize);
+
+        for y in y0..y0 + h {
+            for x in x0..x0 + w {
+                txa[0][0][y][x] = lw;
+                txa[1][0][y][x] = lh;
+            }
+            txa[0][1][y][x0] = t_dim.w;
+        }
+        for x in x0..x0 + w {
+            txa[1][1][y0][x] = t_dim.h;
+        }
+    }
+}
+
+// set bit (pos & 15) of the 16-bit half (pos >> 4) of an edge mask
+#[inline(always)]
+fn set_mask(mask: &mut [u16; 2], pos: usize) {
+    mask[pos >> 4] |= 1 << (pos & 15);
+}
+
+// set bits pos..pos + n of an edge mask split in halves of `bits` bits
+#[inline(always)]
+fn set_mask_range(mask: &mut [u16; 2], pos: usize, n: usize, bits: usize) {
+    let inner = ((1u64 << n) - 1) << pos;
+    mask[0] |= (inner & ((1 << bits) - 1)) as u16;
+    mask[1] |= (inner >> bits) as u16;
+}
+
+fn mask_edges_inter(
+    masks: &mut [[[[u16; 2]; 3]; 32]; 2],
+    by4: usize,
+    bx4: usize,
+    w4: usize,
+    h4: usize,
+    skip: bool,
+    max_tx: usize,
+    tx_masks: &[u16; 2],
+    a: &mut [u8],
+    l: &mut [u8],
+) {
+    let t_dim = &txfm_dimensions[max_tx];
+    let mut txa: TxArea = [[[[0; 32]; 32]; 2]; 2];
+
+    for (y_off, y) in (0..h4).step_by(t_dim.h as usize).enumerate() {
+        for (x_off, x) in (0..w4).step_by(t_dim.w as usize).enumerate() {
+            decomp_tx(&mut txa, y, x, max_tx, 0, y_off, x_off, tx_masks);
+        }
+    }
+
+    // left block edge
+    for y in 0..h4 {
+        let tx = cmp::min(txa[0][0][y][0], l[y]) as usize;
+        set_mask(&mut masks[0][bx4][tx], by4 + y);
+    }
+
+    // top block edge
+    for x in 0..w4 {
+        let tx = cmp::min(txa[1][0][0][x], a[x]) as usize;
+        set_mask(&mut masks[1][by4][tx], bx4 + x);
+    }
+
+    if !skip {
+        // inner (tx) left|right edges
+        for y in 0..h4 {
+            let mut ltx = txa[0][0][y][0];
+            let mut x = txa[0][1][y][0] as usize;
+            while x < w4 {
+                let rtx = txa[0][0][y][x];
+                set_mask(&mut masks[0][bx4 + x][cmp::min(rtx, ltx) as usize], by4 + y);
+                ltx = rtx;
+                x += txa[0][1][y][x] as usize;
+            }
+        }
+
+        //            top
+        // inner (tx) --- edges
+        //           bottom
+        for x in 0..w4 {
+            let mut ttx = txa[1][0][0][x];
+            let mut y = txa[1][1][0][x] as usize;
+            while y < h4 {
+                let btx = txa[1][0][y][x];
+                set_mask(&mut masks[1][by4 + y][cmp::min(ttx, btx) as usize], bx4 + x);
+                ttx = btx;
+                y += txa[1][1][y][x] as usize;
+            }
+        }
+    }
+
+    for y in 0..h4 {
+        l[y] = txa[0][0][y][w4 - 1];
+    }
+    a[..w4].copy_from_slice(&txa[1][0][h4 - 1][..w4]);
+}
+
+fn mask_edges_intra(
+    masks: &mut [[[[u16; 2]; 3]; 32]; 2],
+    by4: usize,
+    bx4: usize,
+    w4: usize,
+    h4: usize,
+    tx: usize,
+    a: &mut [u8],
+    l: &mut [u8],
+) {
+    let t_dim = &txfm_dimensions[tx];
+    let twl4c = cmp::min(2, t_dim.lw);
+    let thl4c = cmp::min(2, t_dim.lh);
+
+    // left block edge
+    for y in 0..h4 {
+        let tx = cmp::min(twl4c, l[y]) as usize;
+        set_mask(&mut masks[0][bx4][tx], by4 + y);
+    }
+
+    // top block edge
+    for x in 0..w4 {
+        let tx = cmp::min(thl4c, a[x]) as usize;
+        set_mask(&mut masks[1][by4][tx], bx4 + x);
+    }
+
+    // inner (tx) left|right edges
+    for x in (t_dim.w as usize..w4).step_by(t_dim.w as usize) {
+        set_mask_range(&mut masks[0][bx4 + x][twl4c as usize], by4, h4, 16);
+    }
+
+    //            top
+    // inner (tx) --- edges
+    //           bottom
+    for y in (t_dim.h as usize..h4).step_by(t_dim.h as usize) {
+        set_mask_range(&mut masks[1][by4 + y][thl4c as usize], bx4, w4, 16);
+    }
+
+    a[..w4].iter_mut().for_each(|v| *v = thl4c);
+    l[..h4].iter_mut().for_each(|v| *v = twl4c);
+}
+
+fn mask_edges_chroma(
+    masks: &mut [[[[u16; 2]; 2]; 32]; 2],
+    cby4: usize,
+    cbx4: usize,
+    cw4: usize,
+    ch4: usize,
+    skip_inter: bool,
+    tx: usize,
+    a: &mut [u8],
+    l: &mut [u8],
+    ss_hor: usize,
+    ss_ver: usize,
+) {
+    let t_dim = &txfm_dimensions[tx];
+    let twl4c = (t_dim.lw != 0) as u8;
+    let thl4c = (t_dim.lh != 0) as u8;
+    let vbits = 4 - ss_ver;
+    let hbits = 4 - ss_hor;
+
+    // left block edge
+    for y in 0..ch4 {
+        let pos = cby4 + y;
+        let tx = cmp::min(twl4c, l[y]) as usize;
+        masks[0][cbx4][tx][pos >> vbits] |= 1 << (pos & ((1 << vbits) - 1));
+    }
+
+    // top block edge
+    for x in 0..cw4 {
+        let pos = cbx4 + x;
+        let tx = cmp::min(thl4c, a[x]) as usize;
+        masks[1][cby4][tx][pos >> hbits] |= 1 << (pos & ((1 << hbits) - 1));
+    }
+
+    if !skip_inter {
+        // inner (tx) left|right edges
+        for x in (t_dim.w as usize..cw4).step_by(t_dim.w as usize) {
+            set_mask_range(
+                &mut masks[0][cbx4 + x][twl4c as usize],
+                cby4,
+                ch4,
+                16 >> ss_ver,
+            );
+        }
+
+        //            top
+        // inner (tx) --- edges
+        //           bottom
+        for y in (t_dim.h as usize..ch4).step_by(t_dim.h as usize) {
+            set_mask_range(
+                &mut masks[1][cby4 + y][thl4c as usize],
+                cbx4,
+                cw4,
+                16 >> ss_hor,
+            );
+        }
+    }
+
+    a[..cw4].iter_mut().for_each(|v| *v = thl4c);
+    l[..ch4].iter_mut().for_each(|v| *v = twl4c);
+}
+
+fn set_level_cache(
+    level_cache: &mut [[u8; 4]],
+    b4_stride: usize,
+    off: usize,
+    w4: usize,
+    h4: usize,
+    dirs: std::ops::Range<usize>,
+    filter_level: &[u8; 4],
+) {
+    for y in 0..h4 {
+        for lvl in &mut level_cache[off + y * b4_stride..][..w4] {
+            for dir in dirs.clone() {
+                lvl[dir] = filter_level[dir];
+            }
+        }
+    }
+}
+
+// Set the luma and chroma edge masks and level cache of an intra block.
+// filter_level holds the level per direction (see FilterLevels) for the
+// block's segment; a/l are the above/left transform size contexts,
+// auv/luv are None for monochrome.
+pub(crate) fn create_lf_mask_intra(
+    lflvl: &mut Av1Filter,
+    level_cache: &mut [[u8; 4]],
+    b4_stride: usize,
+    filter_level: &[u8; 4],
+    bx: usize,
+    by: usize,
+    iw: usize,
+    ih: usize,
+    bs: BlockSize,
+    ytx: usize,
+    uvtx: usize,
+    layout: PixelLayout,
+    ay: &mut [u8],
+    ly: &mut [u8],
+    auv_luv: Option<(&mut [u8], &mut [u8])>,
+) {
+    let b_dim = &block_dimensions[bs as usize];
+    let bw4 = cmp::min(iw - bx, b_dim[0] as usize);
+    let bh4 = cmp::min(ih - by, b_dim[1] as usize);
+    let bx4 = bx & 31;
+    let by4 = by & 31;
+
+    if bw4 != 0 && bh4 != 0 {
+        let off = by * b4_stride + bx;
+        set_level_cache(level_cache, b4_stride, off, bw4, bh4, 0..2, filter_level);
+        mask_edges_intra(&mut lflvl.filter_y, by4, bx4, bw4, bh4, ytx, ay, ly);
+    }
+
+    let (auv, luv) = match auv_luv {
+        Some(auv_luv) => auv_luv,
+        None => return,
+    };
+
+    let ss_ver = (layout == PixelLayout::PIXEL_LAYOUT_I420) as usize;
+    let ss_hor = (layout != PixelLayout::PIXEL_LAYOUT_I444) as usize;
+    let cbw4 = cmp::min(
+        ((iw + ss_hor) >> ss_hor) - (bx >> ss_hor),
+        (b_dim[0] as usize + ss_hor) >> ss_hor,
+    );
+    let cbh4 = cmp::min(
+        ((ih + ss_ver) >> ss_ver) - (by >> ss_ver),
+        (b_dim[1] as usize + ss_ver) >> ss_ver,
+    );
+    if cbw4 == 0 || cbh4 == 0 {
+        return;
+    }
+
+    let off = (by >> ss_ver) * b4_stride + (bx >> ss_hor);
+    set_level_cache(level_cache, b4_stride, off, cbw4, cbh4, 2..4, filter_level);
+    mask_edges_chroma(
+        &mut lflvl.filter_uv,
+        by4 >> ss_ver,
+        bx4 >> ss_hor,
+        cbw4,
+        cbh4,
+        false,
+        uvtx,
+        auv,
+        luv,
+        ss_hor,
+        ss_ver,
+    );
+}
+
+// Same as create_lf_mask_intra for inter blocks, where the luma transform
+// may be split (down to 2 levels, as signalled in tx_masks) from max_ytx,
+// and inner transform edges are skipped for skip blocks.
+pub(crate) fn create_lf_mask_inter(
+    lflvl: &mut Av1Filter,
+    level_cache: &mut [[u8; 4]],
+    b4_stride: usize,
+    filter_level: &[u8; 4],
+    bx: usize,
+    by: usize,
+    iw: usize,
+    ih: usize,
+    skip: bool,
+    bs: BlockSize,
+    max_ytx: usize,
+    tx_masks: &[u16; 2],
+    uvtx: usize,
+    layout: PixelLayout,
+    ay: &mut [u8],
+    ly: &mut [u8],
+    auv_luv: Option<(&mut [u8], &mut [u8])>,
+) {
+    let b_dim = &block_dimensions[bs as usize];
+    let bw4 = cmp::min(iw - bx, b_dim[0] as usize);
+    let bh4 = cmp::min(ih - by, b_dim[1] as usize);
+    let bx4 = bx & 31;
+    let by4 = by & 31;
+
+    if bw4 != 0 && bh4 != 0 {
+        let off = by * b4_stride + bx;
+        set_level_cache(level_cache, b4_stride, off, bw4, bh4, 0..2, filter_level);
+        mask_edges_inter(
+            &mut lflvl.filter_y,
+            by4,
+            bx4,
+            bw4,
+            bh4,
+            skip,
+            max_ytx,
+            tx_masks,
+            ay,
+            ly,
+        );
+    }
+
+    let (auv, luv) = match auv_luv {
+        Some(auv_luv) => auv_luv,
+        None => return,
+    };
+
+    let ss_ver = (layout == PixelLayout::PIXEL_LAYOUT_I420) as usize;
+    let ss_hor = (layout != PixelLayout::PIXEL_LAYOUT_I444) as usize;
+    let cbw4 = cmp::min(
+        ((iw + ss_hor) >> ss_hor) - (bx >> ss_hor),
+        (b_dim[0] as usize + ss_hor) >> ss_hor,
+    );
+    let cbh4 = cmp::min(
+        ((ih + ss_ver) >> ss_ver) - (by >> ss_ver),
+        (b_dim[1] as usize + ss_ver) >> ss_ver,
+    );
+    if cbw4 == 0 || cbh4 == 0 {
+        return;
+    }
+
+    let off = (by >> ss_ver) * b4_stride + (bx >> ss_hor);
+    set_level_cache(level_cache, b4_stride, off, cbw4, cbh4, 2..4, filter_level);
+    mask_edges_chroma(
+        &mut lflvl.filter_uv,
+        by4 >> ss_ver,
+        bx4 >> ss_hor,
+        cbw4,
+        cbh4,
+        skip,
+        uvtx,
+        auv,
+        luv,
+        ss_hor,
+        ss_ver,
+    );
+}
+
+// E/I limits per filter level and the sharpness-derived shift/clamp, see
+// 7.14.4 "Filter limits process"
+pub(crate) fn calc_eih(lim_lut: &mut Av1FilterLUT, filter_sharpness: i32) {
+    let sharp = filter_sharpness;
+    for level in 0..64 {
+        let mut limit = level;
+
+        if sharp > 0 {
+            limit >>= (sharp + 3) >> 2;
+            limit = cmp::min(limit, 9 - sharp);
+        }
+        limit = cmp::max(limit, 1);
+
+        lim_lut.i[level as usize] = limit as u8;
+        lim_lut.e[level as usize] = (2 * (level + 2) + limit) as u8;
+    }
+    lim_lut.sharp[0] = ((sharp + 3) >> 2) as u64;
+    lim_lut.sharp[1] = if sharp != 0 { 9 - sharp as u64 } else { 0xff };
+}
+
+fn calc_lf_value(
+    lflvl_values: &mut [[u8; 2]; 8],
+    is_chroma: bool,
+    base_lvl: i32,
+    lf_delta: i32,
+    seg_delta: i32,
+    mr_delta: Option<&LoopfilterModeRefDeltas>,
+) {
+    let base = clip(clip(base_lvl + lf_delta, 0, 63) + seg_delta, 0, 63);
+
+    if base_lvl == 0 && is_chroma {
+        *lflvl_values = [[0; 2]; 8];
+    } else if let Some(mr_delta) = mr_delta {
+        let sh = (base >= 32) as i32;
+        let lvl = clip(base + (mr_delta.ref_delta[0] << sh), 0, 63) as u8;
+        lflvl_values[0] = [lvl; 2];
+        for r in 1..8 {
+            for m in 0..2 {
+                let delta = mr_delta.mode_delta[m] + mr_delta.ref_delta[r];
+                lflvl_values[r][m] = clip(base + (delta << sh), 0, 63) as u8;
+            }
+        }
+    } else {
+        *lflvl_values = [[base as u8; 2]; 8];
+    }
+}
+
+// Derive the filter levels of all segments, directions, references and
+// modes from the frame header and the current delta_lf values, see
+// 7.14.4 "Filter level derivation"
+pub(crate) fn calc_lf_values(
+    lflvl_values: &mut FilterLevels,
+    hdr: &FrameHeader,
+    lf_delta: &[i8; 4],
+) {
+    let n_seg = if hdr.segmentation.enabled { 8 } else { 1 };
+
+    if hdr.loopfilter.level_y[0] == 0 && hdr.loopfilter.level_y[1] == 0 {
+        lflvl_values[..n_seg]
+            .iter_mut()
+            .for_each(|v| *v = Default::default());
+        return;
+    }
+
+    let mr_deltas = if hdr.loopfilter.mode_ref_delta_enabled {
+        Some(&hdr.loopfilter.mode_ref_deltas)
+    } else {
+        None
+    };
+    let multi = hdr.delta.lf.multi;
+    for s in 0..n_seg {
+        let segd = if hdr.segmentation.enabled {
+            hdr.segmentation.seg_data.d[s]
+        } else {
+            SegmentationData::default()
+        };
+        let lf = &hdr.loopfilter;
+
+        calc_lf_value(
+            &mut lflvl_values[s][0],
+            false,
+            lf.level_y[0],
+            lf_delta[0] as i32,
+            segd.delta_lf_y_v,
+            mr_deltas,
+        );
+        calc_lf_value(
+            &mut lflvl_values[s][1],
+            false,
+            lf.level_y[1],
+            lf_delta[if multi { 1 } else { 0 }] as i32,
+            segd.delta_lf_y_h,
+            mr_deltas,
+        );
+        calc_lf_value(
+            &mut lflvl_values[s][2],
+            true,
+            lf.level_u,
+            lf_delta[if multi { 2 } else { 0 }] as i32,
+            segd.delta_lf_u,
+            mr_deltas,
+        );
+        calc_lf_value(
+            &mut lflvl_values[s][3],
+            true,
+            lf.level_v,
+            lf_delta[if multi { 3 } else { 0 }] as i32,
+            segd.delta_lf_v,
+            mr_deltas,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::internal::TileState;
+
+    const B4_STRIDE: usize = 32;
+
+    // 16x16 blocks of a 64x64 4:2:0 frame, with 4x4 chroma transforms
+    struct Blocks {
+        lflvl: Av1Filter,
+        level: Vec<[u8; 4]>,
+        a: [[u8; 32]; 2],
+        l: [[u8; 32]; 2],
+    }
+
+    impl Blocks {
+        fn new() -> Self {
+            Blocks {
+                lflvl: Av1Filter::default(),
+                level: vec![[0; 4]; B4_STRIDE * 16],
+                a: [[2; 32], [1; 32]],
+                l: [[2; 32], [1; 32]],
+            }
+        }
+
+        fn intra(&mut self, filter_level: [u8; 4], bx: usize, by: usize, ytx: TxfmSize) {
+            let ([ay, auv], [ly, luv]) = (&mut self.a, &mut self.l);
+            create_lf_mask_intra(
+                &mut self.lflvl,
+                &mut self.level,
+                B4_STRIDE,
+                &filter_level,
+                bx,
+                by,
+                16,
+                16,
+                BlockSize::BS_16x16,
+                ytx as usize,
+                TxfmSize::TX_4X4 as usize,
+                PixelLayout::PIXEL_LAYOUT_I420,
+                &mut ay[bx..],
+                &mut ly[by..],
+                Some((&mut auv[bx >> 1..], &mut luv[by >> 1..])),
+            );
+        }
+
+        fn inter(
+            &mut self,
+            filter_level: [u8; 4],
+            bx: usize,
+            by: usize,
+            skip: bool,
+            max_ytx: TxfmSize,
+            tx_masks: [u16; 2],
+        ) {
+            let ([ay, auv], [ly, luv]) = (&mut self.a, &mut self.l);
+            create_lf_mask_inter(
+                &mut self.lflvl,
+                &mut self.level,
+                B4_STRIDE,
+                &filter_level,
+                bx,
+                by,
+                16,
+                16,
+                skip,
+                BlockSize::BS_16x16,
+                max_ytx as usize,
+                &tx_masks,
+                TxfmSize::TX_4X4 as usize,
+                PixelLayout::PIXEL_LAYOUT_I420,
+                &mut ay[bx..],
+                &mut ly[by..],
+                Some((&mut auv[bx >> 1..], &mut luv[by >> 1..])),
+            );
+        }
+    }
+
+    #[test]
+    fn create_lf_mask() {
+        let mut hdr = FrameHeader::default();
+        hdr.loopfilter.level_y = [30, 20];
+        hdr.loopfilter.level_u = 10;
+        hdr.loopfilter.level_v = 12;
+        hdr.loopfilter.mode_ref_delta_enabled = true;
+        hdr.segmentation.enabled = true;
+        hdr.segmentation.seg_data.d[1].delta_lf_y_v = -10;
+        hdr.delta.lf.present = true;
+        hdr.delta.lf.res_log2 = 1;
+        let mut frame_lvl = FilterLevels::default();
+        calc_lf_values(&mut frame_lvl, &hdr, &[0; 4]);
+        assert_eq!(filter_level(&frame_lvl, 0, 0, false), [31, 21, 11, 13]);
+
+        // a superblock raising the levels of the rest of the tile by 4
+        let mut ts = TileState::default();
+        ts.update_delta_lf(&hdr, &[2, 0, 0, 0]);
+        let lflvl = ts.lflvl(&hdr, &frame_lvl);
+
+        //   A: intra, 8x8 transforms     B: inter from LAST, one split
+        //                                   of its 16x16 transform
+        //   C: skipped inter from        D: intra, 4x4 transforms,
+        //      GOLDEN with global motion,   segment 1
+        //      8x8 transforms
+        let mut b = Blocks::new();
+        b.intra(filter_level(lflvl, 0, 0, false), 0, 0, TxfmSize::TX_8X8);
+        let tx = TxfmSize::TX_16X16;
+        b.inter(filter_level(lflvl, 0, 1, false), 4, 0, false, tx, [1, 0]);
+        let tx = TxfmSize::TX_8X8;
+        b.inter(filter_level(lflvl, 0, 4, true), 0, 4, true, tx, [0, 0]);
+        b.intra(filter_level(lflvl, 1, 0, false), 4, 4, TxfmSize::TX_4X4);
+
+        // vertical edges per column and horizontal ones per row, by
+        // transform size (4, 8 and 16+ px) and 4px row/column: the block
+        // edges and the inner transform edges, except those of C
+        let y = &b.lflvl.filter_y;
+        assert_eq!(y[0][0], [[0, 0], [0xff, 0], [0, 0]]);
+        assert_eq!(y[0][1], [[0, 0]; 3]);
+        assert_eq!(y[0][2], [[0, 0], [0x0f, 0], [0, 0]]);
+        assert_eq!(y[0][4], [[0xf0, 0], [0x0f, 0], [0, 0]]);
+        assert_eq!(y[0][5], [[0xf0, 0], [0, 0], [0, 0]]);
+        assert_eq!(y[0][6], [[0xf0, 0], [0x0f, 0], [0, 0]]);
+        assert_eq!(y[0][8], [[0, 0]; 3]);
+        assert_eq!(y[1][0], [[0, 0], [0xff, 0], [0, 0]]);
+        assert_eq!(y[1][2], [[0, 0], [0xff, 0], [0, 0]]);
+        assert_eq!(y[1][4], [[0xf0, 0], [0x0f, 0], [0, 0]]);
+        assert_eq!(y[1][6], [[0xf0, 0], [0, 0], [0, 0]]);
+        // the 8px chroma blocks
+        let uv = &b.lflvl.filter_uv;
+        assert_eq!(uv[0][0], [[0x0f, 0], [0, 0]]);
+        assert_eq!(uv[0][1], [[0x03, 0], [0, 0]]);
+        assert_eq!(uv[0][2], [[0x0f, 0], [0, 0]]);
+        assert_eq!(uv[0][3], [[0x0f, 0], [0, 0]]);
+        assert_eq!(uv[1][1], [[0x0f, 0], [0, 0]]);
+        assert_eq!(uv[1][3], [[0x0c, 0], [0, 0]]);
+
+        // levels raised by the tile's delta_lf, then by the intra, LAST
+        // (none) and GOLDEN with global motion deltas, doubled from level 32
+        let level = &b.level;
+        assert_eq!(level[0], [36, 25, 15, 17]);
+        assert_eq!(level[3], [36, 25, 14, 16]);
+        assert_eq!(level[2 * B4_STRIDE + 1], [36, 25, 13, 15]);
+        assert_eq!(level[4 * B4_STRIDE + 1], [32, 23, 0, 0]);
+        assert_eq!(level[5 * B4_STRIDE + 6], [25, 25, 0, 0]);
+        assert_eq!(level[16 * B4_STRIDE - 1], [0; 4]);
+    }
 }
@@ -1,17 +1,39 @@
 #![allow(warnings)]
 
 pub mod api;
+pub mod cdef;
+pub mod cdef_apply;
+pub mod cdf;
+#[cfg(test)]
+mod checkasm;
 pub mod context;
+pub mod cpu;
 pub mod decode;
 pub mod dequant_tables;
+pub mod error;
+pub mod fg_apply;
+pub mod filmgrain;
 pub mod frame;
 pub mod getbits;
+pub mod hdr10plus;
 pub mod headers;
 pub mod internal;
 pub mod levels;
+pub mod lf_apply;
 pub mod lf_mask;
+pub mod loopfilter;
+pub mod looprestoration;
+pub mod lr_apply;
 pub mod macros;
+pub mod mc;
+pub mod msac;
 pub mod obu;
 pub mod plane;
 pub mod plane_region;
-pub mod util;
\ No newline at end of file
+pub mod recon;
+pub mod ref_mvs;
+pub mod tables;
+pub mod util;
+pub mod warpmv;
+#[cfg(target_arch = "x86_64")]
+mod x86;
@@ -0,0 +1,359 @@
+use crate::lf_mask::Av1FilterLUT;
+use crate::util::*;
+
+use std::cmp;
+
+// Filter 4 pixel lines across one edge, see 7.14.6 "Edge loop filter
+// process". off is the first pixel after the edge, stridea steps along the
+// edge and strideb across it; wd is the filter size (4, 6, 8 or 16).
+fn loop_filter<T: Pixel>(
+    dst: &mut [T],
+    mut off: isize,
+    e: i32,
+    i: i32,
+    h: i32,
+    stridea: isize,
+    strideb: isize,
+    wd: usize,
+    bitdepth_max: i32,
+) {
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+    let flat = 1 << bitdepth_min_8;
+    let e = e << bitdepth_min_8;
+    let i = i << bitdepth_min_8;
+    let h = h << bitdepth_min_8;
+
+    for _ in 0..4 {
+        // px[7 + k] is the pixel at distance k from the edge, i.e. q0 is
+        // px[7] and p0 is px[6]
+        let mut px = [0i32; 14];
+        let n_px = match wd {
+            16 => 7,
+            8 => 4,
+            6 => 3,
+            _ => 2,
+        };
+        for k in -n_px..n_px {
+            px[(7 + k) as usize] = i32::cast_from(dst[(off + k * strideb) as usize]);
+        }
+        let p = |k: usize| px[6 - k];
+        let q = |k: usize| px[7 + k];
+
+        let mut fm = (p(1) - p(0)).abs() <= i
+            && (q(1) - q(0)).abs() <= i
+            && (p(0) - q(0)).abs() * 2 + ((p(1) - q(1)).abs() >> 1) <= e;
+        if wd > 4 {
+            fm &= (p(2) - p(1)).abs() <= i && (q(2) - q(1)).abs() <= i;
+            if wd > 6 {
+                fm &= (p(3) - p(2)).abs() <= i && (q(3) - q(2)).abs() <= i;
+            }
+        }
+        if !fm {
+            off += stridea;
+            continue;
+        }
+
+        let flat8out =
+            wd >= 16 && (4..7).all(|k| (p(k) - p(0)).abs() <= flat && (q(k) - q(0)).abs() <= flat);
+        let flat8in = wd >= 6
+            && (1..cmp::min(wd, 8) / 2)
+                .all(|k| (p(k) - p(0)).abs() <= flat && (q(k) - q(0)).abs() <= flat);
+
+        if flat8in {
+            // (2n + 1)-tap smoothing of the 2n pixels around the edge, with
+            // the center taps doubled to make up a power of two; the 14-tap
+            // filter falls back to the 8-tap one if only the inner pixels
+            // are flat
+            let (n, n2, log2size): (isize, isize, usize) = if wd == 16 && flat8out {
+                (6, 1, 4)
+            } else if wd >= 8 {
+                (3, 0, 3)
+            } else {
+                (2, 1, 3)
+            };
+            let mut out = [0i32; 12];
+            for k in -n..n {
+                let mut t = 0;
+                for j in -n..=n {
+                    let pos = clip(k + j, -(n + 1), n);
+                    let tap = if j.abs() <= n2 { 2 } else { 1 };
+                    t += px[(7 + pos) as usize] * tap;
+                }
+                out[(k + n) as usize] = round_shift(t, log2size);
+            }
+            for k in -n..n {
+                dst[(off + k * strideb) as usize] = T::cast_from(out[(k + n) as usize]);
+            }
+        } else {
+            let hev = (p(1) - p(0)).abs() > h || (q(1) - q(0)).abs() > h;
+            let diff_max = (128 << bitdepth_min_8) - 1;
+            let iclip_diff = |v: i32| clip(v, -128 << bitdepth_min_8, diff_max);
+            let iclip_pixel = |v: i32| T::cast_from(clip(v, 0, bitdepth_max));
+
+            let base = if hev { iclip_diff(p(1) - q(1)) } else { 0 };
+            let f = iclip_diff(3 * (q(0) - p(0)) + base);
+            let f1 = cmp::min(f + 4, diff_max) >> 3;
+            let f2 = cmp::min(f + 3, diff_max) >> 3;
+
+            dst[(off - strideb) as usize] = iclip_pixel(p(0) + f2);
+            dst[off as usize] = iclip_pixel(q(0) - f1);
+
+            if !hev {
+                let f = (f1 + 1) >> 1;
+                dst[(off - 2 * strideb) as usize] = iclip_pixel(p(1) + f);
+                dst[(off + strideb) as usize] = iclip_pixel(q(1) - f);
+            }
+        }
+        off += stridea;
+    }
+}
+
+// The filter applied to each edge by the superblock functions below, so
+// that they can be shared by the scalar and the SIMD DSP tables.
+pub(crate) trait LoopFilterEdge {
+    fn loop_filter<T: Pixel>(
+        dst: &mut [T],
+        off: isize,
+        e: i32,
+        i: i32,
+        h: i32,
+        stridea: isize,
+        strideb: isize,
+        wd: usize,
+        bitdepth_max: i32,
+    );
+}
+
+pub(crate) struct LoopFilterC;
+
+impl LoopFilterEdge for LoopFilterC {
+    #[inline(always)]
+    fn loop_filter<T: Pixel>(
+        dst: &mut [T],
+        off: isize,
+        e: i32,
+        i: i32,
+        h: i32,
+        stridea: isize,
+        strideb: isize,
+        wd: usize,
+        bitdepth_max: i32,
+    ) {
+        loop_filter(dst, off, e, i, h, stridea, strideb, wd, bitdepth_max);
+    }
+}
+
+// Filter level of a 4x4 block, or of its left/top neighbour if the block
+// itself is unfiltered
+#[inline(always)]
+pub(crate) fn filter_level(lvl: &[[u8; 4]], off: usize, neighbour: usize, dir: usize) -> usize {
+    match lvl[off][dir] {
+        0 => lvl[off - neighbour][dir] as usize,
+        l => l as usize,
+    }
+}
+
+// Filter the vertical edges of one 4px column of a superblock; bit y of
+// vmask[idx] is set if the edge in 4px row y uses filter size idx.
+pub(crate) fn loop_filter_h_sb128y<T: Pixel, E: LoopFilterEdge>(
+    dst: &mut [T],
+    mut off: usize,
+    stride: usize,
+    vmask: &[u32; 3],
+    lvl: &[[u8; 4]],
+    mut lvl_off: usize,
+    b4_stride: usize,
+    lut: &Av1FilterLUT,
+    bitdepth_max: i32,
+) {
+    let vm = vmask[0] | vmask[1] | vmask[2];
+    let mut y = 1u32;
+    while y != 0 && vm & !(y - 1) != 0 {
+        if vm & y != 0 {
+            let l = filter_level(lvl, lvl_off, 1, 0);
+            if l != 0 {
+                let idx = if vmask[2] & y != 0 {
+                    2
+                } else {
+                    (vmask[1] & y != 0) as usize
+                };
+                let (e, i) = (lut.e[l] as i32, lut.i[l] as i32);
+                let h = (l >> 4) as i32;
+                E::loop_filter(
+                    dst,
+                    off as isize,
+                    e,
+                    i,
+                    h,
+                    stride as isize,
+                    1,
+                    4 << idx,
+                    bitdepth_max,
+                );
+            }
+        }
+        y <<= 1;
+        off += 4 * stride;
+        lvl_off += b4_stride;
+    }
+}
+
+// Filter the horizontal edges of one 4px row of a superblock
+pub(crate) fn loop_filter_v_sb128y<T: Pixel, E: LoopFilterEdge>(
+    dst: &mut [T],
+    mut off: usize,
+    stride: usize,
+    vmask: &[u32; 3],
+    lvl: &[[u8; 4]],
+    mut lvl_off: usize,
+    b4_stride: usize,
+    lut: &Av1FilterLUT,
+    bitdepth_max: i32,
+) {
+    let vm = vmask[0] | vmask[1] | vmask[2];
+    let mut x = 1u32;
+    while x != 0 && vm & !(x - 1) != 0 {
+        if vm & x != 0 {
+            let l = filter_level(lvl, lvl_off, b4_stride, 1);
+            if l != 0 {
+                let idx = if vmask[2] & x != 0 {
+                    2
+                } else {
+                    (vmask[1] & x != 0) as usize
+                };
+                let (e, i) = (lut.e[l] as i32, lut.i[l] as i32);
+                let h = (l >> 4) as i32;
+                E::loop_filter(
+                    dst,
+                    off as isize,
+                    e,
+                    i,
+                    h,
+                    1,
+                    stride as isize,
+                    4 << idx,
+                    bitdepth_max,
+                );
+            }
+        }
+        x <<= 1;
+        off += 4;
+        lvl_off += 1;
+    }
+}
+
+// Same as loop_filter_h_sb128y for a chroma plane, dir selects the u (2)
+// or v (3) filter level
+pub(crate) fn loop_filter_h_sb128uv<T: Pixel, E: LoopFilterEdge>(
+    dst: &mut [T],
+    mut off: usize,
+    stride: usize,
+    vmask: &[u32; 2],
+    lvl: &[[u8; 4]],
+    mut lvl_off: usize,
+    dir: usize,
+    b4_stride: usize,
+    lut: &Av1FilterLUT,
+    bitdepth_max: i32,
+) {
+    let vm = vmask[0] | vmask[1];
+    let mut y = 1u32;
+    while y != 0 && vm & !(y - 1) != 0 {
+        if vm & y != 0 {
+            let l = filter_level(lvl, lvl_off, 1, dir);
+            if l != 0 {
+                let idx = (vmask[1] & y != 0) as usize;
+                let (e, i) = (lut.e[l] as i32, lut.i[l] as i32);
+                let h = (l >> 4) as i32;
+                E::loop_filter(
+                    dst,
+                    off as isize,
+                    e,
+                    i,
+                    h,
+                    stride as isize,
+                    1,
+                    4 + 2 * idx,
+                    bitdepth_max,
+                );
+            }
+        }
+        y <<= 1;
+        off += 4 * stride;
+        lvl_off += b4_stride;
+    }
+}
+
+// Same as loop_filter_v_sb128y for a chroma plane
+pub(crate) fn loop_filter_v_sb128uv<T: Pixel, E: LoopFilterEdge>(
+    dst: &mut [T],
+    mut off: usize,
+    stride: usize,
+    vmask: &[u32; 2],
+    lvl: &[[u8; 4]],
+    mut lvl_off: usize,
+    dir: usize,
+    b4_stride: usize,
+    lut: &Av1FilterLUT,
+    bitdepth_max: i32,
+) {
+    let vm = vmask[0] | vmask[1];
+    let mut x = 1u32;
+    while x != 0 && vm & !(x - 1) != 0 {
+        if vm & x != 0 {
+            let l = filter_level(lvl, lvl_off, b4_stride, dir);
+            if l != 0 {
+                let idx = (vmask[1] & x != 0) as usize;
+                let (e, i) = (lut.e[l] as i32, lut.i[l] as i32);
+                let h = (l >> 4) as i32;
+                E::loop_filter(
+                    dst,
+                    off as isize,
+                    e,
+                    i,
+                    h,
+                    1,
+                    stride as isize,
+                    4 + 2 * idx,
+                    bitdepth_max,
+                );
+            }
+        }
+        x <<= 1;
+        off += 4;
+        lvl_off += 1;
+    }
+}
+
+pub(crate) type LoopFilterSbYFn<T> =
+    fn(&mut [T], usize, usize, &[u32; 3], &[[u8; 4]], usize, usize, &Av1FilterLUT, i32);
+pub(crate) type LoopFilterSbUVFn<T> =
+    fn(&mut [T], usize, usize, &[u32; 2], &[[u8; 4]], usize, usize, usize, &Av1FilterLUT, i32);
+
+#[derive(Clone, Copy)]
+pub(crate) struct LoopFilterDSPContext<T: Pixel> {
+    // [0] filters the vertical edges of a column (h), [1] the horizontal
+    // edges of a row (v)
+    pub(crate) loop_filter_sby: [LoopFilterSbYFn<T>; 2],
+    pub(crate) loop_filter_sbuv: [LoopFilterSbUVFn<T>; 2],
+}
+
+impl<T: Pixel> LoopFilterDSPContext<T> {
+    pub(crate) fn new(flags: u32) -> Self {
+        let mut c = LoopFilterDSPContext {
+            loop_filter_sby: [
+                loop_filter_h_sb128y::<T, LoopFilterC>,
+                loop_filter_v_sb128y::<T, LoopFilterC>,
+            ],
+            loop_filter_sbuv: [
+                loop_filter_h_sb128uv::<T, LoopFilterC>,
+                loop_filter_v_sb128uv::<T, LoopFilterC>,
+            ],
+        };
+
+        #[cfg(target_arch = "x86_64")]
+        crate::x86::loopfilter::lf_dsp_init_x86(&mut c, flags);
+
+        c
+    }
+}
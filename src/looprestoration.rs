@@ -0,0 +1,579 @@
+use crate::tables::*;
+use crate::util::*;
+
+use std::cmp;
+
+pub(crate) const LR_HAVE_LEFT: u32 = 1 << 0;
+pub(crate) const LR_HAVE_RIGHT: u32 = 1 << 1;
+pub(crate) const LR_HAVE_TOP: u32 = 1 << 2;
+pub(crate) const LR_HAVE_BOTTOM: u32 = 1 << 3;
+
+// stride of the padded filter input, i.e. the maximum restoration unit
+// width (256 * 1.5) plus 3 px on either side
+const REST_UNIT_STRIDE: usize = 390;
+
+// stride of the self-guided filter output
+const SGR_DST_STRIDE: usize = 384;
+
+// Copy the unit_w x stripe_h stripe at p_off of p and the 3 px border
+// around it into dst (stride REST_UNIT_STRIDE). Rows above and below the
+// stripe come from the deblocked lines at lpf_off of lpf, pixels to the
+// left from the pre-filter column backup; unavailable ones replicate the
+// nearest edge pixel.
+fn padding<T: Pixel>(
+    dst: &mut [T],
+    p: &[T],
+    p_off: usize,
+    p_stride: usize,
+    left: &[[T; 4]],
+    lpf: &[T],
+    lpf_off: usize,
+    lpf_stride: usize,
+    unit_w: usize,
+    stripe_h: usize,
+    edges: u32,
+) {
+    let have_left = (edges & LR_HAVE_LEFT != 0) as usize;
+    let have_right = (edges & LR_HAVE_RIGHT != 0) as usize;
+
+    // copy more pixels if we don't have to pad them
+    let unit_w = unit_w + 3 * have_left + 3 * have_right;
+    let dst_l = 3 * (1 - have_left);
+    let p_off = p_off - 3 * have_left;
+    let lpf_off = lpf_off - 3 * have_left;
+
+    if edges & LR_HAVE_TOP != 0 {
+        // copy previous loop filtered rows
+        let above_1 = &lpf[lpf_off..lpf_off + unit_w];
+        let above_2 = &lpf[lpf_off + lpf_stride..lpf_off + lpf_stride + unit_w];
+        dst[dst_l..dst_l + unit_w].copy_from_slice(above_1);
+        dst[dst_l + REST_UNIT_STRIDE..][..unit_w].copy_from_slice(above_1);
+        dst[dst_l + 2 * REST_UNIT_STRIDE..][..unit_w].copy_from_slice(above_2);
+    } else {
+        // pad with first row
+        for j in 0..3 {
+            let row = dst_l + j * REST_UNIT_STRIDE;
+            dst[row..row + unit_w].copy_from_slice(&p[p_off..p_off + unit_w]);
+            if have_left != 0 {
+                dst[row..row + 3].copy_from_slice(&left[0][1..]);
+            }
+        }
+    }
+
+    let dst_tl = dst_l + 3 * REST_UNIT_STRIDE;
+    if edges & LR_HAVE_BOTTOM != 0 {
+        // copy next loop filtered rows
+        let below_1 = lpf_off + 6 * lpf_stride;
+        let below_2 = below_1 + lpf_stride;
+        for (j, src) in [below_1, below_2, below_2].iter().enumerate() {
+            let row = dst_tl + (stripe_h + j) * REST_UNIT_STRIDE;
+            dst[row..row + unit_w].copy_from_slice(&lpf[*src..*src + unit_w]);
+        }
+    } else {
+        // pad with last row
+        let src = p_off + (stripe_h - 1) * p_stride;
+        for j in 0..3 {
+            let row = dst_tl + (stripe_h + j) * REST_UNIT_STRIDE;
+            dst[row..row + unit_w].copy_from_slice(&p[src..src + unit_w]);
+            if have_left != 0 {
+                dst[row..row + 3].copy_from_slice(&left[stripe_h - 1][1..]);
+            }
+        }
+    }
+
+    // inner unit_w x stripe_h
+    for j in 0..stripe_h {
+        let row = dst_tl + j * REST_UNIT_STRIDE + 3 * have_left;
+        let src = p_off + j * p_stride + 3 * have_left;
+        let n = unit_w - 3 * have_left;
+        dst[row..row + n].copy_from_slice(&p[src..src + n]);
+    }
+
+    if have_right == 0 {
+        // pad 3 x (stripe_h + 6) with last column
+        for j in 0..stripe_h + 6 {
+            let row = dst_l + j * REST_UNIT_STRIDE;
+            let last = dst[row + unit_w - 1];
+            for v in dst[row + unit_w..row + unit_w + 3].iter_mut() {
+                *v = last;
+            }
+        }
+    }
+
+    if have_left == 0 {
+        // pad 3 x (stripe_h + 6) with first column
+        for j in 0..stripe_h + 6 {
+            let row = j * REST_UNIT_STRIDE;
+            let first = dst[row + dst_l];
+            for v in dst[row..row + 3].iter_mut() {
+                *v = first;
+            }
+        }
+    } else {
+        for j in 0..stripe_h {
+            let row = (j + 3) * REST_UNIT_STRIDE;
+            dst[row..row + 3].copy_from_slice(&left[j][1..]);
+        }
+    }
+}
+
+// Apply the separable 7-tap Wiener filter to a w x h stripe in place, see
+// 7.17.4 "Wiener filter process". The center taps are implicitly offset
+// by 128.
+pub(crate) fn wiener_filter<T: Pixel>(
+    p: &mut [T],
+    p_off: usize,
+    p_stride: usize,
+    left: &[[T; 4]],
+    lpf: &[T],
+    lpf_off: usize,
+    lpf_stride: usize,
+    w: usize,
+    h: usize,
+    filterh: &[i16; 7],
+    filterv: &[i16; 7],
+    edges: u32,
+    bitdepth_max: i32,
+) {
+    let mut tmp = vec![T::cast_from(0); (h + 6) * REST_UNIT_STRIDE];
+    padding(
+        &mut tmp, p, p_off, p_stride, left, lpf, lpf_off, lpf_stride, w, h, edges,
+    );
+
+    // values stored between horizontal and vertical filtering don't fit
+    // in a u8
+    let mut hor = vec![0u16; (h + 6) * REST_UNIT_STRIDE];
+
+    let bitdepth = 32 - bitdepth_max.leading_zeros() as i32;
+    let round_bits_h = 3 + (bitdepth == 12) as i32 * 2;
+    let rounding_off_h = 1 << (round_bits_h - 1);
+    let clip_limit = 1 << (bitdepth + 1 + 7 - round_bits_h);
+    for j in 0..h + 6 {
+        for i in 0..w {
+            let row = &tmp[j * REST_UNIT_STRIDE + i..][..7];
+            let mut sum = (i32::cast_from(row[3]) << 7) + (1 << (bitdepth + 6));
+            for k in 0..7 {
+                sum += i32::cast_from(row[k]) * filterh[k] as i32;
+            }
+            hor[j * REST_UNIT_STRIDE + i] =
+                clip((sum + rounding_off_h) >> round_bits_h, 0, clip_limit - 1) as u16;
+        }
+    }
+
+    let round_bits_v = 11 - (bitdepth == 12) as i32 * 2;
+    let rounding_off_v = 1 << (round_bits_v - 1);
+    let round_offset = 1 << (bitdepth + (round_bits_v - 1));
+    for j in 0..h {
+        for i in 0..w {
+            let at = |k: usize| hor[(j + k) * REST_UNIT_STRIDE + i] as i32;
+            let mut sum = (at(3) << 7) - round_offset;
+            for k in 0..7 {
+                sum += at(k) * filterv[k] as i32;
+            }
+            p[p_off + j * p_stride + i] = T::cast_from(clip(
+                (sum + rounding_off_v) >> round_bits_v,
+                0,
+                bitdepth_max,
+            ));
+        }
+    }
+}
+
+// One pass of the self-guided filter with radius 2 (n = 25) or 1 (n = 9)
+// over the padded stripe src, see 7.17.3 "Box filter process". dst
+// receives the filtered w x h stripe, scaled up by 4 bits.
+fn selfguided_pass<T: Pixel>(
+    dst: &mut [i32],
+    src: &[T],
+    w: usize,
+    h: usize,
+    n: i32,
+    s: i32,
+    bitdepth_max: i32,
+) {
+    let r = if n == 25 { 2 } else { 1 };
+    let one_by_x = if n == 25 { 164 } else { 455 };
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+
+    // a2 and b2 per position, including a 1 px border around the stripe;
+    // the radius 2 pass only computes every other row
+    let mut a = vec![0i32; (h + 2) * REST_UNIT_STRIDE];
+    let mut b = vec![0i32; (h + 2) * REST_UNIT_STRIDE];
+    for j in (0..h + 2).step_by(r) {
+        for i in 0..w + 2 {
+            let (mut sum, mut sumsq) = (0i32, 0i32);
+            for y in j + 2 - r..=j + 2 + r {
+                for x in i + 2 - r..=i + 2 + r {
+                    let v = i32::cast_from(src[y * REST_UNIT_STRIDE + x]);
+                    sum += v;
+                    sumsq += v * v;
+                }
+            }
+            let aa = (sumsq + ((1 << (2 * bitdepth_min_8)) >> 1)) >> (2 * bitdepth_min_8);
+            let bb = (sum + ((1 << bitdepth_min_8) >> 1)) >> bitdepth_min_8;
+            let p = cmp::max(aa * n - bb * bb, 0) as u64;
+            let z = (p * s as u64 + (1 << 19)) >> 20;
+            let x = sgr_x_by_x[cmp::min(z, 255) as usize] as u64;
+
+            a[j * REST_UNIT_STRIDE + i] = 256 - x as i32;
+            b[j * REST_UNIT_STRIDE + i] = ((x * sum as u64 * one_by_x + (1 << 11)) >> 12) as i32;
+        }
+    }
+
+    for j in 0..h {
+        for i in 0..w {
+            // (j, i) of the stripe is at (j + 1, i + 1) in a and b
+            let pos = (j + 1) * REST_UNIT_STRIDE + i + 1;
+            let (weighted_a, weighted_b, shift) = if r == 2 && j & 1 == 0 {
+                let six_neighbors = |v: &[i32]| {
+                    let (up, down) = (pos - REST_UNIT_STRIDE, pos + REST_UNIT_STRIDE);
+                    (v[up] + v[down]) * 6 + (v[up - 1] + v[up + 1] + v[down - 1] + v[down + 1]) * 5
+                };
+                (six_neighbors(&a), six_neighbors(&b), 9)
+            } else if r == 2 {
+                let three_neighbors = |v: &[i32]| v[pos] * 6 + (v[pos - 1] + v[pos + 1]) * 5;
+                (three_neighbors(&a), three_neighbors(&b), 8)
+            } else {
+                let eight_neighbors = |v: &[i32]| {
+                    let (up, down) = (pos - REST_UNIT_STRIDE, pos + REST_UNIT_STRIDE);
+                    (v[pos] + v[pos - 1] + v[pos + 1] + v[up] + v[down]) * 4
+                        + (v[up - 1] + v[up + 1] + v[down - 1] + v[down + 1]) * 3
+                };
+                (eight_neighbors(&a), eight_neighbors(&b), 9)
+            };
+            let px = i32::cast_from(src[(j + 3) * REST_UNIT_STRIDE + i + 3]);
+            dst[j * SGR_DST_STRIDE + i] =
+                (weighted_a * px + weighted_b + (1 << (shift - 1))) >> shift;
+        }
+    }
+}
+
+// Apply the self-guided filter with parameter set sgr_idx to a w x h
+// stripe in place, projecting the output of its one or two passes with
+// sgr_w, see 7.17.3 "Box filter process" and 7.17.2 "Self guided filter
+// process".
+pub(crate) fn selfguided_filter<T: Pixel>(
+    p: &mut [T],
+    p_off: usize,
+    p_stride: usize,
+    left: &[[T; 4]],
+    lpf: &[T],
+    lpf_off: usize,
+    lpf_stride: usize,
+    w: usize,
+    h: usize,
+    sgr_idx: usize,
+    sgr_w: &[i16; 2],
+    edges: u32,
+    bitdepth_max: i32,
+) {
+    let mut tmp = vec![T::cast_from(0); (h + 6) * REST_UNIT_STRIDE];
+    padding(
+        &mut tmp, p, p_off, p_stride, left, lpf, lpf_off, lpf_stride, w, h, edges,
+    );
+
+    let [r0, r1, s0, s1] = sgr_params[sgr_idx];
+    let mut dst0 = vec![0i32; h * SGR_DST_STRIDE];
+    let mut dst1 = vec![0i32; h * SGR_DST_STRIDE];
+    // r0 and r1 can't both be zero
+    let (w0, w1) = if r0 == 0 {
+        (0, (1 << 7) - sgr_w[1] as i32)
+    } else if r1 == 0 {
+        (sgr_w[0] as i32, 0)
+    } else {
+        (
+            sgr_w[0] as i32,
+            (1 << 7) - sgr_w[0] as i32 - sgr_w[1] as i32,
+        )
+    };
+    if r0 != 0 {
+        selfguided_pass(&mut dst0, &tmp, w, h, 25, s0 as i32, bitdepth_max);
+    }
+    if r1 != 0 {
+        selfguided_pass(&mut dst1, &tmp, w, h, 9, s1 as i32, bitdepth_max);
+    }
+
+    for j in 0..h {
+        for i in 0..w {
+            let px = &mut p[p_off + j * p_stride + i];
+            let u = i32::cast_from(*px) << 4;
+            let v = (u << 7)
+                + w0 * (dst0[j * SGR_DST_STRIDE + i] - u)
+                + w1 * (dst1[j * SGR_DST_STRIDE + i] - u);
+            *px = T::cast_from(clip((v + (1 << 10)) >> 11, 0, bitdepth_max));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // a pixel of the stripe: row, column and value
+    type Px = (usize, usize, u16);
+
+    const ALL: u32 = LR_HAVE_LEFT | LR_HAVE_RIGHT | LR_HAVE_TOP | LR_HAVE_BOTTOM;
+
+    // Apply the Wiener filter with the given 3 outer taps to the 16x8
+    // stripe at (0, 4) of a 24x8 image of 100 px, with the given pixels
+    // set and the deblocked lines and left column backup at border.
+    // Returns the pixels that changed.
+    fn wiener(set: &[Px], border: u16, h: [i16; 3], v: [i16; 3], edges: u32) -> Vec<Px> {
+        let mut p = [100u16; 24 * 8];
+        for &(y, x, v) in set {
+            p[y * 24 + x + 4] = v;
+        }
+        let src = p;
+        let taps = |f: [i16; 3]| {
+            [
+                f[0],
+                f[1],
+                f[2],
+                -2 * (f[0] + f[1] + f[2]),
+                f[2],
+                f[1],
+                f[0],
+            ]
+        };
+        let (left, lpf) = ([[border; 4]; 8], [border; 24 * 8]);
+        wiener_filter(
+            &mut p,
+            4,
+            24,
+            &left,
+            &lpf,
+            4,
+            24,
+            16,
+            8,
+            &taps(h),
+            &taps(v),
+            edges,
+            255,
+        );
+        let px = |i: usize| (i / 16) * 24 + i % 16 + 4;
+        let changed = (0..16 * 8).filter(|&i| p[px(i)] != src[px(i)]);
+        changed.map(|i| (i / 16, i % 16, p[px(i)])).collect()
+    }
+
+    #[test]
+    fn wiener_taps() {
+        // the taps sum to 128, so a flat area stays as is
+        assert_eq!(wiener(&[], 100, [3, -7, 15], [-1, 8, -20], ALL), vec![]);
+        // the response to a 64 px impulse is the outer product of the
+        // normalized taps [16, 96, 16] / 128: 64 * 96 * 96 / 128^2 = 36,
+        // 64 * 96 * 16 / 128^2 = 6 and 64 * 16 * 16 / 128^2 = 1, all
+        // rounded down in the rounding of both passes
+        let changed = vec![
+            (3, 7, 101),
+            (3, 8, 106),
+            (3, 9, 101),
+            (4, 7, 106),
+            (4, 8, 136),
+            (4, 9, 106),
+            (5, 7, 101),
+            (5, 8, 106),
+            (5, 9, 101),
+        ];
+        assert_eq!(
+            wiener(&[(4, 8, 164)], 100, [0, 0, 16], [0, 0, 16], ALL),
+            changed
+        );
+    }
+
+    #[test]
+    fn wiener_edges() {
+        // the 200 px lines above and below the stripe add
+        // 100 * 16 / 128 = 12.5 to its first and last row, rounded up in
+        // the vertical pass
+        let row = |y| (0..16).map(move |x| (y, x, 113));
+        let top = ALL & !LR_HAVE_BOTTOM;
+        assert_eq!(
+            wiener(&[], 200, [0; 3], [0, 0, 16], top),
+            row(0).collect::<Vec<_>>()
+        );
+        let bottom = ALL & !LR_HAVE_TOP;
+        assert_eq!(
+            wiener(&[], 200, [0; 3], [0, 0, 16], bottom),
+            row(7).collect::<Vec<_>>()
+        );
+        let both = row(0).chain(row(7)).collect::<Vec<_>>();
+        assert_eq!(wiener(&[], 200, [0; 3], [0, 0, 16], ALL), both);
+        // at the top and bottom of the frame, the rows of the stripe are
+        // repeated instead
+        let neither = ALL & !(LR_HAVE_TOP | LR_HAVE_BOTTOM);
+        assert_eq!(wiener(&[], 200, [0; 3], [0, 0, 16], neither), vec![]);
+        // likewise left of the stripe, whose columns come from the backup
+        let first_col = (0..8).map(|y| (y, 0, 113)).collect::<Vec<_>>();
+        assert_eq!(wiener(&[], 200, [0, 0, 16], [0; 3], ALL), first_col);
+        assert_eq!(
+            wiener(&[], 200, [0, 0, 16], [0; 3], ALL & !LR_HAVE_LEFT),
+            vec![]
+        );
+    }
+
+    // Apply the self-guided filter to a 16x8 stripe of f(y, x) px, at the
+    // edges of the frame on all sides
+    fn sgr(f: impl Fn(usize, usize) -> u16, sgr_idx: usize, w: [i16; 2], bd_max: i32) -> Vec<u16> {
+        let mut p: Vec<u16> = (0..16 * 8).map(|i| f(i / 16, i % 16)).collect();
+        let left = [[0; 4]; 8];
+        selfguided_filter(
+            &mut p,
+            0,
+            16,
+            &left,
+            &[],
+            0,
+            16,
+            16,
+            8,
+            sgr_idx,
+            &w,
+            0,
+            bd_max,
+        );
+        p
+    }
+
+    #[test]
+    fn selfguided_flat_and_impulse() {
+        // no variance: the box means are the pixels themselves
+        for &idx in &[0, 10, 14] {
+            assert_eq!(sgr(|_, _| 100, idx, [-32, 31], 255), vec![100; 16 * 8]);
+        }
+        // an isolated 255 px has a variance well above the strength of
+        // the radius 1 pass, z saturates at 255 and a2 is 256, so it and
+        // its neighbours keep their values
+        let impulse = |y, x| if (y, x) == (4, 8) { 255 } else { 100 };
+        let expected: Vec<u16> = (0..16 * 8).map(|i| impulse(i / 16, i % 16)).collect();
+        assert_eq!(sgr(impulse, 10, [0, 0], 255), expected);
+    }
+
+    #[test]
+    fn selfguided_smooths_noise() {
+        // a 100/102 checkerboard has a 3x3 variance of 80 / 9^2 px, so z
+        // is 0 and a2 is 1 in the radius 1 pass: the output is the mean of
+        // the box means, 101 * 16 in both phases. Weighted with 128 - 0
+        // that's the output, apart from the 2 px along the edges, where
+        // the repeated pixels shift the means.
+        let board = |y: usize, x: usize| 100 + 2 * ((x + y) & 1) as u16;
+        let p = sgr(board, 10, [0, 0], 255);
+        for y in 2..6 {
+            assert_eq!(p[y * 16 + 2..y * 16 + 14], [101; 12]);
+        }
+        // in 10 bits the variance is scaled back to 8 bits first
+        let p = sgr(|y, x| board(y, x) << 2, 10, [0, 0], 1023);
+        for y in 2..6 {
+            assert_eq!(p[y * 16 + 2..y * 16 + 14], [404; 12]);
+        }
+    }
+
+    // 7.17.3 "Box filter process" and the projection of 7.17.2 as written
+    // in the spec, with a2 computed instead of looked up, for the 16x8
+    // stripe of f(y, x) px with its pixels repeated past its edges
+    fn sgr_reference(
+        f: impl Fn(usize, usize) -> u16,
+        sgr_idx: usize,
+        w: [i16; 2],
+        bitdepth: u32,
+    ) -> Vec<u16> {
+        let round2 = |x: i64, n: u32| if n == 0 { x } else { (x + (1 << (n - 1))) >> n };
+        let px = |y: isize, x: isize| f(y.max(0).min(7) as usize, x.max(0).min(15) as usize) as i64;
+        let [r0, r1, s0, s1] = sgr_params[sgr_idx];
+        let flt = |r: isize, s: i64, pass: usize, i: isize, j: isize| {
+            let n = ((2 * r + 1) * (2 * r + 1)) as i64;
+            let one_over_n = ((1 << 12) + n / 2) / n;
+            let box_ab = |i: isize, j: isize| {
+                let (mut a, mut b) = (0, 0);
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        let c = px(i + dy, j + dx);
+                        a += c * c;
+                        b += c;
+                    }
+                }
+                let a = round2(a, 2 * (bitdepth - 8));
+                let d = round2(b, bitdepth - 8);
+                let p = cmp::max(0, a * n - d * d);
+                let z = round2(p * s, 20);
+                let a2 = if z >= 255 {
+                    256
+                } else if z == 0 {
+                    1
+                } else {
+                    ((z << 8) + z / 2) / (z + 1)
+                };
+                (a2, round2((256 - a2) * b * one_over_n, 12))
+            };
+            let shift = if pass == 0 && i & 1 != 0 { 4 } else { 5 };
+            let (mut a, mut b) = (0, 0);
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let weight = if pass == 0 {
+                        if (i + dy) & 1 != 0 {
+                            if dx == 0 {
+                                6
+                            } else {
+                                5
+                            }
+                        } else {
+                            0
+                        }
+                    } else if dx == 0 || dy == 0 {
+                        4
+                    } else {
+                        3
+                    };
+                    let (a2, b2) = box_ab(i + dy, j + dx);
+                    a += weight * a2;
+                    b += weight * b2;
+                }
+            }
+            round2(a * px(i, j) + b, 8 + shift - 4)
+        };
+        let (w0, w1) = (w[0] as i64, w[1] as i64);
+        let w2 = (1 << 7) - w0 - w1;
+        (0..16 * 8)
+            .map(|k| {
+                let (i, j) = ((k / 16) as isize, (k % 16) as isize);
+                let u = px(i, j) << 4;
+                let flt0 = if r0 != 0 {
+                    flt(r0 as isize, s0 as i64, 0, i, j)
+                } else {
+                    u
+                };
+                let flt1 = if r1 != 0 {
+                    flt(r1 as isize, s1 as i64, 1, i, j)
+                } else {
+                    u
+                };
+                let v = round2(w1 * u + w0 * flt0 + w2 * flt1, 11);
+                cmp::max(0, cmp::min(v, (1 << bitdepth) - 1)) as u16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn selfguided_matches_spec() {
+        // noise of increasing amplitude from left to right, so that z
+        // spans the whole range of sgr_x_by_x
+        let noise = |y: usize, x: usize| {
+            let r = (y * 16 + x) as u32;
+            let r = r.wrapping_mul(2654435761) >> 24;
+            (128 + r as usize % (16 * x + 1) - 8 * x) as u16
+        };
+        for &(idx, w) in &[
+            (0, [-32, 31]),
+            (7, [20, 60]),
+            (10, [0, -20]),
+            (14, [-96, 95]),
+        ] {
+            assert_eq!(sgr(noise, idx, w, 255), sgr_reference(noise, idx, w, 8));
+            let noise10 = |y, x| noise(y, x) << 2 | (x as u16 & 3);
+            assert_eq!(
+                sgr(noise10, idx, w, 1023),
+                sgr_reference(noise10, idx, w, 10)
+            );
+        }
+    }
+}
@@ -0,0 +1,527 @@
+use crate::headers::*;
+use crate::internal::*;
+use crate::lf_mask::*;
+use crate::looprestoration::*;
+use crate::mc::resize;
+use crate::plane::Plane;
+use crate::util::*;
+
+use std::cmp;
+
+pub(crate) const LR_RESTORE_Y: i32 = 1 << 0;
+pub(crate) const LR_RESTORE_U: i32 = 1 << 1;
+pub(crate) const LR_RESTORE_V: i32 = 1 << 2;
+
+#[inline(always)]
+fn pixel_offset<T: Pixel>(p: &Plane<T>, x: usize, y: usize) -> usize {
+    (p.cfg.yorigin + y) * p.cfg.stride + p.cfg.xorigin + x
+}
+
+// The line buffer stores 12 rows of pixels. A superblock row contains at
+// most 2 stripe boundaries, each of which requires 4 rows (2 above and 2
+// below it); the first 4 rows carry the last boundary of the previous
+// superblock row over to the top of the first stripe of this one.
+fn backup_lpf<T: Pixel>(
+    dst: &mut [T],
+    dst_stride: usize,
+    src: &Plane<T>,
+    ss_ver: usize,
+    sb128: bool,
+    mut row: usize,
+    row_h: usize,
+    src_w: usize,
+    dst_w: usize,
+    h: usize,
+    resize_step: Option<(i32, i32)>,
+    bitdepth_max: i32,
+) {
+    // the first stripe of the frame is shorter by 8 luma pixel rows
+    let mut stripe_h = (64 - 8 * (row == 0) as usize) >> ss_ver;
+
+    if row != 0 {
+        // copy the top part of the stored loop filtered pixels from the
+        // previous sb row needed above the first stripe of this sb row
+        let top = 4 << sb128 as usize;
+        dst.copy_within(top * dst_stride..(top + 4) * dst_stride, 0);
+    }
+
+    let mut dst_off = 4 * dst_stride;
+    let mut src_y = row + stripe_h - 2;
+    while row + stripe_h <= row_h {
+        let n_lines = 4 - (row + stripe_h + 1 == h) as usize;
+        let off = pixel_offset(src, 0, src_y);
+        if let Some((dx, mx0)) = resize_step {
+            // the lines are stored in upscaled coordinates
+            resize(
+                &mut dst[dst_off..],
+                dst_stride,
+                &src.data[off..],
+                src.cfg.stride,
+                dst_w,
+                n_lines,
+                src_w,
+                dx,
+                mx0,
+                bitdepth_max,
+            );
+        } else {
+            for i in 0..n_lines {
+                let (dst_off, off) = (dst_off + i * dst_stride, off + i * src.cfg.stride);
+                dst[dst_off..dst_off + src_w].copy_from_slice(&src.data[off..off + src_w]);
+            }
+        }
+        dst_off += n_lines * dst_stride;
+        if n_lines == 3 {
+            dst.copy_within(dst_off - dst_stride..dst_off - dst_stride + dst_w, dst_off);
+            dst_off += dst_stride;
+        }
+        row += stripe_h; // unmodified stripe_h for the first stripe
+        stripe_h = 64 >> ss_ver;
+        src_y += stripe_h;
+    }
+}
+
+// Store the deblocked, not yet CDEF filtered rows around the restoration
+// stripe boundaries of superblock row sby in lf.lr_lpf_line
+pub(crate) fn lr_copy_lpf<T: Pixel>(f: &mut FrameContext<T>, sby: usize) {
+    let FrameContext {
+        seq_hdr,
+        frame_hdr,
+        cur,
+        lf,
+        ..
+    } = f;
+    let seq_hdr = seq_hdr.as_ref().unwrap();
+    let frame_hdr = frame_hdr.as_ref().unwrap();
+    let planes = &cur.as_ref().unwrap().planes;
+    let offset = 8 * (sby > 0) as usize;
+    let lr_stride = lf.lr_line_sz as usize;
+    let sb128 = seq_hdr.sb128;
+
+    // TODO also check block level restore type to reduce copying
+    let restore_planes = lf.restore_planes;
+    let bitdepth_max = seq_hdr.bitdepth_max();
+    let (step, start) = (f.resize_step, f.resize_start);
+    let resize_step = |ss_hor: usize| {
+        if frame_hdr.super_res.enabled {
+            Some((step[ss_hor], start[ss_hor]))
+        } else {
+            None
+        }
+    };
+
+    if restore_planes & LR_RESTORE_Y != 0 {
+        let h = frame_hdr.height as usize;
+        let w = f.bw as usize * 4;
+        let row_h = cmp::min((sby + 1) << (6 + sb128 as usize), h - 1);
+        let y_stripe = (sby << (6 + sb128 as usize)) - offset;
+        backup_lpf(
+            &mut lf.lr_lpf_line[0],
+            lr_stride,
+            &planes[0],
+            0,
+            sb128,
+            y_stripe,
+            row_h,
+            w,
+            frame_hdr.width[1] as usize,
+            h,
+            resize_step(0),
+            bitdepth_max,
+        );
+    }
+    if restore_planes & (LR_RESTORE_U | LR_RESTORE_V) != 0 {
+        let ss_ver = (seq_hdr.layout == PixelLayout::PIXEL_LAYOUT_I420) as usize;
+        let ss_hor = (seq_hdr.layout != PixelLayout::PIXEL_LAYOUT_I444) as usize;
+        let h = (frame_hdr.height as usize + ss_ver) >> ss_ver;
+        let w = (f.bw as usize * 4) >> ss_hor;
+        let row_h = cmp::min((sby + 1) << (6 - ss_ver + sb128 as usize), h - 1);
+        let offset_uv = offset >> ss_ver;
+        let y_stripe = (sby << (6 - ss_ver + sb128 as usize)) - offset_uv;
+
+        for pl in 1..3 {
+            if restore_planes & (LR_RESTORE_Y << pl) != 0 {
+                backup_lpf(
+                    &mut lf.lr_lpf_line[pl],
+                    lr_stride,
+                    &planes[pl],
+                    ss_ver,
+                    sb128,
+                    y_stripe,
+                    row_h,
+                    w,
+                    (frame_hdr.width[1] as usize + ss_hor) >> ss_hor,
+                    h,
+                    resize_step(ss_hor),
+                    bitdepth_max,
+                );
+            }
+        }
+    }
+}
+
+// Filter the rows y..row_h of one restoration unit at x, stripe by stripe
+fn lr_stripe<T: Pixel>(
+    p: &mut Plane<T>,
+    left: &[[T; 4]],
+    lpf: &[T],
+    lpf_stride: usize,
+    x: usize,
+    mut y: usize,
+    ss_ver: usize,
+    unit_w: usize,
+    row_h: usize,
+    lr: &Av1RestorationUnit,
+    mut edges: u32,
+    bitdepth_max: i32,
+) {
+    let sbrow_has_bottom = edges & LR_HAVE_BOTTOM != 0;
+    let stride = p.cfg.stride;
+    let mut lpf_off = x;
+    let mut left_off = 0;
+
+    // the first stripe of the frame is shorter by 8 luma pixel rows
+    let mut stripe_h = cmp::min((64 - 8 * (y == 0) as usize) >> ss_ver, row_h - y);
+
+    let mut filterh = [0i16; 7];
+    let mut filterv = [0i16; 7];
+    if lr.t == RestorationType::RESTORATION_WIENER {
+        for k in 0..3 {
+            filterh[k] = lr.filter_h[k];
+            filterh[6 - k] = lr.filter_h[k];
+            filterv[k] = lr.filter_v[k];
+            filterv[6 - k] = lr.filter_v[k];
+        }
+        filterh[3] = -((filterh[0] + filterh[1] + filterh[2]) * 2);
+        filterv[3] = -((filterv[0] + filterv[1] + filterv[2]) * 2);
+    }
+
+    while y + stripe_h <= row_h {
+        if y + stripe_h == row_h {
+            edges &= !LR_HAVE_BOTTOM;
+        } else {
+            edges |= LR_HAVE_BOTTOM;
+        }
+        let off = pixel_offset(p, x, y);
+        if lr.t == RestorationType::RESTORATION_WIENER {
+            wiener_filter(
+                &mut p.data,
+                off,
+                stride,
+                &left[left_off..],
+                lpf,
+                lpf_off,
+                lpf_stride,
+                unit_w,
+                stripe_h,
+                &filterh,
+                &filterv,
+                edges,
+                bitdepth_max,
+            );
+        } else {
+            debug_assert!(lr.t == RestorationType::RESTORATION_SGRPROJ);
+            selfguided_filter(
+                &mut p.data,
+                off,
+                stride,
+                &left[left_off..],
+                lpf,
+                lpf_off,
+                lpf_stride,
+                unit_w,
+                stripe_h,
+                lr.sgr_idx as usize,
+                &lr.sgr_weights,
+                edges,
+                bitdepth_max,
+            );
+        }
+
+        left_off += stripe_h;
+        y += stripe_h;
+        if y + stripe_h > row_h && sbrow_has_bottom {
+            break;
+        }
+        edges |= LR_HAVE_TOP;
+        stripe_h = cmp::min(64 >> ss_ver, row_h - y);
+        if stripe_h == 0 {
+            break;
+        }
+        lpf_off += 4 * lpf_stride;
+    }
+}
+
+// Save the 4 px columns at x of the rows y..y + u before they get filtered
+fn backup4xu<T: Pixel>(dst: &mut [[T; 4]], src: &Plane<T>, x: usize, y: usize, u: usize) {
+    for (i, dst) in dst[..u].iter_mut().enumerate() {
+        let off = pixel_offset(src, x, y + i);
+        dst.copy_from_slice(&src.data[off..off + 4]);
+    }
+}
+
+// Apply loop restoration to the rows y..row_h of a w x h plane, unit by
+// unit, see 7.17 "Loop restoration process"
+fn lr_sbrow_plane<T: Pixel>(
+    p: &mut Plane<T>,
+    lr_mask: &[Av1Restoration],
+    lpf: &[T],
+    lpf_stride: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+    row_h: usize,
+    plane: usize,
+    unit_size_log2: i32,
+    sr_sb128w: usize,
+    layout: PixelLayout,
+    bitdepth_max: i32,
+) {
+    let chroma = plane != 0;
+    let ss_ver = (chroma && layout == PixelLayout::PIXEL_LAYOUT_I420) as usize;
+    let ss_hor = (chroma && layout != PixelLayout::PIXEL_LAYOUT_I444) as usize;
+
+    let unit_size = 1 << unit_size_log2;
+    let half_unit_size = unit_size >> 1;
+    let max_unit_size = unit_size + half_unit_size;
+
+    // y coordinate of the sbrow (y is 8 luma pixel rows above row_y)
+    let row_y = y + ((8 >> ss_ver) * (y != 0) as usize);
+
+    // the restoration units of the chroma planes are stored with the
+    // collocated luma 128x128 area
+    let shift_hor = 7 - ss_hor;
+
+    let zero = T::cast_from(0);
+    // maximum sbrow height is 128 + 8 rows offset
+    let mut pre_lr_border = [[[zero; 4]; 128 + 8]; 2];
+
+    let mut edges = (if y > 0 { LR_HAVE_TOP } else { 0 })
+        | LR_HAVE_RIGHT
+        | (if row_h < h { LR_HAVE_BOTTOM } else { 0 });
+
+    let mut aligned_unit_pos = row_y & !(unit_size - 1);
+    if aligned_unit_pos != 0 && aligned_unit_pos + half_unit_size > h {
+        aligned_unit_pos -= unit_size;
+    }
+    aligned_unit_pos <<= ss_ver;
+    let sb_idx = (aligned_unit_pos >> 7) * sr_sb128w;
+    let unit_idx = ((aligned_unit_pos >> 6) & 1) << 1;
+    let mut lr = [lr_mask[sb_idx].lr[plane][unit_idx]; 2];
+    let mut restore = lr[0].t != RestorationType::RESTORATION_NONE;
+    let (mut x, mut bit) = (0, 0);
+    while x + max_unit_size <= w {
+        let next_x = x + unit_size;
+        let next_u_idx = unit_idx + ((next_x >> (shift_hor - 1)) & 1);
+        lr[bit ^ 1] = lr_mask[sb_idx + (next_x >> shift_hor)].lr[plane][next_u_idx];
+        let restore_next = lr[bit ^ 1].t != RestorationType::RESTORATION_NONE;
+        if restore_next {
+            backup4xu(&mut pre_lr_border[bit], p, next_x - 4, y, row_h - y);
+        }
+        if restore {
+            lr_stripe(
+                p,
+                &pre_lr_border[bit ^ 1],
+                lpf,
+                lpf_stride,
+                x,
+                y,
+                ss_ver,
+                unit_size,
+                row_h,
+                &lr[bit],
+                edges,
+                bitdepth_max,
+            );
+        }
+        x = next_x;
+        restore = restore_next;
+        edges |= LR_HAVE_LEFT;
+        bit ^= 1;
+    }
+    if restore {
+        edges &= !LR_HAVE_RIGHT;
+        lr_stripe(
+            p,
+            &pre_lr_border[bit ^ 1],
+            lpf,
+            lpf_stride,
+            x,
+            y,
+            ss_ver,
+            w - x,
+            row_h,
+            &lr[bit],
+            edges,
+            bitdepth_max,
+        );
+    }
+}
+
+// Apply loop restoration to superblock row sby. Like CDEF this trails the
+// deblocking by 8 luma px rows, so that stripes always end at the stripe
+// boundaries stored by lr_copy_lpf.
+pub(crate) fn lr_sbrow<T: Pixel>(f: &mut FrameContext<T>, sby: usize) {
+    let FrameContext {
+        seq_hdr,
+        frame_hdr,
+        cur,
+        sr_cur,
+        lf,
+        ..
+    } = f;
+    let seq_hdr = seq_hdr.as_ref().unwrap();
+    let frame_hdr = frame_hdr.as_ref().unwrap();
+    let planes = &mut sr_cur.as_mut().or_else(|| cur.as_mut()).unwrap().planes;
+    let offset_y = 8 * (sby > 0) as usize;
+    let lr_stride = lf.lr_line_sz as usize;
+    let sb128 = seq_hdr.sb128 as usize;
+    let sr_sb128w = f.sr_sb128w as usize;
+    let layout = seq_hdr.layout;
+    let bitdepth_max = seq_hdr.bitdepth_max();
+
+    let restore_planes = lf.restore_planes;
+
+    if restore_planes & LR_RESTORE_Y != 0 {
+        let h = frame_hdr.height as usize;
+        let w = frame_hdr.width[1] as usize;
+        let row_h = cmp::min((sby + 1) << (6 + sb128), h);
+        let y_stripe = (sby << (6 + sb128)) - offset_y;
+        lr_sbrow_plane(
+            &mut planes[0],
+            &lf.lr_mask,
+            &lf.lr_lpf_line[0],
+            lr_stride,
+            y_stripe,
+            w,
+            h,
+            row_h,
+            0,
+            frame_hdr.restoration.unit_size[0],
+            sr_sb128w,
+            layout,
+            bitdepth_max,
+        );
+    }
+    if restore_planes & (LR_RESTORE_U | LR_RESTORE_V) != 0 {
+        let ss_ver = (layout == PixelLayout::PIXEL_LAYOUT_I420) as usize;
+        let ss_hor = (layout != PixelLayout::PIXEL_LAYOUT_I444) as usize;
+        let h = (frame_hdr.height as usize + ss_ver) >> ss_ver;
+        let w = (frame_hdr.width[1] as usize + ss_hor) >> ss_hor;
+        let row_h = cmp::min((sby + 1) << (6 - ss_ver + sb128), h);
+        let offset_uv = offset_y >> ss_ver;
+        let y_stripe = (sby << (6 - ss_ver + sb128)) - offset_uv;
+
+        for pl in 1..3 {
+            if restore_planes & (LR_RESTORE_Y << pl) != 0 {
+                lr_sbrow_plane(
+                    &mut planes[pl],
+                    &lf.lr_mask,
+                    &lf.lr_lpf_line[pl],
+                    lr_stride,
+                    y_stripe,
+                    w,
+                    h,
+                    row_h,
+                    pl,
+                    frame_hdr.restoration.unit_size[1],
+                    sr_sb128w,
+                    layout,
+                    bitdepth_max,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // low amplitude noise, which all the filters smooth
+    fn noise(i: usize) -> u16 {
+        ((i as u32).wrapping_mul(2654435761) >> 29) as u16 + 100
+    }
+
+    // Restore both 64 px superblock rows of the luma plane of a 256x128
+    // frame of noise, with the 64x64 units in lr_mask
+    fn restore(lr_mask: &[Av1Restoration]) -> Plane<u16> {
+        let mut p = Plane::wrap((0..256 * 128).map(noise).collect(), 256);
+        let lpf: Vec<u16> = (0..256 * 12).map(|i| noise(i + 7)).collect();
+        for &(y, row_h) in &[(0, 64), (56, 128)] {
+            let layout = PixelLayout::PIXEL_LAYOUT_I420;
+            lr_sbrow_plane(
+                &mut p, lr_mask, &lpf, 256, y, 256, 128, row_h, 0, 6, 2, layout, 255,
+            );
+        }
+        p
+    }
+
+    #[test]
+    fn units_per_64x64_area() {
+        let wiener = |v: [i16; 3], h: [i16; 3]| Av1RestorationUnit {
+            t: RestorationType::RESTORATION_WIENER,
+            filter_v: v,
+            filter_h: h,
+            ..Default::default()
+        };
+        let sgr = |idx: u8, w: [i16; 2]| Av1RestorationUnit {
+            t: RestorationType::RESTORATION_SGRPROJ,
+            sgr_idx: idx,
+            sgr_weights: w,
+            ..Default::default()
+        };
+        let none = Av1RestorationUnit::default();
+        // the 4x2 units in raster order; the second row starts 8 px above
+        // the second superblock row, like its stripes
+        let units = [
+            wiener([3, -7, 15], [3, -7, 15]),
+            none,
+            sgr(0, [-32, 31]),
+            wiener([-1, 8, -20], [0, 5, 10]),
+            sgr(10, [0, 20]),
+            wiener([0, 0, 16], [2, -3, 4]),
+            none,
+            sgr(14, [-50, 95]),
+        ];
+        let area = |n: usize| (n % 4 * 64, if n < 4 { 0..56 } else { 56..128 });
+        // each 128x128 area holds 2x2 units
+        let mut lr_mask = vec![Av1Restoration::default(); 2];
+        for (n, lr) in units.iter().enumerate() {
+            let (x, y) = (n % 4, n / 4);
+            lr_mask[x >> 1].lr[0][y * 2 + (x & 1)] = *lr;
+        }
+        let restored = restore(&lr_mask);
+
+        // a unit only depends on the unfiltered pixels around it, so each
+        // area must be the same as if all units were its own, and differ
+        // from the areas restored with any other unit
+        let input = Plane::wrap((0..256 * 128).map(noise).collect(), 256);
+        let uniform: Vec<_> = units
+            .iter()
+            .map(|lr| restore(&[Av1Restoration { lr: [[*lr; 4]; 3] }; 2]))
+            .collect();
+        let pixels = |p: &Plane<u16>, n: usize| {
+            let (x, rows) = area(n);
+            rows.flat_map(|y| (x..x + 64).map(move |x| (x, y)))
+                .map(|(x, y)| p.p(x, y))
+                .collect::<Vec<_>>()
+        };
+        for n in 0..8 {
+            let got = pixels(&restored, n);
+            assert_eq!(got, pixels(&uniform[n], n), "unit {}", n);
+            for m in (0..8).filter(|&m| units[m] != units[n]) {
+                assert_ne!(
+                    got,
+                    pixels(&uniform[m], n),
+                    "unit {} restored with unit {}",
+                    n,
+                    m
+                );
+            }
+            if units[n].t == RestorationType::RESTORATION_NONE {
+                assert_eq!(got, pixels(&input, n));
+            }
+        }
+    }
+}
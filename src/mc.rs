@@ -0,0 +1,509 @@
+use crate::headers::FilterMode;
+use crate::levels::*;
+use crate::plane::Plane;
+use crate::tables::*;
+use crate::util::*;
+
+// rounding of the two filter passes, see 7.11.3.2 "Rounding variables
+// derivation process"
+#[inline(always)]
+pub(crate) fn inter_round(bitdepth_max: i32, is_compound: bool) -> (i32, i32) {
+    let twelve_bit = bitdepth_max == 4095;
+    let round0 = if twelve_bit { 5 } else { 3 };
+    let round1 = if is_compound {
+        7
+    } else if twelve_bit {
+        9
+    } else {
+        11
+    };
+    (round0, round1)
+}
+
+// the 4-tap variants are used for blocks of 4 px or less in the filtered
+// direction
+#[inline(always)]
+pub(crate) fn subpel_filter(mode: FilterMode, size: usize, pos: usize) -> &'static [i16; 8] {
+    let idx = match mode {
+        FilterMode::FILTER_8TAP_SMOOTH if size <= 4 => 5,
+        FilterMode::FILTER_8TAP_REGULAR | FilterMode::FILTER_8TAP_SHARP if size <= 4 => 4,
+        FilterMode::N_SWITCHABLE_FILTERS_OR_FILTER_BILINEAR => 3,
+        _ => mode as usize,
+    };
+    &mc_subpel_filters[idx][pos]
+}
+
+// Separable 8-tap subpel filter. src points 3 rows above and 3 columns left
+// of the block, i.e. it covers (w + 7) x (h + 7) pixels; mx and my are in
+// 1/16 pel. Results are left at the second-pass rounding precision.
+fn filter_8tap<T: Pixel>(
+    dst: &mut [i32],
+    src: &[T],
+    src_stride: usize,
+    w: usize,
+    h: usize,
+    mx: usize,
+    my: usize,
+    filter: Filter2d,
+    bitdepth_max: i32,
+    is_compound: bool,
+) {
+    let (round0, round1) = inter_round(bitdepth_max, is_compound);
+    let [h_mode, v_mode] = filter_2d_modes[filter as usize];
+    let fh = subpel_filter(h_mode, w, mx);
+    let fv = subpel_filter(v_mode, h, my);
+
+    let tmp_h = h + 7;
+    let mut tmp = vec![0i32; w * tmp_h];
+    for y in 0..tmp_h {
+        let src = &src[y * src_stride..];
+        for x in 0..w {
+            let sum: i32 = (0..8)
+                .map(|k| fh[k] as i32 * i32::cast_from(src[x + k]))
+                .sum();
+            tmp[y * w + x] = round_shift(sum, round0 as usize);
+        }
+    }
+    for y in 0..h {
+        for x in 0..w {
+            let sum: i32 = (0..8)
+                .map(|k| fv[k] as i32 * tmp[(y + k) * w + x])
+                .sum();
+            dst[y * w + x] = round_shift(sum, round1 as usize);
+        }
+    }
+}
+
+pub fn put_8tap<T: Pixel>(
+    dst: &mut [T],
+    dst_stride: usize,
+    src: &[T],
+    src_stride: usize,
+    w: usize,
+    h: usize,
+    mx: usize,
+    my: usize,
+    filter: Filter2d,
+    bitdepth_max: i32,
+) {
+    let mut tmp = vec![0i32; w * h];
+    filter_8tap(
+        &mut tmp,
+        src,
+        src_stride,
+        w,
+        h,
+        mx,
+        my,
+        filter,
+        bitdepth_max,
+        false,
+    );
+    for y in 0..h {
+        let dst = &mut dst[y * dst_stride..];
+        for x in 0..w {
+            dst[x] = T::cast_from(clip(tmp[y * w + x], 0, bitdepth_max));
+        }
+    }
+}
+
+// same as put_8tap, but keeps the intermediate precision for compound
+// prediction; tmp has a stride of w
+pub fn prep_8tap<T: Pixel>(
+    tmp: &mut [i32],
+    src: &[T],
+    src_stride: usize,
+    w: usize,
+    h: usize,
+    mx: usize,
+    my: usize,
+    filter: Filter2d,
+    bitdepth_max: i32,
+) {
+    filter_8tap(tmp, src, src_stride, w, h, mx, my, filter, bitdepth_max, true);
+}
+
+// Same as filter_8tap for a reference of a different size: the source
+// position advances by dx/dy per output px, all positions in 1/1024 px.
+fn filter_8tap_scaled<T: Pixel>(
+    dst: &mut [i32],
+    src: &[T],
+    src_stride: usize,
+    w: usize,
+    h: usize,
+    mx: usize,
+    my: usize,
+    dx: usize,
+    dy: usize,
+    filter: Filter2d,
+    bitdepth_max: i32,
+    is_compound: bool,
+) {
+    let (round0, round1) = inter_round(bitdepth_max, is_compound);
+    let [h_mode, v_mode] = filter_2d_modes[filter as usize];
+
+    let tmp_h = (((h - 1) * dy + my) >> 10) + 8;
+    let mut tmp = vec![0i32; w * tmp_h];
+    for y in 0..tmp_h {
+        let src = &src[y * src_stride..];
+        let (mut imx, mut ioff) = (mx, 0);
+        for x in 0..w {
+            let fh = subpel_filter(h_mode, w, imx >> 6);
+            let sum: i32 = (0..8)
+                .map(|k| fh[k] as i32 * i32::cast_from(src[ioff + k]))
+                .sum();
+            tmp[y * w + x] = round_shift(sum, round0 as usize);
+            imx += dx;
+            ioff += imx >> 10;
+            imx &= 0x3ff;
+        }
+    }
+    let (mut my, mut ioff) = (my, 0);
+    for y in 0..h {
+        let fv = subpel_filter(v_mode, h, my >> 6);
+        for x in 0..w {
+            let sum: i32 = (0..8)
+                .map(|k| fv[k] as i32 * tmp[(ioff + k) * w + x])
+                .sum();
+            dst[y * w + x] = round_shift(sum, round1 as usize);
+        }
+        my += dy;
+        ioff += my >> 10;
+        my &= 0x3ff;
+    }
+}
+
+pub fn put_8tap_scaled<T: Pixel>(
+    dst: &mut [T],
+    dst_stride: usize,
+    src: &[T],
+    src_stride: usize,
+    w: usize,
+    h: usize,
+    mx: usize,
+    my: usize,
+    dx: usize,
+    dy: usize,
+    filter: Filter2d,
+    bitdepth_max: i32,
+) {
+    let mut tmp = vec![0i32; w * h];
+    filter_8tap_scaled(
+        &mut tmp,
+        src,
+        src_stride,
+        w,
+        h,
+        mx,
+        my,
+        dx,
+        dy,
+        filter,
+        bitdepth_max,
+        false,
+    );
+    for y in 0..h {
+        let dst = &mut dst[y * dst_stride..];
+        for x in 0..w {
+            dst[x] = T::cast_from(clip(tmp[y * w + x], 0, bitdepth_max));
+        }
+    }
+}
+
+pub fn prep_8tap_scaled<T: Pixel>(
+    tmp: &mut [i32],
+    src: &[T],
+    src_stride: usize,
+    w: usize,
+    h: usize,
+    mx: usize,
+    my: usize,
+    dx: usize,
+    dy: usize,
+    filter: Filter2d,
+    bitdepth_max: i32,
+) {
+    filter_8tap_scaled(
+        tmp,
+        src,
+        src_stride,
+        w,
+        h,
+        mx,
+        my,
+        dx,
+        dy,
+        filter,
+        bitdepth_max,
+        true,
+    );
+}
+
+pub fn avg<T: Pixel>(
+    dst: &mut [T],
+    dst_stride: usize,
+    tmp1: &[i32],
+    tmp2: &[i32],
+    w: usize,
+    h: usize,
+    bitdepth_max: i32,
+) {
+    let (round0, round1) = inter_round(bitdepth_max, true);
+    let sh = 1 + 14 - (round0 + round1);
+    for y in 0..h {
+        let dst = &mut dst[y * dst_stride..];
+        for x in 0..w {
+            let v = round_shift(tmp1[y * w + x] + tmp2[y * w + x], sh as usize);
+            dst[x] = T::cast_from(clip(v, 0, bitdepth_max));
+        }
+    }
+}
+
+// Affine warp of an 8x8 block, see 7.11.3.5 "Block warp process". src is
+// the (8 + 7) x (8 + 7) area starting 3 px above/left of the block, abcd the
+// shear parameters and mx/my the subpel position of the block's first
+// filter tap in 1/65536 pel. Results are left at the second-pass rounding
+// precision.
+fn warp_affine_8x8_filter<T: Pixel>(
+    dst: &mut [i32; 64],
+    src: &[T],
+    src_stride: usize,
+    abcd: &[i16; 4],
+    mut mx: i32,
+    mut my: i32,
+    bitdepth_max: i32,
+    is_compound: bool,
+) {
+    let (round0, round1) = inter_round(bitdepth_max, is_compound);
+    let mut mid = [0i32; 15 * 8];
+
+    for y in 0..15 {
+        let src = &src[y * src_stride..];
+        let mut tmx = mx;
+        for x in 0..8 {
+            let filter = &mc_warp_filter[(64 + ((tmx + 512) >> 10)) as usize];
+            let sum: i32 = (0..8)
+                .map(|k| filter[k] as i32 * i32::cast_from(src[x + k]))
+                .sum();
+            mid[y * 8 + x] = round_shift(sum, round0 as usize);
+            tmx += abcd[0] as i32;
+        }
+        mx += abcd[1] as i32;
+    }
+
+    for y in 0..8 {
+        let mut tmy = my;
+        for x in 0..8 {
+            let filter = &mc_warp_filter[(64 + ((tmy + 512) >> 10)) as usize];
+            let sum: i32 = (0..8)
+                .map(|k| filter[k] as i32 * mid[(y + k) * 8 + x])
+                .sum();
+            dst[y * 8 + x] = round_shift(sum, round1 as usize);
+            tmy += abcd[2] as i32;
+        }
+        my += abcd[3] as i32;
+    }
+}
+
+pub fn warp_affine_8x8<T: Pixel>(
+    dst: &mut [T],
+    dst_stride: usize,
+    src: &[T],
+    src_stride: usize,
+    abcd: &[i16; 4],
+    mx: i32,
+    my: i32,
+    bitdepth_max: i32,
+) {
+    let mut tmp = [0i32; 64];
+    warp_affine_8x8_filter(&mut tmp, src, src_stride, abcd, mx, my, bitdepth_max, false);
+    for y in 0..8 {
+        let dst = &mut dst[y * dst_stride..];
+        for x in 0..8 {
+            dst[x] = T::cast_from(clip(tmp[y * 8 + x], 0, bitdepth_max));
+        }
+    }
+}
+
+// same as warp_affine_8x8, but keeps the intermediate precision for
+// compound prediction
+pub fn warp_affine_8x8t<T: Pixel>(
+    tmp: &mut [i32],
+    tmp_stride: usize,
+    src: &[T],
+    src_stride: usize,
+    abcd: &[i16; 4],
+    mx: i32,
+    my: i32,
+    bitdepth_max: i32,
+) {
+    let mut blk = [0i32; 64];
+    warp_affine_8x8_filter(&mut blk, src, src_stride, abcd, mx, my, bitdepth_max, true);
+    for y in 0..8 {
+        tmp[y * tmp_stride..y * tmp_stride + 8].copy_from_slice(&blk[y * 8..y * 8 + 8]);
+    }
+}
+
+#[inline(always)]
+fn blend_px<T: Pixel>(a: T, b: T, m: u8) -> T {
+    let (a, b, m) = (i32::cast_from(a), i32::cast_from(b), m as i32);
+    T::cast_from((a * (64 - m) + b * m + 32) >> 6)
+}
+
+// OBMC blend with the left neighbour's prediction, the mask runs along x
+pub fn blend_v<T: Pixel>(dst: &mut [T], dst_stride: usize, tmp: &[T], w: usize, h: usize) {
+    let mask = &obmc_masks[w..];
+    for y in 0..h {
+        let dst = &mut dst[y * dst_stride..];
+        let tmp = &tmp[y * w..];
+        for x in 0..(w * 3) >> 2 {
+            dst[x] = blend_px(dst[x], tmp[x], mask[x]);
+        }
+    }
+}
+
+// OBMC blend with the above neighbour's prediction, the mask runs along y
+pub fn blend_h<T: Pixel>(dst: &mut [T], dst_stride: usize, tmp: &[T], w: usize, h: usize) {
+    let mask = &obmc_masks[h..];
+    for y in 0..(h * 3) >> 2 {
+        let dst = &mut dst[y * dst_stride..];
+        let tmp = &tmp[y * w..];
+        for x in 0..w {
+            dst[x] = blend_px(dst[x], tmp[x], mask[y]);
+        }
+    }
+}
+
+// Copy a bw x bh block at (x, y) of the plane into dst, replicating the
+// edge pixels of the visible iw x ih area for positions outside of it.
+pub fn emu_edge<T: Pixel>(
+    dst: &mut [T],
+    dst_stride: usize,
+    src: &Plane<T>,
+    x: isize,
+    y: isize,
+    bw: usize,
+    bh: usize,
+    iw: usize,
+    ih: usize,
+) {
+    let stride = src.cfg.stride;
+    let origin = src.cfg.yorigin * stride + src.cfg.xorigin;
+    for j in 0..bh {
+        let sy = clip(y + j as isize, 0, ih as isize - 1) as usize;
+        let src_row = &src.data[origin + sy * stride..];
+        let dst_row = &mut dst[j * dst_stride..];
+        for i in 0..bw {
+            let sx = clip(x + i as isize, 0, iw as isize - 1) as usize;
+            dst_row[i] = src_row[sx];
+        }
+    }
+}
+
+// Fetch the (w + 7) x (h + 7) source area needed to filter a w x h block
+// at (x, y), emulating the edges if it isn't fully inside the picture.
+pub fn fetch_src<T: Pixel>(
+    src: &Plane<T>,
+    x: isize,
+    y: isize,
+    w: usize,
+    h: usize,
+    iw: usize,
+    ih: usize,
+) -> (Vec<T>, usize) {
+    let (src_w, src_h) = (w + 7, h + 7);
+    let mut buf = vec![T::cast_from(0); src_w * src_h];
+    if x < 3 || y < 3 || x + w as isize + 4 > iw as isize || y + h as isize + 4 > ih as isize {
+        emu_edge(&mut buf, src_w, src, x - 3, y - 3, src_w, src_h, iw, ih);
+    } else {
+        let stride = src.cfg.stride;
+        let origin = (src.cfg.yorigin + y as usize - 3) * stride + src.cfg.xorigin + x as usize - 3;
+        for (j, row) in buf.chunks_mut(src_w).enumerate() {
+            let base = origin + j * stride;
+            row.copy_from_slice(&src.data[base..base + src_w]);
+        }
+    }
+    (buf, src_w)
+}
+
+// Horizontally upscale h rows of src_w px to dst_w px, see 7.16
+// "Upscaling process". dx is the step and mx0 the initial subpel position,
+// both in 1/16384 px; source pixels outside of the row are replicated.
+pub fn resize<T: Pixel>(
+    dst: &mut [T],
+    dst_stride: usize,
+    src: &[T],
+    src_stride: usize,
+    dst_w: usize,
+    h: usize,
+    src_w: usize,
+    dx: i32,
+    mx0: i32,
+    bitdepth_max: i32,
+) {
+    for y in 0..h {
+        let src = &src[y * src_stride..];
+        let dst = &mut dst[y * dst_stride..];
+        let (mut mx, mut src_x) = (mx0, -1isize);
+        for x in 0..dst_w {
+            let f = &resize_filter[(mx >> 8) as usize];
+            let sum: i32 = (0..8)
+                .map(|k| {
+                    let sx = clip(src_x + k as isize - 3, 0, src_w as isize - 1) as usize;
+                    f[k] as i32 * i32::cast_from(src[sx])
+                })
+                .sum();
+            dst[x] = T::cast_from(clip((sum + 64) >> 7, 0, bitdepth_max));
+            mx += dx;
+            src_x += (mx >> 14) as isize;
+            mx &= 0x3fff;
+        }
+    }
+}
+
+pub(crate) type Put8tapFn<T> =
+    fn(&mut [T], usize, &[T], usize, usize, usize, usize, usize, Filter2d, i32);
+pub(crate) type Prep8tapFn<T> = fn(&mut [i32], &[T], usize, usize, usize, usize, usize, Filter2d, i32);
+pub(crate) type Put8tapScaledFn<T> =
+    fn(&mut [T], usize, &[T], usize, usize, usize, usize, usize, usize, usize, Filter2d, i32);
+pub(crate) type Prep8tapScaledFn<T> =
+    fn(&mut [i32], &[T], usize, usize, usize, usize, usize, usize, usize, Filter2d, i32);
+pub(crate) type AvgFn<T> = fn(&mut [T], usize, &[i32], &[i32], usize, usize, i32);
+pub(crate) type WarpAffine8x8Fn<T> = fn(&mut [T], usize, &[T], usize, &[i16; 4], i32, i32, i32);
+pub(crate) type WarpAffine8x8tFn<T> =
+    fn(&mut [i32], usize, &[T], usize, &[i16; 4], i32, i32, i32);
+pub(crate) type BlendDirFn<T> = fn(&mut [T], usize, &[T], usize, usize);
+
+#[derive(Clone, Copy)]
+pub(crate) struct MCDSPContext<T: Pixel> {
+    pub(crate) mc: Put8tapFn<T>,
+    pub(crate) mct: Prep8tapFn<T>,
+    pub(crate) mc_scaled: Put8tapScaledFn<T>,
+    pub(crate) mct_scaled: Prep8tapScaledFn<T>,
+    pub(crate) avg: AvgFn<T>,
+    pub(crate) warp8x8: WarpAffine8x8Fn<T>,
+    pub(crate) warp8x8t: WarpAffine8x8tFn<T>,
+    pub(crate) blend_v: BlendDirFn<T>,
+    pub(crate) blend_h: BlendDirFn<T>,
+}
+
+impl<T: Pixel> MCDSPContext<T> {
+    pub(crate) fn new(flags: u32) -> Self {
+        let mut c = MCDSPContext {
+            mc: put_8tap,
+            mct: prep_8tap,
+            mc_scaled: put_8tap_scaled,
+            mct_scaled: prep_8tap_scaled,
+            avg,
+            warp8x8: warp_affine_8x8,
+            warp8x8t: warp_affine_8x8t,
+            blend_v,
+            blend_h,
+        };
+
+        #[cfg(target_arch = "x86_64")]
+        crate::x86::mc::mc_dsp_init_x86(&mut c, flags);
+
+        c
+    }
+}
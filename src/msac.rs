@@ -0,0 +1,363 @@
+// Multi-symbol arithmetic decoder, see 8.2 "Symbol decoding process".
+//
+// CDFs are stored inverted, as 32768 minus the cumulative probability of
+// each symbol but the last, followed by an adaptation counter; n_symbols is
+// the number of symbols minus one.
+
+type EcWin = u64;
+
+const EC_PROB_SHIFT: u32 = 6;
+const EC_MIN_PROB: u32 = 4;
+const EC_WIN_SIZE: i32 = 64;
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct MsacContext {
+    // the data of the tile, and the position of the next byte to refill
+    buf: Vec<u8>,
+    buf_pos: usize,
+    dif: EcWin,
+    rng: u32,
+    pub(crate) cnt: i32,
+    allow_update_cdf: bool,
+}
+
+impl MsacContext {
+    pub(crate) fn new(data: &[u8], disable_cdf_update: bool) -> Self {
+        let mut s = MsacContext {
+            buf: data.to_vec(),
+            buf_pos: 0,
+            dif: 0,
+            rng: 0x8000,
+            cnt: -15,
+            allow_update_cdf: !disable_cdf_update,
+        };
+        s.refill();
+        s
+    }
+
+    fn refill(&mut self) {
+        let mut c = EC_WIN_SIZE - self.cnt - 24;
+        let mut dif = self.dif;
+        loop {
+            if self.buf_pos >= self.buf.len() {
+                // past the end of the data, shift in ones
+                dif |= !(!0xff << c);
+                break;
+            }
+            dif |= ((self.buf[self.buf_pos] ^ 0xff) as EcWin) << c;
+            self.buf_pos += 1;
+            c -= 8;
+            if c < 0 {
+                break;
+            }
+        }
+        self.dif = dif;
+        self.cnt = EC_WIN_SIZE - c - 24;
+    }
+
+    #[inline]
+    fn norm(&mut self, dif: EcWin, rng: u32) {
+        let d = 15 ^ (31 ^ rng.leading_zeros() as i32);
+        let cnt = self.cnt;
+        debug_assert!(rng <= 65535);
+        self.dif = dif << d;
+        self.rng = rng << d;
+        self.cnt = cnt - d;
+        // the unsigned compare avoids refilling again and again at the end
+        // of the data
+        if (cnt as u32) < d as u32 {
+            self.refill();
+        }
+    }
+
+    // a bool with an inverted probability f (in 1/32768) of being 0
+    fn decode_bool_prob(&mut self, f: u32) -> bool {
+        let r = self.rng;
+        let mut dif = self.dif;
+        debug_assert!((dif >> (EC_WIN_SIZE - 16)) < r as EcWin);
+        let mut v = ((r >> 8) * (f >> EC_PROB_SHIFT) >> (7 - EC_PROB_SHIFT)) + EC_MIN_PROB;
+        let vw = (v as EcWin) << (EC_WIN_SIZE - 16);
+        let ret = dif >= vw;
+        if ret {
+            dif -= vw;
+            v = r - v;
+        }
+        self.norm(dif, v);
+        !ret
+    }
+
+    // an equiprobable bool, as used for literals
+    pub(crate) fn decode_bool_equi(&mut self) -> bool {
+        self.decode_bool_prob(1 << 14)
+    }
+
+    // n equiprobable bits, MSB first
+    pub(crate) fn decode_bools(&mut self, n: u32) -> u32 {
+        (0..n).fold(0, |v, _| v << 1 | self.decode_bool_equi() as u32)
+    }
+
+    // a value in 0..n coded as a subexponential delta to r, see 4.10.8
+    // "ns(n)" and 5.11.58 "Read loop restoration unit syntax"
+    pub(crate) fn decode_subexp(&mut self, r: i32, n: i32, mut k: u32) -> i32 {
+        debug_assert!(n >> k == 8);
+        let mut a = 0;
+        if self.decode_bool_equi() {
+            if self.decode_bool_equi() {
+                k += self.decode_bool_equi() as u32 + 1;
+            }
+            a = 1 << k;
+        }
+        let v = (self.decode_bools(k) + a) as i32;
+        if r * 2 <= n {
+            inv_recenter(r, v)
+        } else {
+            n - 1 - inv_recenter(n - 1 - r, v)
+        }
+    }
+
+    pub(crate) fn decode_bool_adapt(&mut self, cdf: &mut [u16; 2]) -> bool {
+        let bit = self.decode_bool_prob(cdf[0] as u32);
+        if self.allow_update_cdf {
+            let count = cdf[1];
+            let rate = 4 + (count >> 4);
+            if bit {
+                cdf[0] += (32768 - cdf[0]) >> rate;
+            } else {
+                cdf[0] -= cdf[0] >> rate;
+            }
+            cdf[1] = count + (count < 32) as u16;
+        }
+        bit
+    }
+
+    pub(crate) fn decode_symbol_adapt(&mut self, cdf: &mut [u16], n_symbols: usize) -> usize {
+        let c = (self.dif >> (EC_WIN_SIZE - 16)) as u32;
+        let r = self.rng >> 8;
+        let mut u;
+        let mut v = self.rng;
+        let mut val = 0;
+        loop {
+            u = v;
+            // the counter following the last symbol is at most 32, so that
+            // v ends up at 0 for it
+            v = r * (cdf[val] as u32 >> EC_PROB_SHIFT) >> (7 - EC_PROB_SHIFT);
+            v += EC_MIN_PROB * (n_symbols - val) as u32;
+            if c >= v {
+                break;
+            }
+            val += 1;
+        }
+        debug_assert!(u <= self.rng);
+        self.norm(self.dif - ((v as EcWin) << (EC_WIN_SIZE - 16)), u - v);
+
+        if self.allow_update_cdf {
+            let count = cdf[n_symbols];
+            let rate = 4 + (count >> 4) + (n_symbols > 2) as u16;
+            for (i, p) in cdf[..n_symbols].iter_mut().enumerate() {
+                if i < val {
+                    *p += (32768 - *p) >> rate;
+                } else {
+                    *p -= *p >> rate;
+                }
+            }
+            cdf[n_symbols] = count + (count < 32) as u16;
+        }
+        val
+    }
+}
+
+fn inv_recenter(r: i32, v: i32) -> i32 {
+    if v > r << 1 {
+        v
+    } else if v & 1 == 0 {
+        (v >> 1) + r
+    } else {
+        r - ((v + 1) >> 1)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    // The arithmetic encoder of the reference encoder, which writes the
+    // symbols MsacContext reads back, for the tests of the symbol parsing.
+    pub(crate) struct MsacWriter {
+        low: u64,
+        rng: u32,
+        cnt: i32,
+        // output bytes before carry propagation, which may exceed 0xff
+        precarry: Vec<u16>,
+        allow_update_cdf: bool,
+    }
+
+    impl MsacWriter {
+        pub(crate) fn new(disable_cdf_update: bool) -> Self {
+            MsacWriter {
+                low: 0,
+                rng: 0x8000,
+                cnt: -9,
+                precarry: vec![],
+                allow_update_cdf: !disable_cdf_update,
+            }
+        }
+
+        fn normalize(&mut self, mut low: u64, rng: u32) {
+            let d = rng.leading_zeros() as i32 - 16;
+            let mut c = self.cnt;
+            let mut s = c + d;
+            if s >= 0 {
+                c += 16;
+                let mut m = (1u64 << c) - 1;
+                if s >= 8 {
+                    self.precarry.push((low >> c) as u16);
+                    low &= m;
+                    c -= 8;
+                    m >>= 8;
+                }
+                self.precarry.push((low >> c) as u16);
+                s = c + d - 24;
+                low &= m;
+            }
+            self.low = low << d;
+            self.rng = rng << d;
+            self.cnt = s;
+        }
+
+        // symbol s of an inverted CDF over n_symbols + 1 symbols
+        fn encode_q15(&mut self, fl: u32, fh: u32, s: usize, n_symbols: usize) {
+            let (mut l, mut r) = (self.low, self.rng);
+            let p = |f: u32, n: usize| {
+                ((r >> 8) * (f >> EC_PROB_SHIFT) >> (7 - EC_PROB_SHIFT)) + EC_MIN_PROB * n as u32
+            };
+            let v = p(fh, n_symbols - s);
+            if fl < 32768 {
+                let u = p(fl, n_symbols + 1 - s);
+                l += (r - u) as u64;
+                r = u - v;
+            } else {
+                r -= v;
+            }
+            self.normalize(l, r);
+        }
+
+        pub(crate) fn encode_bool_equi(&mut self, bit: bool) {
+            let (fl, fh) = if bit { (1 << 14, 0) } else { (32768, 1 << 14) };
+            self.encode_q15(fl, fh, bit as usize, 1);
+        }
+
+        pub(crate) fn encode_bools(&mut self, v: u32, n: u32) {
+            for i in (0..n).rev() {
+                self.encode_bool_equi(v >> i & 1 != 0);
+            }
+        }
+
+        // the inverse of decode_subexp()
+        pub(crate) fn encode_subexp(&mut self, x: i32, r: i32, n: i32, k: u32) {
+            let recenter = |r: i32, x: i32| {
+                if x > r << 1 {
+                    x
+                } else if x >= r {
+                    (x - r) << 1
+                } else {
+                    ((r - x) << 1) - 1
+                }
+            };
+            let v = if r * 2 <= n {
+                recenter(r, x)
+            } else {
+                recenter(n - 1 - r, n - 1 - x)
+            } as u32;
+            if v < 1 << k {
+                self.encode_bool_equi(false);
+                self.encode_bools(v, k);
+            } else if v < 2 << k {
+                self.encode_bools(0b10, 2);
+                self.encode_bools(v - (1 << k), k);
+            } else {
+                let b = (v >= 4 << k) as u32;
+                self.encode_bools(0b110 | b, 3);
+                self.encode_bools(v - (2 << (k + b)), k + b + 1);
+            }
+        }
+
+        pub(crate) fn encode_bool_adapt(&mut self, bit: bool, cdf: &mut [u16; 2]) {
+            self.encode_symbol_adapt(bit as usize, cdf, 1);
+        }
+
+        pub(crate) fn encode_symbol_adapt(&mut self, s: usize, cdf: &mut [u16], n_symbols: usize) {
+            let fl = if s > 0 { cdf[s - 1] as u32 } else { 32768 };
+            let fh = if s < n_symbols { cdf[s] as u32 } else { 0 };
+            self.encode_q15(fl, fh, s, n_symbols);
+            if self.allow_update_cdf {
+                let count = cdf[n_symbols];
+                let rate = 4 + (count > 15) as u16 + (count > 31) as u16 + (n_symbols > 2) as u16;
+                for (i, p) in cdf[..n_symbols].iter_mut().enumerate() {
+                    let target = if i < s { 32768 } else { 0 };
+                    if target < *p {
+                        *p -= (*p - target) >> rate;
+                    } else {
+                        *p += (target - *p) >> rate;
+                    }
+                }
+                cdf[n_symbols] += (count < 32) as u16;
+            }
+        }
+
+        // flush the encoder state and propagate the carries
+        pub(crate) fn data(mut self) -> Vec<u8> {
+            let (mut c, mut s) = (self.cnt, self.cnt + 10);
+            let m = 0x3fff;
+            let mut e = ((self.low + m) & !m) | (m + 1);
+            if s > 0 {
+                let mut n = (1u64 << (c + 16)) - 1;
+                while s > 0 {
+                    self.precarry.push((e >> (c + 16)) as u16);
+                    e &= n;
+                    s -= 8;
+                    c -= 8;
+                    n >>= 8;
+                }
+            }
+            let mut out = vec![0u8; self.precarry.len()];
+            let mut carry = 0;
+            for (o, &b) in out.iter_mut().zip(self.precarry.iter()).rev() {
+                carry += b as u32;
+                *o = carry as u8;
+                carry >>= 8;
+            }
+            out
+        }
+    }
+
+    #[test]
+    fn symbols_round_trip() {
+        for &disable_cdf_update in &[true, false] {
+            // a 4-symbol CDF and a bool one, adapted alike on both sides
+            let init4 = [32768 - 4096, 32768 - 20480, 32768 - 28672, 0];
+            let init2 = [32768 - 24576, 0];
+            let (mut cdf4, mut cdf2) = (init4, init2);
+            let mut w = MsacWriter::new(disable_cdf_update);
+            for i in 0..200usize {
+                w.encode_symbol_adapt((i * 7 + i / 5) % 4, &mut cdf4, 3);
+                w.encode_bool_adapt(i % 3 != 0, &mut cdf2);
+                w.encode_bools(i as u32 & 0x1f, 5);
+                let (x, r) = ((i * 13 % 64) as i32, (i % 64) as i32);
+                w.encode_subexp(x, r, 64, 3);
+            }
+            let data = w.data();
+
+            let (mut cdf4, mut cdf2) = (init4, init2);
+            let mut s = MsacContext::new(&data, disable_cdf_update);
+            for i in 0..200usize {
+                assert_eq!(s.decode_symbol_adapt(&mut cdf4, 3), (i * 7 + i / 5) % 4);
+                assert_eq!(s.decode_bool_adapt(&mut cdf2), i % 3 != 0);
+                assert_eq!(s.decode_bools(5), i as u32 & 0x1f);
+                let (x, r) = ((i * 13 % 64) as i32, (i % 64) as i32);
+                assert_eq!(s.decode_subexp(r, 64, 3), x);
+            }
+            assert!(s.cnt >= -15, "overread");
+            assert_eq!(cdf2 == init2, disable_cdf_update);
+        }
+    }
+}
@@ -1,15 +1,17 @@
 use crate::api::*;
+use crate::error::DecodeError;
 use crate::frame::Frame;
 use crate::getbits::*;
 use crate::headers::*;
 use crate::levels::*;
 use crate::util::*;
+use crate::warpmv::get_shear_params;
 use crate::internal::*;
 
 use std::rc::Rc;
 use std::slice;
 use std::vec::Vec;
-use std::{cmp, io};
+use std::{cmp, mem};
 
 use crate::headers::SequenceHeader;
 use num_traits::FromPrimitive;
@@ -18,7 +20,7 @@ fn parse_seq_hdr(
     gb: &mut GetBits,
     hdr: &mut SequenceHeader,
     operating_point: usize,
-) -> io::Result<u32> {
+) -> Result<u32, DecodeError> {
     let init_bit_pos = gb.get_bits_pos();
 
     hdr.profile = gb.get_bits(3) as u8;
@@ -309,33 +311,84 @@ fn parse_seq_hdr(
     Ok(operating_point_idc)
 }
 
-fn parse_frame_size(
+// Parse the first sequence header found in the OBUs of data without a
+// Context, so that the caller can pick the pixel type of the Context to
+// decode the stream with, see SequenceHeader::bit_depth().
+pub fn parse_sequence_header(data: &[u8]) -> Result<SequenceHeader, DecodeError> {
+    let mut offset = 0;
+    while offset < data.len() {
+        let data = &data[offset..];
+        let mut gb = GetBits::new(data);
+
+        // obu header
+        gb.get_bits(1); // obu_forbidden_bit
+        let obu_type = gb.get_bits(4);
+        let has_extension = gb.get_bits(1) != 0;
+        let has_length_field = gb.get_bits(1) != 0;
+        gb.get_bits(1); // reserved
+        if has_extension {
+            gb.get_bits(8); // temporal_id, spatial_id, reserved
+        }
+
+        // obu length field
+        let len = if has_length_field {
+            gb.get_uleb128() as usize
+        } else {
+            (data.len() as isize - 1 - has_extension as isize) as usize
+        };
+        gb.check_error()?;
+
+        let init_bit_pos = gb.get_bits_pos();
+        let init_byte_pos = (init_bit_pos >> 3) as usize;
+        check_error(
+            len > data.len() - init_byte_pos,
+            "len > data.len() - init_byte_pos",
+        )?;
+
+        if obu_type == ObuType::OBU_SEQ_HDR as u32 {
+            let mut seq_hdr = SequenceHeader::default();
+            parse_seq_hdr(&mut gb, &mut seq_hdr, 0)?;
+            gb.check_for_overrun(init_bit_pos, len as u32)?;
+            return Ok(seq_hdr);
+        }
+
+        offset += init_byte_pos + len;
+    }
+
+    Err(DecodeError::invalid("No sequence header found"))
+}
+
+// see 5.9.5 and 5.9.7, with use_ref the size may be copied from one of the
+// references
+fn parse_frame_size<T: Pixel>(
     gb: &mut GetBits,
     seqhdr: &SequenceHeader,
+    refs: &[RefState<T>; 8],
     hdr: &mut FrameHeader,
     use_ref: bool,
-) -> io::Result<()> {
+) -> Result<(), DecodeError> {
     if use_ref {
         for i in 0..7 {
             if gb.get_bits(1) != 0 {
-                unimplemented!();
-                /*Dav1dThreadPicture *const r =
-                    &c->refs[c->frame_hdr.refidx[i]].p;
-                if (!ref->p.data[0]) return -1;
-                // FIXME render_* may be wrong
-                hdr.render_width = hdr.width[1] = r->p.p.w;
-                hdr.render_height = hdr.height = r->p.p.h;
+                let ref_hdr = refs[hdr.refidx[i] as usize].frame_hdr.as_ref();
+                check_error(ref_hdr.is_none(), "found_ref refers to an empty slot")?;
+                let ref_hdr = ref_hdr.unwrap();
+                hdr.width[1] = ref_hdr.width[1];
+                hdr.height = ref_hdr.height;
+                hdr.render_width = ref_hdr.render_width;
+                hdr.render_height = ref_hdr.render_height;
                 hdr.super_res.enabled = seqhdr.super_res && gb.get_bits(1) != 0;
                 if hdr.super_res.enabled {
-                    hdr.super_res.width_scale_denominator =
-                        9 + gb.get_bits( 3);
+                    hdr.super_res.width_scale_denominator = 9 + gb.get_bits(3);
                     let d = hdr.super_res.width_scale_denominator;
-                    hdr.width[0] = cmp::max((hdr.width[1] * 8 + (d >> 1)) / d,
-                                     cmp::min(16, hdr.width[1]));
+                    hdr.width[0] = cmp::max(
+                        (hdr.width[1] * 8 + (d >> 1)) / d,
+                        cmp::min(16, hdr.width[1]),
+                    );
                 } else {
                     hdr.super_res.width_scale_denominator = 8;
                     hdr.width[0] = hdr.width[1];
-                }*/
+                }
                 return Ok(());
             }
         }
@@ -371,11 +424,157 @@ fn parse_frame_size(
     Ok(())
 }
 
-fn parse_frame_hdr(
+// Derive the references other than LAST and GOLDEN from the order hints of
+// the slots, see 7.8 "Set frame refs process"
+fn set_frame_refs<T: Pixel>(
+    seqhdr: &SequenceHeader,
+    refs: &[RefState<T>; 8],
+    hdr: &mut FrameHeader,
+) -> Result<(), DecodeError> {
+    let n_bits = seqhdr.order_hint_n_bits;
+    let cur_frame_offset = 1 << (n_bits - 1);
+    let mut shifted_frame_offset = [0i32; 8];
+    for i in 0..8 {
+        let ref_hdr = refs[i].frame_hdr.as_ref();
+        check_error(
+            ref_hdr.is_none(),
+            "frame_refs_short_signaling with an empty slot",
+        )?;
+        shifted_frame_offset[i] = cur_frame_offset
+            + get_poc_diff(
+                n_bits,
+                ref_hdr.unwrap().frame_offset as i32,
+                hdr.frame_offset as i32,
+            );
+    }
+
+    let mut used_frame = [false; 8];
+    used_frame[hdr.refidx[0] as usize] = true;
+    used_frame[hdr.refidx[3] as usize] = true;
+    for i in [1, 2, 4, 5, 6].iter() {
+        hdr.refidx[*i] = -1;
+    }
+
+    // ALTREF is the furthest frame in the future, BWDREF and ALTREF2 the
+    // closest ones
+    let mut latest_frame_offset = -1;
+    for i in 0..8 {
+        let hint = shifted_frame_offset[i];
+        if !used_frame[i] && hint >= cur_frame_offset && hint >= latest_frame_offset {
+            hdr.refidx[6] = i as i32;
+            latest_frame_offset = hint;
+        }
+    }
+    if latest_frame_offset != -1 {
+        used_frame[hdr.refidx[6] as usize] = true;
+    }
+
+    for &r in [4, 5].iter() {
+        let mut earliest_frame_offset = i32::max_value();
+        for i in 0..8 {
+            let hint = shifted_frame_offset[i];
+            if !used_frame[i] && hint >= cur_frame_offset && hint < earliest_frame_offset {
+                hdr.refidx[r] = i as i32;
+                earliest_frame_offset = hint;
+            }
+        }
+        if earliest_frame_offset != i32::max_value() {
+            used_frame[hdr.refidx[r] as usize] = true;
+        }
+    }
+
+    // the remaining ones are the closest frames in the past
+    for r in 1..7 {
+        if hdr.refidx[r] < 0 {
+            let mut latest_frame_offset = -1;
+            for i in 0..8 {
+                let hint = shifted_frame_offset[i];
+                if !used_frame[i] && hint < cur_frame_offset && hint >= latest_frame_offset {
+                    hdr.refidx[r] = i as i32;
+                    latest_frame_offset = hint;
+                }
+            }
+            if latest_frame_offset != -1 {
+                used_frame[hdr.refidx[r] as usize] = true;
+            }
+        }
+    }
+
+    // and if there are none left, the earliest frame
+    let mut earliest_frame_offset = i32::max_value();
+    let mut earliest = 0;
+    for i in 0..8 {
+        let hint = shifted_frame_offset[i];
+        if hint < earliest_frame_offset {
+            earliest = i as i32;
+            earliest_frame_offset = hint;
+        }
+    }
+    for r in 0..7 {
+        if hdr.refidx[r] < 0 {
+            hdr.refidx[r] = earliest;
+        }
+    }
+
+    Ok(())
+}
+
+// Skip mode uses the closest forward and backward references, or the two
+// closest forward ones if there's no backward one, see 7.21 "Skip mode
+// params process"
+fn skip_mode_refs<T: Pixel>(
+    seqhdr: &SequenceHeader,
+    refs: &[RefState<T>; 8],
+    hdr: &FrameHeader,
+) -> Result<Option<[isize; 2]>, DecodeError> {
+    let n_bits = seqhdr.order_hint_n_bits;
+    let poc = hdr.frame_offset as i32;
+    let mut off_before: [Option<i32>; 2] = [None, None];
+    let mut off_after: Option<i32> = None;
+    let mut off_before_idx = [0isize; 2];
+    let mut off_after_idx = 0isize;
+    for i in 0..7 {
+        let ref_hdr = refs[hdr.refidx[i] as usize].frame_hdr.as_ref();
+        check_error(ref_hdr.is_none(), "reference frame_hdr.is_none()")?;
+        let refpoc = ref_hdr.unwrap().frame_offset as i32;
+
+        let diff = get_poc_diff(n_bits, refpoc, poc);
+        if diff > 0 {
+            if off_after.map_or(true, |off| get_poc_diff(n_bits, off, refpoc) > 0) {
+                off_after = Some(refpoc);
+                off_after_idx = i as isize;
+            }
+        } else if diff < 0 {
+            if off_before[0].map_or(true, |off| get_poc_diff(n_bits, refpoc, off) > 0) {
+                off_before[1] = off_before[0];
+                off_before[0] = Some(refpoc);
+                off_before_idx[1] = off_before_idx[0];
+                off_before_idx[0] = i as isize;
+            } else if off_before[0] != Some(refpoc)
+                && off_before[1].map_or(true, |off| get_poc_diff(n_bits, refpoc, off) > 0)
+            {
+                off_before[1] = Some(refpoc);
+                off_before_idx[1] = i as isize;
+            }
+        }
+    }
+
+    let pair = if off_before[0].is_some() && off_after.is_some() {
+        Some((off_before_idx[0], off_after_idx))
+    } else if off_before[0].is_some() && off_before[1].is_some() {
+        Some((off_before_idx[0], off_before_idx[1]))
+    } else {
+        None
+    };
+    Ok(pair.map(|(a, b)| [cmp::min(a, b), cmp::max(a, b)]))
+}
+
+fn parse_frame_hdr<T: Pixel>(
     gb: &mut GetBits,
     seqhdr: &SequenceHeader,
+    refs: &[RefState<T>; 8],
     hdr: &mut FrameHeader,
-) -> io::Result<()> {
+) -> Result<(), DecodeError> {
     let init_bit_pos = gb.get_bits_pos();
 
     hdr.show_existing_frame = !seqhdr.reduced_still_picture_header && gb.get_bits(1) != 0;
@@ -441,7 +640,7 @@ fn parse_frame_hdr(
     } else {
         hdr.force_integer_mv = AdaptiveBoolean::OFF;
     }
-    if !hdr.frame_is_intra() {
+    if hdr.frame_is_intra() {
         hdr.force_integer_mv = AdaptiveBoolean::ON;
     }
 
@@ -490,26 +689,67 @@ fn parse_frame_hdr(
         }
     }
 
-    if hdr.frame_type == FrameType::FRAME_TYPE_KEY || hdr.frame_type == FrameType::FRAME_TYPE_INTRA
-    {
-        hdr.refresh_frame_flags = if hdr.frame_type == FrameType::FRAME_TYPE_KEY && hdr.show_frame {
-            0xff
-        } else {
-            gb.get_bits(8)
-        };
-        if hdr.refresh_frame_flags != 0xff && hdr.error_resilient_mode && seqhdr.order_hint {
-            for _ in 0..8 {
-                gb.get_bits(seqhdr.order_hint_n_bits);
+    hdr.refresh_frame_flags = if hdr.frame_type == FrameType::FRAME_TYPE_KEY && hdr.show_frame {
+        0xff
+    } else {
+        gb.get_bits(8)
+    };
+    // the order hints the slots are expected to have, see 6.8.2
+    hdr.invalid_refs = 0;
+    if hdr.refresh_frame_flags != 0xff && hdr.error_resilient_mode && seqhdr.order_hint {
+        for i in 0..8 {
+            let ref_order_hint = gb.get_bits(seqhdr.order_hint_n_bits);
+            let ref_hdr = refs[i].frame_hdr.as_ref();
+            if ref_hdr.map_or(true, |h| h.frame_offset != ref_order_hint) {
+                hdr.invalid_refs |= 1 << i;
             }
         }
+    }
 
-        parse_frame_size(gb, seqhdr, hdr, false)?;
+    if hdr.frame_is_intra() {
+        parse_frame_size(gb, seqhdr, refs, hdr, false)?;
         hdr.allow_intrabc = hdr.allow_screen_content_tools != AdaptiveBoolean::OFF
             && !hdr.super_res.enabled
             && gb.get_bits(1) != 0;
         hdr.use_ref_frame_mvs = false;
     } else {
-        unimplemented!();
+        hdr.allow_intrabc = false;
+        hdr.frame_ref_short_signaling = (seqhdr.order_hint && gb.get_bits(1) != 0) as isize;
+        if hdr.frame_ref_short_signaling != 0 {
+            // only LAST and GOLDEN are coded, see 7.8
+            hdr.refidx[0] = gb.get_bits(3) as i32;
+            hdr.refidx[3] = gb.get_bits(3) as i32;
+            set_frame_refs(seqhdr, refs, hdr)?;
+        }
+        for i in 0..7 {
+            if hdr.frame_ref_short_signaling == 0 {
+                hdr.refidx[i] = gb.get_bits(3) as i32;
+            }
+            if seqhdr.frame_id_numbers_present {
+                let delta_ref_frame_id = gb.get_bits(seqhdr.delta_frame_id_n_bits) + 1;
+                let id_mask = (1 << seqhdr.frame_id_n_bits) - 1;
+                let ref_frame_id =
+                    (hdr.frame_id + (1 << seqhdr.frame_id_n_bits) - delta_ref_frame_id) & id_mask;
+                let ref_hdr = refs[hdr.refidx[i] as usize].frame_hdr.as_ref();
+                check_error(
+                    ref_hdr.map_or(true, |h| h.frame_id != ref_frame_id),
+                    "reference frame id mismatch",
+                )?;
+            }
+        }
+        let use_ref = !hdr.error_resilient_mode && hdr.frame_size_override;
+        parse_frame_size(gb, seqhdr, refs, hdr, use_ref)?;
+        hdr.hp = hdr.force_integer_mv == AdaptiveBoolean::OFF && gb.get_bits(1) != 0;
+        hdr.subpel_filter_mode = if gb.get_bits(1) != 0 {
+            FilterMode::N_FILTERS_OR_FILTER_SWITCHABLE
+        } else {
+            FromPrimitive::from_u32(gb.get_bits(2)).unwrap()
+        };
+        hdr.switchable_motion_mode = gb.get_bits(1) as isize;
+        hdr.use_ref_frame_mvs = !hdr.error_resilient_mode
+            && seqhdr.ref_frame_mvs
+            && seqhdr.order_hint
+            && gb.get_bits(1) != 0;
     }
     rav1d_log!(
         "HDR: post-frametype-specific-bits: off={}\n",
@@ -517,7 +757,7 @@ fn parse_frame_hdr(
     );
 
     hdr.refresh_context =
-        !seqhdr.reduced_still_picture_header && !hdr.disable_cdf_update && !gb.get_bits(1) != 0;
+        !seqhdr.reduced_still_picture_header && !hdr.disable_cdf_update && gb.get_bits(1) == 0;
     rav1d_log!(
         "HDR: post-refresh_context: off={}\n",
         gb.get_bits_pos() - init_bit_pos
@@ -753,14 +993,12 @@ fn parse_frame_hdr(
                 }
             }
         } else {
-            unimplemented!();
             // segmentation.update_data was false so we should copy
-            // segmentation data from the reference frame.
-            /*debug_assert!(hdr.primary_ref_frame != PRIMARY_REF_NONE as u32);
-            let pri_ref = hdr.refidx[hdr.primary_ref_frame as usize];
-            if (!c->refs[pri_ref].p.p.frame_hdr) return DAV1D_ERR(EINVAL);
-            hdr.segmentation.seg_data =
-                c->refs[pri_ref].p.p.frame_hdr.segmentation.seg_data;*/
+            // segmentation data from the reference frame, see load_previous()
+            let pri_ref = hdr.refidx[hdr.primary_ref_frame as usize] as usize;
+            let ref_hdr = refs[pri_ref].frame_hdr.as_ref();
+            check_error(ref_hdr.is_none(), "primary ref frame_hdr.is_none()")?;
+            hdr.segmentation.seg_data = ref_hdr.unwrap().segmentation.seg_data;
         }
     } else {
         // TODO: what's the optimized way to do memset to 0 in Rust?
@@ -846,11 +1084,11 @@ fn parse_frame_hdr(
         if hdr.primary_ref_frame == PRIMARY_REF_NONE as u32 {
             hdr.loopfilter.mode_ref_deltas = LoopfilterModeRefDeltas::default();
         } else {
-            unimplemented!();
-            /*let ref_frame = hdr.refidx[hdr.primary_ref_frame as usize];
-            if (!c->refs[ref].p.p.frame_hdr) return DAV1D_ERR(EINVAL);
-            hdr.loopfilter.mode_ref_deltas =
-                c->refs[ref].p.p.frame_hdr.loopfilter.mode_ref_deltas;*/
+            // the deltas are only coded if they change, see load_previous()
+            let pri_ref = hdr.refidx[hdr.primary_ref_frame as usize] as usize;
+            let ref_hdr = refs[pri_ref].frame_hdr.as_ref();
+            check_error(ref_hdr.is_none(), "primary ref frame_hdr.is_none()")?;
+            hdr.loopfilter.mode_ref_deltas = ref_hdr.unwrap().loopfilter.mode_ref_deltas;
         }
         hdr.loopfilter.mode_ref_delta_enabled = gb.get_bits(1) != 0;
         if hdr.loopfilter.mode_ref_delta_enabled {
@@ -956,56 +1194,10 @@ fn parse_frame_hdr(
 
     hdr.skip_mode_allowed = false;
     if hdr.switchable_comp_refs && !hdr.frame_is_intra() && seqhdr.order_hint {
-        unimplemented!();
-        /*let poc = hdr.frame_offset;
-        let off_before: [u32;2] = [ 0xFFFFFFFF, 0xFFFFFFFF ];
-        let off_after = -1;
-        let mut off_before_idx:[i32;2];
-        let mut off_after_idx:i32;
-        off_before_idx[0] = 0;
-        for i in 0..7 {
-            if (!c->refs[hdr.refidx[i]].p.p.data[0]) return DAV1D_ERR(EINVAL);
-            const unsigned refpoc = c->refs[hdr.refidx[i]].p.p.frame_hdr.frame_offset;
-
-            const int diff = get_poc_diff(seqhdr.order_hint_n_bits, refpoc, poc);
-            if (diff > 0) {
-                if (off_after == -1 || get_poc_diff(seqhdr.order_hint_n_bits,
-                                                    off_after, refpoc) > 0)
-                {
-                    off_after = refpoc;
-                    off_after_idx = i;
-                }
-            } else if (diff < 0) {
-                if (off_before[0] == 0xFFFFFFFFU ||
-                    get_poc_diff(seqhdr.order_hint_n_bits,
-                                 refpoc, off_before[0]) > 0)
-                {
-                    off_before[1] = off_before[0];
-                    off_before[0] = refpoc;
-                    off_before_idx[1] = off_before_idx[0];
-                    off_before_idx[0] = i;
-                } else if (refpoc != off_before[0] &&
-                           (off_before[1] == 0xFFFFFFFFU ||
-                            get_poc_diff(seqhdr.order_hint_n_bits,
-                                         refpoc, off_before[1]) > 0))
-                {
-                    off_before[1] = refpoc;
-                    off_before_idx[1] = i;
-                }
-            }
+        if let Some(refs) = skip_mode_refs(seqhdr, refs, hdr)? {
+            hdr.skip_mode_refs = refs;
+            hdr.skip_mode_allowed = true;
         }
-
-        if (off_before[0] != 0xFFFFFFFFU && off_after != -1) {
-            hdr.skip_mode_refs[0] = imin(off_before_idx[0], off_after_idx);
-            hdr.skip_mode_refs[1] = imax(off_before_idx[0], off_after_idx);
-            hdr.skip_mode_allowed = 1;
-        } else if (off_before[0] != 0xFFFFFFFFU &&
-                   off_before[1] != 0xFFFFFFFFU)
-        {
-            hdr.skip_mode_refs[0] = imin(off_before_idx[0], off_before_idx[1]);
-            hdr.skip_mode_refs[1] = imax(off_before_idx[0], off_before_idx[1]);
-            hdr.skip_mode_allowed = 1;
-        }*/
     }
     hdr.skip_mode_enabled = if hdr.skip_mode_allowed {
         gb.get_bits(1) != 0
@@ -1052,15 +1244,15 @@ fn parse_frame_hdr(
                 continue;
             }
 
-            let mut ref_gmv = &mut WarpedMotionParams::default();
-            if hdr.primary_ref_frame != PRIMARY_REF_NONE as u32 {
-                unimplemented!();
-                /*let pri_ref = hdr.refidx[hdr.primary_ref_frame as usize];
-                if (!c->refs[pri_ref].p.p.frame_hdr) return DAV1D_ERR(EINVAL);
-                ref_gmv = &c->refs[pri_ref].p.p.frame_hdr.gmv[i];*/
-            }
+            let ref_mat = if hdr.primary_ref_frame == PRIMARY_REF_NONE as u32 {
+                WarpedMotionParams::default().matrix
+            } else {
+                let pri_ref = hdr.refidx[hdr.primary_ref_frame as usize] as usize;
+                let ref_hdr = refs[pri_ref].frame_hdr.as_ref();
+                check_error(ref_hdr.is_none(), "primary ref frame_hdr.is_none()")?;
+                ref_hdr.unwrap().gmv[i].matrix
+            };
             let mat = &mut hdr.gmv[i].matrix;
-            let ref_mat = &mut ref_gmv.matrix;
             let bits: u32;
             let shift: u32;
 
@@ -1085,6 +1277,10 @@ fn parse_frame_hdr(
 
             mat[0] = gb.get_bits_subexp(ref_mat[0] >> shift as i32, bits) * (1 << shift as i32);
             mat[1] = gb.get_bits_subexp(ref_mat[1] >> shift as i32, bits) * (1 << shift as i32);
+
+            // the shear parameters are used by the warped prediction, whether
+            // the model may be used for it is checked in decode_frame
+            get_shear_params(&mut hdr.gmv[i]);
         }
     }
     rav1d_log!("HDR: post-gmv: off={}\n", gb.get_bits_pos() - init_bit_pos);
@@ -1092,83 +1288,92 @@ fn parse_frame_hdr(
     hdr.film_grain.present =
         seqhdr.film_grain_present && (hdr.show_frame || hdr.showable_frame) && gb.get_bits(1) != 0;
     if hdr.film_grain.present {
-        unimplemented!();
-    /*
-    let seed = gb.get_bits( 16) as u16;
-    hdr.film_grain.update = hdr.frame_type != FrameType::FRAME_TYPE_INTER || gb.get_bits( 1) != 0;
-    if !hdr.film_grain.update {
-        let refidx = gb.get_bits( 3) as i32;
-        let mut i = 0;
-        while i < 7 {
-            if hdr.refidx[i] == refidx {
-                break;
-            }
-            i+=1;
-        }
-        if (i == 7 || !c->refs[refidx].p.p.frame_hdr)  goto error;
-        hdr.film_grain.data = c->refs[refidx].p.p.frame_hdr.film_grain.data;
-        hdr.film_grain.data.seed = seed;
-    } else {
-        Dav1dFilmGrainData *const fgd = &hdr.film_grain.data;
-        fgd->seed = seed;
-
-        fgd->num_y_points = gb.get_bits( 4);
-        if (fgd->num_y_points > 14) goto error;
-        for (int i = 0; i < fgd->num_y_points; i++) {
-            fgd->y_points[i][0] = gb.get_bits( 8);
-            if (i && fgd->y_points[i - 1][0] >= fgd->y_points[i][0])
-                goto error;
-            fgd->y_points[i][1] = gb.get_bits( 8);
-        }
+        let seed = gb.get_bits(16) as u16;
+        hdr.film_grain.update =
+            hdr.frame_type != FrameType::FRAME_TYPE_INTER || gb.get_bits(1) != 0;
+        if !hdr.film_grain.update {
+            let refidx = gb.get_bits(3) as i32;
+            check_error(
+                !hdr.refidx.contains(&refidx),
+                "film_grain refidx not in refidx",
+            )?;
+            let ref_hdr = refs[refidx as usize].frame_hdr.as_ref();
+            check_error(ref_hdr.is_none(), "film_grain ref frame_hdr.is_none()")?;
+            hdr.film_grain.data = ref_hdr.unwrap().film_grain.data;
+            hdr.film_grain.data.seed = seed;
+        } else {
+            let fgd = &mut hdr.film_grain.data;
+            fgd.seed = seed;
 
-        fgd->chroma_scaling_from_luma =
-            !seqhdr.monochrome && gb.get_bits( 1);
-        if (seqhdr.monochrome || fgd->chroma_scaling_from_luma ||
-            (seqhdr.ss_ver == 1 && seqhdr.ss_hor == 1 && !fgd->num_y_points))
-        {
-            fgd->num_uv_points[0] = fgd->num_uv_points[1] = 0;
-        } else for (int pl = 0; pl < 2; pl++) {
-            fgd->num_uv_points[pl] = gb.get_bits( 4);
-            if (fgd->num_uv_points[pl] > 10) goto error;
-            for (int i = 0; i < fgd->num_uv_points[pl]; i++) {
-                fgd->uv_points[pl][i][0] = gb.get_bits( 8);
-                if (i && fgd->uv_points[pl][i - 1][0] >= fgd->uv_points[pl][i][0])
-                    goto error;
-                fgd->uv_points[pl][i][1] = gb.get_bits( 8);
+            fgd.num_y_points = gb.get_bits(4) as isize;
+            check_error(fgd.num_y_points > 14, "fgd.num_y_points > 14")?;
+            for i in 0..fgd.num_y_points as usize {
+                fgd.y_points[i][0] = gb.get_bits(8) as u8;
+                check_error(
+                    i > 0 && fgd.y_points[i - 1][0] >= fgd.y_points[i][0],
+                    "fgd.y_points not increasing",
+                )?;
+                fgd.y_points[i][1] = gb.get_bits(8) as u8;
             }
-        }
 
-        if (seqhdr.ss_hor == 1 && seqhdr.ss_ver == 1 &&
-            !!fgd->num_uv_points[0] != !!fgd->num_uv_points[1])
-        {
-            goto error;
-        }
+            fgd.chroma_scaling_from_luma = (!seqhdr.monochrome && gb.get_bits(1) != 0) as isize;
+            if seqhdr.monochrome
+                || fgd.chroma_scaling_from_luma != 0
+                || (seqhdr.ss_ver == 1 && seqhdr.ss_hor == 1 && fgd.num_y_points == 0)
+            {
+                fgd.num_uv_points = [0; 2];
+            } else {
+                for pl in 0..2 {
+                    fgd.num_uv_points[pl] = gb.get_bits(4) as isize;
+                    check_error(fgd.num_uv_points[pl] > 10, "fgd.num_uv_points > 10")?;
+                    for i in 0..fgd.num_uv_points[pl] as usize {
+                        fgd.uv_points[pl][i][0] = gb.get_bits(8) as u8;
+                        check_error(
+                            i > 0 && fgd.uv_points[pl][i - 1][0] >= fgd.uv_points[pl][i][0],
+                            "fgd.uv_points not increasing",
+                        )?;
+                        fgd.uv_points[pl][i][1] = gb.get_bits(8) as u8;
+                    }
+                }
+            }
 
-        fgd->scaling_shift = gb.get_bits( 2) + 8;
-        fgd->ar_coeff_lag = gb.get_bits( 2);
-        const int num_y_pos = 2 * fgd->ar_coeff_lag * (fgd->ar_coeff_lag + 1);
-        if (fgd->num_y_points)
-            for (int i = 0; i < num_y_pos; i++)
-                fgd->ar_coeffs_y[i] = gb.get_bits( 8) - 128;
-        for (int pl = 0; pl < 2; pl++)
-            if (fgd->num_uv_points[pl] || fgd->chroma_scaling_from_luma) {
-                const int num_uv_pos = num_y_pos + !!fgd->num_y_points;
-                for (int i = 0; i < num_uv_pos; i++)
-                    fgd->ar_coeffs_uv[pl][i] = gb.get_bits( 8) - 128;
+            check_error(
+                seqhdr.ss_hor == 1
+                    && seqhdr.ss_ver == 1
+                    && (fgd.num_uv_points[0] != 0) != (fgd.num_uv_points[1] != 0),
+                "fgd.num_uv_points mismatch for 4:2:0",
+            )?;
+
+            fgd.scaling_shift = gb.get_bits(2) as isize + 8;
+            fgd.ar_coeff_lag = gb.get_bits(2) as isize;
+            let num_y_pos = (2 * fgd.ar_coeff_lag * (fgd.ar_coeff_lag + 1)) as usize;
+            if fgd.num_y_points != 0 {
+                for i in 0..num_y_pos {
+                    fgd.ar_coeffs_y[i] = (gb.get_bits(8) as i32 - 128) as i8;
+                }
+            }
+            for pl in 0..2 {
+                if fgd.num_uv_points[pl] != 0 || fgd.chroma_scaling_from_luma != 0 {
+                    let num_uv_pos = num_y_pos + (fgd.num_y_points != 0) as usize;
+                    for i in 0..num_uv_pos {
+                        fgd.ar_coeffs_uv[pl][i] = (gb.get_bits(8) as i32 - 128) as i8;
+                    }
+                }
             }
-        fgd->ar_coeff_shift = gb.get_bits( 2) + 6;
-        fgd->grain_scale_shift = gb.get_bits( 2);
-        for (int pl = 0; pl < 2; pl++)
-            if (fgd->num_uv_points[pl]) {
-                fgd->uv_mult[pl] = gb.get_bits( 8) - 128;
-                fgd->uv_luma_mult[pl] = gb.get_bits( 8) - 128;
-                fgd->uv_offset[pl] = gb.get_bits( 9) - 256;
+            fgd.ar_coeff_shift = gb.get_bits(2) as isize + 6;
+            fgd.grain_scale_shift = gb.get_bits(2) as isize;
+            for pl in 0..2 {
+                if fgd.num_uv_points[pl] != 0 {
+                    fgd.uv_mult[pl] = gb.get_bits(8) as isize - 128;
+                    fgd.uv_luma_mult[pl] = gb.get_bits(8) as isize - 128;
+                    fgd.uv_offset[pl] = gb.get_bits(9) as isize - 256;
+                }
             }
-        fgd->overlap_flag = gb.get_bits( 1);
-        fgd->clip_to_restricted_range = gb.get_bits( 1);
-    }*/
+            fgd.overlap_flag = gb.get_bits(1) as isize;
+            fgd.clip_to_restricted_range = gb.get_bits(1) as isize;
+        }
     } else {
-        // TODO: hdr.film_grain.data, 0, sizeof(hdr.film_grain.data));
+        hdr.film_grain.data = FilmGrainData::default();
     }
     rav1d_log!(
         "HDR: post-filmgrain: off={}\n",
@@ -1178,11 +1383,88 @@ fn parse_frame_hdr(
     Ok(())
 }
 
+// see 5.8.5 and 5.8.6
+fn parse_scalability(gb: &mut GetBits) -> Scalability {
+    let mut scalability = Scalability::default();
+    scalability.mode_idc = gb.get_bits(8) as u8;
+    if scalability.mode_idc != SCALABILITY_SS {
+        return scalability;
+    }
+
+    let spatial_layers_cnt = gb.get_bits(2) as usize + 1;
+    let spatial_layer_dimensions_present = gb.get_bits(1) != 0;
+    let spatial_layer_description_present = gb.get_bits(1) != 0;
+    let temporal_group_description_present = gb.get_bits(1) != 0;
+    gb.get_bits(3); // reserved
+    scalability.spatial_layers_cnt = spatial_layers_cnt as u8;
+
+    if spatial_layer_dimensions_present {
+        scalability.spatial_layer_max_dimensions = (0..spatial_layers_cnt)
+            .map(|_| [gb.get_bits(16) as u16, gb.get_bits(16) as u16])
+            .collect();
+    }
+    if spatial_layer_description_present {
+        scalability.spatial_layer_ref_id = (0..spatial_layers_cnt)
+            .map(|_| gb.get_bits(8) as u8)
+            .collect();
+    }
+    if temporal_group_description_present {
+        let temporal_group_size = gb.get_bits(8);
+        scalability.temporal_group = (0..temporal_group_size)
+            .map(|_| {
+                let temporal_id = gb.get_bits(3) as u8;
+                let temporal_switching_up_point = gb.get_bits(1) != 0;
+                let spatial_switching_up_point = gb.get_bits(1) != 0;
+                let ref_cnt = gb.get_bits(3);
+                TemporalGroupEntry {
+                    temporal_id,
+                    temporal_switching_up_point,
+                    spatial_switching_up_point,
+                    ref_pic_diff: (0..ref_cnt).map(|_| gb.get_bits(8) as u8).collect(),
+                }
+            })
+            .collect();
+    }
+
+    scalability
+}
+
+// see 5.8.7
+fn parse_timecode(gb: &mut GetBits) -> Timecode {
+    let mut timecode = Timecode::default();
+    timecode.counting_type = gb.get_bits(5) as u8;
+    timecode.full_timestamp = gb.get_bits(1) != 0;
+    timecode.discontinuity = gb.get_bits(1) != 0;
+    timecode.cnt_dropped = gb.get_bits(1) != 0;
+    timecode.n_frames = gb.get_bits(9) as u16;
+
+    if timecode.full_timestamp {
+        timecode.seconds = Some(gb.get_bits(6) as u8);
+        timecode.minutes = Some(gb.get_bits(6) as u8);
+        timecode.hours = Some(gb.get_bits(5) as u8);
+    } else if gb.get_bits(1) != 0 {
+        timecode.seconds = Some(gb.get_bits(6) as u8);
+        if gb.get_bits(1) != 0 {
+            timecode.minutes = Some(gb.get_bits(6) as u8);
+            if gb.get_bits(1) != 0 {
+                timecode.hours = Some(gb.get_bits(5) as u8);
+            }
+        }
+    }
+
+    timecode.time_offset_length = gb.get_bits(5) as u8;
+    if timecode.time_offset_length > 0 {
+        timecode.time_offset_value = gb.get_bits(timecode.time_offset_length as u32);
+    }
+
+    timecode
+}
+
 fn parse_tile_hdr(
     gb: &mut GetBits,
     tile: &mut Vec<TileGroup>,
     frame_hdr: &FrameHeader,
-) -> io::Result<()> {
+) -> Result<(), DecodeError> {
     let mut have_tile_pos = false;
     let n_tiles = frame_hdr.tiling.cols * frame_hdr.tiling.rows;
     if n_tiles > 1 {
@@ -1212,8 +1494,72 @@ fn parse_tile_hdr(
     Ok(())
 }
 
+// see 5.12.1 and 5.12.2, data is the payload of the OBU, which starts at
+// offset in the packet
+fn parse_tile_list(
+    data: &[u8],
+    offset: usize,
+    frame_hdr: &FrameHeader,
+) -> Result<TileList, DecodeError> {
+    check_error(data.len() < 4, "truncated tile list")?;
+    let mut gb = GetBits::new(&data[..4]);
+    let output_width = gb.get_bits(8) as usize + 1;
+    let output_height = gb.get_bits(8) as usize + 1;
+    let tile_count = gb.get_bits(16) as usize + 1;
+    check_error(
+        tile_count > MAX_TILE_LIST_ENTRIES,
+        "too many tile list entries",
+    )?;
+
+    let mut entries = Vec::with_capacity(tile_count);
+    let mut pos = 4;
+    for _ in 0..tile_count {
+        check_error(data.len() - pos < 5, "truncated tile list entry")?;
+        let mut gb = GetBits::new(&data[pos..pos + 5]);
+        let entry = TileListEntry {
+            anchor_frame_idx: gb.get_bits(8) as usize,
+            tile_row: gb.get_bits(8) as i32,
+            tile_col: gb.get_bits(8) as i32,
+            data_offset: offset + pos + 5,
+            data_sz: gb.get_bits(16) as usize + 1,
+        };
+        pos += 5;
+        check_error(
+            entry.anchor_frame_idx >= MAX_ANCHOR_FRAMES
+                || entry.tile_row >= frame_hdr.tiling.rows
+                || entry.tile_col >= frame_hdr.tiling.cols,
+            "invalid tile list entry",
+        )?;
+        check_error(entry.data_sz > data.len() - pos, "truncated tile data")?;
+        pos += entry.data_sz;
+        entries.push(entry);
+    }
+
+    Ok(TileList {
+        output_width,
+        output_height,
+        entries,
+    })
+}
+
 impl<T: Pixel> Context<T> {
-    pub fn parse_obus(&mut self, offset: usize, global: bool) -> io::Result<usize> {
+    // Parse the OBU at offset of the packet, and decode the frame it
+    // completes. On errors the frame being parsed is dropped, and decoding
+    // resumes with the next random access point.
+    pub fn parse_obus(&mut self, offset: usize, global: bool) -> Result<usize, DecodeError> {
+        self.parse_obu(offset, global).map_err(|e| {
+            self.frame_hdr = None;
+            self.tile.clear();
+            self.n_tiles = 0;
+            self.waiting_for_rap = true;
+            rav1d_log!("Error parsing OBU data: {}\n", e);
+
+            let obu_type = (self.packet.as_ref().unwrap().data[offset] >> 3) & 0xf;
+            e.in_obu(obu_type as u32, offset)
+        })
+    }
+
+    fn parse_obu(&mut self, offset: usize, global: bool) -> Result<usize, DecodeError> {
         let data = &self.packet.as_ref().unwrap().data[offset..];
         let mut gb = GetBits::new(data);
 
@@ -1256,10 +1602,7 @@ impl<T: Pixel> Context<T> {
         // Make sure that there are enough bits left in the buffer for the
         // rest of the OBU.
         if len > data.len() - init_byte_pos {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Error parsing frame header",
-            ));
+            return Err(DecodeError::invalid("Error parsing frame header"));
         }
 
         if obu_type != ObuType::OBU_SEQ_HDR as u32
@@ -1280,25 +1623,24 @@ impl<T: Pixel> Context<T> {
                 self.operating_point_idc =
                     parse_seq_hdr(&mut gb, Rc::make_mut(&mut seq_hdr), self.operating_point)?;
                 gb.check_for_overrun(init_bit_pos as u32, len as u32)?;
+                let spatial_mask = self.operating_point_idc >> 8;
+                self.max_spatial_id = if spatial_mask != 0 { ulog2(spatial_mask) } else { 0 };
+                // high bit depth pictures don't fit into 8-bit pixels
+                check_error(
+                    seq_hdr.hbd != 0 && mem::size_of::<T>() == 1,
+                    "high bit depth stream needs a Context<u16>",
+                )?;
                 if self.seq_hdr.is_none() {
                     self.frame_hdr = None;
-                } else if seq_hdr == *self.seq_hdr.as_ref().unwrap() {
+                } else if seq_hdr != *self.seq_hdr.as_ref().unwrap() {
+                    // a new coded video sequence starts, none of the
+                    // references can be used anymore
                     self.frame_hdr = None;
-
-                    /*
-                    //TODO
-                    c->mastering_display = NULL;
-                    c->content_light = NULL;
-                    dav1d_ref_dec(&c->mastering_display_ref);
-                    dav1d_ref_dec(&c->content_light_ref);
-                    for (int i = 0; i < 8; i++) {
-                        if (c->refs[i].p.p.data[0])
-                            dav1d_thread_picture_unref(&c->refs[i].p);
-                        dav1d_ref_dec(&c->refs[i].segmap);
-                        dav1d_ref_dec(&c->refs[i].refmvs);
-                        dav1d_cdf_thread_unref(&c->cdf[i]);
-                    }
-                    */
+                    self.mastering_display = None;
+                    self.content_light = None;
+                    self.scalability = None;
+                    self.refs = Default::default();
+                    self.waiting_for_rap = true;
                 }
                 self.seq_hdr = Some(seq_hdr);
             }
@@ -1323,7 +1665,10 @@ impl<T: Pixel> Context<T> {
                     if let (Some(seq_hdr), Some(frame_hdr)) =
                         (self.seq_hdr.as_ref(), self.frame_hdr.as_mut())
                     {
-                        parse_frame_hdr(&mut gb, seq_hdr, Rc::make_mut(frame_hdr))?;
+                        let hdr = Rc::make_mut(frame_hdr);
+                        hdr.temporal_id = temporal_id;
+                        hdr.spatial_id = spatial_id;
+                        parse_frame_hdr(&mut gb, seq_hdr, &self.refs, hdr)?;
 
                         self.tile = vec![];
                         self.n_tiles = 0;
@@ -1343,18 +1688,18 @@ impl<T: Pixel> Context<T> {
                         )?;
                         // TODO: set ctx->frame_hdr = NULL;
 
-                        check_error(
-                            self.frame_size_limit != 0
-                                && frame_hdr.width[1] as usize * frame_hdr.height as usize
-                                    > self.frame_size_limit,
-                            "Frame size exceeds limit",
-                        )?;
-                        /*{
-                            rav1d_log("Frame size %dx%d exceeds limit %u\n", c->frame_hdr->width[1],
-                                      c->frame_hdr->height, self.frame_size_limit);
-                            c->frame_hdr = NULL;
-                            return DAV1D_ERR(ERANGE);
-                        }*/
+                        if self.frame_size_limit != 0
+                            && frame_hdr.width[1] as usize * frame_hdr.height as usize
+                                > self.frame_size_limit
+                        {
+                            rav1d_log!(
+                                "Frame size {}x{} exceeds limit {}\n",
+                                frame_hdr.width[1],
+                                frame_hdr.height,
+                                self.frame_size_limit
+                            );
+                            return Err(DecodeError::ResourceLimit("frame size"));
+                        }
 
                         // This is the frame header at the start of a frame OBU.
                         // There's no trailing bit at the end to skip, but we do need
@@ -1386,27 +1731,147 @@ impl<T: Pixel> Context<T> {
                         debug_assert!((bit_pos & 7) == 0);
                         debug_assert!(pkt_bytelen >= (bit_pos >> 3));
                         //dav1d_data_ref(&c->tile[c->n_tile_data].data, in);
-                        last.data_offset = bit_pos >> 3;
+                        // the tiles are located in the packet
+                        last.data_offset = offset + (bit_pos >> 3);
                         last.data_sz = pkt_bytelen - (bit_pos >> 3);
+                        let last = *last;
                         // ensure tile groups are in order and sane, see 6.10.1
-                        check_error(
-                            last.start > last.end || last.start != self.n_tiles,
-                            "last.start > last.end || last.start != self.n_tiles",
-                        )?;
-                        /*for i = 0; i <= c->n_tile_data; i++)
-                            dav1d_data_unref_internal(&c->tile[i].data);
-                            c->n_tile_data = 0;
-                            c->n_tiles = 0;
-                            goto error;
-                        }*/
-                        self.n_tiles += 1 + last.end - last.start;
+                        let tiling = &self.frame_hdr.as_ref().unwrap().tiling;
+                        let invalid = last.start > last.end
+                            || last.end >= tiling.cols * tiling.rows
+                            || last.start < self.n_tiles;
+                        if self.conceal_errors && (invalid || last.start > self.n_tiles) {
+                            // the tiles of lost tile groups are concealed, a
+                            // tile group overlapping the previous ones or out
+                            // of range is dropped
+                            rav1d_log!("Concealing tile group {}-{}\n", last.start, last.end);
+                            self.tile.pop();
+                            if !invalid {
+                                self.tile.push(TileGroup {
+                                    data_offset: 0,
+                                    data_sz: 0,
+                                    start: self.n_tiles,
+                                    end: last.start - 1,
+                                });
+                                self.tile.push(last);
+                                self.n_tiles = last.end + 1;
+                            }
+                        } else {
+                            check_error(
+                                invalid || last.start != self.n_tiles,
+                                "tile group out of order or out of range",
+                            )?;
+                            /*for i = 0; i <= c->n_tile_data; i++)
+                                dav1d_data_unref_internal(&c->tile[i].data);
+                                c->n_tile_data = 0;
+                                c->n_tiles = 0;
+                                goto error;
+                            }*/
+                            self.n_tiles += 1 + last.end - last.start;
+                        }
                     }
                 }
             }),
             Some(ObuType::OBU_METADATA) => {
-                unimplemented!();
+                // obu metadata type field
+                let meta_type = gb.get_uleb128();
+                gb.check_error()?;
+
+                match FromPrimitive::from_u32(meta_type) {
+                    Some(ObuMetaType::OBU_META_HDR_CLL) => {
+                        let content_light = ContentLightLevel {
+                            max_content_light_level: gb.get_bits(16) as isize,
+                            max_frame_average_light_level: gb.get_bits(16) as isize,
+                        };
+                        // Skip the trailing bit, align to the next byte boundary and
+                        // check for overrun.
+                        gb.get_bits(1);
+                        gb.bytealign_get_bits();
+                        gb.check_for_overrun(init_bit_pos as u32, len as u32)?;
+                        self.content_light = Some(content_light);
+                    }
+                    Some(ObuMetaType::OBU_META_HDR_MDCV) => {
+                        let mut mastering_display = MasteringDisplay::default();
+                        for primary in mastering_display.primaries.iter_mut() {
+                            primary[0] = gb.get_bits(16) as u16;
+                            primary[1] = gb.get_bits(16) as u16;
+                        }
+                        mastering_display.white_point[0] = gb.get_bits(16) as u16;
+                        mastering_display.white_point[1] = gb.get_bits(16) as u16;
+                        mastering_display.max_luminance = gb.get_bits(32);
+                        mastering_display.min_luminance = gb.get_bits(32);
+                        // Skip the trailing bit, align to the next byte boundary and
+                        // check for overrun.
+                        gb.get_bits(1);
+                        gb.bytealign_get_bits();
+                        gb.check_for_overrun(init_bit_pos as u32, len as u32)?;
+                        self.mastering_display = Some(mastering_display);
+                    }
+                    Some(ObuMetaType::OBU_META_ITUT_T35) => {
+                        // Don't take into account all the trailing bits for
+                        // payload_size, nor the meta_type bytes
+                        let obu = &data[init_byte_pos..pkt_bytelen];
+                        let trailing = obu.iter().rev().take_while(|&&b| b == 0).count() + 1;
+                        let meta_type_len = (gb.get_bits_pos() as usize >> 3) - init_byte_pos;
+                        let mut payload_size =
+                            obu.len() as isize - trailing as isize - meta_type_len as isize;
+
+                        let mut itut_t35 = ITUTT35::default();
+                        itut_t35.country_code = gb.get_bits(8) as u8;
+                        payload_size -= 1;
+                        if itut_t35.country_code == 0xff {
+                            itut_t35.country_code_extension_byte = gb.get_bits(8) as u8;
+                            payload_size -= 1;
+                        }
+
+                        if payload_size <= 0 {
+                            rav1d_log!("Malformed ITU-T T.35 metadata message format\n");
+                        } else {
+                            itut_t35.payload =
+                                (0..payload_size).map(|_| gb.get_bits(8) as u8).collect();
+                            self.itut_t35.push(itut_t35);
+                        }
+                    }
+                    Some(ObuMetaType::OBU_META_SCALABILITY) => {
+                        let scalability = parse_scalability(&mut gb);
+                        // Skip the trailing bit, align to the next byte boundary and
+                        // check for overrun.
+                        gb.get_bits(1);
+                        gb.bytealign_get_bits();
+                        gb.check_for_overrun(init_bit_pos as u32, len as u32)?;
+                        self.scalability = Some(scalability);
+                    }
+                    Some(ObuMetaType::OBU_META_TIMECODE) => {
+                        let timecode = parse_timecode(&mut gb);
+                        // Skip the trailing bit, align to the next byte boundary and
+                        // check for overrun.
+                        gb.get_bits(1);
+                        gb.bytealign_get_bits();
+                        gb.check_for_overrun(init_bit_pos as u32, len as u32)?;
+                        self.timecode = Some(timecode);
+                    }
+                    None => {
+                        // print a warning but don't fail for unknown types
+                        rav1d_log!("Unknown Metadata OBU type {}\n", meta_type);
+                    }
+                }
+            }
+            Some(ObuType::OBU_TILE_LIST) => {
+                if !global {
+                    check_error(!self.large_scale_tile, "tile list outside large scale tile mode")?;
+                    check_error(self.frame_hdr.is_none(), "frame_hdr.is_none()")?;
+                    let tile_list = parse_tile_list(
+                        &data[init_byte_pos..pkt_bytelen],
+                        offset + init_byte_pos,
+                        self.frame_hdr.as_ref().unwrap(),
+                    )?;
+                    self.decode_tile_list(&tile_list)?;
+                }
             }
-            Some(ObuType::OBU_PADDING) | Some(ObuType::OBU_TD) => {
+            Some(ObuType::OBU_TD) => {
+                self.new_temporal_unit = true;
+            }
+            Some(ObuType::OBU_PADDING) => {
                 // ignore OBUs we don't care about
             }
             _ => {
@@ -1415,15 +1880,17 @@ impl<T: Pixel> Context<T> {
             }
         }
 
-        if let (Some(seq_hdr), Some(frame_hdr)) = (self.seq_hdr.as_ref(), self.frame_hdr.as_ref()) {
+        if let (Some(_), Some(frame_hdr)) = (self.seq_hdr.as_ref(), self.frame_hdr.as_ref()) {
             if frame_hdr.show_existing_frame {
-                unimplemented!();
-            } else {
+                self.show_existing_frame()?;
+                self.frame_hdr = None;
+            } else if self.n_tiles == frame_hdr.tiling.cols * frame_hdr.tiling.rows {
+                // all tile groups of the frame have been received
                 check_error(self.tile.is_empty(), "tile_groups.is_empty()")?;
 
                 self.submit_frame()?;
 
-                //self.frame = Some(Frame::new(352, 288, ChromaSampling::Cs420));
+                self.frame_hdr = None;
                 self.n_tiles = 0;
             }
         }
@@ -1431,3 +1898,464 @@ impl<T: Pixel> Context<T> {
         Ok(len + init_byte_pos)
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::getbits::test::PutBits;
+
+    fn seq_hdr(n_bits: u32) -> SequenceHeader {
+        let mut seqhdr = SequenceHeader::default();
+        seqhdr.order_hint = true;
+        seqhdr.order_hint_n_bits = n_bits;
+        seqhdr
+    }
+
+    // reference slots holding frames with the given order hints
+    fn ref_slots(hints: [u32; 8]) -> [RefState<u8>; 8] {
+        let mut refs: [RefState<u8>; 8] = Default::default();
+        for (r, &hint) in refs.iter_mut().zip(hints.iter()) {
+            let mut hdr = FrameHeader::default();
+            hdr.frame_offset = hint;
+            r.frame_hdr = Some(Rc::new(hdr));
+        }
+        refs
+    }
+
+    fn frame_refs(n_bits: u32, hints: [u32; 8], poc: u32, last: i32, gold: i32) -> [i32; 7] {
+        let mut hdr = FrameHeader::default();
+        hdr.frame_offset = poc;
+        hdr.refidx = [last, 0, 0, gold, 0, 0, 0];
+        set_frame_refs(&seq_hdr(n_bits), &ref_slots(hints), &mut hdr).unwrap();
+        hdr.refidx
+    }
+
+    #[test]
+    fn set_frame_refs_order() {
+        // shifted order hints 127 126 130 132 125 129 124 123 around 128:
+        // ALTREF is the furthest future frame, BWDREF and ALTREF2 the
+        // closest ones, LAST2 and LAST3 the closest past ones
+        let hints = [9, 8, 12, 14, 7, 11, 6, 5];
+        assert_eq!(frame_refs(8, hints, 10, 0, 1), [0, 4, 6, 1, 5, 2, 3]);
+
+        // with no third future frame, ALTREF2 takes the closest past one
+        // left; the order hints wrap around at 16, giving the shifted ones
+        // 7 6 10 5 9 4 7 3 around 8
+        let hints = [0, 15, 3, 14, 2, 13, 0, 12];
+        assert_eq!(frame_refs(4, hints, 1, 0, 3), [0, 6, 1, 3, 4, 5, 2]);
+
+        // ties go to the last slot
+        let hints = [5; 8];
+        assert_eq!(frame_refs(8, hints, 10, 0, 0), [0, 7, 6, 0, 5, 4, 3]);
+    }
+
+    #[test]
+    fn set_frame_refs_empty_slot() {
+        let mut refs = ref_slots([0; 8]);
+        refs[5].frame_hdr = None;
+        let mut hdr = FrameHeader::default();
+        hdr.frame_offset = 1;
+        assert!(set_frame_refs(&seq_hdr(8), &refs, &mut hdr).is_err());
+    }
+
+    fn skip_mode(n_bits: u32, hints: [u32; 8], poc: u32) -> Option<[isize; 2]> {
+        let mut hdr = FrameHeader::default();
+        hdr.frame_offset = poc;
+        hdr.refidx = [0, 1, 2, 3, 4, 5, 6];
+        skip_mode_refs(&seq_hdr(n_bits), &ref_slots(hints), &hdr).unwrap()
+    }
+
+    #[test]
+    fn skip_mode_frames() {
+        // the closest forward and backward references, the first one on
+        // ties
+        let hints = [8, 9, 7, 12, 11, 9, 6, 10];
+        assert_eq!(skip_mode(8, hints, 10), Some([1, 4]));
+        // the two closest forward references, counting equal order hints
+        // once
+        let hints = [8, 9, 7, 6, 5, 9, 4, 10];
+        assert_eq!(skip_mode(8, hints, 10), Some([0, 1]));
+        // a single forward order hint
+        assert_eq!(skip_mode(8, [8; 8], 10), None);
+        // only backward references
+        assert_eq!(skip_mode(8, [12; 8], 10), None);
+        // the order hints wrap around at 16: 0 is just before 1, and 15
+        // further away
+        let hints = [15, 14, 2, 13, 12, 11, 0, 3];
+        assert_eq!(skip_mode(4, hints, 1), Some([2, 6]));
+    }
+
+    #[test]
+    fn inter_frame_hdr() {
+        let mut seqhdr = seq_hdr(7);
+        seqhdr.max_width = 64;
+        seqhdr.max_height = 64;
+        seqhdr.screen_content_tools = AdaptiveBoolean::ADAPTIVE;
+        seqhdr.force_integer_mv = AdaptiveBoolean::ADAPTIVE;
+
+        let mut pb = PutBits::default();
+        // show_existing_frame, frame_type, show_frame, error_resilient_mode
+        pb.put_bits(0, 1);
+        pb.put_bits(FrameType::FRAME_TYPE_INTER as u32, 2);
+        pb.put_bits(1, 1).put_bits(0, 1);
+        // disable_cdf_update, allow_screen_content_tools, force_integer_mv
+        pb.put_bits(0, 1).put_bits(1, 1).put_bits(0, 1);
+        // frame_size_override_flag, order_hint, primary_ref_frame
+        pb.put_bits(0, 1).put_bits(9, 7);
+        pb.put_bits(PRIMARY_REF_NONE as u32, 3);
+        // refresh_frame_flags, frame_refs_short_signaling, ref_frame_idx
+        pb.put_bits(0x12, 8).put_bits(0, 1);
+        for i in 0..7 {
+            pb.put_bits(7 - i, 3);
+        }
+        // render_and_frame_size_different, allow_high_precision_mv,
+        // is_filter_switchable, interpolation_filter,
+        // is_motion_mode_switchable
+        pb.put_bits(0, 1).put_bits(1, 1);
+        pb.put_bits(0, 1).put_bits(2, 2).put_bits(1, 1);
+        // disable_frame_end_update_cdf, uniform_tile_spacing_flag
+        pb.put_bits(1, 1).put_bits(1, 1);
+        // base_q_idx, without DC or chroma deltas, quantizer matrices,
+        // segmentation or delta_q
+        pb.put_bits(100, 8).put_bits(0, 3);
+        pb.put_bits(0, 1).put_bits(0, 1).put_bits(0, 1);
+        // loop filter levels and sharpness, loop_filter_delta_enabled
+        pb.put_bits(10, 6).put_bits(12, 6);
+        pb.put_bits(3, 6).put_bits(4, 6);
+        pb.put_bits(2, 3).put_bits(0, 1);
+        // tx_mode_select, reference_select, reduced_tx_set, and identity
+        // global motion for all references
+        pb.put_bits(1, 1).put_bits(0, 1);
+        pb.put_bits(0, 1).put_bits(0, 7);
+        let data = pb.trailing_bits().data();
+
+        let mut gb = GetBits::new(&data);
+        let mut hdr = FrameHeader::default();
+        parse_frame_hdr(&mut gb, &seqhdr, &ref_slots([0; 8]), &mut hdr).unwrap();
+        // all of the header was read, up to its trailing bits
+        assert_eq!(gb.get_bits(1), 1);
+        assert_eq!(gb.get_bits(7 - (gb.get_bits_pos() + 7) % 8), 0);
+        assert_eq!(gb.get_bits_pos() as usize, data.len() * 8);
+
+        assert_eq!(hdr.allow_screen_content_tools, AdaptiveBoolean::ON);
+        assert_eq!(hdr.force_integer_mv, AdaptiveBoolean::OFF);
+        assert_eq!(hdr.frame_offset, 9);
+        assert_eq!(hdr.refresh_frame_flags, 0x12);
+        assert_eq!(hdr.refidx, [7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!((hdr.width, hdr.height), ([64, 64], 64));
+        assert!(hdr.hp);
+        assert_eq!(hdr.subpel_filter_mode as u32, 2);
+        assert_eq!(hdr.switchable_motion_mode, 1);
+        assert!(!hdr.refresh_context);
+        assert_eq!(hdr.quant.yac, 100);
+        assert_eq!(hdr.loopfilter.level_y, [10, 12]);
+        assert_eq!((hdr.loopfilter.level_u, hdr.loopfilter.level_v), (3, 4));
+        assert_eq!(hdr.loopfilter.sharpness, 2);
+        assert_eq!(hdr.txfm_mode, TxfmMode::TX_SWITCHABLE);
+        assert!(!hdr.switchable_comp_refs);
+    }
+
+    // an OBU with a size field and no extension
+    pub(crate) fn obu(obu_type: ObuType, payload: &[u8]) -> Vec<u8> {
+        let mut pb = PutBits::default();
+        pb.put_bits(obu_type as u32, 5).put_bits(0b010, 3);
+        pb.put_uleb128(payload.len() as u32);
+        let mut data = pb.data();
+        data.extend_from_slice(payload);
+        data
+    }
+
+    // an OBU with a size field and an extension for the given layers
+    pub(crate) fn layer_obu(
+        obu_type: ObuType,
+        temporal_id: u32,
+        spatial_id: u32,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut pb = PutBits::default();
+        pb.put_bits(obu_type as u32, 5).put_bits(0b110, 3);
+        // obu_extension_header
+        pb.put_bits(temporal_id, 3).put_bits(spatial_id, 2);
+        pb.put_bits(0, 3);
+        pb.put_uleb128(payload.len() as u32);
+        let mut data = pb.data();
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn parse_obus(ctx: &mut Context<u8>, data: Vec<u8>) -> Result<usize, DecodeError> {
+        ctx.packet = Some(Packet {
+            data,
+            offset: 0,
+            pts: 0,
+        });
+        ctx.parse_obus(0, false)
+    }
+
+    fn t35_metadata(country_code: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut pb = PutBits::default();
+        pb.put_uleb128(ObuMetaType::OBU_META_ITUT_T35 as u32);
+        for &b in country_code.iter().chain(payload.iter()) {
+            pb.put_bits(b as u32, 8);
+        }
+        obu(ObuType::OBU_METADATA, &pb.trailing_bits().data())
+    }
+
+    #[test]
+    fn itut_t35_metadata() {
+        let mut ctx = Context::<u8>::new(&Config::default());
+        let data = t35_metadata(&[0xb5], &[1, 2, 3]);
+        assert_eq!(parse_obus(&mut ctx, data.clone()).unwrap(), data.len());
+        // a country code of 0xff is followed by an extension byte
+        let data = t35_metadata(&[0xff, 0x42], &[4, 5]);
+        assert_eq!(parse_obus(&mut ctx, data.clone()).unwrap(), data.len());
+        assert_eq!(
+            ctx.itut_t35,
+            [
+                ITUTT35 {
+                    country_code: 0xb5,
+                    country_code_extension_byte: 0,
+                    payload: vec![1, 2, 3],
+                },
+                ITUTT35 {
+                    country_code: 0xff,
+                    country_code_extension_byte: 0x42,
+                    payload: vec![4, 5],
+                },
+            ]
+        );
+
+        // messages without payload are dropped, but don't fail the OBU
+        ctx.itut_t35.clear();
+        let data = t35_metadata(&[0xff, 0x42], &[]);
+        assert_eq!(parse_obus(&mut ctx, data.clone()).unwrap(), data.len());
+        let data = t35_metadata(&[0xb5], &[]);
+        assert_eq!(parse_obus(&mut ctx, data.clone()).unwrap(), data.len());
+        assert!(ctx.itut_t35.is_empty());
+
+        // the OBU is cut short
+        let mut data = t35_metadata(&[0xb5], &[1, 2, 3]);
+        data.truncate(data.len() - 2);
+        assert!(parse_obus(&mut ctx, data).is_err());
+        assert!(ctx.itut_t35.is_empty());
+    }
+
+    fn timecode(full_timestamp: bool, units: &[u32], time_offset_length: u32) -> Timecode {
+        let mut pb = PutBits::default();
+        pb.put_bits(3, 5).put_bits(full_timestamp as u32, 1);
+        pb.put_bits(0, 1).put_bits(1, 1).put_bits(29, 9);
+        // seconds, minutes and hours, each preceded by a flag without a
+        // full timestamp
+        for (&v, &n) in units.iter().zip([6, 6, 5].iter()) {
+            if !full_timestamp {
+                pb.put_bits(1, 1);
+            }
+            pb.put_bits(v, n);
+        }
+        if !full_timestamp && units.len() < 3 {
+            pb.put_bits(0, 1);
+        }
+        pb.put_bits(time_offset_length, 5);
+        pb.put_bits(0x1234, time_offset_length);
+        parse_timecode(&mut GetBits::new(&pb.trailing_bits().data()))
+    }
+
+    #[test]
+    fn timecode_metadata() {
+        let expected = Timecode {
+            counting_type: 3,
+            full_timestamp: true,
+            discontinuity: false,
+            cnt_dropped: true,
+            n_frames: 29,
+            seconds: Some(59),
+            minutes: Some(58),
+            hours: Some(23),
+            time_offset_length: 0,
+            time_offset_value: 0,
+        };
+        assert_eq!(timecode(true, &[59, 58, 23], 0), expected);
+
+        let expected = Timecode {
+            full_timestamp: false,
+            time_offset_length: 16,
+            time_offset_value: 0x1234,
+            ..expected
+        };
+        assert_eq!(timecode(false, &[59, 58, 23], 16), expected);
+        let expected = Timecode {
+            hours: None,
+            ..expected
+        };
+        assert_eq!(timecode(false, &[59, 58], 16), expected);
+        let expected = Timecode {
+            minutes: None,
+            ..expected
+        };
+        assert_eq!(timecode(false, &[59], 16), expected);
+        let expected = Timecode {
+            seconds: None,
+            ..expected
+        };
+        assert_eq!(timecode(false, &[], 16), expected);
+    }
+
+    #[test]
+    fn scalability_metadata() {
+        let mut pb = PutBits::default();
+        pb.put_bits(SCALABILITY_SS as u32, 8);
+        pb.put_bits(1, 2).put_bits(0b111, 3).put_bits(0, 3);
+        pb.put_bits(640, 16).put_bits(360, 16);
+        pb.put_bits(1280, 16).put_bits(720, 16);
+        pb.put_bits(0, 8).put_bits(0, 8);
+        pb.put_bits(2, 8);
+        pb.put_bits(0, 3).put_bits(0b01, 2).put_bits(1, 3);
+        pb.put_bits(4, 8);
+        pb.put_bits(1, 3).put_bits(0b10, 2).put_bits(2, 3);
+        pb.put_bits(1, 8).put_bits(3, 8);
+        let data = pb.trailing_bits().data();
+        let expected = Scalability {
+            mode_idc: SCALABILITY_SS,
+            spatial_layers_cnt: 2,
+            spatial_layer_max_dimensions: vec![[640, 360], [1280, 720]],
+            spatial_layer_ref_id: vec![0, 0],
+            temporal_group: vec![
+                TemporalGroupEntry {
+                    temporal_id: 0,
+                    temporal_switching_up_point: false,
+                    spatial_switching_up_point: true,
+                    ref_pic_diff: vec![4],
+                },
+                TemporalGroupEntry {
+                    temporal_id: 1,
+                    temporal_switching_up_point: true,
+                    spatial_switching_up_point: false,
+                    ref_pic_diff: vec![1, 3],
+                },
+            ],
+        };
+        assert_eq!(parse_scalability(&mut GetBits::new(&data)), expected);
+
+        // the other modes only have their mode_idc coded
+        let expected = Scalability {
+            mode_idc: 3,
+            ..Default::default()
+        };
+        assert_eq!(parse_scalability(&mut GetBits::new(&[3, 0x80])), expected);
+    }
+
+    // a tile list of entries (anchor_frame_idx, tile_row, tile_col, data)
+    fn tile_list(
+        output_size: [u32; 2],
+        tile_count: u32,
+        entries: &[(u32, u32, u32, &[u8])],
+    ) -> Vec<u8> {
+        let mut pb = PutBits::default();
+        pb.put_bits(output_size[0] - 1, 8);
+        pb.put_bits(output_size[1] - 1, 8);
+        pb.put_bits(tile_count - 1, 16);
+        for &(anchor_frame_idx, tile_row, tile_col, data) in entries.iter() {
+            pb.put_bits(anchor_frame_idx, 8);
+            pb.put_bits(tile_row, 8).put_bits(tile_col, 8);
+            pb.put_bits(data.len() as u32 - 1, 16);
+            for &b in data.iter() {
+                pb.put_bits(b as u32, 8);
+            }
+        }
+        pb.data()
+    }
+
+    #[test]
+    fn tile_list_entries() {
+        let mut frame_hdr = FrameHeader::default();
+        frame_hdr.tiling.rows = 2;
+        frame_hdr.tiling.cols = 3;
+        let last_anchor = MAX_ANCHOR_FRAMES as u32 - 1;
+        let entries: [(u32, u32, u32, &[u8]); 2] =
+            [(last_anchor, 1, 2, &[1, 2, 3]), (0, 0, 0, &[4])];
+        let data = tile_list([4, 2], 2, &entries);
+        let expected = TileList {
+            output_width: 4,
+            output_height: 2,
+            entries: vec![
+                TileListEntry {
+                    anchor_frame_idx: MAX_ANCHOR_FRAMES - 1,
+                    tile_row: 1,
+                    tile_col: 2,
+                    data_offset: 10 + 4 + 5,
+                    data_sz: 3,
+                },
+                TileListEntry {
+                    anchor_frame_idx: 0,
+                    tile_row: 0,
+                    tile_col: 0,
+                    data_offset: 10 + 12 + 5,
+                    data_sz: 1,
+                },
+            ],
+        };
+        assert_eq!(parse_tile_list(&data, 10, &frame_hdr).unwrap(), expected);
+        // the largest output frame
+        let data = tile_list([256, 256], 1, &[(0, 0, 0, &[4])]);
+        let list = parse_tile_list(&data, 0, &frame_hdr).unwrap();
+        assert_eq!((list.output_width, list.output_height), (256, 256));
+
+        let invalid = [
+            tile_list([1, 1], 1, &[(last_anchor + 1, 0, 0, &[4])]),
+            tile_list([1, 1], 1, &[(0, 2, 0, &[4])]),
+            tile_list([1, 1], 1, &[(0, 0, 3, &[4])]),
+            tile_list([1, 1], MAX_TILE_LIST_ENTRIES as u32 + 1, &[(0, 0, 0, &[4])]),
+            // fewer entries than tile_count
+            tile_list([1, 1], 2, &[(0, 0, 0, &[4])]),
+            // no header
+            tile_list([1, 1], 1, &[])[..3].to_vec(),
+        ];
+        for data in invalid.iter() {
+            assert!(parse_tile_list(data, 0, &frame_hdr).is_err());
+        }
+        // less tile data than data_sz
+        let mut data = tile_list([1, 1], 1, &[(0, 0, 0, &[4, 5])]);
+        data.pop();
+        assert!(parse_tile_list(&data, 0, &frame_hdr).is_err());
+    }
+
+    #[test]
+    fn invalid_seq_hdr() {
+        let mut ctx = Context::<u8>::new(&Config::default());
+        // the sequence headers follow a temporal delimiter
+        let td = obu(ObuType::OBU_TD, &[]);
+        let mut parse_seq_hdr = |payload: &[u8]| {
+            let mut data = td.clone();
+            data.extend(obu(ObuType::OBU_SEQ_HDR, payload));
+            data.extend(td.iter());
+            ctx.packet = Some(Packet {
+                data,
+                offset: 0,
+                pts: 0,
+            });
+            ctx.parse_obus(td.len(), false).unwrap_err()
+        };
+
+        // seq_profile 3, reported at the start of the OBU
+        assert_eq!(
+            parse_seq_hdr(&[0x60, 0x80]),
+            DecodeError::InvalidBitstream {
+                obu_type: ObuType::OBU_SEQ_HDR as u32,
+                bit_pos: td.len() * 8,
+                reason: "hdr.profile > 2",
+            }
+        );
+
+        // the header runs into the next OBU, reported where this is noticed
+        match parse_seq_hdr(&[0x00]) {
+            DecodeError::InvalidBitstream {
+                obu_type, bit_pos, ..
+            } => {
+                assert_eq!(obu_type, ObuType::OBU_SEQ_HDR as u32);
+                // past the end of the 1 byte payload
+                assert!(bit_pos > (td.len() + 3) * 8);
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,784 @@
+use crate::cdef_apply::*;
+use crate::error::DecodeError;
+use crate::frame::Frame;
+use crate::headers::*;
+use crate::internal::*;
+use crate::levels::*;
+use crate::lf_apply::*;
+use crate::lr_apply::*;
+use crate::mc::*;
+use crate::ref_mvs::{fix_mv_precision, get_gmv_2d};
+use crate::tables::*;
+use crate::util::*;
+
+use std::cmp;
+
+// Destination of an inter prediction: pixels with their stride, or samples
+// at the intermediate precision of compound prediction, with a stride of
+// the block width.
+pub(crate) enum PredDst<'a, T: Pixel> {
+    Pixels(&'a mut [T], usize),
+    Prep(&'a mut [i32]),
+}
+
+// Predict a bw4 x bh4 block (in 4 luma px units) at (bx, by) of plane pl
+// from the reference picture, displaced by mv (in 1/8 luma px). svc scales
+// the position and step for references of a different size, see 7.11.3.3.
+pub(crate) fn mc<T: Pixel>(
+    dst: PredDst<T>,
+    bw4: usize,
+    bh4: usize,
+    bx: i32,
+    by: i32,
+    pl: usize,
+    mv: MV,
+    refp: &Frame<T>,
+    svc: &[ScalableMotionParams; 2],
+    filter: Filter2d,
+    seq_hdr: &SequenceHeader,
+    dsp: &MCDSPContext<T>,
+) {
+    let ss_hor = (pl != 0 && seq_hdr.ss_hor != 0) as i32;
+    let ss_ver = (pl != 0 && seq_hdr.ss_ver != 0) as i32;
+    let (h_mul, v_mul) = (4 >> ss_hor, 4 >> ss_ver);
+    let (mvx, mvy) = (mv.x as i32, mv.y as i32);
+    let (w, h) = (bw4 * h_mul as usize, bh4 * v_mul as usize);
+    let iw = (refp.width + ss_hor as usize) >> ss_hor;
+    let ih = (refp.height + ss_ver as usize) >> ss_ver;
+    let bitdepth_max = seq_hdr.bitdepth_max();
+
+    if svc[0].scale == 0 && svc[1].scale == 0 {
+        let mx = ((mvx & (15 >> (ss_hor ^ 1))) << (ss_hor ^ 1)) as usize;
+        let my = ((mvy & (15 >> (ss_ver ^ 1))) << (ss_ver ^ 1)) as usize;
+        let dx = bx * h_mul + (mvx >> (3 + ss_hor));
+        let dy = by * v_mul + (mvy >> (3 + ss_ver));
+
+        let (src, src_stride) = fetch_src(&refp.planes[pl], dx as isize, dy as isize, w, h, iw, ih);
+        match dst {
+            PredDst::Pixels(dst, dst_stride) => (dsp.mc)(
+                dst,
+                dst_stride,
+                &src,
+                src_stride,
+                w,
+                h,
+                mx,
+                my,
+                filter,
+                bitdepth_max,
+            ),
+            PredDst::Prep(tmp) => {
+                (dsp.mct)(tmp, &src, src_stride, w, h, mx, my, filter, bitdepth_max)
+            }
+        }
+    } else {
+        // position of the block in the reference, in 1/1024 px
+        let scale_mv = |val: i32, scale: i32| {
+            let tmp = val as i64 * scale as i64 + (scale as i64 - 0x4000) * 8;
+            let res = ((tmp.abs() + 128) >> 8) as i32;
+            (if tmp < 0 { -res } else { res }) + 32
+        };
+        let pos_x = scale_mv((bx * h_mul << 4) + mvx * (1 << (ss_hor ^ 1)), svc[0].scale);
+        let pos_y = scale_mv((by * v_mul << 4) + mvy * (1 << (ss_ver ^ 1)), svc[1].scale);
+        let (left, top) = (pos_x >> 10, pos_y >> 10);
+        let right = ((pos_x + (w as i32 - 1) * svc[0].step) >> 10) + 1;
+        let bottom = ((pos_y + (h as i32 - 1) * svc[1].step) >> 10) + 1;
+
+        let (src, src_stride) = fetch_src(
+            &refp.planes[pl],
+            left as isize,
+            top as isize,
+            (right - left) as usize,
+            (bottom - top) as usize,
+            iw,
+            ih,
+        );
+        let (mx, my) = ((pos_x & 0x3ff) as usize, (pos_y & 0x3ff) as usize);
+        let (dx, dy) = (svc[0].step as usize, svc[1].step as usize);
+        match dst {
+            PredDst::Pixels(dst, dst_stride) => (dsp.mc_scaled)(
+                dst,
+                dst_stride,
+                &src,
+                src_stride,
+                w,
+                h,
+                mx,
+                my,
+                dx,
+                dy,
+                filter,
+                bitdepth_max,
+            ),
+            PredDst::Prep(tmp) => (dsp.mct_scaled)(
+                tmp,
+                &src,
+                src_stride,
+                w,
+                h,
+                mx,
+                my,
+                dx,
+                dy,
+                filter,
+                bitdepth_max,
+            ),
+        }
+    }
+}
+
+// Overlapped block motion compensation: blend the prediction of the current
+// block in dst with predictions made from the motion of the above and left
+// neighbours. bx4/by4 are the block position within the superblock, and
+// w4/h4 the block size clipped to the frame, both in 4 luma px units.
+pub(crate) fn obmc<T: Pixel>(
+    f: &FrameContext<T>,
+    t: &TileContext,
+    ts: &TileState,
+    a: &BlockContext,
+    dst: &mut [T],
+    dst_stride: usize,
+    b_dim: &[u8; 4],
+    pl: usize,
+    bx4: usize,
+    by4: usize,
+    w4: usize,
+    h4: usize,
+) -> Result<(), DecodeError> {
+    debug_assert!((t.bx & 1) == 0 && (t.by & 1) == 0);
+    let seq_hdr = f.seq_hdr.as_ref().unwrap();
+    let ss_hor = (pl != 0 && seq_hdr.ss_hor != 0) as usize;
+    let ss_ver = (pl != 0 && seq_hdr.ss_ver != 0) as usize;
+    let (h_mul, v_mul) = (4 >> ss_hor, 4 >> ss_ver);
+    let r = t.by as usize * f.b4_stride + t.bx as usize;
+
+    if t.by > ts.tiling.row_start
+        && (pl == 0 || b_dim[0] as usize * h_mul + b_dim[1] as usize * v_mul >= 16)
+    {
+        let (mut i, mut x) = (0, 0);
+        while x < w4 && i < cmp::min(b_dim[2], 4) {
+            // only odd blocks are considered for overlap handling, hence +1
+            let a_r = &f.mvs[r + x + 1 - f.b4_stride];
+            let a_b_dim = &block_dimensions[a_r.sb_type as usize];
+
+            if a_r.ref_frame[0] > 0 {
+                let ow4 = clip(a_b_dim[0], 2, b_dim[0]) as usize;
+                let oh4 = cmp::min(b_dim[1], 16) as usize >> 1;
+                let refp = f.refp[a_r.ref_frame[0] as usize - 1].as_ref();
+                check_error(refp.is_none(), "OBMC reference frame is missing")?;
+                let mut lap = vec![T::cast_from(0); h_mul * ow4 * v_mul * oh4];
+                mc(
+                    PredDst::Pixels(&mut lap, ow4 * h_mul),
+                    ow4,
+                    (oh4 * 3 + 3) >> 2,
+                    t.bx + x as i32,
+                    t.by,
+                    pl,
+                    a_r.mv[0],
+                    refp.unwrap(),
+                    &f.svc[a_r.ref_frame[0] as usize - 1],
+                    filter_2d[a.filter.array[1][bx4 + x + 1] as usize]
+                        [a.filter.array[0][bx4 + x + 1] as usize],
+                    seq_hdr,
+                    &f.dsp.mc,
+                );
+                (f.dsp.mc.blend_h)(&mut dst[x * h_mul..], dst_stride, &lap, h_mul * ow4, v_mul * oh4);
+                i += 1;
+            }
+            x += cmp::max(a_b_dim[0], 2) as usize;
+        }
+    }
+
+    if t.bx > ts.tiling.col_start {
+        let (mut i, mut y) = (0, 0);
+        while y < h4 && i < cmp::min(b_dim[3], 4) {
+            // only odd blocks are considered for overlap handling, hence +1
+            let l_r = &f.mvs[r + (y + 1) * f.b4_stride - 1];
+            let l_b_dim = &block_dimensions[l_r.sb_type as usize];
+
+            if l_r.ref_frame[0] > 0 {
+                let ow4 = cmp::min(b_dim[0], 16) as usize >> 1;
+                let oh4 = clip(l_b_dim[1], 2, b_dim[1]) as usize;
+                let refp = f.refp[l_r.ref_frame[0] as usize - 1].as_ref();
+                check_error(refp.is_none(), "OBMC reference frame is missing")?;
+                let mut lap = vec![T::cast_from(0); h_mul * ow4 * v_mul * oh4];
+                mc(
+                    PredDst::Pixels(&mut lap, ow4 * h_mul),
+                    (ow4 * 3 + 3) >> 2,
+                    oh4,
+                    t.bx,
+                    t.by + y as i32,
+                    pl,
+                    l_r.mv[0],
+                    refp.unwrap(),
+                    &f.svc[l_r.ref_frame[0] as usize - 1],
+                    filter_2d[t.l.filter.array[1][by4 + y + 1] as usize]
+                        [t.l.filter.array[0][by4 + y + 1] as usize],
+                    seq_hdr,
+                    &f.dsp.mc,
+                );
+                (f.dsp.mc.blend_v)(
+                    &mut dst[y * v_mul * dst_stride..],
+                    dst_stride,
+                    &lap,
+                    h_mul * ow4,
+                    v_mul * oh4,
+                );
+                i += 1;
+            }
+            y += cmp::max(l_b_dim[1], 2) as usize;
+        }
+    }
+
+    Ok(())
+}
+
+// Predict a block of plane pl with the affine model wmp, in 8x8 units. The
+// model maps the centre of each 8x8 block, in luma px, into the reference.
+pub(crate) fn warp_affine<T: Pixel>(
+    f: &FrameContext<T>,
+    t: &TileContext,
+    mut dst: PredDst<T>,
+    b_dim: &[u8; 4],
+    pl: usize,
+    refp: &Frame<T>,
+    wmp: &WarpedMotionParams,
+) {
+    let seq_hdr = f.seq_hdr.as_ref().unwrap();
+    let ss_hor = (pl != 0 && seq_hdr.ss_hor != 0) as usize;
+    let ss_ver = (pl != 0 && seq_hdr.ss_ver != 0) as usize;
+    let (h_mul, v_mul) = (4 >> ss_hor, 4 >> ss_ver);
+    debug_assert!((b_dim[0] as usize * h_mul) & 7 == 0 && (b_dim[1] as usize * v_mul) & 7 == 0);
+    let mat = &wmp.matrix;
+    let abcd = wmp.u.abcd();
+    let width = (refp.width + ss_hor) >> ss_hor;
+    let height = (refp.height + ss_ver) >> ss_ver;
+    let bitdepth_max = seq_hdr.bitdepth_max();
+    let tmp_stride = b_dim[0] as usize * h_mul;
+
+    for y in (0..b_dim[1] as usize * v_mul).step_by(8) {
+        let src_y = t.by as i64 * 4 + ((y as i64 + 4) << ss_ver);
+        let mat3_y = mat[3] as i64 * src_y + mat[0] as i64;
+        let mat5_y = mat[5] as i64 * src_y + mat[1] as i64;
+        for x in (0..b_dim[0] as usize * h_mul).step_by(8) {
+            // calculate transformation relative to center of 8x8 block in
+            // luma pixel units
+            let src_x = t.bx as i64 * 4 + ((x as i64 + 4) << ss_hor);
+            let mvx = (mat[2] as i64 * src_x + mat3_y) >> ss_hor;
+            let mvy = (mat[4] as i64 * src_x + mat5_y) >> ss_ver;
+
+            let dx = (mvx >> 16) as isize - 4;
+            let mx = ((mvx as i32 & 0xffff) - abcd[0] as i32 * 4 - abcd[1] as i32 * 7) & !0x3f;
+            let dy = (mvy >> 16) as isize - 4;
+            let my = ((mvy as i32 & 0xffff) - abcd[2] as i32 * 4 - abcd[3] as i32 * 4) & !0x3f;
+
+            let (src, src_stride) = fetch_src(&refp.planes[pl], dx, dy, 8, 8, width, height);
+            match dst {
+                PredDst::Pixels(ref mut dst, dst_stride) => (f.dsp.mc.warp8x8)(
+                    &mut dst[y * dst_stride + x..],
+                    dst_stride,
+                    &src,
+                    src_stride,
+                    &abcd,
+                    mx,
+                    my,
+                    bitdepth_max,
+                ),
+                PredDst::Prep(ref mut tmp) => (f.dsp.mc.warp8x8t)(
+                    &mut tmp[y * tmp_stride + x..],
+                    tmp_stride,
+                    &src,
+                    src_stride,
+                    &abcd,
+                    mx,
+                    my,
+                    bitdepth_max,
+                ),
+            }
+        }
+    }
+}
+
+// Whether plane pl of a block is predicted by warping: GLOBALMV blocks use
+// the global motion model of their reference if it allows warping, MM_WARP
+// blocks their local model. Other blocks are predicted by translation with
+// their mv, GLOBALMV blocks with the mv derived from the global model.
+fn use_warp<T: Pixel>(
+    f: &FrameContext<T>,
+    t: &TileContext,
+    b_dim: &[u8; 4],
+    pl: usize,
+    r#ref: usize,
+    is_globalmv: bool,
+    motion_mode: MotionMode,
+) -> bool {
+    let seq_hdr = f.seq_hdr.as_ref().unwrap();
+    let ss_hor = (pl != 0 && seq_hdr.ss_hor != 0) as u8;
+    let ss_ver = (pl != 0 && seq_hdr.ss_ver != 0) as u8;
+    let cbw4 = (b_dim[0] + ss_hor) >> ss_hor;
+    let cbh4 = (b_dim[1] + ss_ver) >> ss_ver;
+    cmp::min(cbw4, cbh4) > 1
+        && ((is_globalmv && f.gmv_warp_allowed[r#ref])
+            || (motion_mode == MotionMode::MM_WARP
+                && t.warpmv.t as u32 > WarpedMotionType::WM_TYPE_TRANSLATION as u32))
+}
+
+// The mv of a GLOBALMV block that isn't warped: the global motion model of
+// reference r#ref evaluated at the block.
+fn global_mv<T: Pixel>(f: &FrameContext<T>, t: &TileContext, b_dim: &[u8; 4], r#ref: usize) -> MV {
+    let frame_hdr = f.frame_hdr.as_ref().unwrap();
+    let (bw4, bh4) = (b_dim[0] as i32, b_dim[1] as i32);
+    get_gmv_2d(&frame_hdr.gmv[r#ref], t.bx, t.by, bw4, bh4, frame_hdr)
+}
+
+// Inter prediction of plane pl of a single reference block. bx4/by4 are
+// the block position within the superblock, and w4/h4 the block size
+// clipped to the frame, both in 4 luma px units.
+pub(crate) fn predict_inter_single<T: Pixel>(
+    f: &FrameContext<T>,
+    t: &TileContext,
+    ts: &TileState,
+    a: &BlockContext,
+    dst: &mut [T],
+    dst_stride: usize,
+    b_dim: &[u8; 4],
+    pl: usize,
+    bx4: usize,
+    by4: usize,
+    w4: usize,
+    h4: usize,
+    r#ref: usize,
+    inter_mode: InterPredMode,
+    motion_mode: MotionMode,
+    mv: MV,
+    filter: Filter2d,
+) -> Result<(), DecodeError> {
+    let seq_hdr = f.seq_hdr.as_ref().unwrap();
+    let refp = f.refp[r#ref].as_ref();
+    check_error(refp.is_none(), "Inter reference frame is missing")?;
+    let refp = refp.unwrap();
+
+    if use_warp(f, t, b_dim, pl, r#ref, inter_mode == InterPredMode::GLOBALMV, motion_mode) {
+        let wmp = if motion_mode == MotionMode::MM_WARP {
+            &t.warpmv
+        } else {
+            &f.frame_hdr.as_ref().unwrap().gmv[r#ref]
+        };
+        warp_affine(f, t, PredDst::Pixels(dst, dst_stride), b_dim, pl, refp, wmp);
+    } else {
+        let ss_hor = (pl != 0 && seq_hdr.ss_hor != 0) as usize;
+        let ss_ver = (pl != 0 && seq_hdr.ss_ver != 0) as usize;
+        let (bw4, bh4) = (b_dim[0] as usize, b_dim[1] as usize);
+        let mv = if inter_mode == InterPredMode::GLOBALMV {
+            global_mv(f, t, b_dim, r#ref)
+        } else {
+            mv
+        };
+        mc(
+            PredDst::Pixels(dst, dst_stride),
+            bw4 << (bw4 == ss_hor) as usize,
+            bh4 << (bh4 == ss_ver) as usize,
+            t.bx & !(ss_hor as i32),
+            t.by & !(ss_ver as i32),
+            pl,
+            mv,
+            refp,
+            &f.svc[r#ref],
+            filter,
+            seq_hdr,
+            &f.dsp.mc,
+        );
+        if motion_mode == MotionMode::MM_OBMC {
+            obmc(f, t, ts, a, dst, dst_stride, b_dim, pl, bx4, by4, w4, h4)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Averaging compound inter prediction of plane pl of a block; both
+// references of GLOBALMV_GLOBALMV blocks are warped by their global motion
+// model where allowed.
+pub(crate) fn predict_inter_compound<T: Pixel>(
+    f: &FrameContext<T>,
+    t: &TileContext,
+    dst: &mut [T],
+    dst_stride: usize,
+    b_dim: &[u8; 4],
+    pl: usize,
+    refs: [usize; 2],
+    inter_mode: CompInterPredMode,
+    mvs: [MV; 2],
+    filter: Filter2d,
+) -> Result<(), DecodeError> {
+    let seq_hdr = f.seq_hdr.as_ref().unwrap();
+    let ss_hor = (pl != 0 && seq_hdr.ss_hor != 0) as usize;
+    let ss_ver = (pl != 0 && seq_hdr.ss_ver != 0) as usize;
+    let (w, h) = (b_dim[0] as usize * 4 >> ss_hor, b_dim[1] as usize * 4 >> ss_ver);
+    let mut tmp = [vec![0i32; w * h], vec![0i32; w * h]];
+
+    for i in 0..2 {
+        let refp = f.refp[refs[i]].as_ref();
+        check_error(refp.is_none(), "Inter reference frame is missing")?;
+        let refp = refp.unwrap();
+        let is_globalmv = inter_mode == CompInterPredMode::GLOBALMV_GLOBALMV;
+        if use_warp(f, t, b_dim, pl, refs[i], is_globalmv, MotionMode::MM_TRANSLATION) {
+            let wmp = &f.frame_hdr.as_ref().unwrap().gmv[refs[i]];
+            warp_affine(f, t, PredDst::Prep(&mut tmp[i]), b_dim, pl, refp, wmp);
+        } else {
+            let mv = if is_globalmv {
+                global_mv(f, t, b_dim, refs[i])
+            } else {
+                mvs[i]
+            };
+            mc(
+                PredDst::Prep(&mut tmp[i]),
+                b_dim[0] as usize,
+                b_dim[1] as usize,
+                t.bx,
+                t.by,
+                pl,
+                mv,
+                refp,
+                &f.svc[refs[i]],
+                filter,
+                seq_hdr,
+                &f.dsp.mc,
+            );
+        }
+    }
+    (f.dsp.mc.avg)(dst, dst_stride, &tmp[0], &tmp[1], w, h, seq_hdr.bitdepth_max());
+
+    Ok(())
+}
+
+// Prediction of plane pl of a skip_mode block: the averaged compound of
+// the two skip_mode references with the mvs of the first (NEAREST) entry of
+// the ref-mv stack, see 5.11.23 "Inter block mode info syntax". There is no
+// residual to add.
+pub(crate) fn predict_skip_mode<T: Pixel>(
+    f: &FrameContext<T>,
+    t: &TileContext,
+    dst: &mut [T],
+    dst_stride: usize,
+    b_dim: &[u8; 4],
+    pl: usize,
+    nearest: [MV; 2],
+) -> Result<(), DecodeError> {
+    let frame_hdr = f.frame_hdr.as_ref().unwrap();
+    let refs = [
+        frame_hdr.skip_mode_refs[0] as usize,
+        frame_hdr.skip_mode_refs[1] as usize,
+    ];
+    let mut mvs = nearest;
+    for mv in &mut mvs {
+        fix_mv_precision(frame_hdr, mv);
+    }
+    // skip_mode blocks code no interpolation filter
+    let filter = if frame_hdr.subpel_filter_mode == FilterMode::N_FILTERS_OR_FILTER_SWITCHABLE {
+        Filter2d::FILTER_2D_8TAP_REGULAR
+    } else {
+        let m = frame_hdr.subpel_filter_mode as usize;
+        filter_2d[m][m]
+    };
+    predict_inter_compound(
+        f,
+        t,
+        dst,
+        dst_stride,
+        b_dim,
+        pl,
+        refs,
+        CompInterPredMode::NEARESTMV_NEARESTMV,
+        mvs,
+        filter,
+    )
+}
+
+// Run the in-loop filters on superblock row sby once all its tiles are
+// reconstructed.
+pub(crate) fn filter_sbrow<T: Pixel>(f: &mut FrameContext<T>, sby: i32) {
+    let seq_hdr = f.seq_hdr.as_ref().unwrap();
+    let frame_hdr = f.frame_hdr.as_ref().unwrap();
+
+    if frame_hdr.loopfilter.level_y[0] != 0 || frame_hdr.loopfilter.level_y[1] != 0 {
+        let mut start_of_tile_row = 0;
+        if frame_hdr.tiling.row_start_sb[f.lf.tile_row as usize] as i32 == sby {
+            start_of_tile_row = f.lf.tile_row;
+            f.lf.tile_row += 1;
+        }
+        let mask_off = (sby >> !seq_hdr.sb128 as i32) * f.sb128w;
+        loopfilter_sbrow(f, mask_off as usize, sby as usize, start_of_tile_row as usize);
+    }
+    if f.lf.restore_planes != 0 {
+        // store loop filtered pixels required by loop restoration
+        lr_copy_lpf(f, sby as usize);
+    }
+
+    // the last 8 px rows of each sbrow are only final once the next sbrow is
+    // deblocked, so CDEF runs one 8x8 block row behind
+    let seq_hdr = f.seq_hdr.as_ref().unwrap();
+    if seq_hdr.cdef {
+        let sb128 = seq_hdr.sb128;
+        let (sbsz, sbh, bh) = (f.sb_step, f.sbh, f.bh);
+        if sby > 0 {
+            let mask_off = ((sby - 1) >> !sb128 as i32) * f.sb128w;
+            cdef_brow(f, mask_off as usize, (sby * sbsz - 2) as usize, (sby * sbsz) as usize);
+        }
+        let n_blks = sbsz - 2 * (sby + 1 < sbh) as i32;
+        let mask_off = (sby >> !sb128 as i32) * f.sb128w;
+        let by_end = cmp::min(sby * sbsz + n_blks, bh);
+        cdef_brow(f, mask_off as usize, (sby * sbsz) as usize, by_end as usize);
+    }
+    if f.sr_cur.is_some() {
+        superres_sbrow(f, sby);
+    }
+    if f.lf.restore_planes != 0 {
+        lr_sbrow(f, sby as usize);
+    }
+}
+
+// Upscale the rows of superblock row sby that are final after CDEF into
+// the super-resolution picture, trailing by the same 8 luma px rows
+fn superres_sbrow<T: Pixel>(f: &mut FrameContext<T>, sby: i32) {
+    let FrameContext {
+        seq_hdr,
+        frame_hdr,
+        cur,
+        sr_cur,
+        ..
+    } = f;
+    let seq_hdr = seq_hdr.as_ref().unwrap();
+    let frame_hdr = frame_hdr.as_ref().unwrap();
+    let src = &cur.as_ref().unwrap().planes;
+    let dst = &mut sr_cur.as_mut().unwrap().planes;
+    let (sbsz, sbh) = (f.sb_step, f.sbh);
+    let layout = seq_hdr.layout;
+    let n_planes = if layout == PixelLayout::PIXEL_LAYOUT_I400 {
+        1
+    } else {
+        3
+    };
+
+    for pl in 0..n_planes {
+        let ss_ver = (pl != 0 && layout == PixelLayout::PIXEL_LAYOUT_I420) as i32;
+        let ss_hor = (pl != 0 && layout != PixelLayout::PIXEL_LAYOUT_I444) as i32;
+        let h_start = (8 * (sby > 0) as i32) >> ss_ver;
+        let y = ((sby * sbsz * 4) >> ss_ver) - h_start;
+        let h_end = (4 * (sbsz - 2 * (sby + 1 < sbh) as i32)) >> ss_ver;
+        let dst_w = (frame_hdr.width[1] as i32 + ss_hor) >> ss_hor;
+        let src_w = (4 * f.bw + ss_hor) >> ss_hor;
+        let img_h = (frame_hdr.height as i32 - sbsz * 4 * sby + ss_ver) >> ss_ver;
+
+        let (s, d) = (&src[pl], &mut dst[pl]);
+        let src_off = (s.cfg.yorigin + y as usize) * s.cfg.stride + s.cfg.xorigin;
+        let dst_off = (d.cfg.yorigin + y as usize) * d.cfg.stride + d.cfg.xorigin;
+        resize(
+            &mut d.data[dst_off..],
+            d.cfg.stride,
+            &s.data[src_off..],
+            s.cfg.stride,
+            dst_w as usize,
+            (cmp::min(img_h, h_end) + h_start) as usize,
+            src_w as usize,
+            f.resize_step[(pl != 0) as usize],
+            f.resize_start[(pl != 0) as usize],
+            seq_hdr.bitdepth_max(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::ChromaSampling;
+    use crate::ref_mvs::RefMvs;
+    use crate::warpmv::get_shear_params;
+    use std::rc::Rc;
+
+    // a w x h frame whose luma samples are pixel(x, y)
+    fn frame_from(w: usize, h: usize, pixel: impl Fn(usize, usize) -> u8) -> Frame<u8> {
+        let mut frame = Frame::new(w, h, ChromaSampling::Cs420);
+        let p = &mut frame.planes[0];
+        let (stride, origin) = (p.cfg.stride, p.cfg.yorigin * p.cfg.stride + p.cfg.xorigin);
+        for y in 0..h {
+            for x in 0..w {
+                p.data[origin + y * stride + x] = pixel(x, y);
+            }
+        }
+        frame
+    }
+
+    // the 8x8 luma prediction at (bx, by), in 4 px units
+    fn predict(
+        refp: &Frame<u8>,
+        svc: &[ScalableMotionParams; 2],
+        bx: i32,
+        by: i32,
+        mv: MV,
+    ) -> Vec<u8> {
+        let (seq_hdr, dsp) = (SequenceHeader::default(), MCDSPContext::new(0));
+        let mut dst = vec![0u8; 8 * 8];
+        let filter = Filter2d::FILTER_2D_8TAP_REGULAR;
+        let dst_px = PredDst::Pixels(&mut dst, 8);
+        mc(dst_px, 2, 2, bx, by, 0, mv, refp, svc, filter, &seq_hdr, &dsp);
+        dst
+    }
+
+    #[test]
+    fn mc_full_pel() {
+        let refp = frame_from(32, 32, |x, y| (x + y) as u8);
+        let dst = predict(&refp, &Default::default(), 2, 2, MV { y: -8, x: 16 });
+        for y in 0..8 {
+            for x in 0..8 {
+                assert_eq!(dst[y * 8 + x] as usize, (8 + x + 2) + (8 + y - 1));
+            }
+        }
+    }
+
+    #[test]
+    fn mc_scaled_reference() {
+        // a reference twice as large, so that each px of the block is
+        // predicted from half-pel positions 2 px apart in the reference
+        let refp = frame_from(64, 64, |x, y| (x + y) as u8);
+        let svc = [ScalableMotionParams::new(64, 32), ScalableMotionParams::new(64, 32)];
+        assert_eq!(svc[0], ScalableMotionParams { scale: 0x8000, step: 0x800 });
+        let dst = predict(&refp, &svc, 1, 1, MV::default());
+        for y in 0..8 {
+            for x in 0..8 {
+                assert_eq!(dst[y * 8 + x] as usize, 2 * (4 + x) + 2 * (4 + y) + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn warp_translation() {
+        let mut f = FrameContext::<u8>::default();
+        f.seq_hdr = Some(Rc::new(SequenceHeader::default()));
+        let refp = frame_from(32, 32, |x, y| (x + y) as u8);
+        let t = TileContext {
+            bx: 2,
+            by: 2,
+            ..Default::default()
+        };
+        // move by 3 px right and 2 px up
+        let mut wmp = WarpedMotionParams::default();
+        wmp.matrix[0] = 3 << 16;
+        wmp.matrix[1] = -2 << 16;
+        assert!(!get_shear_params(&mut wmp));
+        let b_dim = &block_dimensions[BlockSize::BS_8x8 as usize];
+
+        let mut dst = vec![0u8; 8 * 8];
+        warp_affine(&f, &t, PredDst::Pixels(&mut dst, 8), b_dim, 0, &refp, &wmp);
+        for y in 0..8 {
+            for x in 0..8 {
+                assert_eq!(dst[y * 8 + x] as usize, (8 + x + 3) + (8 + y - 2));
+            }
+        }
+    }
+
+    #[test]
+    fn globalmv_translation() {
+        let mut f = FrameContext::<u8>::default();
+        f.seq_hdr = Some(Rc::new(SequenceHeader::default()));
+        f.refp[0] = Some(Rc::new(frame_from(32, 32, |x, y| (x + y) as u8)));
+        // a translational model moving by 2 px right and 1 px up, which
+        // GLOBALMV blocks use instead of their coded mv
+        let mut frame_hdr = FrameHeader::default();
+        frame_hdr.hp = true;
+        frame_hdr.gmv[0].t = WarpedMotionType::WM_TYPE_TRANSLATION;
+        frame_hdr.gmv[0].matrix[0] = -8 << 13;
+        frame_hdr.gmv[0].matrix[1] = 16 << 13;
+        f.frame_hdr = Some(Rc::new(frame_hdr));
+        let t = TileContext {
+            bx: 2,
+            by: 2,
+            ..Default::default()
+        };
+        let b_dim = &block_dimensions[BlockSize::BS_8x8 as usize];
+        let (ts, a) = (TileState::default(), BlockContext::default());
+
+        let mut dst = vec![0u8; 8 * 8];
+        let mode = MotionMode::MM_TRANSLATION;
+        let filter = Filter2d::FILTER_2D_8TAP_REGULAR;
+        let mv = MV { y: 64, x: 64 };
+        let (globalmv, newmv) = (InterPredMode::GLOBALMV, InterPredMode::NEWMV);
+        predict_inter_single(
+            &f, &t, &ts, &a, &mut dst, 8, b_dim, 0, 2, 2, 2, 2, 0, globalmv, mode, mv, filter,
+        )
+        .unwrap();
+        for y in 0..8 {
+            for x in 0..8 {
+                assert_eq!(dst[y * 8 + x] as usize, (8 + x + 2) + (8 + y - 1));
+            }
+        }
+        predict_inter_single(
+            &f, &t, &ts, &a, &mut dst, 8, b_dim, 0, 2, 2, 2, 2, 0, newmv, mode, mv, filter,
+        )
+        .unwrap();
+        assert_eq!(dst[0], (8 + 8) + (8 + 8));
+    }
+
+    #[test]
+    fn obmc_blends_above_neighbour() {
+        let mut f = FrameContext::<u8>::default();
+        f.seq_hdr = Some(Rc::new(SequenceHeader::default()));
+        f.refp[0] = Some(Rc::new(frame_from(32, 32, |_, _| 200)));
+        f.b4_stride = 8;
+        f.mvs = vec![RefMvs::default(); 8 * 8];
+        // the 8x8 block at (8, 8) has an inter block above it and an intra
+        // block to its left
+        let above = RefMvs {
+            ref_frame: [1, -1],
+            sb_type: BlockSize::BS_8x8 as u8,
+            ..Default::default()
+        };
+        f.mvs[8 + 2..8 + 4].copy_from_slice(&[above; 2]);
+        let t = TileContext {
+            bx: 2,
+            by: 2,
+            ..Default::default()
+        };
+        let b_dim = &block_dimensions[BlockSize::BS_8x8 as usize];
+
+        let mut dst = vec![100u8; 8 * 8];
+        let (ts, a) = (TileState::default(), BlockContext::default());
+        obmc(&f, &t, &ts, &a, &mut dst, 8, b_dim, 0, 2, 2, 2, 2).unwrap();
+        // the top 3 of the 4 overlapped rows are blended
+        for y in 0..8 {
+            let m = if y < 3 { obmc_masks[4 + y] as u32 } else { 0 };
+            let expected = (100 * (64 - m) + 200 * m + 32) >> 6;
+            assert!(dst[y * 8..y * 8 + 8].iter().all(|&px| px as u32 == expected), "row {}", y);
+        }
+    }
+
+    #[test]
+    fn skip_mode_compound() {
+        let mut f = FrameContext::<u8>::default();
+        f.seq_hdr = Some(Rc::new(SequenceHeader::default()));
+        f.refp[2] = Some(Rc::new(frame_from(32, 32, |x, y| (x + y) as u8)));
+        f.refp[4] = Some(Rc::new(frame_from(32, 32, |x, y| (x + y + 10) as u8)));
+        let mut frame_hdr = FrameHeader::default();
+        frame_hdr.skip_mode_refs = [2, 4];
+        frame_hdr.subpel_filter_mode = FilterMode::N_FILTERS_OR_FILTER_SWITCHABLE;
+        frame_hdr.force_integer_mv = AdaptiveBoolean::ON;
+        f.frame_hdr = Some(Rc::new(frame_hdr));
+        let t = TileContext {
+            bx: 2,
+            by: 2,
+            ..Default::default()
+        };
+        let b_dim = &block_dimensions[BlockSize::BS_8x8 as usize];
+
+        // the nearest mvs are rounded to 1 px right and 1 px up, and the
+        // two references averaged
+        let nearest = [MV { y: -5, x: 12 }, MV { y: -9, x: 11 }];
+        let mut dst = vec![0u8; 8 * 8];
+        predict_skip_mode(&f, &t, &mut dst, 8, b_dim, 0, nearest).unwrap();
+        for y in 0..8 {
+            for x in 0..8 {
+                assert_eq!(dst[y * 8 + x] as usize, (8 + x + 1) + (8 + y - 1) + 5);
+            }
+        }
+    }
+}
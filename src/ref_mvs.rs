@@ -0,0 +1,151 @@
+use crate::headers::*;
+use crate::levels::*;
+
+// motion information of each 4x4 block of a frame, as used for OBMC,
+// motion vector prediction and the temporal motion field of references
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct RefMvs {
+    pub(crate) mv: [MV; 2],
+    // [0]: 0 means intra, 1-7 are LAST..ALTREF; [1]: -1 means single reference
+    pub(crate) ref_frame: [i8; 2],
+    pub(crate) mode: u8,
+    pub(crate) sb_type: u8, // BlockSize
+}
+
+// round a motion vector to full-pel precision
+#[inline]
+pub(crate) fn fix_int_mv_precision(mv: &mut MV) {
+    mv.x = ((mv.x as i32 - (mv.x as i32 >> 15) + 3) & !7) as i16;
+    mv.y = ((mv.y as i32 - (mv.y as i32 >> 15) + 3) & !7) as i16;
+}
+
+// round a motion vector to the precision allowed by the frame header
+#[inline]
+pub(crate) fn fix_mv_precision(hdr: &FrameHeader, mv: &mut MV) {
+    if hdr.force_integer_mv != AdaptiveBoolean::OFF {
+        fix_int_mv_precision(mv);
+    } else if !hdr.hp {
+        mv.x = ((mv.x as i32 - (mv.x as i32 >> 15)) & !1) as i16;
+        mv.y = ((mv.y as i32 - (mv.y as i32 >> 15)) & !1) as i16;
+    }
+}
+
+// motion vector of a GLOBALMV block, i.e. the global motion model evaluated
+// at the centre of the block, see 7.10.2.1 "Setup global mv process"
+pub(crate) fn get_gmv_2d(
+    gmv: &WarpedMotionParams,
+    bx4: i32,
+    by4: i32,
+    bw4: i32,
+    bh4: i32,
+    hdr: &FrameHeader,
+) -> MV {
+    let mut res = match gmv.t {
+        WarpedMotionType::WM_TYPE_IDENTITY => return MV::default(),
+        WarpedMotionType::WM_TYPE_TRANSLATION => MV {
+            y: (gmv.matrix[0] >> 13) as i16,
+            x: (gmv.matrix[1] >> 13) as i16,
+        },
+        WarpedMotionType::WM_TYPE_ROT_ZOOM | WarpedMotionType::WM_TYPE_AFFINE => {
+            debug_assert!(
+                gmv.t == WarpedMotionType::WM_TYPE_AFFINE
+                    || (gmv.matrix[5] == gmv.matrix[2] && gmv.matrix[4] == -gmv.matrix[3])
+            );
+            let x = bx4 * 4 + bw4 * 2 - 1;
+            let y = by4 * 4 + bh4 * 2 - 1;
+            let xc = (gmv.matrix[2] - (1 << 16)) * x + gmv.matrix[3] * y + gmv.matrix[0];
+            let yc = (gmv.matrix[5] - (1 << 16)) * y + gmv.matrix[4] * x + gmv.matrix[1];
+            let shift = 16 - (3 - !hdr.hp as i32);
+            let round = (1 << shift) >> 1;
+            let apply = |v: i32| {
+                let r = ((v.abs() + round) >> shift) << !hdr.hp as i32;
+                (if v < 0 { -r } else { r }) as i16
+            };
+            MV {
+                y: apply(yc),
+                x: apply(xc),
+            }
+        }
+    };
+    if hdr.force_integer_mv != AdaptiveBoolean::OFF {
+        fix_int_mv_precision(&mut res);
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gmv_2d(gmv: WarpedMotionParams, hp: bool, force_integer_mv: bool, bx4: i32, bw4: i32) -> MV {
+        let mut hdr = FrameHeader::default();
+        hdr.hp = hp;
+        hdr.force_integer_mv = if force_integer_mv {
+            AdaptiveBoolean::ON
+        } else {
+            AdaptiveBoolean::OFF
+        };
+        get_gmv_2d(&gmv, bx4, 0, bw4, 2, &hdr)
+    }
+
+    #[test]
+    fn gmv_translation() {
+        let mut gmv = WarpedMotionParams::default();
+        gmv.t = WarpedMotionType::WM_TYPE_TRANSLATION;
+        // matrix[0] is the vertical translation for translational models
+        gmv.matrix[0] = -5 << 13;
+        gmv.matrix[1] = 12 << 13;
+        assert_eq!(gmv_2d(gmv, true, false, 0, 2), MV { y: -5, x: 12 });
+        assert_eq!(gmv_2d(gmv, true, true, 0, 2), MV { y: -8, x: 8 });
+        let identity = WarpedMotionParams::default();
+        assert_eq!(gmv_2d(identity, true, true, 0, 2), MV::default());
+    }
+
+    #[test]
+    fn gmv_rot_zoom() {
+        // evaluated at (3, 3), the centre of the 8x8 block at the origin,
+        // the model moves by xc = 3 << 13 and yc = -2 << 13 in 1/65536 px
+        let mut gmv = WarpedMotionParams::default();
+        gmv.t = WarpedMotionType::WM_TYPE_ROT_ZOOM;
+        gmv.matrix = [23424, -16768, (1 << 16) + 256, 128, -128, (1 << 16) + 256];
+        // 3 and -2 in 1/8 px with hp, rounded to 1/4 px without
+        assert_eq!(gmv_2d(gmv, true, false, 0, 2), MV { y: -2, x: 3 });
+        assert_eq!(gmv_2d(gmv, false, false, 0, 2), MV { y: -2, x: 4 });
+        // both round to 0 px with force_integer_mv
+        assert_eq!(gmv_2d(gmv, false, true, 0, 2), MV { y: 0, x: 0 });
+        assert_eq!(gmv_2d(gmv, true, true, 0, 2), MV { y: 0, x: 0 });
+    }
+
+    #[test]
+    fn gmv_affine() {
+        // evaluated at (23, 3), the centre of the 16x8 block at (16, 0)
+        let mut gmv = WarpedMotionParams::default();
+        gmv.t = WarpedMotionType::WM_TYPE_AFFINE;
+        gmv.matrix = [32844, -24260, (1 << 16) + 1000, 500, -700, (1 << 16) - 200];
+        // xc = 57344 (7 << 13), yc = -40960 (-5 << 13)
+        assert_eq!(gmv_2d(gmv, true, false, 4, 4), MV { y: -5, x: 7 });
+        assert_eq!(gmv_2d(gmv, false, false, 4, 4), MV { y: -6, x: 8 });
+        assert_eq!(gmv_2d(gmv, false, true, 4, 4), MV { y: -8, x: 8 });
+        assert_eq!(gmv_2d(gmv, true, true, 4, 4), MV { y: -8, x: 8 });
+    }
+
+    #[test]
+    fn mv_precision() {
+        let mut hdr = FrameHeader::default();
+        let fixed = |hdr: &FrameHeader, y: i16, x: i16| {
+            let mut mv = MV { y, x };
+            fix_mv_precision(hdr, &mut mv);
+            mv
+        };
+        hdr.hp = true;
+        assert_eq!(fixed(&hdr, -3, 5), MV { y: -3, x: 5 });
+        // 1/4 px rounds towards 0
+        hdr.hp = false;
+        assert_eq!(fixed(&hdr, -3, 5), MV { y: -2, x: 4 });
+        // full px rounds to nearest, ties towards 0
+        hdr.force_integer_mv = AdaptiveBoolean::ON;
+        assert_eq!(fixed(&hdr, -4, 5), MV { y: 0, x: 8 });
+        assert_eq!(fixed(&hdr, -5, 4), MV { y: -8, x: 0 });
+    }
+}
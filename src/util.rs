@@ -7,11 +7,13 @@
 // Media Patent License 1.0 was not distributed with this source code in the
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
+use crate::error::DecodeError;
+
 use num_traits::*;
 use std::mem;
 use std::mem::size_of;
 use std::fmt::{Debug, Display};
-use std::{cmp, io};
+use std::cmp;
 
 //TODO: Nice to have (although I wasnt able to find a way to do it yet in rust): zero-fill arrays that are
 // shorter than required.  Need const fn (Rust Issue #24111) or const generics (Rust RFC #2000)
@@ -202,9 +204,9 @@ pub fn clip<T: PartialOrd>(v: T, min: T, max: T) -> T {
 }
 
 #[inline(always)]
-pub fn check_error(condition: bool, msg: &str) -> io::Result<()> {
+pub fn check_error(condition: bool, msg: &'static str) -> Result<(), DecodeError> {
   if condition {
-    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+    Err(DecodeError::invalid(msg))
   } else {
     Ok(())
   }
@@ -217,4 +219,15 @@ pub fn tile_log2(sz: i32, tgt: i32) -> i32 {
     k += 1;
   }
   k
-}
\ No newline at end of file
+}
+
+// signed distance between two order hints, see 7.12.2 get_relative_dist()
+#[inline(always)]
+pub fn get_poc_diff(order_hint_n_bits: u32, poc0: i32, poc1: i32) -> i32 {
+  if order_hint_n_bits == 0 {
+    return 0;
+  }
+  let mask = 1 << (order_hint_n_bits - 1);
+  let diff = poc0 - poc1;
+  (diff & (mask - 1)) - (diff & mask)
+}
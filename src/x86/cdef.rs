@@ -0,0 +1,567 @@
+use crate::cdef::*;
+use crate::cpu::*;
+use crate::tables::*;
+use crate::util::*;
+
+use std::arch::x86_64::*;
+use std::{cmp, mem, ptr};
+
+pub(crate) fn cdef_dsp_init_x86<T: Pixel>(c: &mut CdefDSPContext<T>, flags: u32) {
+    if flags & CPU_FLAG_SSSE3 == 0 {
+        return;
+    }
+
+    c.dir = cdef_find_dir_ssse3;
+    c.fb = cdef_filter_block_ssse3;
+
+    if flags & CPU_FLAG_AVX2 == 0 {
+        return;
+    }
+
+    c.dir = cdef_find_dir_avx2;
+    c.fb = cdef_filter_block_avx2;
+}
+
+// The safe wrappers below are only installed by cdef_dsp_init_x86 after
+// the CPU features their kernels are compiled for have been detected.
+
+fn cdef_find_dir_ssse3<T: Pixel>(
+    img: &[T],
+    off: usize,
+    stride: usize,
+    bitdepth_max: i32,
+) -> (usize, u32) {
+    unsafe { find_dir_ssse3(img, off, stride, bitdepth_max) }
+}
+
+fn cdef_filter_block_ssse3<T: Pixel>(
+    dst: &mut [T],
+    off: usize,
+    stride: usize,
+    left: &[[T; 2]; 8],
+    top: [&[T]; 2],
+    top_off: usize,
+    w: usize,
+    h: usize,
+    pri_strength: i32,
+    sec_strength: i32,
+    dir: usize,
+    damping: i32,
+    edges: u32,
+    bitdepth_max: i32,
+) {
+    let mut tmp = [0i32; 12 * 12];
+    padding(&mut tmp, dst, off, stride, left, top, top_off, w, h, edges);
+    unsafe {
+        filter_ssse3(
+            dst,
+            off,
+            stride,
+            &tmp,
+            w,
+            h,
+            pri_strength,
+            sec_strength,
+            dir,
+            damping,
+            bitdepth_max,
+        );
+    }
+}
+
+fn cdef_find_dir_avx2<T: Pixel>(
+    img: &[T],
+    off: usize,
+    stride: usize,
+    bitdepth_max: i32,
+) -> (usize, u32) {
+    unsafe { find_dir_avx2(img, off, stride, bitdepth_max) }
+}
+
+fn cdef_filter_block_avx2<T: Pixel>(
+    dst: &mut [T],
+    off: usize,
+    stride: usize,
+    left: &[[T; 2]; 8],
+    top: [&[T]; 2],
+    top_off: usize,
+    w: usize,
+    h: usize,
+    pri_strength: i32,
+    sec_strength: i32,
+    dir: usize,
+    damping: i32,
+    edges: u32,
+    bitdepth_max: i32,
+) {
+    let mut tmp = [0i32; 12 * 12];
+    padding(&mut tmp, dst, off, stride, left, top, top_off, w, h, edges);
+    unsafe {
+        filter_avx2(
+            dst,
+            off,
+            stride,
+            &tmp,
+            w,
+            h,
+            pri_strength,
+            sec_strength,
+            dir,
+            damping,
+            bitdepth_max,
+        );
+    }
+}
+
+// 8 pixels at src, widened to 16 bits
+#[target_feature(enable = "ssse3")]
+#[inline]
+unsafe fn load8<T: Pixel>(src: &[T]) -> __m128i {
+    let src = &src[..8];
+    if mem::size_of::<T>() == 1 {
+        let v = _mm_loadl_epi64(src.as_ptr() as *const __m128i);
+        _mm_unpacklo_epi8(v, _mm_setzero_si128())
+    } else {
+        _mm_loadu_si128(src.as_ptr() as *const __m128i)
+    }
+}
+
+// store the first w (4 or 8) lanes of v, which must be in pixel range
+#[target_feature(enable = "ssse3")]
+#[inline]
+unsafe fn store<T: Pixel>(dst: &mut [T], v: __m128i, w: usize) {
+    let dst = &mut dst[..w];
+    if mem::size_of::<T>() == 1 {
+        let v = _mm_packus_epi16(v, v);
+        if w == 8 {
+            _mm_storel_epi64(dst.as_mut_ptr() as *mut __m128i, v);
+        } else {
+            ptr::write_unaligned(dst.as_mut_ptr() as *mut i32, _mm_cvtsi128_si32(v));
+        }
+    } else if w == 8 {
+        _mm_storeu_si128(dst.as_mut_ptr() as *mut __m128i, v);
+    } else {
+        _mm_storel_epi64(dst.as_mut_ptr() as *mut __m128i, v);
+    }
+}
+
+// the w (4 or 8) values of the padded block at pos, narrowed to 16 bits
+#[target_feature(enable = "ssse3")]
+#[inline]
+unsafe fn load_tmp(tmp: &[i32], pos: isize, w: usize) -> __m128i {
+    let tmp = &tmp[pos as usize..pos as usize + w];
+    let lo = _mm_loadu_si128(tmp.as_ptr() as *const __m128i);
+    let hi = if w == 8 {
+        _mm_loadu_si128(tmp[4..].as_ptr() as *const __m128i)
+    } else {
+        _mm_setzero_si128()
+    };
+    _mm_packs_epi32(lo, hi)
+}
+
+// see constrain() in cdef.rs; threshold is non-zero
+#[target_feature(enable = "ssse3")]
+#[inline]
+unsafe fn constrain(diff: __m128i, threshold: __m128i, shift: __m128i) -> __m128i {
+    let adiff = _mm_abs_epi16(diff);
+    let v = _mm_sub_epi16(threshold, _mm_srl_epi16(adiff, shift));
+    let v = _mm_min_epi16(adiff, _mm_max_epi16(v, _mm_setzero_si128()));
+    _mm_sign_epi16(v, diff)
+}
+
+// cdef_filter_block on the padded block in tmp, for a row at a time; all
+// intermediate values fit in 16 bits up to 12 bits per pixel
+#[target_feature(enable = "ssse3")]
+unsafe fn filter_ssse3<T: Pixel>(
+    dst: &mut [T],
+    off: usize,
+    stride: usize,
+    tmp: &[i32],
+    w: usize,
+    h: usize,
+    pri_strength: i32,
+    sec_strength: i32,
+    dir: usize,
+    damping: i32,
+    bitdepth_max: i32,
+) {
+    let tmp_stride = w + 4;
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+    let pri_tap = 4 - ((pri_strength >> bitdepth_min_8) & 1);
+    let pri_shift = if pri_strength != 0 {
+        cmp::max(0, damping - ulog2(pri_strength))
+    } else {
+        0
+    };
+    let sec_shift = if sec_strength != 0 {
+        cmp::max(0, damping - ulog2(sec_strength))
+    } else {
+        0
+    };
+    let tap_off = |d: usize, k: usize| {
+        let [y, x] = cdef_directions[d][k];
+        y as isize * tmp_stride as isize + x as isize
+    };
+
+    let pri = (
+        _mm_set1_epi16(pri_strength as i16),
+        _mm_cvtsi32_si128(pri_shift),
+    );
+    let sec = (
+        _mm_set1_epi16(sec_strength as i16),
+        _mm_cvtsi32_si128(sec_shift),
+    );
+    let large = _mm_set1_epi16(CDEF_VERY_LARGE as i16);
+
+    for y in 0..h {
+        let pos = ((y + 2) * tmp_stride + 2) as isize;
+        let px = load_tmp(tmp, pos, w);
+        let (mut sum, mut max, mut min) = (_mm_setzero_si128(), px, px);
+        let mut pri_tap_k = pri_tap;
+        for k in 0..2 {
+            let off1 = tap_off(dir, k);
+            let p = [load_tmp(tmp, pos + off1, w), load_tmp(tmp, pos - off1, w)];
+            if pri_strength != 0 {
+                let c0 = constrain(_mm_sub_epi16(p[0], px), pri.0, pri.1);
+                let c1 = constrain(_mm_sub_epi16(p[1], px), pri.0, pri.1);
+                let tap = _mm_set1_epi16(pri_tap_k as i16);
+                sum = _mm_add_epi16(sum, _mm_mullo_epi16(tap, _mm_add_epi16(c0, c1)));
+            }
+            // if pri_tap_k == 4 then it becomes 2 else it remains 3
+            pri_tap_k -= (pri_tap_k << 1) - 6;
+
+            let off2 = tap_off((dir + 2) & 7, k);
+            let off3 = tap_off((dir + 6) & 7, k);
+            let s = [
+                load_tmp(tmp, pos + off2, w),
+                load_tmp(tmp, pos - off2, w),
+                load_tmp(tmp, pos + off3, w),
+                load_tmp(tmp, pos - off3, w),
+            ];
+            for &v in p.iter().chain(s.iter()) {
+                max = _mm_max_epi16(max, _mm_andnot_si128(_mm_cmpeq_epi16(v, large), v));
+                min = _mm_min_epi16(min, v);
+            }
+            if sec_strength != 0 {
+                // sec_tap starts at 2 and becomes 1
+                let mut c = _mm_setzero_si128();
+                for &v in s.iter() {
+                    c = _mm_add_epi16(c, constrain(_mm_sub_epi16(v, px), sec.0, sec.1));
+                }
+                if k == 0 {
+                    c = _mm_add_epi16(c, c);
+                }
+                sum = _mm_add_epi16(sum, c);
+            }
+        }
+        // px + ((8 + sum - (sum < 0)) >> 4)
+        let neg = _mm_cmpgt_epi16(_mm_setzero_si128(), sum);
+        let v = _mm_srai_epi16(_mm_add_epi16(_mm_add_epi16(sum, _mm_set1_epi16(8)), neg), 4);
+        let v = _mm_add_epi16(px, v);
+        store(
+            &mut dst[off + y * stride..],
+            _mm_min_epi16(_mm_max_epi16(v, min), max),
+            w,
+        );
+    }
+}
+
+// Shift the 16 lanes of (lo, hi) up by one and add v, so that after all
+// rows have been accumulated the row added first is shifted the most.
+#[target_feature(enable = "ssse3")]
+#[inline]
+unsafe fn shift_add(acc: &mut (__m128i, __m128i), v: __m128i) {
+    acc.1 = _mm_or_si128(_mm_slli_si128(acc.1, 2), _mm_srli_si128(acc.0, 14));
+    acc.0 = _mm_add_epi16(_mm_slli_si128(acc.0, 2), v);
+}
+
+#[target_feature(enable = "ssse3")]
+#[inline]
+unsafe fn store_sums(sums: &mut [i32], acc: (__m128i, __m128i)) {
+    let mut v = [0i16; 16];
+    _mm_storeu_si128(v.as_mut_ptr() as *mut __m128i, acc.0);
+    _mm_storeu_si128(v[8..].as_mut_ptr() as *mut __m128i, acc.1);
+    for (s, &v) in sums.iter_mut().zip(v.iter()) {
+        *s = v as i32;
+    }
+}
+
+// cdef_find_dir with the partial sums of each line computed in 16 bit
+// lanes; diagonal lines are summed by shifting the rows against each other.
+#[target_feature(enable = "ssse3")]
+unsafe fn find_dir_ssse3<T: Pixel>(
+    img: &[T],
+    off: usize,
+    stride: usize,
+    bitdepth_max: i32,
+) -> (usize, u32) {
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+    let shift = _mm_cvtsi32_si128(bitdepth_min_8);
+    let c128 = _mm_set1_epi16(128);
+    let reverse = _mm_setr_epi8(14, 15, 12, 13, 10, 11, 8, 9, 6, 7, 4, 5, 2, 3, 0, 1);
+    let reverse4 = _mm_setr_epi8(6, 7, 4, 5, 2, 3, 0, 1, -1, -1, -1, -1, -1, -1, -1, -1);
+    let low4 = _mm_set_epi64x(0, -1);
+
+    let mut row = [_mm_setzero_si128(); 8];
+    for (y, r) in row.iter_mut().enumerate() {
+        let px = load8(&img[off + y * stride..]);
+        *r = _mm_sub_epi16(_mm_srl_epi16(px, shift), c128);
+    }
+
+    // the sums of the rows and columns
+    let r01 = _mm_hadd_epi16(row[0], row[1]);
+    let r23 = _mm_hadd_epi16(row[2], row[3]);
+    let r45 = _mm_hadd_epi16(row[4], row[5]);
+    let r67 = _mm_hadd_epi16(row[6], row[7]);
+    let hv0 = _mm_hadd_epi16(_mm_hadd_epi16(r01, r23), _mm_hadd_epi16(r45, r67));
+    let hv1 = row
+        .iter()
+        .fold(_mm_setzero_si128(), |acc, &r| _mm_add_epi16(acc, r));
+
+    let zero = (_mm_setzero_si128(), _mm_setzero_si128());
+    let (mut diag0, mut diag1, mut alt0, mut alt1) = (zero, zero, zero, zero);
+    for &r in row.iter().rev() {
+        // [y + x], [7 + y - x]
+        shift_add(&mut diag0, r);
+        shift_add(&mut diag1, _mm_shuffle_epi8(r, reverse));
+        // [y + (x >> 1)], [3 + y - (x >> 1)]
+        let pairs = _mm_hadd_epi16(r, r);
+        shift_add(&mut alt0, _mm_and_si128(pairs, low4));
+        shift_add(&mut alt1, _mm_shuffle_epi8(pairs, reverse4));
+    }
+    let (mut alt2, mut alt3) = (zero, zero);
+    for y in 0..4 {
+        // [3 - (y >> 1) + x], [(y >> 1) + x]
+        shift_add(&mut alt2, _mm_add_epi16(row[2 * y], row[2 * y + 1]));
+        let y = 3 - y;
+        shift_add(&mut alt3, _mm_add_epi16(row[2 * y], row[2 * y + 1]));
+    }
+
+    let mut partial_sum_hv = [[0i32; 8]; 2];
+    let mut partial_sum_diag = [[0i32; 15]; 2];
+    let mut partial_sum_alt = [[0i32; 11]; 4];
+    store_sums(&mut partial_sum_hv[0], (hv0, zero.1));
+    store_sums(&mut partial_sum_hv[1], (hv1, zero.1));
+    store_sums(&mut partial_sum_diag[0], diag0);
+    store_sums(&mut partial_sum_diag[1], diag1);
+    for (sums, &acc) in partial_sum_alt
+        .iter_mut()
+        .zip([alt0, alt1, alt2, alt3].iter())
+    {
+        store_sums(sums, acc);
+    }
+
+    best_dir(&partial_sum_hv, &partial_sum_diag, &partial_sum_alt)
+}
+
+// the w (4 or 8) values of 2 consecutive rows of the padded block at pos,
+// one row per 128-bit lane
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn load_tmp2(tmp: &[i32], pos: isize, tmp_stride: usize, w: usize) -> __m256i {
+    let lo = load_tmp(tmp, pos, w);
+    let hi = load_tmp(tmp, pos + tmp_stride as isize, w);
+    _mm256_set_m128i(hi, lo)
+}
+
+// see constrain()
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn constrain_avx2(diff: __m256i, threshold: __m256i, shift: __m128i) -> __m256i {
+    let adiff = _mm256_abs_epi16(diff);
+    let v = _mm256_sub_epi16(threshold, _mm256_srl_epi16(adiff, shift));
+    let v = _mm256_min_epi16(adiff, _mm256_max_epi16(v, _mm256_setzero_si256()));
+    _mm256_sign_epi16(v, diff)
+}
+
+// filter_ssse3 for 2 rows at a time
+#[target_feature(enable = "avx2")]
+unsafe fn filter_avx2<T: Pixel>(
+    dst: &mut [T],
+    off: usize,
+    stride: usize,
+    tmp: &[i32],
+    w: usize,
+    h: usize,
+    pri_strength: i32,
+    sec_strength: i32,
+    dir: usize,
+    damping: i32,
+    bitdepth_max: i32,
+) {
+    let tmp_stride = w + 4;
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+    let pri_tap = 4 - ((pri_strength >> bitdepth_min_8) & 1);
+    let pri_shift = if pri_strength != 0 {
+        cmp::max(0, damping - ulog2(pri_strength))
+    } else {
+        0
+    };
+    let sec_shift = if sec_strength != 0 {
+        cmp::max(0, damping - ulog2(sec_strength))
+    } else {
+        0
+    };
+    let tap_off = |d: usize, k: usize| {
+        let [y, x] = cdef_directions[d][k];
+        y as isize * tmp_stride as isize + x as isize
+    };
+
+    let pri = (
+        _mm256_set1_epi16(pri_strength as i16),
+        _mm_cvtsi32_si128(pri_shift),
+    );
+    let sec = (
+        _mm256_set1_epi16(sec_strength as i16),
+        _mm_cvtsi32_si128(sec_shift),
+    );
+    let large = _mm256_set1_epi16(CDEF_VERY_LARGE as i16);
+    let zero = _mm256_setzero_si256();
+
+    for y in (0..h).step_by(2) {
+        let pos = ((y + 2) * tmp_stride + 2) as isize;
+        let load = |pos: isize| load_tmp2(tmp, pos, tmp_stride, w);
+        let px = load(pos);
+        let (mut sum, mut max, mut min) = (zero, px, px);
+        let mut pri_tap_k = pri_tap;
+        for k in 0..2 {
+            let off1 = tap_off(dir, k);
+            let p = [load(pos + off1), load(pos - off1)];
+            if pri_strength != 0 {
+                let c0 = constrain_avx2(_mm256_sub_epi16(p[0], px), pri.0, pri.1);
+                let c1 = constrain_avx2(_mm256_sub_epi16(p[1], px), pri.0, pri.1);
+                let tap = _mm256_set1_epi16(pri_tap_k as i16);
+                sum = _mm256_add_epi16(sum, _mm256_mullo_epi16(tap, _mm256_add_epi16(c0, c1)));
+            }
+            // if pri_tap_k == 4 then it becomes 2 else it remains 3
+            pri_tap_k -= (pri_tap_k << 1) - 6;
+
+            let off2 = tap_off((dir + 2) & 7, k);
+            let off3 = tap_off((dir + 6) & 7, k);
+            let s = [
+                load(pos + off2),
+                load(pos - off2),
+                load(pos + off3),
+                load(pos - off3),
+            ];
+            for &v in p.iter().chain(s.iter()) {
+                let v_max = _mm256_andnot_si256(_mm256_cmpeq_epi16(v, large), v);
+                max = _mm256_max_epi16(max, v_max);
+                min = _mm256_min_epi16(min, v);
+            }
+            if sec_strength != 0 {
+                // sec_tap starts at 2 and becomes 1
+                let mut c = zero;
+                for &v in s.iter() {
+                    c = _mm256_add_epi16(c, constrain_avx2(_mm256_sub_epi16(v, px), sec.0, sec.1));
+                }
+                if k == 0 {
+                    c = _mm256_add_epi16(c, c);
+                }
+                sum = _mm256_add_epi16(sum, c);
+            }
+        }
+        // px + ((8 + sum - (sum < 0)) >> 4)
+        let neg = _mm256_cmpgt_epi16(zero, sum);
+        let v = _mm256_add_epi16(_mm256_add_epi16(sum, _mm256_set1_epi16(8)), neg);
+        let v = _mm256_add_epi16(px, _mm256_srai_epi16(v, 4));
+        let v = _mm256_min_epi16(_mm256_max_epi16(v, min), max);
+        let dst = &mut dst[off + y * stride..];
+        store(dst, _mm256_castsi256_si128(v), w);
+        store(&mut dst[stride..], _mm256_extracti128_si256(v, 1), w);
+    }
+}
+
+// shift_add on a single register of 16 lanes
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn shift_add_avx2(acc: &mut __m256i, v: __m128i) {
+    // the high lane of carry is the low lane of acc, its low lane is zero
+    let carry = _mm256_permute2x128_si256(*acc, *acc, 0x08);
+    let shifted = _mm256_alignr_epi8(*acc, carry, 14);
+    *acc = _mm256_add_epi16(shifted, _mm256_set_m128i(_mm_setzero_si128(), v));
+}
+
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn store_sums_avx2(sums: &mut [i32], acc: __m256i) {
+    let mut v = [0i16; 16];
+    _mm256_storeu_si256(v.as_mut_ptr() as *mut __m256i, acc);
+    for (s, &v) in sums.iter_mut().zip(v.iter()) {
+        *s = v as i32;
+    }
+}
+
+// find_dir_ssse3 with each diagonal partial sum accumulated in a single
+// register
+#[target_feature(enable = "avx2")]
+unsafe fn find_dir_avx2<T: Pixel>(
+    img: &[T],
+    off: usize,
+    stride: usize,
+    bitdepth_max: i32,
+) -> (usize, u32) {
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+    let shift = _mm_cvtsi32_si128(bitdepth_min_8);
+    let c128 = _mm_set1_epi16(128);
+    let reverse = _mm_setr_epi8(14, 15, 12, 13, 10, 11, 8, 9, 6, 7, 4, 5, 2, 3, 0, 1);
+    let reverse4 = _mm_setr_epi8(6, 7, 4, 5, 2, 3, 0, 1, -1, -1, -1, -1, -1, -1, -1, -1);
+    let low4 = _mm_set_epi64x(0, -1);
+
+    let mut row = [_mm_setzero_si128(); 8];
+    for (y, r) in row.iter_mut().enumerate() {
+        let px = load8(&img[off + y * stride..]);
+        *r = _mm_sub_epi16(_mm_srl_epi16(px, shift), c128);
+    }
+
+    // the sums of the rows and columns
+    let r01 = _mm_hadd_epi16(row[0], row[1]);
+    let r23 = _mm_hadd_epi16(row[2], row[3]);
+    let r45 = _mm_hadd_epi16(row[4], row[5]);
+    let r67 = _mm_hadd_epi16(row[6], row[7]);
+    let hv0 = _mm_hadd_epi16(_mm_hadd_epi16(r01, r23), _mm_hadd_epi16(r45, r67));
+    let hv1 = row
+        .iter()
+        .fold(_mm_setzero_si128(), |acc, &r| _mm_add_epi16(acc, r));
+
+    let zero = _mm256_setzero_si256();
+    let (mut diag0, mut diag1, mut alt0, mut alt1) = (zero, zero, zero, zero);
+    for &r in row.iter().rev() {
+        // [y + x], [7 + y - x]
+        shift_add_avx2(&mut diag0, r);
+        shift_add_avx2(&mut diag1, _mm_shuffle_epi8(r, reverse));
+        // [y + (x >> 1)], [3 + y - (x >> 1)]
+        let pairs = _mm_hadd_epi16(r, r);
+        shift_add_avx2(&mut alt0, _mm_and_si128(pairs, low4));
+        shift_add_avx2(&mut alt1, _mm_shuffle_epi8(pairs, reverse4));
+    }
+    let (mut alt2, mut alt3) = (zero, zero);
+    for y in 0..4 {
+        // [3 - (y >> 1) + x], [(y >> 1) + x]
+        shift_add_avx2(&mut alt2, _mm_add_epi16(row[2 * y], row[2 * y + 1]));
+        let y = 3 - y;
+        shift_add_avx2(&mut alt3, _mm_add_epi16(row[2 * y], row[2 * y + 1]));
+    }
+
+    let mut partial_sum_hv = [[0i32; 8]; 2];
+    let mut partial_sum_diag = [[0i32; 15]; 2];
+    let mut partial_sum_alt = [[0i32; 11]; 4];
+    store_sums_avx2(
+        &mut partial_sum_hv[0],
+        _mm256_set_m128i(_mm_setzero_si128(), hv0),
+    );
+    store_sums_avx2(
+        &mut partial_sum_hv[1],
+        _mm256_set_m128i(_mm_setzero_si128(), hv1),
+    );
+    store_sums_avx2(&mut partial_sum_diag[0], diag0);
+    store_sums_avx2(&mut partial_sum_diag[1], diag1);
+    for (sums, &acc) in partial_sum_alt
+        .iter_mut()
+        .zip([alt0, alt1, alt2, alt3].iter())
+    {
+        store_sums_avx2(sums, acc);
+    }
+
+    best_dir(&partial_sum_hv, &partial_sum_diag, &partial_sum_alt)
+}
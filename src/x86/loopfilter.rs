@@ -0,0 +1,678 @@
+use crate::cpu::*;
+use crate::lf_mask::Av1FilterLUT;
+use crate::loopfilter::*;
+use crate::util::*;
+
+use std::arch::x86_64::*;
+use std::{cmp, mem, ptr};
+
+pub(crate) fn lf_dsp_init_x86<T: Pixel>(c: &mut LoopFilterDSPContext<T>, flags: u32) {
+    if flags & CPU_FLAG_SSSE3 == 0 {
+        return;
+    }
+
+    c.loop_filter_sby = [
+        loop_filter_h_sb128y::<T, LoopFilterSsse3>,
+        loop_filter_v_sb128y::<T, LoopFilterSsse3>,
+    ];
+    c.loop_filter_sbuv = [
+        loop_filter_h_sb128uv::<T, LoopFilterSsse3>,
+        loop_filter_v_sb128uv::<T, LoopFilterSsse3>,
+    ];
+
+    if flags & CPU_FLAG_AVX2 == 0 {
+        return;
+    }
+
+    c.loop_filter_sby = [loop_filter_h_sb128y_avx2, loop_filter_v_sb128y_avx2];
+    c.loop_filter_sbuv = [loop_filter_h_sb128uv_avx2, loop_filter_v_sb128uv_avx2];
+}
+
+// Only installed by lf_dsp_init_x86 after SSSE3 has been detected.
+struct LoopFilterSsse3;
+
+impl LoopFilterEdge for LoopFilterSsse3 {
+    #[inline(always)]
+    fn loop_filter<T: Pixel>(
+        dst: &mut [T],
+        off: isize,
+        e: i32,
+        i: i32,
+        h: i32,
+        stridea: isize,
+        strideb: isize,
+        wd: usize,
+        bitdepth_max: i32,
+    ) {
+        unsafe { loop_filter_ssse3(dst, off, e, i, h, stridea, strideb, wd, bitdepth_max) }
+    }
+}
+
+// The pixels at off of the 4 lines across the edge, widened to 16 bits in
+// the low 4 lanes; the lines are contiguous for horizontal edges only.
+#[target_feature(enable = "ssse3")]
+#[inline]
+unsafe fn load_px<T: Pixel>(dst: &[T], off: isize, stridea: isize) -> __m128i {
+    if stridea != 1 {
+        let mut px = [0i16; 8];
+        for (j, p) in px[..4].iter_mut().enumerate() {
+            *p = i32::cast_from(dst[(off + j as isize * stridea) as usize]) as i16;
+        }
+        return _mm_loadu_si128(px.as_ptr() as *const __m128i);
+    }
+
+    let src = &dst[off as usize..off as usize + 4];
+    if mem::size_of::<T>() == 1 {
+        let v = _mm_cvtsi32_si128(ptr::read_unaligned(src.as_ptr() as *const i32));
+        _mm_unpacklo_epi8(v, _mm_setzero_si128())
+    } else {
+        _mm_loadl_epi64(src.as_ptr() as *const __m128i)
+    }
+}
+
+// store the low 4 lanes of v, which must be in pixel range, at off
+#[target_feature(enable = "ssse3")]
+#[inline]
+unsafe fn store_px<T: Pixel>(dst: &mut [T], off: isize, stridea: isize, v: __m128i) {
+    if stridea != 1 {
+        let mut px = [0i16; 8];
+        _mm_storeu_si128(px.as_mut_ptr() as *mut __m128i, v);
+        for (j, &p) in px[..4].iter().enumerate() {
+            dst[(off + j as isize * stridea) as usize] = T::cast_from(p);
+        }
+        return;
+    }
+
+    let dst = &mut dst[off as usize..off as usize + 4];
+    if mem::size_of::<T>() == 1 {
+        let v = _mm_packus_epi16(v, v);
+        ptr::write_unaligned(dst.as_mut_ptr() as *mut i32, _mm_cvtsi128_si32(v));
+    } else {
+        _mm_storel_epi64(dst.as_mut_ptr() as *mut __m128i, v);
+    }
+}
+
+#[target_feature(enable = "ssse3")]
+#[inline]
+unsafe fn abs_diff(a: __m128i, b: __m128i) -> __m128i {
+    _mm_abs_epi16(_mm_sub_epi16(a, b))
+}
+
+// lanes where |a - b| > thresh
+#[target_feature(enable = "ssse3")]
+#[inline]
+unsafe fn exceeds(a: __m128i, b: __m128i, thresh: __m128i) -> __m128i {
+    _mm_cmpgt_epi16(abs_diff(a, b), thresh)
+}
+
+#[target_feature(enable = "ssse3")]
+#[inline]
+unsafe fn clip16(v: __m128i, min: __m128i, max: __m128i) -> __m128i {
+    _mm_min_epi16(_mm_max_epi16(v, min), max)
+}
+
+// take b in the lanes of mask and a elsewhere
+#[target_feature(enable = "ssse3")]
+#[inline]
+unsafe fn blend(a: __m128i, b: __m128i, mask: __m128i) -> __m128i {
+    _mm_or_si128(_mm_and_si128(mask, b), _mm_andnot_si128(mask, a))
+}
+
+// lanes where |p(k) - p(0)| and |q(k) - q(0)| are at most flat for all k
+#[target_feature(enable = "ssse3")]
+#[inline]
+unsafe fn is_flat(px: &[__m128i; 14], ks: std::ops::Range<usize>, flat: __m128i) -> __m128i {
+    let mut not_flat = _mm_setzero_si128();
+    for k in ks {
+        let d = _mm_or_si128(
+            exceeds(px[6 - k], px[6], flat),
+            exceeds(px[7 + k], px[7], flat),
+        );
+        not_flat = _mm_or_si128(not_flat, d);
+    }
+    _mm_xor_si128(not_flat, _mm_set1_epi16(-1))
+}
+
+// The (2n + 1)-tap smoothing of loop_filter's flat8in case, applied to the
+// 2n pixels around the edge in the lanes of mask. The sums of up to 16
+// pixels don't fit in 16 signed bits, but wrap around to the right value
+// as unsigned ones.
+#[target_feature(enable = "ssse3")]
+#[inline]
+unsafe fn flat_filter(px: &mut [__m128i; 14], n: isize, n2: isize, log2size: i32, mask: __m128i) {
+    let rnd = _mm_set1_epi16(1 << (log2size - 1));
+    let shift = _mm_cvtsi32_si128(log2size);
+    let mut out = [_mm_setzero_si128(); 12];
+    for k in -n..n {
+        let mut t = rnd;
+        for j in -n..=n {
+            let v = px[(7 + clip(k + j, -(n + 1), n)) as usize];
+            t = _mm_add_epi16(t, v);
+            if j.abs() <= n2 {
+                t = _mm_add_epi16(t, v);
+            }
+        }
+        out[(k + n) as usize] = _mm_srl_epi16(t, shift);
+    }
+    for k in -n..n {
+        let pos = (7 + k) as usize;
+        px[pos] = blend(px[pos], out[(k + n) as usize], mask);
+    }
+}
+
+// loop_filter for the 4 lines of an edge at once, one line per lane
+#[target_feature(enable = "ssse3")]
+unsafe fn loop_filter_ssse3<T: Pixel>(
+    dst: &mut [T],
+    off: isize,
+    e: i32,
+    i: i32,
+    h: i32,
+    stridea: isize,
+    strideb: isize,
+    wd: usize,
+    bitdepth_max: i32,
+) {
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+    let flat = _mm_set1_epi16(1 << bitdepth_min_8);
+    let e = _mm_set1_epi16((e << bitdepth_min_8) as i16);
+    let i = _mm_set1_epi16((i << bitdepth_min_8) as i16);
+    let h = _mm_set1_epi16((h << bitdepth_min_8) as i16);
+
+    let n_px = match wd {
+        16 => 7,
+        8 => 4,
+        6 => 3,
+        _ => 2,
+    };
+    // px[7 + k] holds the pixels at distance k from the edge, as in
+    // loop_filter
+    let mut px = [_mm_setzero_si128(); 14];
+    for k in -n_px..n_px {
+        px[(7 + k) as usize] = load_px(dst, off + k * strideb, stridea);
+    }
+    let (p1, p0, q0, q1) = (px[5], px[6], px[7], px[8]);
+
+    let mut not_fm = _mm_or_si128(exceeds(p1, p0, i), exceeds(q1, q0, i));
+    let edge = _mm_add_epi16(
+        _mm_slli_epi16(abs_diff(p0, q0), 1),
+        _mm_srli_epi16(abs_diff(p1, q1), 1),
+    );
+    not_fm = _mm_or_si128(not_fm, _mm_cmpgt_epi16(edge, e));
+    if wd > 4 {
+        let d = _mm_or_si128(exceeds(px[4], p1, i), exceeds(px[9], q1, i));
+        not_fm = _mm_or_si128(not_fm, d);
+        if wd > 6 {
+            let d = _mm_or_si128(exceeds(px[3], px[4], i), exceeds(px[10], px[9], i));
+            not_fm = _mm_or_si128(not_fm, d);
+        }
+    }
+    // only the low 4 lanes hold pixels
+    let fm = _mm_andnot_si128(not_fm, _mm_set_epi64x(0, -1));
+    if _mm_movemask_epi8(fm) == 0 {
+        return;
+    }
+
+    let flat8out = if wd >= 16 {
+        _mm_and_si128(is_flat(&px, 4..7, flat), fm)
+    } else {
+        _mm_setzero_si128()
+    };
+    let flat8in = if wd >= 6 {
+        _mm_and_si128(is_flat(&px, 1..cmp::min(wd, 8) / 2, flat), fm)
+    } else {
+        _mm_setzero_si128()
+    };
+
+    // the narrow filter, in the lanes that aren't flat
+    let narrow = _mm_andnot_si128(flat8in, fm);
+    let hev = _mm_or_si128(exceeds(p1, p0, h), exceeds(q1, q0, h));
+    let diff_min = _mm_set1_epi16(-128 << bitdepth_min_8);
+    let diff_max = _mm_set1_epi16((128 << bitdepth_min_8) - 1);
+    let (zero, pixel_max) = (_mm_setzero_si128(), _mm_set1_epi16(bitdepth_max as i16));
+
+    let base = _mm_and_si128(hev, clip16(_mm_sub_epi16(p1, q1), diff_min, diff_max));
+    let d = _mm_sub_epi16(q0, p0);
+    let f = _mm_add_epi16(_mm_add_epi16(d, _mm_add_epi16(d, d)), base);
+    let f = clip16(f, diff_min, diff_max);
+    let f1 = _mm_srai_epi16(
+        _mm_min_epi16(_mm_add_epi16(f, _mm_set1_epi16(4)), diff_max),
+        3,
+    );
+    let f2 = _mm_srai_epi16(
+        _mm_min_epi16(_mm_add_epi16(f, _mm_set1_epi16(3)), diff_max),
+        3,
+    );
+    px[6] = blend(p0, clip16(_mm_add_epi16(p0, f2), zero, pixel_max), narrow);
+    px[7] = blend(q0, clip16(_mm_sub_epi16(q0, f1), zero, pixel_max), narrow);
+
+    let f = _mm_srai_epi16(_mm_add_epi16(f1, _mm_set1_epi16(1)), 1);
+    let narrow = _mm_andnot_si128(hev, narrow);
+    px[5] = blend(p1, clip16(_mm_add_epi16(p1, f), zero, pixel_max), narrow);
+    px[8] = blend(q1, clip16(_mm_sub_epi16(q1, f), zero, pixel_max), narrow);
+
+    // the flat lanes; as all operations are per lane and the lane masks
+    // are disjoint, the filters don't see each other's output
+    match wd {
+        16 => {
+            flat_filter(&mut px, 6, 1, 4, _mm_and_si128(flat8in, flat8out));
+            flat_filter(&mut px, 3, 0, 3, _mm_andnot_si128(flat8out, flat8in));
+        }
+        8 => flat_filter(&mut px, 3, 0, 3, flat8in),
+        6 => flat_filter(&mut px, 2, 1, 3, flat8in),
+        _ => {}
+    }
+
+    let n_out = match wd {
+        16 => 6,
+        8 => 3,
+        _ => 2,
+    };
+    for k in -n_out..n_out {
+        store_px(dst, off + k * strideb, stridea, px[(7 + k) as usize]);
+    }
+}
+
+// The AVX2 superblock functions filter the edges of 4 consecutive 4px units
+// at once, with one lane per pixel line, so that each group of 16 lanes can
+// mix filter levels and sizes. The safe wrappers are only installed by
+// lf_dsp_init_x86 after AVX2 has been detected.
+
+fn loop_filter_h_sb128y_avx2<T: Pixel>(
+    dst: &mut [T],
+    off: usize,
+    stride: usize,
+    vmask: &[u32; 3],
+    lvl: &[[u8; 4]],
+    lvl_off: usize,
+    b4_stride: usize,
+    lut: &Av1FilterLUT,
+    bitdepth_max: i32,
+) {
+    let vm = vmask[0] | vmask[1] | vmask[2];
+    let edge = |pos: usize| {
+        let l = filter_level(lvl, lvl_off + pos * b4_stride, 1, 0);
+        (l, 4 << luma_filter_idx(vmask, pos))
+    };
+    unsafe { filter_sb_avx2(dst, off, stride as isize, 1, vm, edge, lut, bitdepth_max) }
+}
+
+fn loop_filter_v_sb128y_avx2<T: Pixel>(
+    dst: &mut [T],
+    off: usize,
+    stride: usize,
+    vmask: &[u32; 3],
+    lvl: &[[u8; 4]],
+    lvl_off: usize,
+    b4_stride: usize,
+    lut: &Av1FilterLUT,
+    bitdepth_max: i32,
+) {
+    let vm = vmask[0] | vmask[1] | vmask[2];
+    let edge = |pos: usize| {
+        let l = filter_level(lvl, lvl_off + pos, b4_stride, 1);
+        (l, 4 << luma_filter_idx(vmask, pos))
+    };
+    unsafe { filter_sb_avx2(dst, off, 1, stride as isize, vm, edge, lut, bitdepth_max) }
+}
+
+fn loop_filter_h_sb128uv_avx2<T: Pixel>(
+    dst: &mut [T],
+    off: usize,
+    stride: usize,
+    vmask: &[u32; 2],
+    lvl: &[[u8; 4]],
+    lvl_off: usize,
+    dir: usize,
+    b4_stride: usize,
+    lut: &Av1FilterLUT,
+    bitdepth_max: i32,
+) {
+    let vm = vmask[0] | vmask[1];
+    let edge = |pos: usize| {
+        let l = filter_level(lvl, lvl_off + pos * b4_stride, 1, dir);
+        (l, 4 + 2 * (vmask[1] >> pos & 1) as usize)
+    };
+    unsafe { filter_sb_avx2(dst, off, stride as isize, 1, vm, edge, lut, bitdepth_max) }
+}
+
+fn loop_filter_v_sb128uv_avx2<T: Pixel>(
+    dst: &mut [T],
+    off: usize,
+    stride: usize,
+    vmask: &[u32; 2],
+    lvl: &[[u8; 4]],
+    lvl_off: usize,
+    dir: usize,
+    b4_stride: usize,
+    lut: &Av1FilterLUT,
+    bitdepth_max: i32,
+) {
+    let vm = vmask[0] | vmask[1];
+    let edge = |pos: usize| {
+        let l = filter_level(lvl, lvl_off + pos, b4_stride, dir);
+        (l, 4 + 2 * (vmask[1] >> pos & 1) as usize)
+    };
+    unsafe { filter_sb_avx2(dst, off, 1, stride as isize, vm, edge, lut, bitdepth_max) }
+}
+
+// index of the filter size of the luma edge at pos, as in vmask
+#[inline(always)]
+fn luma_filter_idx(vmask: &[u32; 3], pos: usize) -> usize {
+    if vmask[2] >> pos & 1 != 0 {
+        2
+    } else {
+        (vmask[1] >> pos & 1) as usize
+    }
+}
+
+// thresholds and filter size (4, 6, 8 or 16) of the edge of one 4px unit
+#[derive(Clone, Copy)]
+struct EdgeParams {
+    e: i32,
+    i: i32,
+    h: i32,
+    wd: usize,
+}
+
+// Filter the edges of the 4px units whose bit is set in vm, 4 units at a
+// time; edge(pos) returns the filter level and size of the edge of unit
+// pos, which is skipped if its level is 0.
+#[target_feature(enable = "avx2")]
+unsafe fn filter_sb_avx2<T: Pixel>(
+    dst: &mut [T],
+    off: usize,
+    stridea: isize,
+    strideb: isize,
+    vm: u32,
+    edge: impl Fn(usize) -> (usize, usize),
+    lut: &Av1FilterLUT,
+    bitdepth_max: i32,
+) {
+    let mut pos = 0;
+    while pos < 32 && vm >> pos != 0 {
+        let mut edges = [None; 4];
+        for (u, params) in edges.iter_mut().enumerate() {
+            if vm >> (pos + u) & 1 == 0 {
+                continue;
+            }
+            let (l, wd) = edge(pos + u);
+            if l != 0 {
+                *params = Some(EdgeParams {
+                    e: lut.e[l] as i32,
+                    i: lut.i[l] as i32,
+                    h: (l >> 4) as i32,
+                    wd,
+                });
+            }
+        }
+        if edges.iter().any(Option::is_some) {
+            let off = off as isize + pos as isize * 4 * stridea;
+            loop_filter_avx2(dst, off, stridea, strideb, &edges, bitdepth_max);
+        }
+        pos += 4;
+    }
+}
+
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn abs_diff_avx2(a: __m256i, b: __m256i) -> __m256i {
+    _mm256_abs_epi16(_mm256_sub_epi16(a, b))
+}
+
+// lanes where |a - b| > thresh
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn exceeds_avx2(a: __m256i, b: __m256i, thresh: __m256i) -> __m256i {
+    _mm256_cmpgt_epi16(abs_diff_avx2(a, b), thresh)
+}
+
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn clip16_avx2(v: __m256i, min: __m256i, max: __m256i) -> __m256i {
+    _mm256_min_epi16(_mm256_max_epi16(v, min), max)
+}
+
+// take b in the lanes of mask and a elsewhere
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn blend_avx2(a: __m256i, b: __m256i, mask: __m256i) -> __m256i {
+    _mm256_blendv_epi8(a, b, mask)
+}
+
+// lanes where |p(k) - p(0)| and |q(k) - q(0)| are at most flat for all k
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn is_flat_avx2(px: &[__m256i; 14], ks: std::ops::Range<usize>, flat: __m256i) -> __m256i {
+    let mut not_flat = _mm256_setzero_si256();
+    for k in ks {
+        let d = _mm256_or_si256(
+            exceeds_avx2(px[6 - k], px[6], flat),
+            exceeds_avx2(px[7 + k], px[7], flat),
+        );
+        not_flat = _mm256_or_si256(not_flat, d);
+    }
+    _mm256_xor_si256(not_flat, _mm256_set1_epi16(-1))
+}
+
+// see flat_filter
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn flat_filter_avx2(
+    px: &mut [__m256i; 14],
+    n: isize,
+    n2: isize,
+    log2size: i32,
+    mask: __m256i,
+) {
+    if _mm256_testz_si256(mask, mask) != 0 {
+        return;
+    }
+    let rnd = _mm256_set1_epi16(1 << (log2size - 1));
+    let shift = _mm_cvtsi32_si128(log2size);
+    let mut out = [_mm256_setzero_si256(); 12];
+    for k in -n..n {
+        let mut t = rnd;
+        for j in -n..=n {
+            let v = px[(7 + clip(k + j, -(n + 1), n)) as usize];
+            t = _mm256_add_epi16(t, v);
+            if j.abs() <= n2 {
+                t = _mm256_add_epi16(t, v);
+            }
+        }
+        out[(k + n) as usize] = _mm256_srl_epi16(t, shift);
+    }
+    for k in -n..n {
+        let pos = (7 + k) as usize;
+        px[pos] = blend_avx2(px[pos], out[(k + n) as usize], mask);
+    }
+}
+
+// number of pixels read on each side of an edge of filter size wd
+#[inline(always)]
+fn edge_px(wd: usize) -> isize {
+    match wd {
+        16 => 7,
+        8 => 4,
+        6 => 3,
+        _ => 2,
+    }
+}
+
+// loop_filter_ssse3 for the 16 lines of the edges of 4 consecutive 4px
+// units, with the thresholds and filter sizes set per lane; the lanes of
+// units without an edge are left alone.
+#[target_feature(enable = "avx2")]
+unsafe fn loop_filter_avx2<T: Pixel>(
+    dst: &mut [T],
+    off: isize,
+    stridea: isize,
+    strideb: isize,
+    edges: &[Option<EdgeParams>; 4],
+    bitdepth_max: i32,
+) {
+    let bitdepth_min_8 = (32 - bitdepth_max.leading_zeros()) as i32 - 8;
+    let flat = _mm256_set1_epi16(1 << bitdepth_min_8);
+
+    // per lane e, i and h thresholds, and masks of the lanes with a filter
+    // size of at least 6, 8 and 16, and of those with an edge at all
+    let mut lanes = [[0i16; 16]; 7];
+    let mut n_px = 0;
+    for (u, params) in edges.iter().enumerate() {
+        if let Some(p) = params {
+            let values = [
+                p.e << bitdepth_min_8,
+                p.i << bitdepth_min_8,
+                p.h << bitdepth_min_8,
+                -((p.wd >= 6) as i32),
+                -((p.wd >= 8) as i32),
+                -((p.wd == 16) as i32),
+                -1,
+            ];
+            for (l, &v) in lanes.iter_mut().zip(values.iter()) {
+                l[4 * u..4 * u + 4].copy_from_slice(&[v as i16; 4]);
+            }
+            n_px = cmp::max(n_px, edge_px(p.wd));
+        }
+    }
+    let lane_vec = |l: usize| _mm256_loadu_si256(lanes[l].as_ptr() as *const __m256i);
+    let (e, i, h) = (lane_vec(0), lane_vec(1), lane_vec(2));
+    let (wd6, wd8, wd16, active) = (lane_vec(3), lane_vec(4), lane_vec(5), lane_vec(6));
+
+    // px[7 + k] holds the pixels at distance k from the edge; the pixels of
+    // each unit are gathered with the 4 lane accessors of the SSSE3 version
+    let mut rows = [[0i16; 16]; 14];
+    for k in -n_px..n_px {
+        let row = &mut rows[(7 + k) as usize];
+        for (u, params) in edges.iter().enumerate() {
+            if params.is_some() {
+                let v = load_px(dst, off + 4 * u as isize * stridea + k * strideb, stridea);
+                _mm_storel_epi64(row[4 * u..].as_mut_ptr() as *mut __m128i, v);
+            }
+        }
+    }
+    let mut px = [_mm256_setzero_si256(); 14];
+    for (v, row) in px.iter_mut().zip(rows.iter()) {
+        *v = _mm256_loadu_si256(row.as_ptr() as *const __m256i);
+    }
+    let (p1, p0, q0, q1) = (px[5], px[6], px[7], px[8]);
+
+    let mut not_fm = _mm256_or_si256(exceeds_avx2(p1, p0, i), exceeds_avx2(q1, q0, i));
+    let edge = _mm256_add_epi16(
+        _mm256_slli_epi16(abs_diff_avx2(p0, q0), 1),
+        _mm256_srli_epi16(abs_diff_avx2(p1, q1), 1),
+    );
+    not_fm = _mm256_or_si256(not_fm, _mm256_cmpgt_epi16(edge, e));
+    if n_px > 2 {
+        let d = _mm256_or_si256(exceeds_avx2(px[4], p1, i), exceeds_avx2(px[9], q1, i));
+        not_fm = _mm256_or_si256(not_fm, _mm256_and_si256(d, wd6));
+        if n_px > 3 {
+            let d = _mm256_or_si256(
+                exceeds_avx2(px[3], px[4], i),
+                exceeds_avx2(px[10], px[9], i),
+            );
+            not_fm = _mm256_or_si256(not_fm, _mm256_and_si256(d, wd8));
+        }
+    }
+    let fm = _mm256_andnot_si256(not_fm, active);
+    if _mm256_movemask_epi8(fm) == 0 {
+        return;
+    }
+
+    let flat8out = if n_px > 4 {
+        _mm256_and_si256(is_flat_avx2(&px, 4..7, flat), _mm256_and_si256(fm, wd16))
+    } else {
+        _mm256_setzero_si256()
+    };
+    let flat8in = if n_px > 2 {
+        // the 8 and 16 px filters also need p3 and q3 to be flat
+        let mut flat8in = is_flat_avx2(&px, 1..3, flat);
+        if n_px > 3 {
+            let flat3 =
+                _mm256_or_si256(is_flat_avx2(&px, 3..4, flat), _mm256_xor_si256(wd8, active));
+            flat8in = _mm256_and_si256(flat8in, flat3);
+        }
+        _mm256_and_si256(flat8in, _mm256_and_si256(fm, wd6))
+    } else {
+        _mm256_setzero_si256()
+    };
+
+    // the narrow filter, in the lanes that aren't flat
+    let narrow = _mm256_andnot_si256(flat8in, fm);
+    let hev = _mm256_or_si256(exceeds_avx2(p1, p0, h), exceeds_avx2(q1, q0, h));
+    let diff_min = _mm256_set1_epi16(-128 << bitdepth_min_8);
+    let diff_max = _mm256_set1_epi16((128 << bitdepth_min_8) - 1);
+    let (zero, pixel_max) = (
+        _mm256_setzero_si256(),
+        _mm256_set1_epi16(bitdepth_max as i16),
+    );
+
+    let base = _mm256_and_si256(
+        hev,
+        clip16_avx2(_mm256_sub_epi16(p1, q1), diff_min, diff_max),
+    );
+    let d = _mm256_sub_epi16(q0, p0);
+    let f = _mm256_add_epi16(_mm256_add_epi16(d, _mm256_add_epi16(d, d)), base);
+    let f = clip16_avx2(f, diff_min, diff_max);
+    let f1 = _mm256_srai_epi16(
+        _mm256_min_epi16(_mm256_add_epi16(f, _mm256_set1_epi16(4)), diff_max),
+        3,
+    );
+    let f2 = _mm256_srai_epi16(
+        _mm256_min_epi16(_mm256_add_epi16(f, _mm256_set1_epi16(3)), diff_max),
+        3,
+    );
+    px[6] = blend_avx2(
+        p0,
+        clip16_avx2(_mm256_add_epi16(p0, f2), zero, pixel_max),
+        narrow,
+    );
+    px[7] = blend_avx2(
+        q0,
+        clip16_avx2(_mm256_sub_epi16(q0, f1), zero, pixel_max),
+        narrow,
+    );
+
+    let f = _mm256_srai_epi16(_mm256_add_epi16(f1, _mm256_set1_epi16(1)), 1);
+    let narrow = _mm256_andnot_si256(hev, narrow);
+    px[5] = blend_avx2(
+        p1,
+        clip16_avx2(_mm256_add_epi16(p1, f), zero, pixel_max),
+        narrow,
+    );
+    px[8] = blend_avx2(
+        q1,
+        clip16_avx2(_mm256_sub_epi16(q1, f), zero, pixel_max),
+        narrow,
+    );
+
+    // the flat lanes of each filter size
+    let flat16 = _mm256_and_si256(flat8in, flat8out);
+    let flat8 = _mm256_and_si256(_mm256_andnot_si256(flat8out, flat8in), wd8);
+    let flat6 = _mm256_andnot_si256(wd8, flat8in);
+    flat_filter_avx2(&mut px, 6, 1, 4, flat16);
+    flat_filter_avx2(&mut px, 3, 0, 3, flat8);
+    flat_filter_avx2(&mut px, 2, 1, 3, flat6);
+
+    for (row, &v) in rows.iter_mut().zip(px.iter()) {
+        _mm256_storeu_si256(row.as_mut_ptr() as *mut __m256i, v);
+    }
+    for (u, params) in edges.iter().enumerate() {
+        if let Some(p) = params {
+            let n_out = match p.wd {
+                16 => 6,
+                8 => 3,
+                _ => 2,
+            };
+            for k in -n_out..n_out {
+                let v = _mm_loadl_epi64(rows[(7 + k) as usize][4 * u..].as_ptr() as *const __m128i);
+                store_px(
+                    dst,
+                    off + 4 * u as isize * stridea + k * strideb,
+                    stridea,
+                    v,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,482 @@
+use crate::cpu::*;
+use crate::levels::Filter2d;
+use crate::mc::*;
+use crate::tables::*;
+use crate::util::*;
+
+use std::arch::x86_64::*;
+use std::{mem, ptr};
+
+pub(crate) fn mc_dsp_init_x86<T: Pixel>(c: &mut MCDSPContext<T>, flags: u32) {
+    if flags & CPU_FLAG_SSE41 == 0 {
+        return;
+    }
+
+    c.mc = put_8tap_sse41;
+    c.mct = prep_8tap_sse41;
+    c.avg = avg_sse41;
+
+    if flags & CPU_FLAG_AVX2 == 0 {
+        return;
+    }
+
+    c.mc = put_8tap_avx2;
+    c.mct = prep_8tap_avx2;
+    c.avg = avg_avx2;
+}
+
+// The safe wrappers below are only installed by mc_dsp_init_x86 after the
+// CPU features their kernels are compiled for have been detected.
+
+fn put_8tap_sse41<T: Pixel>(
+    dst: &mut [T],
+    dst_stride: usize,
+    src: &[T],
+    src_stride: usize,
+    w: usize,
+    h: usize,
+    mx: usize,
+    my: usize,
+    filter: Filter2d,
+    bitdepth_max: i32,
+) {
+    unsafe {
+        filter_8tap_sse41(
+            src,
+            src_stride,
+            w,
+            h,
+            mx,
+            my,
+            filter,
+            bitdepth_max,
+            false,
+            |y, row| clip_row_sse41(&mut dst[y * dst_stride..], row, None, w, 0, bitdepth_max),
+        );
+    }
+}
+
+fn prep_8tap_sse41<T: Pixel>(
+    tmp: &mut [i32],
+    src: &[T],
+    src_stride: usize,
+    w: usize,
+    h: usize,
+    mx: usize,
+    my: usize,
+    filter: Filter2d,
+    bitdepth_max: i32,
+) {
+    unsafe {
+        filter_8tap_sse41(
+            src,
+            src_stride,
+            w,
+            h,
+            mx,
+            my,
+            filter,
+            bitdepth_max,
+            true,
+            |y, row| tmp[y * w..(y + 1) * w].copy_from_slice(row),
+        );
+    }
+}
+
+fn avg_sse41<T: Pixel>(
+    dst: &mut [T],
+    dst_stride: usize,
+    tmp1: &[i32],
+    tmp2: &[i32],
+    w: usize,
+    h: usize,
+    bitdepth_max: i32,
+) {
+    let (round0, round1) = inter_round(bitdepth_max, true);
+    let sh = 1 + 14 - (round0 + round1);
+    for y in 0..h {
+        let rows = (&tmp1[y * w..(y + 1) * w], &tmp2[y * w..(y + 1) * w]);
+        unsafe {
+            clip_row_sse41(
+                &mut dst[y * dst_stride..],
+                rows.0,
+                Some(rows.1),
+                w,
+                sh,
+                bitdepth_max,
+            );
+        }
+    }
+}
+
+fn put_8tap_avx2<T: Pixel>(
+    dst: &mut [T],
+    dst_stride: usize,
+    src: &[T],
+    src_stride: usize,
+    w: usize,
+    h: usize,
+    mx: usize,
+    my: usize,
+    filter: Filter2d,
+    bitdepth_max: i32,
+) {
+    unsafe {
+        filter_8tap_avx2(
+            src,
+            src_stride,
+            w,
+            h,
+            mx,
+            my,
+            filter,
+            bitdepth_max,
+            false,
+            |y, row| clip_row_avx2(&mut dst[y * dst_stride..], row, None, w, 0, bitdepth_max),
+        );
+    }
+}
+
+fn prep_8tap_avx2<T: Pixel>(
+    tmp: &mut [i32],
+    src: &[T],
+    src_stride: usize,
+    w: usize,
+    h: usize,
+    mx: usize,
+    my: usize,
+    filter: Filter2d,
+    bitdepth_max: i32,
+) {
+    unsafe {
+        filter_8tap_avx2(
+            src,
+            src_stride,
+            w,
+            h,
+            mx,
+            my,
+            filter,
+            bitdepth_max,
+            true,
+            |y, row| tmp[y * w..(y + 1) * w].copy_from_slice(row),
+        );
+    }
+}
+
+fn avg_avx2<T: Pixel>(
+    dst: &mut [T],
+    dst_stride: usize,
+    tmp1: &[i32],
+    tmp2: &[i32],
+    w: usize,
+    h: usize,
+    bitdepth_max: i32,
+) {
+    let (round0, round1) = inter_round(bitdepth_max, true);
+    let sh = 1 + 14 - (round0 + round1);
+    for y in 0..h {
+        let rows = (&tmp1[y * w..(y + 1) * w], &tmp2[y * w..(y + 1) * w]);
+        unsafe {
+            clip_row_avx2(
+                &mut dst[y * dst_stride..],
+                rows.0,
+                Some(rows.1),
+                w,
+                sh,
+                bitdepth_max,
+            );
+        }
+    }
+}
+
+// the largest block width, and so row length of the filter passes
+const MAX_W: usize = 128;
+
+// scalar filter taps for the columns left over by the vector loops
+#[inline(always)]
+fn filter_px<T: Copy>(src: &[T], stride: usize, f: &[i16; 8], round: i32) -> i32
+where
+    i32: CastFromPrimitive<T>,
+{
+    let sum: i32 = (0..8)
+        .map(|k| f[k] as i32 * i32::cast_from(src[k * stride]))
+        .sum();
+    round_shift(sum, round as usize)
+}
+
+// 4 pixels at src, widened to 32 bits
+#[target_feature(enable = "sse4.1")]
+#[inline]
+unsafe fn load4_sse41<T: Pixel>(src: &[T]) -> __m128i {
+    let src = &src[..4];
+    if mem::size_of::<T>() == 1 {
+        _mm_cvtepu8_epi32(_mm_cvtsi32_si128(ptr::read_unaligned(
+            src.as_ptr() as *const i32
+        )))
+    } else {
+        _mm_cvtepu16_epi32(_mm_loadl_epi64(src.as_ptr() as *const __m128i))
+    }
+}
+
+// store 4 32-bit values, which must be in pixel range, as pixels at dst
+#[target_feature(enable = "sse4.1")]
+#[inline]
+unsafe fn store4_sse41<T: Pixel>(dst: &mut [T], v: __m128i) {
+    let dst = &mut dst[..4];
+    let v = _mm_packus_epi32(v, v);
+    if mem::size_of::<T>() == 1 {
+        let v = _mm_packus_epi16(v, v);
+        ptr::write_unaligned(dst.as_mut_ptr() as *mut i32, _mm_cvtsi128_si32(v));
+    } else {
+        _mm_storel_epi64(dst.as_mut_ptr() as *mut __m128i, v);
+    }
+}
+
+// Same as filter_8tap in mc.rs, but each output row is passed to emit as
+// soon as it is done, so that only the 8 rows of the first pass the second
+// one reads need to be kept, in a ring buffer on the stack.
+#[target_feature(enable = "sse4.1")]
+unsafe fn filter_8tap_sse41<T: Pixel>(
+    src: &[T],
+    src_stride: usize,
+    w: usize,
+    h: usize,
+    mx: usize,
+    my: usize,
+    filter: Filter2d,
+    bitdepth_max: i32,
+    is_compound: bool,
+    mut emit: impl FnMut(usize, &[i32]),
+) {
+    let (round0, round1) = inter_round(bitdepth_max, is_compound);
+    let [h_mode, v_mode] = filter_2d_modes[filter as usize];
+    let fh = subpel_filter(h_mode, w, mx);
+    let fv = subpel_filter(v_mode, h, my);
+
+    let mut coef_h = [_mm_setzero_si128(); 8];
+    let mut coef_v = [_mm_setzero_si128(); 8];
+    for k in 0..8 {
+        coef_h[k] = _mm_set1_epi32(fh[k] as i32);
+        coef_v[k] = _mm_set1_epi32(fv[k] as i32);
+    }
+    let (rnd0, sh0) = (_mm_set1_epi32(1 << round0 >> 1), _mm_cvtsi32_si128(round0));
+    let (rnd1, sh1) = (_mm_set1_epi32(1 << round1 >> 1), _mm_cvtsi32_si128(round1));
+
+    // row y of the first pass is at mid[(y & 7) * MAX_W..]
+    let mut mid = [0i32; 8 * MAX_W];
+    let mut out = [0i32; MAX_W];
+    let mut filter_h = |y: usize, mid: &mut [i32; 8 * MAX_W]| {
+        let src = &src[y * src_stride..];
+        let row = &mut mid[(y & 7) * MAX_W..(y & 7) * MAX_W + w];
+        let mut x = 0;
+        while x + 4 <= w {
+            let mut sum = _mm_setzero_si128();
+            for k in 0..8 {
+                sum = _mm_add_epi32(sum, _mm_mullo_epi32(coef_h[k], load4_sse41(&src[x + k..])));
+            }
+            let v = _mm_sra_epi32(_mm_add_epi32(sum, rnd0), sh0);
+            _mm_storeu_si128(row[x..x + 4].as_mut_ptr() as *mut __m128i, v);
+            x += 4;
+        }
+        for x in x..w {
+            row[x] = filter_px(&src[x..], 1, fh, round0);
+        }
+    };
+
+    for y in 0..7 {
+        filter_h(y, &mut mid);
+    }
+    for y in 0..h {
+        filter_h(y + 7, &mut mid);
+        let mut x = 0;
+        while x + 4 <= w {
+            let mut sum = _mm_setzero_si128();
+            for k in 0..8 {
+                let t = &mid[((y + k) & 7) * MAX_W + x..];
+                let t = _mm_loadu_si128(t[..4].as_ptr() as *const __m128i);
+                sum = _mm_add_epi32(sum, _mm_mullo_epi32(coef_v[k], t));
+            }
+            let v = _mm_sra_epi32(_mm_add_epi32(sum, rnd1), sh1);
+            _mm_storeu_si128(out[x..x + 4].as_mut_ptr() as *mut __m128i, v);
+            x += 4;
+        }
+        for x in x..w {
+            let sum: i32 = (0..8)
+                .map(|k| fv[k] as i32 * mid[((y + k) & 7) * MAX_W + x])
+                .sum();
+            out[x] = round_shift(sum, round1 as usize);
+        }
+        emit(y, &out[..w]);
+    }
+}
+
+// Write the w pixels of t1 (+ t2), rounded by sh and clipped to the pixel
+// range, to dst.
+#[target_feature(enable = "sse4.1")]
+unsafe fn clip_row_sse41<T: Pixel>(
+    dst: &mut [T],
+    t1: &[i32],
+    t2: Option<&[i32]>,
+    w: usize,
+    sh: i32,
+    bitdepth_max: i32,
+) {
+    let (rnd, shift) = (_mm_set1_epi32(1 << sh >> 1), _mm_cvtsi32_si128(sh));
+    let (zero, max) = (_mm_setzero_si128(), _mm_set1_epi32(bitdepth_max));
+    let mut x = 0;
+    while x + 4 <= w {
+        let mut v = _mm_loadu_si128(t1[x..x + 4].as_ptr() as *const __m128i);
+        if let Some(t2) = t2 {
+            let v2 = _mm_loadu_si128(t2[x..x + 4].as_ptr() as *const __m128i);
+            v = _mm_add_epi32(v, v2);
+        }
+        v = _mm_sra_epi32(_mm_add_epi32(v, rnd), shift);
+        v = _mm_min_epi32(_mm_max_epi32(v, zero), max);
+        store4_sse41(&mut dst[x..], v);
+        x += 4;
+    }
+    for x in x..w {
+        let v = t1[x] + t2.map_or(0, |t2| t2[x]);
+        dst[x] = T::cast_from(clip(round_shift(v, sh as usize), 0, bitdepth_max));
+    }
+}
+
+// 8 pixels at src, widened to 32 bits
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn load8_avx2<T: Pixel>(src: &[T]) -> __m256i {
+    let src = &src[..8];
+    if mem::size_of::<T>() == 1 {
+        _mm256_cvtepu8_epi32(_mm_loadl_epi64(src.as_ptr() as *const __m128i))
+    } else {
+        _mm256_cvtepu16_epi32(_mm_loadu_si128(src.as_ptr() as *const __m128i))
+    }
+}
+
+// store 8 32-bit values, which must be in pixel range, as pixels at dst
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn store8_avx2<T: Pixel>(dst: &mut [T], v: __m256i) {
+    let dst = &mut dst[..8];
+    // packus works within 128-bit lanes, gather both halves in the low lane
+    let v = _mm256_permute4x64_epi64(_mm256_packus_epi32(v, v), 0b1000);
+    let v = _mm256_castsi256_si128(v);
+    if mem::size_of::<T>() == 1 {
+        _mm_storel_epi64(dst.as_mut_ptr() as *mut __m128i, _mm_packus_epi16(v, v));
+    } else {
+        _mm_storeu_si128(dst.as_mut_ptr() as *mut __m128i, v);
+    }
+}
+
+// same as filter_8tap_sse41, 8 pixels at a time
+#[target_feature(enable = "avx2")]
+unsafe fn filter_8tap_avx2<T: Pixel>(
+    src: &[T],
+    src_stride: usize,
+    w: usize,
+    h: usize,
+    mx: usize,
+    my: usize,
+    filter: Filter2d,
+    bitdepth_max: i32,
+    is_compound: bool,
+    mut emit: impl FnMut(usize, &[i32]),
+) {
+    let (round0, round1) = inter_round(bitdepth_max, is_compound);
+    let [h_mode, v_mode] = filter_2d_modes[filter as usize];
+    let fh = subpel_filter(h_mode, w, mx);
+    let fv = subpel_filter(v_mode, h, my);
+
+    let mut coef_h = [_mm256_setzero_si256(); 8];
+    let mut coef_v = [_mm256_setzero_si256(); 8];
+    for k in 0..8 {
+        coef_h[k] = _mm256_set1_epi32(fh[k] as i32);
+        coef_v[k] = _mm256_set1_epi32(fv[k] as i32);
+    }
+    let (rnd0, sh0) = (
+        _mm256_set1_epi32(1 << round0 >> 1),
+        _mm_cvtsi32_si128(round0),
+    );
+    let (rnd1, sh1) = (
+        _mm256_set1_epi32(1 << round1 >> 1),
+        _mm_cvtsi32_si128(round1),
+    );
+
+    let mut mid = [0i32; 8 * MAX_W];
+    let mut out = [0i32; MAX_W];
+    let mut filter_h = |y: usize, mid: &mut [i32; 8 * MAX_W]| {
+        let src = &src[y * src_stride..];
+        let row = &mut mid[(y & 7) * MAX_W..(y & 7) * MAX_W + w];
+        let mut x = 0;
+        while x + 8 <= w {
+            let mut sum = _mm256_setzero_si256();
+            for k in 0..8 {
+                let px = load8_avx2(&src[x + k..]);
+                sum = _mm256_add_epi32(sum, _mm256_mullo_epi32(coef_h[k], px));
+            }
+            let v = _mm256_sra_epi32(_mm256_add_epi32(sum, rnd0), sh0);
+            _mm256_storeu_si256(row[x..x + 8].as_mut_ptr() as *mut __m256i, v);
+            x += 8;
+        }
+        for x in x..w {
+            row[x] = filter_px(&src[x..], 1, fh, round0);
+        }
+    };
+
+    for y in 0..7 {
+        filter_h(y, &mut mid);
+    }
+    for y in 0..h {
+        filter_h(y + 7, &mut mid);
+        let mut x = 0;
+        while x + 8 <= w {
+            let mut sum = _mm256_setzero_si256();
+            for k in 0..8 {
+                let t = &mid[((y + k) & 7) * MAX_W + x..];
+                let t = _mm256_loadu_si256(t[..8].as_ptr() as *const __m256i);
+                sum = _mm256_add_epi32(sum, _mm256_mullo_epi32(coef_v[k], t));
+            }
+            let v = _mm256_sra_epi32(_mm256_add_epi32(sum, rnd1), sh1);
+            _mm256_storeu_si256(out[x..x + 8].as_mut_ptr() as *mut __m256i, v);
+            x += 8;
+        }
+        for x in x..w {
+            let sum: i32 = (0..8)
+                .map(|k| fv[k] as i32 * mid[((y + k) & 7) * MAX_W + x])
+                .sum();
+            out[x] = round_shift(sum, round1 as usize);
+        }
+        emit(y, &out[..w]);
+    }
+}
+
+// same as clip_row_sse41, 8 pixels at a time
+#[target_feature(enable = "avx2")]
+unsafe fn clip_row_avx2<T: Pixel>(
+    dst: &mut [T],
+    t1: &[i32],
+    t2: Option<&[i32]>,
+    w: usize,
+    sh: i32,
+    bitdepth_max: i32,
+) {
+    let (rnd, shift) = (_mm256_set1_epi32(1 << sh >> 1), _mm_cvtsi32_si128(sh));
+    let (zero, max) = (_mm256_setzero_si256(), _mm256_set1_epi32(bitdepth_max));
+    let mut x = 0;
+    while x + 8 <= w {
+        let mut v = _mm256_loadu_si256(t1[x..x + 8].as_ptr() as *const __m256i);
+        if let Some(t2) = t2 {
+            let v2 = _mm256_loadu_si256(t2[x..x + 8].as_ptr() as *const __m256i);
+            v = _mm256_add_epi32(v, v2);
+        }
+        v = _mm256_sra_epi32(_mm256_add_epi32(v, rnd), shift);
+        v = _mm256_min_epi32(_mm256_max_epi32(v, zero), max);
+        store8_avx2(&mut dst[x..], v);
+        x += 8;
+    }
+    for x in x..w {
+        let v = t1[x] + t2.map_or(0, |t2| t2[x]);
+        dst[x] = T::cast_from(clip(round_shift(v, sh as usize), 0, bitdepth_max));
+    }
+}
@@ -0,0 +1,3 @@
+pub(crate) mod cdef;
+pub(crate) mod loopfilter;
+pub(crate) mod mc;